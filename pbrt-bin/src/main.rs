@@ -11,6 +11,9 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
 #[macro_use]
@@ -24,7 +27,18 @@ extern crate structopt_derive;
 use structopt::StructOpt;
 
 extern crate pbrt;
+extern crate serde_json;
 use pbrt::core::api;
+use pbrt::core::gltf;
+use pbrt::core::lint;
+use pbrt::core::mitsuba;
+use pbrt::core::obj;
+use pbrt::core::paramset::Value;
+use pbrt::core::parser;
+use pbrt::core::parser::{Directive, Span};
+use pbrt::core::ply;
+use pbrt::core::upgrade;
+use pbrt::core::writer::Writer;
 
 #[derive(Clone, Debug, Default, StructOpt)]
 #[structopt(name = "pbrt", about = "Rust implementation of http://pbrt.org/")]
@@ -44,9 +58,203 @@ pub struct Options {
     #[structopt(short = "o", long = "outfile")]
     /// Write the final image to the given filename.
     pub image_file: Option<String>,
+    #[structopt(long = "cat")]
+    /// Print a reformatted version of the input file(s) to standard output. Does not render an
+    /// image.
+    pub cat: bool,
+    #[structopt(long = "upgrade")]
+    /// Upgrade a pbrt-v3 file to pbrt-v4's format, printing it to standard output. Does not
+    /// render an image.
+    pub upgrade: bool,
+    #[structopt(long = "lint")]
+    /// Check the input file(s) for mistakes, printing what's found to standard output. Does not
+    /// render an image.
+    pub lint: bool,
+    #[structopt(long = "json")]
+    /// Print the input file(s) as a single JSON encoded scene to standard output. Does not render
+    /// an image. Input files whose names end in .json are read as JSON encoded scenes, those
+    /// ending in .gltf or .glb are converted from glTF, and those ending in .xml are converted
+    /// from Mitsuba.
+    pub json: bool,
+    #[structopt(long = "toply")]
+    /// Like --cat, but writes large triangle meshes to PLY files, mesh_00001.ply and so on in the
+    /// current directory, and refers to them with plymesh shapes.
+    pub toply: bool,
+    #[structopt(long = "fromobj")]
+    /// Convert Wavefront .obj file(s), and the .mtl files they use, to the shapes and materials
+    /// of a scene's world block, printing them to standard output. Does not render an image.
+    pub fromobj: bool,
     pub scene_files: Vec<String>,
 }
 
+/// Reads a JSON encoded scene.
+fn read_json(scene_file: &str) -> Result<parser::Scene, String> {
+    let f = File::open(scene_file).map_err(|e| format!("{}: {}", scene_file, e))?;
+    serde_json::from_reader(io::BufReader::new(f)).map_err(|e| format!("{}: {}", scene_file, e))
+}
+
+/// Reads a glTF file, logging warnings for anything that isn't converted.
+fn read_gltf(scene_file: &str) -> Result<parser::Scene, String> {
+    let mut warnings = Vec::new();
+    let scene = gltf::read_file(scene_file, &mut warnings);
+    for warning in warnings {
+        warn!("{}: {}", scene_file, warning);
+    }
+    scene.map_err(|e| format!("{}: {}", scene_file, e))
+}
+
+/// Reads a Mitsuba XML scene, logging warnings for anything that has no pbrt equivalent.
+fn read_mitsuba(scene_file: &str) -> Result<parser::Scene, String> {
+    let mut warnings = Vec::new();
+    let scene = mitsuba::read_file(scene_file, &mut warnings);
+    for warning in warnings {
+        warn!("{}: {}", scene_file, warning);
+    }
+    scene.map_err(|e| e.to_string())
+}
+
+/// Reads scene_file if it isn't in pbrt's format, i.e. its name ends in .json, .gltf, .glb or
+/// .xml.
+/// Returns None for pbrt files.
+fn read_converted(scene_file: &str) -> Option<Result<parser::Scene, String>> {
+    let ext = Path::new(scene_file).extension()?.to_str()?;
+    match ext.to_lowercase().as_str() {
+        "json" => Some(read_json(scene_file)),
+        "gltf" | "glb" => Some(read_gltf(scene_file)),
+        "xml" => Some(read_mitsuba(scene_file)),
+        _ => None,
+    }
+}
+
+/// Reads the scene in scene_file, converting it if it's JSON, glTF or Mitsuba.
+fn read_scene(scene_file: &str) -> Result<parser::Scene, String> {
+    match read_converted(scene_file) {
+        Some(scene) => scene,
+        None => parser::parse_file(scene_file).map_err(|e| e.to_string()),
+    }
+}
+
+/// Calls f with each directive in each file, and where it's from, as it's parsed.  If upgrade is set, directives are
+/// rewritten in pbrt-v4 syntax first, with warnings logged for anything that can't be.
+fn for_each_directive<F>(scene_files: &[String], upgrade: bool, mut f: F) -> Result<(), String>
+where
+    F: FnMut(Directive, &Span) -> Result<(), String>,
+{
+    let mut upgrade_and_call = |mut d, span: &Span| {
+        if upgrade {
+            let mut warnings = Vec::new();
+            d = upgrade::upgrade(d, span, &mut warnings);
+            for warning in warnings {
+                warn!("{}", warning);
+            }
+        }
+        f(d, span)
+    };
+    for scene_file in scene_files {
+        if let Some(scene) = read_converted(scene_file) {
+            let scene = scene?;
+            for (d, span) in scene.directives.into_iter().zip(&scene.spans) {
+                upgrade_and_call(d, span)?;
+            }
+            continue;
+        }
+        let directives = parser::FileDirectives::open(scene_file).map_err(|e| e.to_string())?;
+        for d in directives {
+            let (d, span) = d.map_err(|e| e.to_string())?;
+            upgrade_and_call(d, &span)?;
+        }
+    }
+    Ok(())
+}
+
+// Like pbrt's, trianglemesh shapes with fewer indices than this are left inline by --toply.
+const TOPLY_MIN_INDICES: usize = 500;
+
+/// If d is a trianglemesh shape with at least TOPLY_MIN_INDICES indices, writes its mesh to the
+/// next PLY file and returns the plymesh shape that replaces it.  Otherwise, returns d.  meshes
+/// counts the files written.
+fn to_ply(d: Directive, span: &Span, meshes: &mut usize) -> Result<Directive, String> {
+    let ps = match d {
+        Directive::Shape(ref name, ref ps) if name == "trianglemesh" => ps,
+        _ => return Ok(d),
+    };
+    match ps.get("indices") {
+        Some(Value::Int(indices)) if indices.0.len() >= TOPLY_MIN_INDICES => (),
+        _ => return Ok(d),
+    }
+    let (mesh, mut rest) = match ply::split_trianglemesh(ps) {
+        Ok(split) => split,
+        Err(e) => {
+            warn!("{}: {}, leaving the mesh inline", span, e);
+            return Ok(d);
+        }
+    };
+    *meshes += 1;
+    let filename = format!("mesh_{:05}.ply", meshes);
+    ply::write_file(&filename, &mesh).map_err(|e| format!("{}: {}", filename, e))?;
+    rest.add("filename", Value::String(vec![filename].into()));
+    Ok(Directive::Shape("plymesh".to_owned(), rest))
+}
+
+/// Writes the scene in each file to stdout in canonical form, as it's parsed.  If upgrade is set,
+/// the scene is rewritten in pbrt-v4 syntax, with warnings logged for anything that can't be.  If
+/// toply is set, large triangle meshes are moved to PLY files.
+fn cat(scene_files: &[String], upgrade: bool, toply: bool) -> Result<(), String> {
+    let stdout = io::stdout();
+    let mut w = Writer::new(stdout.lock());
+    let mut meshes = 0;
+    for_each_directive(scene_files, upgrade, |mut d, span| {
+        if toply {
+            d = to_ply(d, span, &mut meshes)?;
+        }
+        w.write(&d).map_err(|e| e.to_string())
+    })
+}
+
+/// Writes the shapes and materials in each .obj file to stdout as directives.
+fn from_obj(obj_files: &[String]) -> Result<(), String> {
+    let stdout = io::stdout();
+    let mut w = Writer::new(stdout.lock());
+    for f in obj_files {
+        let mut warnings = Vec::new();
+        let directives = obj::read_file(f, &mut warnings).map_err(|e| format!("{}: {}", f, e))?;
+        for warning in warnings {
+            warn!("{}: {}", f, warning);
+        }
+        for d in directives {
+            w.write(&d).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the scenes in all the files to stdout as one JSON encoded scene, upgraded to pbrt-v4
+/// syntax if upgrade is set.
+fn json(scene_files: &[String], upgrade: bool) -> Result<(), String> {
+    let mut scene = parser::Scene::default();
+    for_each_directive(scene_files, upgrade, |d, _| {
+        scene.push(d, Default::default());
+        Ok(())
+    })?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    serde_json::to_writer_pretty(&mut out, &scene).map_err(|e| e.to_string())?;
+    writeln!(out).map_err(|e| e.to_string())
+}
+
+/// Prints the problems lint finds in each file.  Returns whether any of them are errors.
+fn lint(scene_files: &[String]) -> Result<bool, String> {
+    let mut errors = false;
+    for f in scene_files {
+        let scene = read_scene(f)?;
+        for d in lint::lint(&scene) {
+            errors |= d.severity == lint::Severity::Error;
+            println!("{}", d);
+        }
+    }
+    Ok(errors)
+}
+
 fn main() {
     let flags = Options::from_args();
     if flags.verbose {
@@ -62,6 +270,41 @@ fn main() {
         process::exit(1);
     }
 
+    if flags.fromobj {
+        if let Err(err) = from_obj(&flags.scene_files) {
+            error!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if flags.json {
+        if let Err(err) = json(&flags.scene_files, flags.upgrade) {
+            error!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if flags.cat || flags.upgrade || flags.toply {
+        if let Err(err) = cat(&flags.scene_files, flags.upgrade, flags.toply) {
+            error!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if flags.lint {
+        match lint(&flags.scene_files) {
+            Ok(false) => return,
+            Ok(true) => process::exit(1),
+            Err(err) => {
+                error!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
     info!("Options: {:#?}", &flags);
     let opts = pbrt::core::pbrt::Options {
         num_threads: flags.num_threads.unwrap_or(1),
@@ -73,14 +316,18 @@ fn main() {
     let ref mut pbrt = api::Pbrt::new(&opts);
     pbrt.init();
     for f in &flags.scene_files {
-        match pbrt.parse_file(&f) {
+        let result = match read_converted(f) {
+            Some(scene) => scene.map(|scene| pbrt.execute_scene(scene)),
+            None => pbrt.parse_file(&f).map_err(|e| e.to_string()),
+        };
+        match result {
             Ok(_) => {
                 if opts.verbose {
                     println!("Rendered {}\n{:#?}", f, pbrt);
                 }
             }
             Err(err) => {
-                error!("Failed to parse {}: {:?}", f, err);
+                error!("Failed to parse {}: {}", f, err);
                 process::exit(1);
             }
         }
@@ -0,0 +1,698 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! The editor features, in terms of pbrt's own Locations: 1-based lines, and 1-based columns
+//! counted in bytes.  main.rs converts them to and from the protocol's positions.
+use pbrt::core::lexer::{Lexer, Token, TokenKind};
+use pbrt::core::lint::{self, Severity};
+use pbrt::core::parser::{Directives, Error, Location, Scene};
+
+/// Directive keywords, their syntax and what they do, for completion and hover.
+const DIRECTIVES: &[(&str, &str, &str)] = &[
+    (
+        "LookAt",
+        "LookAt ex ey ez  lx ly lz  ux uy uz",
+        "Concatenates a viewing transform looking from the eye point at the look point.",
+    ),
+    ("Camera", "Camera \"name\" params", "Sets the camera."),
+    ("Sampler", "Sampler \"name\" params", "Sets the sampler."),
+    (
+        "Integrator",
+        "Integrator \"name\" params",
+        "Sets the integrator.",
+    ),
+    (
+        "Film",
+        "Film \"name\" params",
+        "Sets the film the image is rendered to.",
+    ),
+    (
+        "WorldBegin",
+        "WorldBegin",
+        "Ends the options block and starts describing the scene.",
+    ),
+    (
+        "WorldEnd",
+        "WorldEnd",
+        "Ends the scene description, and renders it.",
+    ),
+    (
+        "AttributeBegin",
+        "AttributeBegin",
+        "Saves the graphics state and transform.",
+    ),
+    (
+        "AttributeEnd",
+        "AttributeEnd",
+        "Restores the graphics state and transform.",
+    ),
+    (
+        "LightSource",
+        "LightSource \"name\" params",
+        "Adds a light.",
+    ),
+    (
+        "AreaLightSource",
+        "AreaLightSource \"name\" params",
+        "Makes the shapes that follow, in the current attribute block, emit light.",
+    ),
+    (
+        "Material",
+        "Material \"name\" params",
+        "Sets the current material.",
+    ),
+    (
+        "MakeNamedMaterial",
+        "MakeNamedMaterial \"name\" \"string type\" \"type\" params",
+        "Defines a material that NamedMaterial can refer to.",
+    ),
+    (
+        "NamedMaterial",
+        "NamedMaterial \"name\"",
+        "Sets the current material to one defined with MakeNamedMaterial.",
+    ),
+    (
+        "MakeNamedMedium",
+        "MakeNamedMedium \"name\" \"string type\" \"type\" params",
+        "Defines a participating medium that MediumInterface can refer to.",
+    ),
+    (
+        "MediumInterface",
+        "MediumInterface \"inside\" \"outside\"",
+        "Sets the media inside and outside the shapes that follow.",
+    ),
+    ("Shape", "Shape \"name\" params", "Adds a shape."),
+    (
+        "Translate",
+        "Translate x y z",
+        "Concatenates a translation.",
+    ),
+    ("Scale", "Scale x y z", "Concatenates a scale."),
+    (
+        "Rotate",
+        "Rotate angle x y z",
+        "Concatenates a rotation by angle degrees about x y z.",
+    ),
+    (
+        "Texture",
+        "Texture \"name\" \"float|spectrum\" \"class\" params",
+        "Defines a texture that parameters of type texture can refer to.",
+    ),
+    ("Identity", "Identity", "Resets the current transform."),
+    (
+        "Transform",
+        "Transform [ m00 ... m33 ]",
+        "Replaces the current transform.",
+    ),
+    (
+        "ConcatTransform",
+        "ConcatTransform [ m00 ... m33 ]",
+        "Concatenates a transform.",
+    ),
+    (
+        "CoordinateSystem",
+        "CoordinateSystem \"name\"",
+        "Names the current transform.",
+    ),
+    (
+        "CoordSysTransform",
+        "CoordSysTransform \"name\"",
+        "Replaces the current transform with one named by CoordinateSystem.",
+    ),
+    (
+        "TransformBegin",
+        "TransformBegin",
+        "Saves the current transform.",
+    ),
+    (
+        "TransformEnd",
+        "TransformEnd",
+        "Restores the current transform.",
+    ),
+    (
+        "ActiveTransform",
+        "ActiveTransform All|StartTime|EndTime",
+        "Chooses which of the start and end transforms the transform directives change.",
+    ),
+    (
+        "TransformTimes",
+        "TransformTimes start end",
+        "Sets the times of the start and end transforms.",
+    ),
+    (
+        "Include",
+        "Include \"filename\"",
+        "Reads directives from another file.",
+    ),
+    (
+        "Import",
+        "Import \"filename\"",
+        "Reads directives from another file, in their own attribute block.",
+    ),
+];
+
+/// Returns the number of quoted arguments directive kw takes before its parameters, and whether
+/// it takes parameters.
+fn arguments(kw: &str) -> (usize, bool) {
+    match kw {
+        "Camera" | "Sampler" | "Integrator" | "Film" | "LightSource" | "AreaLightSource"
+        | "Material" | "MakeNamedMaterial" | "MakeNamedMedium" | "Shape" => (1, true),
+        "Texture" => (3, true),
+        "MediumInterface" => (2, false),
+        "NamedMaterial" | "CoordinateSystem" | "CoordSysTransform" | "Include" | "Import" => {
+            (1, false)
+        }
+        _ => (0, false),
+    }
+}
+
+fn is_directive(kw: &str) -> bool {
+    DIRECTIVES.iter().any(|&(name, _, _)| name == kw)
+}
+
+/// What a token is, in the directive it's part of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Directive,
+    /// The i'th quoted argument, e.g. "sphere" in Shape "sphere".
+    Argument(usize),
+    /// A parameter declaration like "float radius".
+    Declaration,
+    /// One of the values of the parameter declared by tokens[i].
+    Value(usize),
+    Other,
+}
+
+/// A problem in a document, from parsing it or linting it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub start: Location,
+    pub end: Location,
+    pub message: String,
+}
+
+/// A completion for the text being typed between start and end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub detail: String,
+    pub start: Location,
+    pub end: Location,
+}
+
+/// The tokens of a document, annotated with their roles.
+pub struct Document {
+    tokens: Vec<Token>,
+    roles: Vec<Role>,
+    /// directives[i] is the index of the keyword of the directive tokens[i] is part of.
+    directives: Vec<Option<usize>>,
+}
+
+/// Where the role annotation is up to in the current directive.
+#[derive(Debug, Default, Clone)]
+struct State {
+    directive: Option<usize>,
+    /// Quoted arguments read, and still to read.
+    arguments: usize,
+    remaining: usize,
+    has_params: bool,
+    /// The declaration of the parameter whose values are being read.
+    declaration: Option<usize>,
+    in_list: bool,
+}
+
+impl State {
+    fn expects_declaration(&self) -> bool {
+        self.directive.is_some()
+            && self.has_params
+            && self.remaining == 0
+            && self.declaration.is_none()
+    }
+
+    /// Returns the role of t, which is tokens[i], and moves past it.
+    fn annotate(&mut self, i: usize, t: &Token) -> Role {
+        match &t.kind {
+            TokenKind::Keyword(k) if !self.in_list && is_directive(k) => {
+                let (remaining, has_params) = arguments(k);
+                *self = State {
+                    directive: Some(i),
+                    remaining,
+                    has_params,
+                    ..State::default()
+                };
+                Role::Directive
+            }
+            TokenKind::String(_) if self.remaining > 0 => {
+                self.remaining -= 1;
+                self.arguments += 1;
+                Role::Argument(self.arguments - 1)
+            }
+            TokenKind::String(_) if self.expects_declaration() => {
+                self.declaration = Some(i);
+                Role::Declaration
+            }
+            TokenKind::OpenBracket if self.declaration.is_some() && !self.in_list => {
+                self.in_list = true;
+                Role::Other
+            }
+            TokenKind::CloseBracket if self.in_list => {
+                self.in_list = false;
+                self.declaration = None;
+                Role::Other
+            }
+            _ => match self.declaration {
+                Some(d) => {
+                    if !self.in_list {
+                        self.declaration = None;
+                    }
+                    Role::Value(d)
+                }
+                None => Role::Other,
+            },
+        }
+    }
+}
+
+impl Document {
+    /// Splits text into tokens.  If text can't be split completely, the tokens before the
+    /// problem are kept.
+    pub fn new(text: &str) -> Document {
+        let tokens: Vec<Token> = Lexer::new(text.as_bytes())
+            .take_while(|t| t.is_ok())
+            .map(|t| t.unwrap())
+            .collect();
+        let mut roles = Vec::with_capacity(tokens.len());
+        let mut directives = Vec::with_capacity(tokens.len());
+        let mut s = State::default();
+        for (i, t) in tokens.iter().enumerate() {
+            roles.push(s.annotate(i, t));
+            directives.push(s.directive);
+        }
+        Document {
+            tokens,
+            roles,
+            directives,
+        }
+    }
+
+    /// Returns the state after the tokens that start before location, for completion.  Only
+    /// the directive they end in is annotated again.
+    fn state_at(&self, location: Location) -> State {
+        let n = self.tokens.partition_point(|t| t.start < location);
+        let mut s = State::default();
+        if let Some(d) = n.checked_sub(1).and_then(|i| self.directives[i]) {
+            for i in d..n {
+                s.annotate(i, &self.tokens[i]);
+            }
+        }
+        s
+    }
+
+    fn string(&self, i: usize) -> Option<&str> {
+        match &self.tokens[i].kind {
+            TokenKind::String(s) | TokenKind::Keyword(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the keyword of the directive tokens[i] is part of, and the name of its
+    /// implementation: the class of a Texture, or the type of a MakeNamedMaterial or
+    /// MakeNamedMedium.
+    fn implementation(&self, i: usize) -> Option<(&str, &str)> {
+        let d = self.directives[i]?;
+        let kw = self.string(d)?;
+        let name = match kw {
+            "Texture" => self.argument(d, 2)?,
+            "MakeNamedMaterial" | "MakeNamedMedium" => self.param_value(d, "string type")?,
+            _ => self.argument(d, 0)?,
+        };
+        Some((kw, name))
+    }
+
+    /// Returns the n'th quoted argument of the directive starting at tokens[d].
+    fn argument(&self, d: usize, n: usize) -> Option<&str> {
+        let i = (d + 1..self.tokens.len())
+            .take_while(|&i| self.directives[i] == Some(d))
+            .find(|&i| self.roles[i] == Role::Argument(n))?;
+        self.string(i)
+    }
+
+    /// Returns the first value of the parameter declared as decl in the directive at tokens[d].
+    fn param_value(&self, d: usize, decl: &str) -> Option<&str> {
+        let i = (d + 1..self.tokens.len())
+            .take_while(|&i| self.directives[i] == Some(d))
+            .find(|&i| {
+                self.roles[i] == Role::Declaration && same_declaration(self.string(i), decl)
+            })?;
+        let v = (i + 1..self.tokens.len()).find(|&v| self.roles[v] == Role::Value(i))?;
+        self.string(v)
+    }
+
+    /// Returns the index of the token at location, including a location just past its end.
+    fn token_at(&self, location: Location) -> Option<usize> {
+        let i = self.tokens.iter().position(|t| t.end >= location)?;
+        if self.tokens[i].start <= location {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the hover text for the token at location, in markdown.
+    pub fn hover(&self, location: Location) -> Option<String> {
+        let i = self.token_at(location)?;
+        match self.roles[i] {
+            Role::Directive => {
+                let kw = self.string(i)?;
+                let &(_, syntax, doc) = DIRECTIVES.iter().find(|&&(name, _, _)| name == kw)?;
+                Some(format!("```\n{}\n```\n{}", syntax, doc))
+            }
+            Role::Argument(0) => {
+                let (kw, name) = self.implementation(i)?;
+                if name != self.string(i)? {
+                    return None;
+                }
+                let params = lint::parameters(kw, name)?;
+                let mut s = format!("{} \"{}\" parameters:\n", kw, name);
+                for (param, types) in params {
+                    s.push_str(&format!("\n* `{} {}`", types[0], param));
+                }
+                Some(s)
+            }
+            Role::Declaration => {
+                let (kw, name) = self.implementation(i)?;
+                let decl = self.string(i)?;
+                let param = decl.split_whitespace().nth(1)?;
+                let params = lint::parameters(kw, name)?;
+                Some(match params.iter().find(|&&(p, _)| p == param) {
+                    Some(&(_, types)) => format!(
+                        "`{}`: parameter of {} \"{}\", of type {}",
+                        decl,
+                        kw,
+                        name,
+                        types.join(", ")
+                    ),
+                    None => format!("`{}`: not a parameter of {} \"{}\"", decl, kw, name),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the location of the name defined by the NamedMaterial, texture, or mix material
+    /// reference at location.
+    pub fn definition(&self, location: Location) -> Option<(Location, Location)> {
+        let i = self.token_at(location)?;
+        let name = match self.tokens[i].kind {
+            TokenKind::String(ref s) => s.as_str(),
+            _ => return None,
+        };
+        let d = self.directives[i]?;
+        let defined_by = match (self.string(d)?, self.roles[i]) {
+            ("NamedMaterial", Role::Argument(0)) => "MakeNamedMaterial",
+            (_, Role::Value(decl)) => {
+                let decl = self.string(decl)?;
+                if decl.split_whitespace().next() == Some("texture") {
+                    "Texture"
+                } else if same_declaration(Some(decl), "string namedmaterial1")
+                    || same_declaration(Some(decl), "string namedmaterial2")
+                {
+                    "MakeNamedMaterial"
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        // The last definition before the reference is the one in effect, but it's more useful to
+        // go to a later one than nowhere.
+        let definitions: Vec<usize> = self
+            .roles
+            .iter()
+            .enumerate()
+            .filter(|&(j, &r)| {
+                r == Role::Argument(0)
+                    && self.string(j) == Some(name)
+                    && self.directives[j].and_then(|d| self.string(d)) == Some(defined_by)
+            })
+            .map(|(j, _)| j)
+            .collect();
+        let j = definitions
+            .iter()
+            .rev()
+            .find(|&&j| j < i)
+            .or_else(|| definitions.first())?;
+        Some((self.tokens[*j].start, self.tokens[*j].end))
+    }
+}
+
+/// Returns whether decl, e.g. `"float  radius"`, declares the same parameter as want.
+fn same_declaration(decl: Option<&str>, want: &str) -> bool {
+    match decl {
+        Some(decl) => decl.split_whitespace().eq(want.split_whitespace()),
+        None => false,
+    }
+}
+
+/// Returns the syntax error or lint diagnostics for text, whose Document is doc.  Lint is only
+/// run on documents that parse, as it would complain about all the blocks left open by a partial
+/// one.
+pub fn diagnostics(text: &str, doc: &Document) -> Vec<Diagnostic> {
+    let mut scene = Scene::default();
+    for d in Directives::new(text.as_bytes(), None) {
+        match d {
+            Ok((d, span)) => scene.push(d, span),
+            Err(Error::Syntax(e)) => {
+                // Underline the token the error is at, if there is one.
+                let end = doc
+                    .token_at(e.location)
+                    .map(|i| doc.tokens[i].end)
+                    .filter(|&end| end > e.location)
+                    .unwrap_or(Location {
+                        line: e.location.line,
+                        column: e.location.column + 1,
+                    });
+                return vec![Diagnostic {
+                    severity: Severity::Error,
+                    start: e.location,
+                    end,
+                    message: format!("expected {}", e.expected),
+                }];
+            }
+            Err(e) => {
+                return vec![Diagnostic {
+                    severity: Severity::Error,
+                    start: Location { line: 1, column: 1 },
+                    end: Location { line: 1, column: 1 },
+                    message: e.to_string(),
+                }]
+            }
+        }
+    }
+    lint::lint(&scene)
+        .into_iter()
+        .map(|d| Diagnostic {
+            severity: d.severity,
+            start: d.span.start,
+            end: d.span.end,
+            message: d.message,
+        })
+        .collect()
+}
+
+/// Returns the completions for the directive name, or quoted `"type name"` parameter
+/// declaration, being typed at location in doc.  line is the text of location's line.
+pub fn completions(doc: &Document, line: &str, location: Location) -> Vec<Completion> {
+    let before = &line[..location.column - 1];
+    // An odd number of quotes means location is in a string, possibly not closed yet.
+    let quotes: Vec<usize> = before
+        .char_indices()
+        .filter(|&(i, c)| c == '"' && !before[..i].ends_with('\\'))
+        .map(|(i, _)| i)
+        .collect();
+    let (start, quoted) = if quotes.len() % 2 == 1 {
+        (quotes[quotes.len() - 1], true)
+    } else {
+        let word = before
+            .trim_end_matches(|c: char| c.is_ascii_alphanumeric())
+            .len();
+        (word, false)
+    };
+    if before[..start].contains('#') {
+        return Vec::new();
+    }
+    let prefix = &before[start..];
+    let start = Location {
+        line: location.line,
+        column: start + 1,
+    };
+    let s = &doc.state_at(start);
+    let mut completions = Vec::new();
+    if !quoted && !s.in_list {
+        for &(name, syntax, _) in DIRECTIVES {
+            if name.starts_with(prefix) {
+                completions.push(Completion {
+                    label: name.to_owned(),
+                    detail: syntax.to_owned(),
+                    start,
+                    end: location,
+                });
+            }
+        }
+    }
+    if s.expects_declaration() && (quoted || prefix.is_empty()) {
+        let d = s.directive.unwrap();
+        let params = doc
+            .implementation(d)
+            .and_then(|(kw, name)| lint::parameters(kw, name).map(|p| (kw, name, p)));
+        if let Some((kw, name, params)) = params {
+            let typed = prefix.trim_start_matches('"');
+            for (param, types) in params {
+                let decl = format!("{} {}", types[0], param);
+                if decl.starts_with(typed) {
+                    completions.push(Completion {
+                        label: format!("\"{}\"", decl),
+                        detail: format!("{} \"{}\"", kw, name),
+                        start,
+                        end: location,
+                    });
+                }
+            }
+        }
+    }
+    completions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Location {
+        Location { line, column }
+    }
+
+    const SCENE: &str = r#"WorldBegin
+Texture "checks" "spectrum" "checkerboard" "rgb tex1" [1 0 0]
+MakeNamedMaterial "red" "string type" "matte" "texture Kd" "checks"
+NamedMaterial "red"
+Shape "sphere" "float radius" 1
+WorldEnd
+"#;
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = |text| diagnostics(text, &Document::new(text));
+        assert_eq!(diagnostics(SCENE), vec![]);
+        assert_eq!(
+            diagnostics("WorldBegin\nShape \"sphere\" \"integer radius\" 1\nWorldEnd\n"),
+            vec![Diagnostic {
+                severity: Severity::Error,
+                start: at(2, 1),
+                end: at(2, 34),
+                message: "parameter \"radius\" has type integer, expected float".to_owned(),
+            }]
+        );
+        assert_eq!(
+            diagnostics("WorldBegin\nTranslate 1 x 3\n"),
+            vec![Diagnostic {
+                severity: Severity::Error,
+                start: at(2, 13),
+                end: at(2, 14),
+                message: "expected number after Translate".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hover() {
+        let doc = Document::new(SCENE);
+        assert_eq!(
+            doc.hover(at(4, 3)),
+            Some(
+                "```\nNamedMaterial \"name\"\n```\nSets the current material to one defined with \
+                 MakeNamedMaterial."
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            doc.hover(at(5, 18)),
+            Some("`float radius`: parameter of Shape \"sphere\", of type float".to_owned())
+        );
+        assert_eq!(
+            doc.hover(at(3, 50)),
+            Some(
+                "`texture Kd`: parameter of MakeNamedMaterial \"matte\", of type spectrum, rgb, \
+                 xyz, blackbody, texture"
+                    .to_owned()
+            )
+        );
+        assert!(doc.hover(at(5, 8)).unwrap().contains("* `float radius`"));
+        assert_eq!(doc.hover(at(5, 31)), None);
+    }
+
+    #[test]
+    fn test_definition() {
+        let doc = Document::new(SCENE);
+        // NamedMaterial "red".
+        assert_eq!(doc.definition(at(4, 17)), Some((at(3, 19), at(3, 24))));
+        // "texture Kd" "checks".
+        assert_eq!(doc.definition(at(3, 62)), Some((at(2, 9), at(2, 17))));
+        assert_eq!(doc.definition(at(5, 9)), None);
+    }
+
+    fn labels(text: &str) -> Vec<String> {
+        let line = text.lines().last().unwrap();
+        let location = at(text.lines().count(), line.len() + 1);
+        completions(&Document::new(text), line, location)
+            .into_iter()
+            .map(|c| c.label)
+            .collect()
+    }
+
+    #[test]
+    fn test_completions() {
+        assert_eq!(
+            labels("WorldBegin\nAttr"),
+            vec!["AttributeBegin", "AttributeEnd"]
+        );
+        assert_eq!(labels("Shape \"disk\" \"float r"), vec!["\"float radius\""]);
+        assert_eq!(
+            labels("Camera \"perspective\" \"float fov\" 45 \"float f"),
+            vec![
+                "\"float focaldistance\"",
+                "\"float frameaspectratio\"",
+                "\"float fov\""
+            ]
+        );
+        // Inside a list only numbers make sense.
+        assert_eq!(
+            labels("Shape \"disk\" \"float radius\" [ 1 "),
+            Vec::<String>::new()
+        );
+        // The implementation name isn't a parameter.
+        assert_eq!(labels("Shape \"sph"), Vec::<String>::new());
+        // Only the text before the completion matters.
+        let doc = Document::new("Shape \"disk\" \"float r\" [ 1 ]\nWorldEnd\n");
+        let line = "Shape \"disk\" \"float r\" [ 1 ]";
+        assert_eq!(
+            completions(&doc, line, at(1, 22)),
+            vec![Completion {
+                label: "\"float radius\"".to_owned(),
+                detail: "Shape \"disk\"".to_owned(),
+                start: at(1, 14),
+                end: at(1, 22),
+            }]
+        );
+    }
+}
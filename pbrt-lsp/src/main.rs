@@ -0,0 +1,280 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A Language Server Protocol server for pbrt scene files, speaking over stdin and stdout.  It
+//! publishes syntax errors and lint diagnostics as documents change, and answers hover,
+//! completion and go-to-definition requests.
+use std::collections::HashMap;
+use std::error::Error;
+
+extern crate lsp_server;
+extern crate lsp_types;
+extern crate pbrt;
+extern crate serde_json;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use pbrt::core::lint::Severity;
+use pbrt::core::parser::Location;
+
+mod analysis;
+
+/// The text of an open document, where its lines start, and its tokens, which are split once
+/// per change and shared by all the requests about it.
+struct Text {
+    text: String,
+    line_starts: Vec<usize>,
+    doc: analysis::Document,
+}
+
+impl Text {
+    fn new(text: String) -> Text {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let doc = analysis::Document::new(&text);
+        Text {
+            text,
+            line_starts,
+            doc,
+        }
+    }
+
+    /// Returns line n, counting from 0, without its line ending.
+    fn line(&self, n: usize) -> &str {
+        let start = match self.line_starts.get(n) {
+            Some(&start) => start,
+            None => return "",
+        };
+        let end = self
+            .line_starts
+            .get(n + 1)
+            .map_or(self.text.len(), |&next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    /// Converts a protocol position, whose character counts UTF-16 code units, to a Location.
+    fn location(&self, p: Position) -> Location {
+        let line = self.line(p.line as usize);
+        let mut units = 0;
+        let column = line
+            .char_indices()
+            .find(|&(_, c)| {
+                units += c.len_utf16();
+                units > p.character as usize
+            })
+            .map_or(line.len(), |(i, _)| i);
+        Location {
+            line: p.line as usize + 1,
+            column: column + 1,
+        }
+    }
+
+    fn position(&self, l: Location) -> Position {
+        let line = self.line(l.line - 1);
+        let mut column = (l.column - 1).min(line.len());
+        while !line.is_char_boundary(column) {
+            column -= 1;
+        }
+        Position::new(
+            l.line as u32 - 1,
+            line[..column].encode_utf16().count() as u32,
+        )
+    }
+
+    fn range(&self, start: Location, end: Location) -> Range {
+        Range::new(self.position(start), self.position(end))
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Text>,
+}
+
+impl Server {
+    fn run(&mut self, connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for msg in &connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    let response = self.request(req);
+                    connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(n) => {
+                    if let Some(diagnostics) = self.notification(n) {
+                        let n = Notification::new(
+                            "textDocument/publishDiagnostics".to_owned(),
+                            diagnostics,
+                        );
+                        connection.sender.send(Message::Notification(n))?;
+                    }
+                }
+                Message::Response(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&self, req: Request) -> Response {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            "textDocument/hover" => req
+                .extract("textDocument/hover")
+                .map(|(_, p)| serde_json::to_value(self.hover(p))),
+            "textDocument/completion" => req
+                .extract("textDocument/completion")
+                .map(|(_, p)| serde_json::to_value(self.completion(p))),
+            "textDocument/definition" => req
+                .extract("textDocument/definition")
+                .map(|(_, p)| serde_json::to_value(self.definition(p))),
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unhandled request {}", req.method),
+                )
+            }
+        };
+        match result {
+            Ok(Ok(value)) => Response::new_ok(id, value),
+            Ok(Err(e)) => Response::new_err(id, ErrorCode::InternalError as i32, e.to_string()),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    /// Updates the open documents, returning the diagnostics to publish for the one changed.
+    fn notification(&mut self, n: Notification) -> Option<PublishDiagnosticsParams> {
+        let uri = match n.method.as_str() {
+            "textDocument/didOpen" => {
+                let p: DidOpenTextDocumentParams = n.extract("textDocument/didOpen").ok()?;
+                self.documents
+                    .insert(p.text_document.uri.clone(), Text::new(p.text_document.text));
+                p.text_document.uri
+            }
+            "textDocument/didChange" => {
+                let p: DidChangeTextDocumentParams = n.extract("textDocument/didChange").ok()?;
+                // Only full document syncs are asked for, so the last change has all the text.
+                let text = p.content_changes.into_iter().last()?.text;
+                self.documents
+                    .insert(p.text_document.uri.clone(), Text::new(text));
+                p.text_document.uri
+            }
+            "textDocument/didClose" => {
+                let p: DidCloseTextDocumentParams = n.extract("textDocument/didClose").ok()?;
+                self.documents.remove(&p.text_document.uri);
+                return Some(PublishDiagnosticsParams::new(
+                    p.text_document.uri,
+                    vec![],
+                    None,
+                ));
+            }
+            _ => return None,
+        };
+        let text = &self.documents[&uri];
+        let diagnostics = analysis::diagnostics(&text.text, &text.doc)
+            .into_iter()
+            .map(|d| lsp_types::Diagnostic {
+                range: text.range(d.start, d.end),
+                severity: Some(match d.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                source: Some("pbrt".to_owned()),
+                message: d.message,
+                ..Default::default()
+            })
+            .collect();
+        Some(PublishDiagnosticsParams::new(uri, diagnostics, None))
+    }
+
+    fn hover(&self, p: HoverParams) -> Option<Hover> {
+        let p = p.text_document_position_params;
+        let text = self.documents.get(&p.text_document.uri)?;
+        let value = text.doc.hover(text.location(p.position))?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    fn completion(&self, p: CompletionParams) -> Option<CompletionResponse> {
+        let p = p.text_document_position;
+        let text = self.documents.get(&p.text_document.uri)?;
+        let location = text.location(p.position);
+        let line = text.line(p.position.line as usize);
+        let items = analysis::completions(&text.doc, line, location)
+            .into_iter()
+            .map(|c| CompletionItem {
+                kind: Some(if c.label.starts_with('"') {
+                    CompletionItemKind::PROPERTY
+                } else {
+                    CompletionItemKind::KEYWORD
+                }),
+                detail: Some(c.detail),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    text.range(c.start, c.end),
+                    c.label.clone(),
+                ))),
+                label: c.label,
+                ..Default::default()
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn definition(&self, p: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let p = p.text_document_position_params;
+        let text = self.documents.get(&p.text_document.uri)?;
+        let (start, end) = text.doc.definition(text.location(p.position))?;
+        Some(GotoDefinitionResponse::Scalar(lsp_types::Location::new(
+            p.text_document.uri,
+            text.range(start, end),
+        )))
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["\"".to_owned()]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    Server::default().run(&connection)?;
+    // The writer thread finishes once the connection's sender is dropped.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
@@ -12,21 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use std::collections;
-use std::fs::File;
+use std::fmt;
 use std::io;
-use std::io::Read;
 use std::ops::{Index, IndexMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 extern crate nom;
 
-use core::geometry::Vector3f;
+use core::geometry::{Point3f, Vector3f};
 use core::light::Light;
 use core::medium::Medium;
 use core::paramset::ParamSet;
 use core::parser;
-use core::parser::Directive;
+use core::parser::{ActiveTransform, Directive, Scene, Span};
 use core::pbrt::{Float, Options};
+use core::ply;
 use core::transform::{Matrix4x4, Transform};
 
 #[derive(Debug)]
@@ -35,6 +35,15 @@ pub enum Error {
     Parser(parser::Error),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parser(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::Io(err)
@@ -173,7 +182,11 @@ struct GraphicsState {
 macro_rules! verify_initialized {
     ($pbrt:expr, $func:expr) => {
         if $pbrt.current_api_state == APIState::Uninitialized {
-            error!("init() must be before calling \"{}()\".  Ignoring.", $func);
+            error!(
+                "{}init() must be before calling \"{}()\".  Ignoring.",
+                $pbrt.location(),
+                $func
+            );
             debug_assert!(false);
             return;
         }
@@ -186,7 +199,8 @@ macro_rules! verify_options {
         verify_initialized!($pbrt, $func);
         if $pbrt.current_api_state == APIState::WorldBlock {
             error!(
-                "Options cannot be set inside world block; \"{}\" not allowed.  Ignoring.",
+                "{}Options cannot be set inside world block; \"{}\" not allowed.  Ignoring.",
+                $pbrt.location(),
                 $func
             );
             debug_assert!(false);
@@ -201,7 +215,8 @@ macro_rules! verify_world {
         verify_initialized!($pbrt, $func);
         if $pbrt.current_api_state == APIState::OptionsBlock {
             error!(
-                "Scene description must be inside world block; \"{}\" not allowed.  Ignoring.",
+                "{}Scene description must be inside world block; \"{}\" not allowed.  Ignoring.",
+                $pbrt.location(),
                 $func
             );
             debug_assert!(false);
@@ -210,6 +225,17 @@ macro_rules! verify_world {
     };
 }
 
+/// Returns the matrix whose elements, in column-major order as scene files write them, are t.
+fn column_major(t: [Float; 16]) -> Matrix4x4 {
+    Matrix4x4::new(
+        [t[0], t[1], t[2], t[3]],
+        [t[4], t[5], t[6], t[7]],
+        [t[8], t[9], t[10], t[11]],
+        [t[12], t[13], t[14], t[15]],
+    )
+    .transpose()
+}
+
 // Pbrt is the top-level global container for all rendering functionality.
 #[derive(Debug)]
 pub struct Pbrt<'a> {
@@ -223,6 +249,8 @@ pub struct Pbrt<'a> {
     pushed_graphics_states: Vec<GraphicsState>,
     pushed_transforms: Vec<TransformSet>,
     pushed_active_transform_bits: Vec<usize>,
+    // Where the directive currently being executed came from, if it was parsed from a file.
+    current_span: Option<Span>,
     // TODO(wathiede):
     // static TransformCache transformCache;
 }
@@ -240,54 +268,95 @@ impl<'a> Pbrt<'a> {
             pushed_graphics_states: Vec::new(),
             pushed_transforms: Vec::new(),
             pushed_active_transform_bits: Vec::new(),
+            current_span: None,
         }
     }
 
     // TODO(wathiede): replace Ok() with something that prints stats about the scene render.
+    /// Parses the scene file at path, executing each directive as soon as it's parsed.
     pub fn parse_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
-        let mut f = File::open(path)?;
-        let mut buffer = Vec::new();
-
-        // read the whole file
-        f.read_to_end(&mut buffer)?;
-        let scene = parser::parse_scene(&buffer[..])?;
-        info!("Scene {:#?}", &scene);
-        for d in scene.directives {
-            println!("d: {:?}", &d);
+        for d in parser::FileDirectives::open(path)? {
+            let (d, span) = d?;
+            self.current_span = Some(span);
+            self.execute(d);
+        }
+        self.current_span = None;
+        Ok(())
+    }
+
+    /// Executes the directives of scene, e.g. one made with a SceneBuilder.
+    pub fn execute_scene(&mut self, scene: Scene) {
+        for (d, span) in scene.directives.into_iter().zip(scene.spans) {
+            self.current_span = Some(span);
             match d {
-                Directive::LookAt(
-                    eye_x,
-                    eye_y,
-                    eye_z,
-                    look_x,
-                    look_y,
-                    look_z,
-                    up_x,
-                    up_y,
-                    up_z,
-                ) => self.look_at(
-                    [eye_x, eye_y, eye_z],    // eye xyz
-                    [look_x, look_y, look_z], // look xyz
-                    [up_x, up_y, up_z],       // up xyz
+                Directive::Include(path) | Directive::Import(path) => error!(
+                    "{}can't include \"{}\" in a scene that wasn't read from a file",
+                    self.location(),
+                    path
                 ),
-                Directive::Camera(name, ps) => self.camera(name, ps),
-                Directive::Sampler(name, ps) => self.sampler(name, ps),
-                Directive::Integrator(name, ps) => self.integrator(name, ps),
-                Directive::Film(name, ps) => self.film(name, ps),
-                Directive::WorldBegin => self.world_begin(),
-                Directive::WorldEnd => self.world_end(),
-                Directive::AttributeBegin => self.attribute_begin(),
-                Directive::AttributeEnd => self.attribute_end(),
-                Directive::LightSource(_name, _ps) => (),
-                Directive::Material(_name, _ps) => (),
-                Directive::Shape(_name, _ps) => (),
-                Directive::Scale(x, y, z) => self.scale(x, y, z),
-                Directive::Rotate(angle, x, y, z) => self.rotate(angle, x, y, z),
-                Directive::Translate(x, y, z) => self.translate(x, y, z),
-                Directive::Texture(_name, _kind, _class, _ps) => (),
+                d => self.execute(d),
             }
         }
-        Ok(())
+        self.current_span = None;
+    }
+
+    /// Calls the method implementing d.
+    fn execute(&mut self, d: Directive) {
+        match d {
+            Directive::LookAt(
+                eye_x,
+                eye_y,
+                eye_z,
+                look_x,
+                look_y,
+                look_z,
+                up_x,
+                up_y,
+                up_z,
+            ) => self.look_at(
+                [eye_x, eye_y, eye_z],    // eye xyz
+                [look_x, look_y, look_z], // look xyz
+                [up_x, up_y, up_z],       // up xyz
+            ),
+            Directive::Camera(name, ps) => self.camera(name, ps),
+            Directive::Sampler(name, ps) => self.sampler(name, ps),
+            Directive::Integrator(name, ps) => self.integrator(name, ps),
+            Directive::Film(name, ps) => self.film(name, ps),
+            Directive::WorldBegin => self.world_begin(),
+            Directive::WorldEnd => self.world_end(),
+            Directive::AttributeBegin => self.attribute_begin(),
+            Directive::AttributeEnd => self.attribute_end(),
+            Directive::LightSource(_name, _ps) => (),
+            Directive::AreaLightSource(_name, _ps) => (),
+            Directive::Material(_name, _ps) => (),
+            Directive::MakeNamedMaterial(_name, _ps) => (),
+            Directive::NamedMaterial(_name) => (),
+            // make_medium isn't implemented yet.
+            Directive::MakeNamedMedium(_name, _ps) => (),
+            Directive::MediumInterface(inside, outside) => self.medium_interface(&inside, &outside),
+            Directive::Shape(name, ps) => self.shape(name, ps),
+            Directive::Scale(x, y, z) => self.scale(x, y, z),
+            Directive::Rotate(angle, x, y, z) => self.rotate(angle, x, y, z),
+            Directive::Translate(x, y, z) => self.translate(x, y, z),
+            Directive::Texture(_name, _kind, _class, _ps) => (),
+            Directive::Identity => self.identity(),
+            Directive::Transform(m) => self.transform(m),
+            Directive::ConcatTransform(m) => self.concat_transform(m),
+            Directive::CoordinateSystem(name) => self.coordinate_system(&name),
+            Directive::CoordSysTransform(name) => self.coordinate_system_transform(&name),
+            Directive::TransformBegin => self.transform_begin(),
+            Directive::TransformEnd => self.transform_end(),
+            Directive::ActiveTransform(ActiveTransform::All) => self.active_transform_all(),
+            Directive::ActiveTransform(ActiveTransform::StartTime) => {
+                self.active_transform_start_time()
+            }
+            Directive::ActiveTransform(ActiveTransform::EndTime) => {
+                self.active_transform_end_time()
+            }
+            Directive::TransformTimes(start, end) => self.transform_times(start, end),
+            // parser::FileDirectives replaces these with the directives from the named files.
+            Directive::Include(_) | Directive::Import(_) => unreachable!(),
+        }
     }
 
     pub fn init(&mut self) {
@@ -377,7 +446,10 @@ impl<'a> Pbrt<'a> {
             || self.pushed_transforms.is_empty()
             || self.pushed_active_transform_bits.is_empty()
         {
-            error!("Unmatched pbrt.attribute_end() encountered. Ignoring it.");
+            error!(
+                "{}Unmatched pbrt.attribute_end() encountered. Ignoring it.",
+                self.location()
+            );
             return;
         }
         self.graphics_state = self.pushed_graphics_states.pop().unwrap();
@@ -395,7 +467,10 @@ impl<'a> Pbrt<'a> {
     pub fn transform_end(&mut self) {
         verify_world!(self, "pbrt.transform_end");
         if self.pushed_transforms.is_empty() || self.pushed_active_transform_bits.is_empty() {
-            error!("Unmatched pbrt.tranform_end() encountered. Ignoring it.");
+            error!(
+                "{}Unmatched pbrt.tranform_end() encountered. Ignoring it.",
+                self.location()
+            );
             return;
         }
         self.current_transform = self.pushed_transforms.pop().unwrap();
@@ -425,7 +500,20 @@ impl<'a> Pbrt<'a> {
 
     pub fn look_at(&mut self, eye: [Float; 3], look: [Float; 3], up: [Float; 3]) {
         verify_initialized!(self, "pbrt.look_at");
-        info!("eye: {:?} look: {:?} up: {:?}", eye, look, up);
+        let look_at = Transform::look_at(
+            &Point3f {
+                x: eye[0],
+                y: eye[1],
+                z: eye[2],
+            },
+            &Point3f {
+                x: look[0],
+                y: look[1],
+                z: look[2],
+            },
+            &Vector3f::new(up[0], up[1], up[2]),
+        );
+        self.for_active_transforms(|ct| *ct = *ct * look_at);
     }
 
     pub fn scale(&mut self, sx: Float, sy: Float, sz: Float) {
@@ -435,28 +523,12 @@ impl<'a> Pbrt<'a> {
 
     pub fn concat_transform(&mut self, transform: [Float; 16]) {
         verify_initialized!(self, "pbrt.concat_transform");
-        self.for_active_transforms(|ct| {
-            let t = transform;
-            *ct = *ct * Transform::from(Matrix4x4::new(
-                [t[0], t[1], t[2], t[3]],
-                [t[4], t[5], t[6], t[7]],
-                [t[8], t[9], t[10], t[11]],
-                [t[12], t[13], t[14], t[15]],
-            ))
-        });
+        self.for_active_transforms(|ct| *ct = *ct * Transform::from(column_major(transform)));
     }
 
     pub fn transform(&mut self, transform: [Float; 16]) {
         verify_initialized!(self, "pbrt.transform");
-        self.for_active_transforms(|ct| {
-            let t = transform;
-            *ct = Transform::from(Matrix4x4::new(
-                [t[0], t[1], t[2], t[3]],
-                [t[4], t[5], t[6], t[7]],
-                [t[8], t[9], t[10], t[11]],
-                [t[12], t[13], t[14], t[15]],
-            ))
-        });
+        self.for_active_transforms(|ct| *ct = Transform::from(column_major(transform)));
     }
 
     pub fn coordinate_system(&mut self, name: &str) {
@@ -469,7 +541,11 @@ impl<'a> Pbrt<'a> {
         verify_initialized!(self, "pbrt.coordinate_system_transform");
         match self.named_coordinate_systems.get(name) {
             Some(t) => self.current_transform = *t,
-            None => warn!("Couldn’t find named coordinate system \"{}\"", name),
+            None => warn!(
+                "{}Couldn’t find named coordinate system \"{}\"",
+                self.location(),
+                name
+            ),
         }
     }
 
@@ -533,7 +609,13 @@ impl<'a> Pbrt<'a> {
     pub fn make_named_medium(&mut self, name: String, params: &mut ParamSet) {
         verify_initialized!(self, "pbrt.make_named_medium");
         self.warn_if_animated_transform("pbrt.make_named_medium");
-        let kind = params.find_one_string("type", "");
+        let kind = match params.find_one_string("type", "") {
+            Ok(kind) => kind.to_owned(),
+            Err(e) => {
+                error!("{}{}", self.location(), e);
+                return;
+            }
+        };
         let medium = make_medium(&kind, params, self.current_transform[0]);
         self.render_options.named_media.insert(name, medium);
     }
@@ -545,6 +627,63 @@ impl<'a> Pbrt<'a> {
         self.render_options.have_scattering_media = true;
     }
 
+    pub fn shape(&mut self, name: String, mut params: ParamSet) {
+        verify_world!(self, "pbrt.shape");
+        let (name, params) = match name.as_str() {
+            "plymesh" => match self.read_ply_mesh(&params) {
+                Some(mesh) => {
+                    // Keep the other parameters, like "alpha", which apply to the mesh as well.
+                    params.remove("filename");
+                    ("trianglemesh".to_owned(), ply::join_trianglemesh(mesh, params))
+                }
+                None => return,
+            },
+            _ => (name, params),
+        };
+        // TODO(wathiede): make the shape and its primitives once shapes are implemented.
+        debug!("{}shape \"{}\" {:?}", self.location(), name, params);
+    }
+
+    /// Reads the mesh in the file named by a plymesh shape's parameters, logging any problems.
+    fn read_ply_mesh(&self, params: &ParamSet) -> Option<ply::TriangleMesh> {
+        let filename = match params.find_one_string("filename", "") {
+            Ok("") => {
+                error!("{}plymesh requires a \"filename\" parameter", self.location());
+                return None;
+            }
+            Ok(filename) => filename,
+            Err(e) => {
+                error!("{}{}", self.location(), e);
+                return None;
+            }
+        };
+        let path = self.resolve(filename);
+        let mut warnings = Vec::new();
+        let mesh = ply::read_file(&path, &mut warnings);
+        for warning in warnings {
+            warn!("{}{}: {}", self.location(), path.display(), warning);
+        }
+        match mesh {
+            Ok(mesh) => Some(mesh),
+            Err(e) => {
+                error!("{}{}: {}", self.location(), path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Returns the path of the file named by filename, which is relative to the directory of the
+    /// scene file being executed.
+    fn resolve(&self, filename: &str) -> PathBuf {
+        match self.current_span.as_ref().and_then(|span| span.file.as_ref()) {
+            Some(file) => file
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(filename),
+            None => PathBuf::from(filename),
+        }
+    }
+
     fn for_active_transforms<F>(&mut self, mut f: F)
     where
         F: FnMut(&mut Transform),
@@ -556,10 +695,22 @@ impl<'a> Pbrt<'a> {
         }
     }
 
+    /// Returns "file:line:column: " for the directive being executed, to prefix error messages
+    /// with, or "" if the API is being called directly.
+    fn location(&self) -> String {
+        match self.current_span {
+            Some(ref span) => format!("{}: ", span),
+            None => String::new(),
+        }
+    }
+
     fn warn_if_animated_transform(&self, name: &str) {
         if self.current_transform.is_animated() {
             warn!(
-                "Animated transformations set; ignoring for \"{}\" and using the start transform only",                name);
+                "{}Animated transformations set; ignoring for \"{}\" and using the start transform only",
+                self.location(),
+                name
+            );
         }
     }
 }
@@ -602,6 +753,54 @@ mod tests {
         assert_eq!(pbrt.current_transform.t[0].matrix().m[0][0], 2.);
     }
 
+    #[test]
+    fn test_look_at() {
+        let opts = Options {
+            num_threads: 1,
+            quick_render: false,
+            quiet: false,
+            verbose: true,
+            image_file: "".to_owned(),
+        };
+        let mut pbrt = Pbrt::new(&opts);
+        pbrt.init();
+        pbrt.scale(2., 2., 2.);
+        pbrt.look_at([1., 2., 3.], [1., 2., 10.], [0., 1., 0.]);
+        let want =
+            Transform::scale(2., 2., 2.) * Transform::translate(&Vector3f::new(-1., -2., -3.));
+        assert_eq!(pbrt.current_transform.t[0], want);
+        assert_eq!(pbrt.current_transform.t[1], want);
+    }
+
+    #[test]
+    fn test_concat_transform() {
+        let opts = Options {
+            num_threads: 1,
+            quick_render: false,
+            quiet: false,
+            verbose: true,
+            image_file: "".to_owned(),
+        };
+        let mut pbrt = Pbrt::new(&opts);
+        pbrt.init();
+        pbrt.concat_transform([
+            1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 5., 6., 7., 1.,
+        ]);
+        let p = Point3f::new(1., 2., 3.);
+        assert_eq!(
+            pbrt.current_transform.t[0].transform_point(&p),
+            Point3f::new(6., 8., 10.)
+        );
+
+        pbrt.transform([
+            2., 0., 0., 0., 0., 2., 0., 0., 0., 0., 2., 0., 1., 0., 0., 1.,
+        ]);
+        assert_eq!(
+            pbrt.current_transform.t[1].transform_point(&p),
+            Point3f::new(3., 4., 6.)
+        );
+    }
+
     #[test]
     fn test_attribute_begin_end() {
         let opts = Options {
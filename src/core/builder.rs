@@ -0,0 +1,376 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Builds Scenes in Rust, instead of formatting scene text and parsing it.
+//!
+//! SceneBuilder has methods for the directives allowed before WorldBegin, and WorldBuilder for
+//! those allowed after it, so directives can't be put in the wrong block.  Attribute and transform
+//! blocks are built by closures, so they're always closed.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate pbrt;
+//! use pbrt::core::builder::SceneBuilder;
+//!
+//! # fn main() {
+//! let scene = SceneBuilder::new()
+//!     .look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.])
+//!     .camera("perspective", params!{"float fov" => 45.})
+//!     .world(|w| {
+//!         w.light_source("infinite", params!{"rgb L" => [0.4, 0.45, 0.5]})
+//!             .attribute_block(|w| {
+//!                 w.material("matte", params!{"rgb Kd" => [0.8, 0.2, 0.2]})
+//!                     .shape("sphere", params!{"float radius" => 1.})
+//!             })
+//!     });
+//! # }
+//! ```
+use core::lexer::TokenKind;
+use core::paramset::{ParamSet, ParamSetItem};
+use core::parser::{parse_param, ActiveTransform, Directive, Scene, Span};
+use core::pbrt::Float;
+
+/// Builds a ParamSet from `"type name" => values` pairs.  values can be a number, bool or string,
+/// or an array, slice or Vec of them.  They're converted just as they would be if they followed
+/// the declaration in a scene file.
+///
+/// # Panics
+///
+/// If a declaration is malformed, or its values don't suit its type.
+#[macro_export]
+macro_rules! params {
+    ($($decl:expr => $values:expr),* $(,)*) => {{
+        #[allow(unused_mut)]
+        let mut ps = $crate::core::paramset::ParamSet::default();
+        $(
+            let item = $crate::core::builder::param($decl, $values);
+            ps.add(&item.name, item.values);
+        )*
+        ps
+    }};
+}
+
+/// The values of a parameter, as they would be written in a scene file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Values(Vec<TokenKind>);
+
+macro_rules! number_values {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Values {
+                fn from(v: $t) -> Values {
+                    Values(vec![TokenKind::Number(v as f64)])
+                }
+            }
+        )*
+    };
+}
+
+number_values!(f32, f64, i32, u32, usize);
+
+impl From<bool> for Values {
+    fn from(v: bool) -> Values {
+        Values(vec![TokenKind::Keyword(v.to_string())])
+    }
+}
+
+impl From<&str> for Values {
+    fn from(v: &str) -> Values {
+        Values(vec![TokenKind::String(v.to_owned())])
+    }
+}
+
+impl From<String> for Values {
+    fn from(v: String) -> Values {
+        Values(vec![TokenKind::String(v)])
+    }
+}
+
+impl<T: Into<Values>> From<Vec<T>> for Values {
+    fn from(vs: Vec<T>) -> Values {
+        Values(
+            vs.into_iter()
+                .flat_map(|v| Into::<Values>::into(v).0)
+                .collect(),
+        )
+    }
+}
+
+impl<T: Into<Values> + Clone> From<&[T]> for Values {
+    fn from(vs: &[T]) -> Values {
+        vs.to_vec().into()
+    }
+}
+
+impl<T: Into<Values>, const N: usize> From<[T; N]> for Values {
+    fn from(vs: [T; N]) -> Values {
+        Vec::from(vs).into()
+    }
+}
+
+/// Returns the parameter declared by decl, e.g. "float fov", with the given values.  Used by
+/// params!.
+///
+/// # Panics
+///
+/// If decl is malformed, or values don't suit its type.
+pub fn param<V: Into<Values>>(decl: &str, values: V) -> ParamSetItem {
+    match parse_param(decl, values.into().0) {
+        Ok(item) => item,
+        Err(expected) => panic!("bad parameter \"{}\": expected {}", decl, expected),
+    }
+}
+
+/// Methods for the directives allowed in both blocks.
+macro_rules! transform_methods {
+    () => {
+        pub fn look_at(self, eye: [Float; 3], look: [Float; 3], up: [Float; 3]) -> Self {
+            self.push(Directive::LookAt(
+                eye[0], eye[1], eye[2], look[0], look[1], look[2], up[0], up[1], up[2],
+            ))
+        }
+
+        pub fn translate(self, x: Float, y: Float, z: Float) -> Self {
+            self.push(Directive::Translate(x, y, z))
+        }
+
+        pub fn scale(self, x: Float, y: Float, z: Float) -> Self {
+            self.push(Directive::Scale(x, y, z))
+        }
+
+        pub fn rotate(self, angle: Float, x: Float, y: Float, z: Float) -> Self {
+            self.push(Directive::Rotate(angle, x, y, z))
+        }
+
+        pub fn identity(self) -> Self {
+            self.push(Directive::Identity)
+        }
+
+        pub fn transform(self, m: [Float; 16]) -> Self {
+            self.push(Directive::Transform(m))
+        }
+
+        pub fn concat_transform(self, m: [Float; 16]) -> Self {
+            self.push(Directive::ConcatTransform(m))
+        }
+
+        pub fn coordinate_system(self, name: &str) -> Self {
+            self.push(Directive::CoordinateSystem(name.to_owned()))
+        }
+
+        pub fn coord_sys_transform(self, name: &str) -> Self {
+            self.push(Directive::CoordSysTransform(name.to_owned()))
+        }
+
+        pub fn active_transform(self, which: ActiveTransform) -> Self {
+            self.push(Directive::ActiveTransform(which))
+        }
+
+        pub fn make_named_medium(self, name: &str, params: ParamSet) -> Self {
+            self.push(Directive::MakeNamedMedium(name.to_owned(), params))
+        }
+
+        pub fn medium_interface(self, inside: &str, outside: &str) -> Self {
+            self.push(Directive::MediumInterface(
+                inside.to_owned(),
+                outside.to_owned(),
+            ))
+        }
+
+        fn push(mut self, d: Directive) -> Self {
+            self.scene.push(d, Span::default());
+            self
+        }
+    };
+}
+
+/// Builds a Scene, starting with the options block before WorldBegin.
+#[derive(Debug, Default)]
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        Default::default()
+    }
+
+    transform_methods!();
+
+    pub fn camera(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Camera(name.to_owned(), params))
+    }
+
+    pub fn sampler(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Sampler(name.to_owned(), params))
+    }
+
+    pub fn integrator(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Integrator(name.to_owned(), params))
+    }
+
+    pub fn film(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Film(name.to_owned(), params))
+    }
+
+    pub fn transform_times(self, start: Float, end: Float) -> Self {
+        self.push(Directive::TransformTimes(start, end))
+    }
+
+    /// Adds the world block, between WorldBegin and WorldEnd, built by f, and returns the
+    /// finished Scene.
+    pub fn world<F>(self, f: F) -> Scene
+    where
+        F: FnOnce(WorldBuilder) -> WorldBuilder,
+    {
+        let w = WorldBuilder {
+            scene: self.push(Directive::WorldBegin).scene,
+        };
+        f(w).push(Directive::WorldEnd).scene
+    }
+}
+
+/// Builds the world block of a Scene.
+#[derive(Debug)]
+pub struct WorldBuilder {
+    scene: Scene,
+}
+
+impl WorldBuilder {
+    transform_methods!();
+
+    /// Adds an AttributeBegin/AttributeEnd block containing the directives added by f.
+    pub fn attribute_block<F>(self, f: F) -> Self
+    where
+        F: FnOnce(WorldBuilder) -> WorldBuilder,
+    {
+        f(self.push(Directive::AttributeBegin)).push(Directive::AttributeEnd)
+    }
+
+    /// Adds a TransformBegin/TransformEnd block containing the directives added by f.
+    pub fn transform_block<F>(self, f: F) -> Self
+    where
+        F: FnOnce(WorldBuilder) -> WorldBuilder,
+    {
+        f(self.push(Directive::TransformBegin)).push(Directive::TransformEnd)
+    }
+
+    pub fn light_source(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::LightSource(name.to_owned(), params))
+    }
+
+    pub fn area_light_source(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::AreaLightSource(name.to_owned(), params))
+    }
+
+    pub fn material(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Material(name.to_owned(), params))
+    }
+
+    pub fn make_named_material(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::MakeNamedMaterial(name.to_owned(), params))
+    }
+
+    pub fn named_material(self, name: &str) -> Self {
+        self.push(Directive::NamedMaterial(name.to_owned()))
+    }
+
+    pub fn shape(self, name: &str, params: ParamSet) -> Self {
+        self.push(Directive::Shape(name.to_owned(), params))
+    }
+
+    /// Defines a texture.  typ is "float" or "spectrum", and class is its implementation.
+    pub fn texture(self, name: &str, typ: &str, class: &str, params: ParamSet) -> Self {
+        self.push(Directive::Texture(
+            name.to_owned(),
+            typ.to_owned(),
+            class.to_owned(),
+            params,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::parser::parse_scene;
+
+    #[test]
+    fn test_same_as_parsed() {
+        let scene = SceneBuilder::new()
+            .look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.])
+            .camera("perspective", params! {"float fov" => 45})
+            .film(
+                "image",
+                params! {
+                    "string filename" => "out.exr",
+                    "integer xresolution" => 400,
+                    "float cropwindow" => [0., 0.5, 0., 0.5],
+                },
+            )
+            .world(|w| {
+                w.texture(
+                    "checks",
+                    "spectrum",
+                    "checkerboard",
+                    params! {"rgb tex1" => [1., 0., 0.], "spectrum tex2" => "spds/blue.spd"},
+                )
+                .attribute_block(|w| {
+                    w.transform_block(|w| w.translate(0., 1., 0.))
+                        .material(
+                            "matte",
+                            params! {"texture Kd" => "checks", "bool remaproughness" => false},
+                        )
+                        .shape(
+                            "trianglemesh",
+                            params! {
+                                "integer indices" => vec![0, 1, 2],
+                                "point P" => &[0., 0., 0., 1., 0., 0., 0., 1., 0.][..],
+                            },
+                        )
+                })
+            });
+        let parsed = parse_scene(
+            br#"LookAt 0 0 5  0 0 0  0 1 0
+Camera "perspective" "float fov" 45
+Film "image" "string filename" "out.exr" "integer xresolution" 400
+    "float cropwindow" [0 .5 0 .5]
+WorldBegin
+Texture "checks" "spectrum" "checkerboard"
+    "rgb tex1" [1 0 0] "spectrum tex2" "spds/blue.spd"
+AttributeBegin
+  TransformBegin
+    Translate 0 1 0
+  TransformEnd
+  Material "matte" "texture Kd" "checks" "bool remaproughness" "false"
+  Shape "trianglemesh" "integer indices" [0 1 2] "point P" [0 0 0 1 0 0 0 1 0]
+AttributeEnd
+WorldEnd
+"#,
+        )
+        .unwrap();
+        assert_eq!(scene, parsed);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad parameter \"integer n\"")]
+    fn test_bad_param() {
+        params! {"integer n" => 1.5};
+    }
+
+    #[test]
+    #[should_panic(expected = "bad parameter \"spectrum Kd\": expected wavelength/value pairs")]
+    fn test_bad_count() {
+        params! {"spectrum Kd" => [300., 0.3, 400.]};
+    }
+}
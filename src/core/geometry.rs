@@ -11,84 +11,302 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::ops::Div;
+use std::mem;
+use std::ops::{
+    Add, AddAssign, Deref, DerefMut, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub,
+    SubAssign,
+};
+use std::sync::Arc;
 
-use core::pbrt::{Float, Int};
+use core::medium::Medium;
+use core::pbrt::{gamma, next_float_down, next_float_up, Float, Int};
 
 pub trait Sqrt<RHS = Self> {
     type Output;
     fn sqrt(self) -> Self::Output;
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Vector2<T> {
-    pub x: T,
-    pub y: T,
+impl Sqrt for Float {
+    type Output = Float;
+
+    fn sqrt(self) -> Float {
+        Float::sqrt(self)
+    }
 }
 
-pub type Vector2f = Vector2<Float>;
-pub type Vector2i = Vector2<Int>;
+impl Sqrt for Int {
+    type Output = Float;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Vector3<T> {
-    pub x: T,
-    pub y: T,
-    pub z: T,
+    fn sqrt(self) -> Float {
+        (self as Float).sqrt()
+    }
 }
 
-impl<T> Vector3<T> {
-    pub fn new(x: T, y: T, z: T) -> Vector3<T> {
-        Vector3 { x, y, z }
+fn partial_min<T: PartialOrd>(a: T, b: T) -> T {
+    if b < a {
+        b
+    } else {
+        a
     }
 }
 
-pub type Vector3f = Vector3<Float>;
-
-// TODO(wathiede): Make this generic over float vs int.
-impl Vector3f {
-    pub fn normalize(&self) -> Vector3f {
-        self / self.length()
+fn partial_max<T: PartialOrd>(a: T, b: T) -> T {
+    if b > a {
+        b
+    } else {
+        a
     }
+}
 
-    pub fn length_squared(&self) -> Float {
-        self.x * self.x + self.y * self.y + self.z * self.z
+fn abs<T: Copy + Default + PartialOrd + Neg<Output = T>>(v: T) -> T {
+    if v < T::default() {
+        -v
+    } else {
+        v
     }
+}
 
-    pub fn length(&self) -> Float {
-        self.length_squared().sqrt()
-    }
+/// Methods and operators shared by all the geometry types: construction, scaling, negation,
+/// indexing and component-wise functions.
+macro_rules! tuple_methods {
+    ($t:ident { $($i:tt => $c:ident),+ }) => {
+        impl<T> $t<T> {
+            pub fn new($($c: T),+) -> $t<T> {
+                $t { $($c),+ }
+            }
+        }
+
+        impl<T: Copy + PartialOrd> $t<T> {
+            /// Returns the component-wise minimum of self and other.
+            pub fn min(&self, other: &$t<T>) -> $t<T> {
+                $t { $($c: partial_min(self.$c, other.$c)),+ }
+            }
+
+            /// Returns the component-wise maximum of self and other.
+            pub fn max(&self, other: &$t<T>) -> $t<T> {
+                $t { $($c: partial_max(self.$c, other.$c)),+ }
+            }
+
+            pub fn min_component(&self) -> T {
+                let mut m = self[0];
+                $(m = partial_min(m, self.$c);)+
+                m
+            }
+
+            pub fn max_component(&self) -> T {
+                let mut m = self[0];
+                $(m = partial_max(m, self.$c);)+
+                m
+            }
+
+            /// Returns the index of the largest component, the last of them if there's a tie.
+            pub fn max_dimension(&self) -> usize {
+                let mut d = 0;
+                $(if self[$i] >= self[d] {
+                    d = $i;
+                })+
+                d
+            }
+
+            /// Returns a copy with its components taken from self at the given indices.
+            pub fn permute(&self, $($c: usize),+) -> $t<T> {
+                $t { $($c: self[$c]),+ }
+            }
+        }
+
+        impl<T: Copy + Default + PartialOrd + Neg<Output = T>> $t<T> {
+            pub fn abs(&self) -> $t<T> {
+                $t { $($c: abs(self.$c)),+ }
+            }
+        }
+
+        impl<T> Index<usize> for $t<T> {
+            type Output = T;
+
+            fn index(&self, i: usize) -> &T {
+                match i {
+                    $($i => &self.$c,)+
+                    _ => panic!("index {} out of range for {}", i, stringify!($t)),
+                }
+            }
+        }
+
+        impl<T> IndexMut<usize> for $t<T> {
+            fn index_mut(&mut self, i: usize) -> &mut T {
+                match i {
+                    $($i => &mut self.$c,)+
+                    _ => panic!("index {} out of range for {}", i, stringify!($t)),
+                }
+            }
+        }
+
+        impl<T: Neg<Output = T>> Neg for $t<T> {
+            type Output = $t<T>;
+
+            fn neg(self) -> $t<T> {
+                $t { $($c: -self.$c),+ }
+            }
+        }
+
+        impl<T: Copy + Mul<Output = T>> Mul<T> for $t<T> {
+            type Output = $t<T>;
+
+            fn mul(self, rhs: T) -> $t<T> {
+                $t { $($c: self.$c * rhs),+ }
+            }
+        }
+
+        impl<T: Copy + MulAssign> MulAssign<T> for $t<T> {
+            fn mul_assign(&mut self, rhs: T) {
+                $(self.$c *= rhs;)+
+            }
+        }
+
+        impl<T: Copy + Div<Output = T>> Div<T> for $t<T> {
+            type Output = $t<T>;
+
+            fn div(self, rhs: T) -> $t<T> {
+                $t { $($c: self.$c / rhs),+ }
+            }
+        }
+
+        impl<T: Copy + Div<Output = T>> Div<T> for &$t<T> {
+            type Output = $t<T>;
+
+            fn div(self, rhs: T) -> $t<T> {
+                $t { $($c: self.$c / rhs),+ }
+            }
+        }
+
+        impl<T: Copy + DivAssign> DivAssign<T> for $t<T> {
+            fn div_assign(&mut self, rhs: T) {
+                $(self.$c /= rhs;)+
+            }
+        }
+
+        impl Mul<$t<Float>> for Float {
+            type Output = $t<Float>;
+
+            fn mul(self, rhs: $t<Float>) -> $t<Float> {
+                rhs * self
+            }
+        }
+
+        impl Mul<$t<Int>> for Int {
+            type Output = $t<Int>;
+
+            fn mul(self, rhs: $t<Int>) -> $t<Int> {
+                rhs * self
+            }
+        }
+    };
 }
 
-// TODO(wathiede): Make this generic over float vs int.
-impl<'a> Div<Float> for &'a Vector3f {
-    type Output = Vector3f;
+/// Implements the binary operator $op, and its assigning form $op_assign, for $lhs and $rhs with
+/// the result $out.
+macro_rules! binary_op {
+    ($op:ident, $f:ident, $op_assign:ident, $f_assign:ident,
+     $lhs:ident, $rhs:ident => $out:ident { $($c:ident),+ }) => {
+        binary_op!($op, $f, $lhs, $rhs => $out { $($c),+ });
 
-    fn div(self, rhs: Float) -> Vector3f {
-        Vector3 {
-            x: self.x / rhs,
-            y: self.y / rhs,
-            z: self.z / rhs,
+        impl<T: $op_assign> $op_assign<$rhs<T>> for $lhs<T> {
+            fn $f_assign(&mut self, rhs: $rhs<T>) {
+                $(self.$c.$f_assign(rhs.$c);)+
+            }
+        }
+    };
+    ($op:ident, $f:ident, $lhs:ident, $rhs:ident => $out:ident { $($c:ident),+ }) => {
+        impl<T: $op<Output = T>> $op<$rhs<T>> for $lhs<T> {
+            type Output = $out<T>;
+
+            fn $f(self, rhs: $rhs<T>) -> $out<T> {
+                $out { $($c: self.$c.$f(rhs.$c)),+ }
+            }
+        }
+    };
+}
+
+/// Methods for the types with a length: vectors and normals.
+macro_rules! length_methods {
+    ($t:ident { $c0:ident $(, $c:ident)* }) => {
+        impl<T: Copy + Add<Output = T> + Mul<Output = T>> $t<T> {
+            pub fn length_squared(&self) -> T {
+                self.$c0 * self.$c0 $(+ self.$c * self.$c)*
+            }
+        }
+
+        impl<T: Copy + Add<Output = T> + Mul<Output = T> + Sqrt> $t<T> {
+            pub fn length(&self) -> <T as Sqrt>::Output {
+                self.length_squared().sqrt()
+            }
+        }
+
+        impl<T> $t<T>
+        where
+            T: Copy + Add<Output = T> + Mul<Output = T> + Div<Output = T> + Sqrt<Output = T>,
+        {
+            pub fn normalize(&self) -> $t<T> {
+                self / self.length()
+            }
+        }
+    };
+}
+
+/// Conversions between types with the same components.
+macro_rules! convert {
+    ($from:ident => $to:ident { $($c:ident),+ }) => {
+        impl<T> From<$from<T>> for $to<T> {
+            fn from(v: $from<T>) -> $to<T> {
+                $to { $($c: v.$c),+ }
+            }
         }
+    };
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vector2<T> {
+    pub x: T,
+    pub y: T,
+}
+
+pub type Vector2f = Vector2<Float>;
+pub type Vector2i = Vector2<Int>;
+
+tuple_methods!(Vector2 { 0 => x, 1 => y });
+length_methods!(Vector2 { x, y });
+binary_op!(Add, add, AddAssign, add_assign, Vector2, Vector2 => Vector2 { x, y });
+binary_op!(Sub, sub, SubAssign, sub_assign, Vector2, Vector2 => Vector2 { x, y });
+
+impl From<Vector2i> for Vector2f {
+    fn from(v: Vector2i) -> Vector2f {
+        Vector2f::new(v.x as Float, v.y as Float)
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vector3<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+}
+
+pub type Vector3f = Vector3<Float>;
 pub type Vector3i = Vector3<Int>;
 
+tuple_methods!(Vector3 { 0 => x, 1 => y, 2 => z });
+length_methods!(Vector3 { x, y, z });
+binary_op!(Add, add, AddAssign, add_assign, Vector3, Vector3 => Vector3 { x, y, z });
+binary_op!(Sub, sub, SubAssign, sub_assign, Vector3, Vector3 => Vector3 { x, y, z });
+
 impl Vector3i {
+    /// Returns the integer vector nearest to the unit vector in this direction.
     pub fn normalize(&self) -> Vector3i {
         self / self.length()
     }
-
-    pub fn length_squared(&self) -> Float {
-        (self.x * self.x + self.y * self.y + self.z * self.z) as Float
-    }
-
-    pub fn length(&self) -> Float {
-        self.length_squared().sqrt()
-    }
 }
 
-// TODO(wathiede): Make this generic over float vs int.
 impl<'a> Div<Float> for &'a Vector3i {
     type Output = Vector3i;
 
@@ -101,7 +319,8 @@ impl<'a> Div<Float> for &'a Vector3i {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Point2<T> {
     pub x: T,
     pub y: T,
@@ -110,7 +329,42 @@ pub struct Point2<T> {
 pub type Point2f = Point2<Float>;
 pub type Point2i = Point2<Int>;
 
-#[derive(Debug, Clone, PartialEq)]
+tuple_methods!(Point2 { 0 => x, 1 => y });
+binary_op!(Add, add, AddAssign, add_assign, Point2, Vector2 => Point2 { x, y });
+binary_op!(Sub, sub, SubAssign, sub_assign, Point2, Vector2 => Point2 { x, y });
+binary_op!(Sub, sub, Point2, Point2 => Vector2 { x, y });
+// Adding points only makes sense in weighted sums, like lerp's.
+binary_op!(Add, add, AddAssign, add_assign, Point2, Point2 => Point2 { x, y });
+convert!(Vector2 => Point2 { x, y });
+convert!(Point2 => Vector2 { x, y });
+
+impl<T: Copy + Sub<Output = T> + Add<Output = T> + Mul<Output = T>> Point2<T> {
+    pub fn distance_squared(&self, p: &Point2<T>) -> T {
+        (*self - *p).length_squared()
+    }
+}
+
+impl<T: Copy + Sub<Output = T> + Add<Output = T> + Mul<Output = T> + Sqrt> Point2<T> {
+    pub fn distance(&self, p: &Point2<T>) -> <T as Sqrt>::Output {
+        (*self - *p).length()
+    }
+}
+
+impl From<Point2i> for Point2f {
+    fn from(p: Point2i) -> Point2f {
+        Point2f::new(p.x as Float, p.y as Float)
+    }
+}
+
+impl<T> From<Point3<T>> for Point2<T> {
+    /// Drops the z component.
+    fn from(p: Point3<T>) -> Point2<T> {
+        Point2 { x: p.x, y: p.y }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Point3<T> {
     pub x: T,
     pub y: T,
@@ -120,7 +374,35 @@ pub struct Point3<T> {
 pub type Point3f = Point3<Float>;
 pub type Point3i = Point3<Int>;
 
-#[derive(Debug, Clone, PartialEq)]
+tuple_methods!(Point3 { 0 => x, 1 => y, 2 => z });
+binary_op!(Add, add, AddAssign, add_assign, Point3, Vector3 => Point3 { x, y, z });
+binary_op!(Sub, sub, SubAssign, sub_assign, Point3, Vector3 => Point3 { x, y, z });
+binary_op!(Sub, sub, Point3, Point3 => Vector3 { x, y, z });
+// Adding points only makes sense in weighted sums, like lerp's.
+binary_op!(Add, add, AddAssign, add_assign, Point3, Point3 => Point3 { x, y, z });
+convert!(Vector3 => Point3 { x, y, z });
+convert!(Point3 => Vector3 { x, y, z });
+
+impl<T: Copy + Sub<Output = T> + Add<Output = T> + Mul<Output = T>> Point3<T> {
+    pub fn distance_squared(&self, p: &Point3<T>) -> T {
+        (*self - *p).length_squared()
+    }
+}
+
+impl<T: Copy + Sub<Output = T> + Add<Output = T> + Mul<Output = T> + Sqrt> Point3<T> {
+    pub fn distance(&self, p: &Point3<T>) -> <T as Sqrt>::Output {
+        (*self - *p).length()
+    }
+}
+
+impl From<Point3i> for Point3f {
+    fn from(p: Point3i) -> Point3f {
+        Point3f::new(p.x as Float, p.y as Float, p.z as Float)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Normal3<T> {
     pub x: T,
     pub y: T,
@@ -129,10 +411,531 @@ pub struct Normal3<T> {
 
 pub type Normal3f = Normal3<Float>;
 
+tuple_methods!(Normal3 { 0 => x, 1 => y, 2 => z });
+length_methods!(Normal3 { x, y, z });
+binary_op!(Add, add, AddAssign, add_assign, Normal3, Normal3 => Normal3 { x, y, z });
+binary_op!(Sub, sub, SubAssign, sub_assign, Normal3, Normal3 => Normal3 { x, y, z });
+convert!(Vector3 => Normal3 { x, y, z });
+convert!(Normal3 => Vector3 { x, y, z });
+
+/// The three component directions, vectors and normals, that dot, abs_dot, cross and
+/// face_forward accept in any combination.
+pub trait Direction3<T> {
+    fn xyz(&self) -> (T, T, T);
+}
+
+impl<T: Copy> Direction3<T> for Vector3<T> {
+    fn xyz(&self) -> (T, T, T) {
+        (self.x, self.y, self.z)
+    }
+}
+
+impl<T: Copy> Direction3<T> for Normal3<T> {
+    fn xyz(&self) -> (T, T, T) {
+        (self.x, self.y, self.z)
+    }
+}
+
+pub fn dot<T, A, B>(v1: &A, v2: &B) -> T
+where
+    T: Add<Output = T> + Mul<Output = T>,
+    A: Direction3<T>,
+    B: Direction3<T>,
+{
+    let (v1x, v1y, v1z) = v1.xyz();
+    let (v2x, v2y, v2z) = v2.xyz();
+    v1x * v2x + v1y * v2y + v1z * v2z
+}
+
+pub fn abs_dot<T, A, B>(v1: &A, v2: &B) -> T
+where
+    T: Copy + Default + PartialOrd + Neg<Output = T> + Add<Output = T> + Mul<Output = T>,
+    A: Direction3<T>,
+    B: Direction3<T>,
+{
+    abs(dot(v1, v2))
+}
+
+/// Returns the vector perpendicular to both v1 and v2.  The computation is done in f64 to avoid
+/// catastrophic cancellation when the vectors are nearly parallel.
+pub fn cross<A: Direction3<Float>, B: Direction3<Float>>(v1: &A, v2: &B) -> Vector3f {
+    let (v1x, v1y, v1z) = v1.xyz();
+    let (v2x, v2y, v2z) = v2.xyz();
+    let (v1x, v1y, v1z) = (f64::from(v1x), f64::from(v1y), f64::from(v1z));
+    let (v2x, v2y, v2z) = (f64::from(v2x), f64::from(v2y), f64::from(v2z));
+    Vector3f::new(
+        (v1y * v2z - v1z * v2y) as Float,
+        (v1z * v2x - v1x * v2z) as Float,
+        (v1x * v2y - v1y * v2x) as Float,
+    )
+}
+
+/// Returns n, flipped if needed to lie in the same hemisphere as v.
+pub fn face_forward<T, N, V>(n: &N, v: &V) -> N
+where
+    T: Default + PartialOrd + Add<Output = T> + Mul<Output = T>,
+    N: Direction3<T> + Copy + Neg<Output = N>,
+    V: Direction3<T>,
+{
+    if dot(n, v) < T::default() {
+        -*n
+    } else {
+        *n
+    }
+}
+
+/// Returns two vectors that, with the normalized vector v1, form an orthonormal basis.
+pub fn coordinate_system(v1: &Vector3f) -> (Vector3f, Vector3f) {
+    let v2 = if v1.x.abs() > v1.y.abs() {
+        Vector3f::new(-v1.z, 0., v1.x) / (v1.x * v1.x + v1.z * v1.z).sqrt()
+    } else {
+        Vector3f::new(0., v1.z, -v1.y) / (v1.y * v1.y + v1.z * v1.z).sqrt()
+    };
+    let v3 = cross(v1, &v2);
+    (v2, v3)
+}
+
+/// Returns the origin for a ray leaving the surface at p, with absolute error p_error and normal
+/// n, in direction w.  p is pushed along n, to the w side, far enough to clear the box of points
+/// p could really be, so that the new ray can't hit the surface it's leaving.
+pub fn offset_ray_origin(p: &Point3f, p_error: &Vector3f, n: &Normal3f, w: &Vector3f) -> Point3f {
+    let d = dot(&n.abs(), p_error);
+    let mut offset = Vector3f::from(*n) * d;
+    if dot(w, n) < 0. {
+        offset = -offset;
+    }
+    let mut po = *p + offset;
+    // Round away from p, so that rounding the sum can't undo the offset.
+    for i in 0..3 {
+        if offset[i] > 0. {
+            po[i] = next_float_up(po[i]);
+        } else if offset[i] < 0. {
+            po[i] = next_float_down(po[i]);
+        }
+    }
+    po
+}
+
+/// Linearly interpolates between a and b, returning a when t is 0 and b when it's 1.  Works for
+/// scalars and the point and vector types.
+pub fn lerp<T>(t: Float, a: T, b: T) -> T
+where
+    Float: Mul<T, Output = T>,
+    T: Add<Output = T>,
+{
+    (1. - t) * a + t * b
+}
+
+/// Methods shared by Bounds2 and Bounds3.  $p and $v are the point and vector types of the same
+/// dimension.
+macro_rules! bounds_methods {
+    ($b:ident, $p:ident, $v:ident { $($c:ident),+ }) => {
+        impl<T: Copy + PartialOrd> $b<T> {
+            /// Returns the bounds with corners p1 and p2, which may be any two opposite corners.
+            pub fn new(p1: $p<T>, p2: $p<T>) -> $b<T> {
+                $b {
+                    p_min: p1.min(&p2),
+                    p_max: p1.max(&p2),
+                }
+            }
+
+            /// Returns the bounds of self and b.
+            pub fn union(&self, b: &$b<T>) -> $b<T> {
+                $b {
+                    p_min: self.p_min.min(&b.p_min),
+                    p_max: self.p_max.max(&b.p_max),
+                }
+            }
+
+            /// Returns the bounds of self and p.
+            pub fn union_point(&self, p: &$p<T>) -> $b<T> {
+                $b {
+                    p_min: self.p_min.min(p),
+                    p_max: self.p_max.max(p),
+                }
+            }
+
+            /// Returns the overlap of self and b.  It's degenerate, with p_min > p_max in some
+            /// dimension, when they don't overlap.
+            pub fn intersect(&self, b: &$b<T>) -> $b<T> {
+                $b {
+                    p_min: self.p_min.max(&b.p_min),
+                    p_max: self.p_max.min(&b.p_max),
+                }
+            }
+
+            pub fn overlaps(&self, b: &$b<T>) -> bool {
+                $(self.p_max.$c >= b.p_min.$c && self.p_min.$c <= b.p_max.$c)&&+
+            }
+
+            pub fn inside(&self, p: &$p<T>) -> bool {
+                $(p.$c >= self.p_min.$c && p.$c <= self.p_max.$c)&&+
+            }
+
+            /// Like inside, but excludes the points on the upper boundary, so that adjacent integer
+            /// bounds don't share them.
+            pub fn inside_exclusive(&self, p: &$p<T>) -> bool {
+                $(p.$c >= self.p_min.$c && p.$c < self.p_max.$c)&&+
+            }
+        }
+
+        impl<T: Copy + PartialOrd + Sub<Output = T>> $b<T> {
+            pub fn diagonal(&self) -> $v<T> {
+                self.p_max - self.p_min
+            }
+
+            /// Returns the index of the axis along which the bounds are largest.
+            pub fn maximum_extent(&self) -> usize {
+                self.diagonal().max_dimension()
+            }
+        }
+
+        impl<T: Copy + PartialOrd + Add<Output = T> + Sub<Output = T>> $b<T> {
+            /// Returns the bounds grown by delta in every direction.
+            pub fn expand(&self, delta: T) -> $b<T> {
+                let d = $v { $($c: delta),+ };
+                $b {
+                    p_min: self.p_min - d,
+                    p_max: self.p_max + d,
+                }
+            }
+        }
+
+        impl<T: Copy + PartialOrd + Sub<Output = T> + Div<Output = T>> $b<T> {
+            /// Returns the position of p relative to the bounds, (0, ...) at p_min and (1, ...) at
+            /// p_max.
+            pub fn offset(&self, p: &$p<T>) -> $v<T> {
+                let mut o = *p - self.p_min;
+                $(if self.p_max.$c > self.p_min.$c {
+                    o.$c = o.$c / (self.p_max.$c - self.p_min.$c);
+                })+
+                o
+            }
+        }
+
+        impl $b<Float> {
+            /// Returns the point t of the way from p_min to p_max along each axis.
+            pub fn lerp(&self, t: &$p<Float>) -> $p<Float> {
+                $p { $($c: lerp(t.$c, self.p_min.$c, self.p_max.$c)),+ }
+            }
+
+            /// Returns the center and radius of a sphere that contains the bounds.
+            pub fn bounding_sphere(&self) -> ($p<Float>, Float) {
+                let center = (self.p_min + self.p_max) / 2.;
+                let radius = if self.inside(&center) {
+                    center.distance(&self.p_max)
+                } else {
+                    0.
+                };
+                (center, radius)
+            }
+        }
+
+        impl<T> Index<usize> for $b<T> {
+            type Output = $p<T>;
+
+            /// Returns p_min for 0 and p_max for 1.
+            fn index(&self, i: usize) -> &$p<T> {
+                match i {
+                    0 => &self.p_min,
+                    1 => &self.p_max,
+                    _ => panic!("index {} out of range for {}", i, stringify!($b)),
+                }
+            }
+        }
+
+        impl<T> From<$p<T>> for $b<T>
+        where
+            $p<T>: Copy,
+        {
+            fn from(p: $p<T>) -> $b<T> {
+                $b { p_min: p, p_max: p }
+            }
+        }
+    };
+}
+
+/// Implements Default for bounds of the scalar $t as an empty box, with p_min at the type's
+/// maximum and p_max at its minimum, so that the union with anything is the other operand.
+macro_rules! empty_bounds {
+    ($b:ident, $p:ident, $t:ident { $($c:ident),+ }) => {
+        impl Default for $b<$t> {
+            fn default() -> $b<$t> {
+                $b {
+                    p_min: $p { $($c: $t::MAX),+ },
+                    p_max: $p { $($c: $t::MIN),+ },
+                }
+            }
+        }
+    };
+}
+
+/// An axis-aligned rectangle, with p_min <= p_max along both axes unless it's empty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Bounds2<T> {
+    pub p_min: Point2<T>,
+    pub p_max: Point2<T>,
+}
+
+pub type Bounds2f = Bounds2<Float>;
+pub type Bounds2i = Bounds2<Int>;
+
+bounds_methods!(Bounds2, Point2, Vector2 { x, y });
+empty_bounds!(Bounds2, Point2, Float { x, y });
+empty_bounds!(Bounds2, Point2, Int { x, y });
+
+impl<T: Copy + PartialOrd + Sub<Output = T> + Mul<Output = T>> Bounds2<T> {
+    pub fn area(&self) -> T {
+        let d = self.diagonal();
+        d.x * d.y
+    }
+}
+
+impl Bounds2i {
+    /// Returns an iterator over the pixels in the bounds, row by row.  p_max is excluded, so
+    /// adjacent bounds visit each pixel once.
+    pub fn iter(&self) -> PixelIter {
+        PixelIter {
+            bounds: *self,
+            p: self.p_min,
+        }
+    }
+}
+
+impl IntoIterator for &Bounds2i {
+    type Item = Point2i;
+    type IntoIter = PixelIter;
+
+    fn into_iter(self) -> PixelIter {
+        self.iter()
+    }
+}
+
+/// The iterator returned by Bounds2i::iter.
+#[derive(Debug, Clone)]
+pub struct PixelIter {
+    bounds: Bounds2i,
+    p: Point2i,
+}
+
+impl Iterator for PixelIter {
+    type Item = Point2i;
+
+    fn next(&mut self) -> Option<Point2i> {
+        if !self.bounds.inside_exclusive(&self.p) {
+            return None;
+        }
+        let p = self.p;
+        self.p.x += 1;
+        if self.p.x == self.bounds.p_max.x {
+            self.p.x = self.bounds.p_min.x;
+            self.p.y += 1;
+        }
+        Some(p)
+    }
+}
+
+/// An axis-aligned box, with p_min <= p_max along each axis unless it's empty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Bounds3<T> {
+    pub p_min: Point3<T>,
+    pub p_max: Point3<T>,
+}
+
+pub type Bounds3f = Bounds3<Float>;
+pub type Bounds3i = Bounds3<Int>;
+
+bounds_methods!(Bounds3, Point3, Vector3 { x, y, z });
+empty_bounds!(Bounds3, Point3, Float { x, y, z });
+empty_bounds!(Bounds3, Point3, Int { x, y, z });
+
+impl<T: Copy> Bounds3<T> {
+    /// Returns one of the eight corners: bit 0 of corner selects p_max's x, bit 1 its y and bit
+    /// 2 its z.
+    pub fn corner(&self, corner: usize) -> Point3<T> {
+        Point3 {
+            x: self[corner & 1].x,
+            y: self[(corner >> 1) & 1].y,
+            z: self[(corner >> 2) & 1].z,
+        }
+    }
+}
+
+impl<T: Copy + PartialOrd + Add<Output = T> + Sub<Output = T> + Mul<Output = T>> Bounds3<T> {
+    pub fn surface_area(&self) -> T {
+        let d = self.diagonal();
+        let half = d.x * d.y + d.x * d.z + d.y * d.z;
+        half + half
+    }
+
+    pub fn volume(&self) -> T {
+        let d = self.diagonal();
+        d.x * d.y * d.z
+    }
+}
+
+impl Bounds3f {
+    /// Returns the parametric range, within [0, ray.t_max], over which ray is inside the bounds,
+    /// or None if it misses them.  The far end of each slab is pushed out by the error bound of
+    /// its computation, so that a ray can't slip through the corner of the box through rounding.
+    pub fn intersect_p(&self, ray: &Ray) -> Option<(Float, Float)> {
+        let (mut t0, mut t1) = (0., ray.t_max);
+        for i in 0..3 {
+            let inv_dir = 1. / ray.d[i];
+            let mut t_near = (self.p_min[i] - ray.o[i]) * inv_dir;
+            let mut t_far = (self.p_max[i] - ray.o[i]) * inv_dir;
+            if t_near > t_far {
+                mem::swap(&mut t_near, &mut t_far);
+            }
+            t_far *= 1. + 2. * gamma(3);
+            // Written so that a NaN, from a ray in a slab's plane, leaves the range unchanged.
+            t0 = if t_near > t0 { t_near } else { t0 };
+            t1 = if t_far < t1 { t_far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// A faster intersect_p for testing one ray against many boxes, with the ray's reciprocal
+    /// direction and dir_is_neg, 1 for each negative component of its direction and 0 for the
+    /// others, computed once.  Returns whether the ray hits the bounds.
+    pub fn intersect_p_inv(&self, ray: &Ray, inv_dir: &Vector3f, dir_is_neg: [usize; 3]) -> bool {
+        let slab = |i: usize| {
+            let t_near = (self[dir_is_neg[i]][i] - ray.o[i]) * inv_dir[i];
+            let t_far = (self[1 - dir_is_neg[i]][i] - ray.o[i]) * inv_dir[i];
+            (t_near, t_far * (1. + 2. * gamma(3)))
+        };
+        let (mut t_min, mut t_max) = slab(0);
+        for i in 1..3 {
+            let (t_near, t_far) = slab(i);
+            if t_min > t_far || t_near > t_max {
+                return false;
+            }
+            if t_near > t_min {
+                t_min = t_near;
+            }
+            if t_far < t_max {
+                t_max = t_far;
+            }
+        }
+        t_min < ray.t_max && t_max > 0.
+    }
+}
+
+/// A semi-infinite line, the points o + t * d for t in [0, t_max], at an instant in time and
+/// passing through medium, or a vacuum if it's None.
+#[derive(Debug, Clone)]
+pub struct Ray {
+    pub o: Point3f,
+    pub d: Vector3f,
+    pub t_max: Float,
+    pub time: Float,
+    pub medium: Option<Arc<Medium>>,
+}
+
+impl Default for Ray {
+    fn default() -> Ray {
+        Ray {
+            o: Point3f::default(),
+            d: Vector3f::default(),
+            t_max: Float::INFINITY,
+            time: 0.,
+            medium: None,
+        }
+    }
+}
+
+impl Ray {
+    pub fn new(
+        o: Point3f,
+        d: Vector3f,
+        t_max: Float,
+        time: Float,
+        medium: Option<Arc<Medium>>,
+    ) -> Ray {
+        Ray {
+            o,
+            d,
+            t_max,
+            time,
+            medium,
+        }
+    }
+
+    /// Returns the point at parameter t along the ray.
+    pub fn at(&self, t: Float) -> Point3f {
+        self.o + self.d * t
+    }
+}
+
+/// A ray with two auxiliary rays, offset by one pixel in x and y on the film, that texture
+/// filtering uses to estimate the footprint of the main ray.  The auxiliary rays are only
+/// meaningful if has_differentials is set.
+#[derive(Debug, Clone, Default)]
+pub struct RayDifferential {
+    pub ray: Ray,
+    pub has_differentials: bool,
+    pub rx_origin: Point3f,
+    pub ry_origin: Point3f,
+    pub rx_direction: Vector3f,
+    pub ry_direction: Vector3f,
+}
+
+impl RayDifferential {
+    pub fn new(
+        o: Point3f,
+        d: Vector3f,
+        t_max: Float,
+        time: Float,
+        medium: Option<Arc<Medium>>,
+    ) -> RayDifferential {
+        RayDifferential::from(Ray::new(o, d, t_max, time, medium))
+    }
+
+    /// Scales the offsets of the auxiliary rays from the main one by s, for when a pixel is
+    /// sampled s^2 times and each sample covers a correspondingly smaller footprint.
+    pub fn scale_differentials(&mut self, s: Float) {
+        self.rx_origin = self.ray.o + (self.rx_origin - self.ray.o) * s;
+        self.ry_origin = self.ray.o + (self.ry_origin - self.ray.o) * s;
+        self.rx_direction = self.ray.d + (self.rx_direction - self.ray.d) * s;
+        self.ry_direction = self.ray.d + (self.ry_direction - self.ray.d) * s;
+    }
+}
+
+impl From<Ray> for RayDifferential {
+    /// Returns a RayDifferential without differentials.
+    fn from(ray: Ray) -> RayDifferential {
+        RayDifferential {
+            ray,
+            ..Default::default()
+        }
+    }
+}
+
+impl Deref for RayDifferential {
+    type Target = Ray;
+
+    fn deref(&self) -> &Ray {
+        &self.ray
+    }
+}
+
+impl DerefMut for RayDifferential {
+    fn deref_mut(&mut self) -> &mut Ray {
+        &mut self.ray
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use proptest::prelude::*;
+
     #[test]
     fn test_normalize() {
         let v3f = Vector3f {
@@ -184,4 +987,304 @@ mod tests {
         assert_eq!(v3i.length(), 1.);
         assert_eq!(v3i.normalize(), Vector3i { x: 0, y: 0, z: 1 });
     }
+
+    #[test]
+    fn test_cross() {
+        let x = Vector3f::new(1., 0., 0.);
+        let y = Vector3f::new(0., 1., 0.);
+        assert_eq!(cross(&x, &y), Vector3f::new(0., 0., 1.));
+        assert_eq!(cross(&y, &x), Vector3f::new(0., 0., -1.));
+        assert_eq!(cross(&x, &x), Vector3f::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_cross_normal() {
+        let x = Vector3f::new(1., 0., 0.);
+        let n = Normal3f::new(0., 1., 0.);
+        assert_eq!(cross(&x, &n), Vector3f::new(0., 0., 1.));
+        assert_eq!(cross(&n, &x), Vector3f::new(0., 0., -1.));
+    }
+
+    #[test]
+    fn test_ops() {
+        let p = Point3f::new(1., 2., 3.);
+        let v = Vector3f::new(1., 1., 1.);
+        assert_eq!(p + v, Point3f::new(2., 3., 4.));
+        assert_eq!(p - v, Point3f::new(0., 1., 2.));
+        assert_eq!(p - Point3f::new(1., 1., 1.), Vector3f::new(0., 1., 2.));
+        assert_eq!(v * 2., Vector3f::new(2., 2., 2.));
+        assert_eq!(2. * v, Vector3f::new(2., 2., 2.));
+        assert_eq!(-v, Vector3f::new(-1., -1., -1.));
+        assert_eq!(p / 2., Point3f::new(0.5, 1., 1.5));
+        assert_eq!(
+            Point2i::new(1, 2) - Point2i::new(3, 3),
+            Vector2i::new(-2, -1)
+        );
+        let mut q = p;
+        q += v;
+        q -= v * 2.;
+        q *= 2.;
+        assert_eq!(q, Point3f::new(0., 2., 4.));
+        let mut n = Normal3f::new(1., 0., 0.);
+        n += Normal3f::new(0., 1., 0.);
+        assert_eq!(n, Normal3f::new(1., 1., 0.));
+        assert_eq!(p[0], 1.);
+        assert_eq!(p[2], 3.);
+        q[1] = 7.;
+        assert_eq!(q.y, 7.);
+    }
+
+    #[test]
+    #[should_panic(expected = "index 3 out of range for Vector3")]
+    fn test_index_out_of_range() {
+        let _ = Vector3f::new(1., 2., 3.)[3];
+    }
+
+    #[test]
+    fn test_conversions() {
+        let v = Vector3f::new(1., 2., 3.);
+        assert_eq!(Point3f::from(v), Point3f::new(1., 2., 3.));
+        assert_eq!(Normal3f::from(v), Normal3f::new(1., 2., 3.));
+        assert_eq!(Vector3f::from(Normal3f::new(1., 2., 3.)), v);
+        assert_eq!(Vector3f::from(Point3f::new(1., 2., 3.)), v);
+        assert_eq!(
+            Point2f::from(Point3f::new(1., 2., 3.)),
+            Point2f::new(1., 2.)
+        );
+        assert_eq!(Point2f::from(Point2i::new(1, 2)), Point2f::new(1., 2.));
+        assert_eq!(
+            Point3f::from(Point3i::new(1, 2, 3)),
+            Point3f::new(1., 2., 3.)
+        );
+    }
+
+    #[test]
+    fn test_component_functions() {
+        let v = Vector3f::new(3., -4., 1.);
+        let w = Vector3f::new(1., 5., -2.);
+        assert_eq!(v.min(&w), Vector3f::new(1., -4., -2.));
+        assert_eq!(v.max(&w), Vector3f::new(3., 5., 1.));
+        assert_eq!(v.abs(), Vector3f::new(3., 4., 1.));
+        assert_eq!(v.min_component(), -4.);
+        assert_eq!(v.max_component(), 3.);
+        assert_eq!(v.max_dimension(), 0);
+        assert_eq!(v.abs().max_dimension(), 1);
+        assert_eq!(Vector3f::new(1., 1., 1.).max_dimension(), 2);
+        assert_eq!(v.permute(2, 0, 1), Vector3f::new(1., 3., -4.));
+        assert_eq!(Point2i::new(3, 4).permute(1, 0), Point2i::new(4, 3));
+        assert_eq!(Vector2f::new(3., 4.).length(), 5.);
+        assert_eq!(Vector3i::new(2, 3, 6).length_squared(), 49);
+    }
+
+    #[test]
+    fn test_dot() {
+        let v = Vector3f::new(1., 2., 3.);
+        let n = Normal3f::new(-1., 0., 0.);
+        assert_eq!(dot(&v, &v), 14.);
+        assert_eq!(dot(&v, &n), -1.);
+        assert_eq!(abs_dot(&n, &v), 1.);
+        assert_eq!(dot(&Vector3i::new(1, 2, 3), &Vector3i::new(4, 5, 6)), 32);
+        assert_eq!(face_forward(&n, &v), Normal3f::new(1., 0., 0.));
+        assert_eq!(face_forward(&v, &n), Vector3f::new(-1., -2., -3.));
+        assert_eq!(face_forward(&v, &v), v);
+    }
+
+    #[test]
+    fn test_coordinate_system() {
+        for v1 in &[
+            Vector3f::new(1., 0., 0.),
+            Vector3f::new(0., 1., 0.),
+            Vector3f::new(0., 0., -1.),
+            Vector3f::new(1., 2., 3.).normalize(),
+        ] {
+            let (v2, v3) = coordinate_system(v1);
+            for (a, b) in &[(v1, &v2), (v1, &v3), (&v2, &v3)] {
+                assert!(dot(*a, *b).abs() < 1e-6, "{:?} . {:?}", a, b);
+            }
+            assert!((v2.length() - 1.).abs() < 1e-6);
+            assert!((v3.length() - 1.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_distance_lerp() {
+        let p1 = Point3f::new(1., 1., 1.);
+        let p2 = Point3f::new(4., 5., 1.);
+        assert_eq!(p1.distance(&p2), 5.);
+        assert_eq!(p1.distance_squared(&p2), 25.);
+        assert_eq!(Point2i::new(0, 0).distance(&Point2i::new(3, 4)), 5.);
+        assert_eq!(lerp(0.5, p1, p2), Point3f::new(2.5, 3., 1.));
+        assert_eq!(lerp(0., p1, p2), p1);
+        assert_eq!(lerp(1., p1, p2), p2);
+        assert_eq!(lerp(0.25, 0., 8.), 2.);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let b = Bounds3f::new(Point3f::new(1., 2., 3.), Point3f::new(-1., 0., 4.));
+        assert_eq!(b.p_min, Point3f::new(-1., 0., 3.));
+        assert_eq!(b.p_max, Point3f::new(1., 2., 4.));
+        assert_eq!(b.diagonal(), Vector3f::new(2., 2., 1.));
+        assert_eq!(b.surface_area(), 16.);
+        assert_eq!(b.volume(), 4.);
+        assert_eq!(b.maximum_extent(), 1);
+        assert_eq!(b.corner(0), b.p_min);
+        assert_eq!(b.corner(7), b.p_max);
+        assert_eq!(b.corner(5), Point3f::new(1., 0., 4.));
+        assert!(b.inside(&Point3f::new(1., 2., 4.)));
+        assert!(!b.inside_exclusive(&Point3f::new(1., 2., 4.)));
+        assert!(!b.inside(&Point3f::new(0., 0., 0.)));
+        assert_eq!(
+            b.expand(1.),
+            Bounds3f::new(Point3f::new(-2., -1., 2.), Point3f::new(2., 3., 5.))
+        );
+        assert_eq!(
+            b.offset(&Point3f::new(0., 1., 3.5)),
+            Vector3f::new(0.5, 0.5, 0.5)
+        );
+        assert_eq!(
+            b.lerp(&Point3f::new(0.5, 0.5, 0.5)),
+            Point3f::new(0., 1., 3.5)
+        );
+        let (center, radius) = b.bounding_sphere();
+        assert_eq!(center, Point3f::new(0., 1., 3.5));
+        assert_eq!(radius, 1.5);
+
+        let other = Bounds3f::new(Point3f::new(0., 1., 0.), Point3f::new(5., 5., 3.));
+        assert!(b.overlaps(&other));
+        assert_eq!(
+            b.union(&other),
+            Bounds3f::new(Point3f::new(-1., 0., 0.), Point3f::new(5., 5., 4.))
+        );
+        assert_eq!(
+            b.intersect(&other),
+            Bounds3f::new(Point3f::new(0., 1., 3.), Point3f::new(1., 2., 3.))
+        );
+        let far = Bounds3f::from(Point3f::new(9., 9., 9.));
+        assert!(!b.overlaps(&far));
+
+        let empty = Bounds3f::default();
+        assert!(!empty.inside(&Point3f::default()));
+        assert_eq!(empty.union(&b), b);
+        assert_eq!(
+            Bounds2i::default()
+                .union_point(&Point2i::new(1, 2))
+                .union_point(&Point2i::new(3, 0)),
+            Bounds2i::new(Point2i::new(1, 0), Point2i::new(3, 2))
+        );
+        assert_eq!(
+            Bounds2i::new(Point2i::new(1, 0), Point2i::new(3, 2)).area(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_bounds_intersect_p() {
+        let b = Bounds3f::new(Point3f::new(-1., -1., -1.), Point3f::new(1., 1., 1.));
+        let ray = |o: Point3f, d: Vector3f, t_max: Float| Ray::new(o, d, t_max, 0., None);
+        let o = Point3f::new(-3., 0.5, 0.);
+        let d = Vector3f::new(1., 0., 0.);
+        let (t0, t1) = b.intersect_p(&ray(o, d, Float::INFINITY)).unwrap();
+        assert_eq!(t0, 2.);
+        assert!((4.0..4. + 1e-5).contains(&t1));
+        assert_eq!(b.intersect_p(&ray(o, d, 1.)), None);
+        assert_eq!(b.intersect_p(&ray(o, -d, Float::INFINITY)), None);
+        let inside = b.intersect_p(&ray(Point3f::default(), d, 10.)).unwrap();
+        assert_eq!(inside.0, 0.);
+        let above = ray(Point3f::new(-3., 2., 0.), d, Float::INFINITY);
+        assert_eq!(b.intersect_p(&above), None);
+
+        let inv_dir = Vector3f::new(1. / d.x, 1. / d.y, 1. / d.z);
+        assert!(b.intersect_p_inv(&ray(o, d, Float::INFINITY), &inv_dir, [0, 0, 0]));
+        assert!(!b.intersect_p_inv(&ray(o, d, 1.), &inv_dir, [0, 0, 0]));
+        assert!(!b.intersect_p_inv(&ray(o, -d, Float::INFINITY), &-inv_dir, [1, 0, 0]));
+
+        // A ray grazing the box's edge still hits it.
+        let diagonal = Vector3f::new(1., 1., 0.).normalize();
+        let grazing = ray(Point3f::new(-2., 0., 0.), diagonal, Float::INFINITY);
+        assert!(b.intersect_p(&grazing).is_some());
+    }
+
+    #[test]
+    fn test_pixel_iter() {
+        let b = Bounds2i::new(Point2i::new(1, 2), Point2i::new(3, 4));
+        let pixels: Vec<Point2i> = b.iter().collect();
+        assert_eq!(
+            pixels,
+            vec![
+                Point2i::new(1, 2),
+                Point2i::new(2, 2),
+                Point2i::new(1, 3),
+                Point2i::new(2, 3),
+            ]
+        );
+        assert_eq!((&b).into_iter().count(), b.area() as usize);
+        let empty = Bounds2i::new(Point2i::new(1, 2), Point2i::new(1, 4));
+        assert_eq!(empty.iter().next(), None);
+        assert_eq!(Bounds2i::default().iter().next(), None);
+    }
+
+    #[test]
+    fn test_ray() {
+        let ray = Ray::new(
+            Point3f::new(1., 2., 3.),
+            Vector3f::new(0., 0., 2.),
+            Float::INFINITY,
+            0.5,
+            Some(Arc::new(Medium {})),
+        );
+        assert_eq!(ray.at(0.), ray.o);
+        assert_eq!(ray.at(1.5), Point3f::new(1., 2., 6.));
+        assert_eq!(Ray::default().t_max, Float::INFINITY);
+
+        let mut rd = RayDifferential::from(ray.clone());
+        assert!(!rd.has_differentials);
+        assert_eq!(rd.at(1.), Point3f::new(1., 2., 5.));
+        assert_eq!(rd.time, 0.5);
+        assert!(rd.medium.is_some());
+        rd.has_differentials = true;
+        rd.rx_origin = Point3f::new(2., 2., 3.);
+        rd.ry_origin = Point3f::new(1., 4., 3.);
+        rd.rx_direction = Vector3f::new(1., 0., 2.);
+        rd.ry_direction = Vector3f::new(0., -1., 2.);
+        rd.scale_differentials(0.5);
+        assert_eq!(rd.rx_origin, Point3f::new(1.5, 2., 3.));
+        assert_eq!(rd.ry_origin, Point3f::new(1., 3., 3.));
+        assert_eq!(rd.rx_direction, Vector3f::new(0.5, 0., 2.));
+        assert_eq!(rd.ry_direction, Vector3f::new(0., -0.5, 2.));
+    }
+
+    fn vector3f(range: Float) -> impl Strategy<Value = Vector3f> {
+        (-range..range, -range..range, -range..range).prop_map(|(x, y, z)| Vector3f::new(x, y, z))
+    }
+
+    proptest! {
+        #[test]
+        fn offset_ray_origin_clears_error_box(
+            p in vector3f(1e3),
+            p_error in (1e-6f32..1e-2, 1e-6f32..1e-2, 1e-6f32..1e-2),
+            n in vector3f(1.).prop_filter("non-zero", |n| n.length() > 1e-3),
+            w in vector3f(1.),
+            u in (-1f64..=1., -1f64..=1., -1f64..=1.),
+        ) {
+            let p = Point3f::from(p);
+            let p_error = Vector3f::new(p_error.0, p_error.1, p_error.2);
+            let n = Normal3f::from(n.normalize());
+            let po = offset_ray_origin(&p, &p_error, &n, &w);
+            // q is somewhere the exact p could be; po must be strictly on w's side of the plane
+            // through q.
+            let (u, e) = ([u.0, u.1, u.2], p_error);
+            let side = (0..3)
+                .map(|i| {
+                    let q = f64::from(p[i]) + u[i] * f64::from(e[i]);
+                    (f64::from(po[i]) - q) * f64::from(n[i])
+                })
+                .sum::<f64>();
+            if dot(&w, &n) < 0. {
+                prop_assert!(side < 0.);
+            } else {
+                prop_assert!(side > 0.);
+            }
+        }
+    }
 }
@@ -0,0 +1,1240 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Converts glTF 2.0 files, .gltf or .glb, to Scenes.  Only local files are read: buffers may be
+//! embedded as data URIs, stored in a .glb file's binary chunk, or in files next to the .gltf.
+//!
+//! The first camera found in the node hierarchy becomes the scene's Camera, looking down its -z
+//! axis as glTF cameras do.  Each node becomes an AttributeBegin/AttributeEnd block with its
+//! transform, holding its meshes' primitives as trianglemesh shapes and its KHR_lights_punctual
+//! light as a LightSource.  Metallic-roughness materials become named disney materials, with
+//! imagemap textures for their base color textures.
+//!
+//! Only available with the gltf feature.
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::f32::consts::FRAC_PI_4;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use core::geometry::{Normal3f, Point2f, Point3f};
+use core::paramset::{ParamSet, Value};
+use core::parser::{Directive, Scene};
+use core::pbrt::Float;
+use core::ply::TriangleMesh;
+use core::transform::Matrix4x4;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read.
+    Io(PathBuf, io::Error),
+    /// The glTF JSON couldn't be parsed.
+    Json(serde_json::Error),
+    /// The file isn't valid glTF, or uses something that can't be converted, for the reason
+    /// given.
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::Invalid(why) => write!(f, "invalid glTF file: {}", why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+fn invalid<T>(why: String) -> Result<T, Error> {
+    Err(Error::Invalid(why))
+}
+
+// The parts of the glTF document that are converted.  Fields are named as in the JSON, in snake
+// case.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Gltf {
+    scene: Option<usize>,
+    scenes: Vec<GltfScene>,
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+    materials: Vec<Material>,
+    textures: Vec<Texture>,
+    images: Vec<Image>,
+    cameras: Vec<Camera>,
+    extensions: Extensions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GltfScene {
+    nodes: Vec<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Extensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights_punctual: Option<LightsPunctual>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LightsPunctual {
+    lights: Vec<Light>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Light {
+    #[serde(rename = "type")]
+    kind: String,
+    color: [Float; 3],
+    intensity: Float,
+    spot: Spot,
+}
+
+impl Default for Light {
+    fn default() -> Light {
+        Light {
+            kind: String::new(),
+            color: [1., 1., 1.],
+            intensity: 1.,
+            spot: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Spot {
+    inner_cone_angle: Float,
+    outer_cone_angle: Float,
+}
+
+impl Default for Spot {
+    fn default() -> Spot {
+        Spot {
+            inner_cone_angle: 0.,
+            outer_cone_angle: FRAC_PI_4,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Node {
+    children: Vec<usize>,
+    matrix: Option<[Float; 16]>,
+    translation: Option<[Float; 3]>,
+    rotation: Option<[Float; 4]>,
+    scale: Option<[Float; 3]>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    extensions: NodeExtensions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<NodeLight>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeLight {
+    light: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Mesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: u32,
+}
+
+// Primitive.mode for triangle lists.
+const TRIANGLES: u32 = 4;
+
+impl Default for Primitive {
+    fn default() -> Primitive {
+        Primitive {
+            attributes: HashMap::new(),
+            indices: None,
+            material: None,
+            mode: TRIANGLES,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    byte_offset: usize,
+    component_type: u32,
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Buffer {
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Material {
+    name: Option<String>,
+    pbr_metallic_roughness: Pbr,
+    emissive_factor: [Float; 3],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Pbr {
+    base_color_factor: [Float; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: Float,
+    roughness_factor: Float,
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+impl Default for Pbr {
+    fn default() -> Pbr {
+        Pbr {
+            base_color_factor: [1., 1., 1., 1.],
+            base_color_texture: None,
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+            metallic_roughness_texture: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextureInfo {
+    index: usize,
+    #[serde(default)]
+    tex_coord: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Image {
+    uri: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Camera {
+    #[serde(rename = "type")]
+    kind: String,
+    perspective: Option<Perspective>,
+    orthographic: Option<Orthographic>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Perspective {
+    yfov: Float,
+    aspect_ratio: Option<Float>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Orthographic {
+    xmag: Float,
+    ymag: Float,
+}
+
+/// Decodes %xx escapes in a URI.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = uri
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Decodes standard base64, ignoring padding.
+fn base64_decode(s: &str) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut bits, mut nbits) = (0u32, 0);
+    for c in s.bytes().filter(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return invalid(format!("bad base64 character {:?}", c as char)),
+        };
+        bits = (bits << 6) | u32::from(v);
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            out.push((bits >> nbits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| Error::Io(path.to_path_buf(), e))
+}
+
+/// Returns the matrix for a glTF array of 16 numbers, which lists the columns in turn.
+fn from_columns(a: &[Float; 16]) -> Matrix4x4 {
+    let mut m = Matrix4x4::identity();
+    for (i, v) in a.iter().enumerate() {
+        m.m[i % 4][i / 4] = *v;
+    }
+    m
+}
+
+/// Returns the values of m for ConcatTransform, which like glTF lists the columns in turn.
+fn to_columns(m: &Matrix4x4) -> [Float; 16] {
+    let mut a = [0.; 16];
+    for (i, v) in a.iter_mut().enumerate() {
+        *v = m.m[i % 4][i / 4];
+    }
+    a
+}
+
+impl Node {
+    /// Returns the node's transform, or None if it doesn't have one.
+    fn matrix(&self) -> Option<Matrix4x4> {
+        if let Some(ref m) = self.matrix {
+            return Some(from_columns(m));
+        }
+        if self.translation.is_none() && self.rotation.is_none() && self.scale.is_none() {
+            return None;
+        }
+        let [tx, ty, tz] = self.translation.unwrap_or([0., 0., 0.]);
+        let [x, y, z, w] = self.rotation.unwrap_or([0., 0., 0., 1.]);
+        let [sx, sy, sz] = self.scale.unwrap_or([1., 1., 1.]);
+        // Translation * rotation * scale.
+        Some(Matrix4x4::new(
+            [
+                (1. - 2. * (y * y + z * z)) * sx,
+                2. * (x * y - z * w) * sy,
+                2. * (x * z + y * w) * sz,
+                tx,
+            ],
+            [
+                2. * (x * y + z * w) * sx,
+                (1. - 2. * (x * x + z * z)) * sy,
+                2. * (y * z - x * w) * sz,
+                ty,
+            ],
+            [
+                2. * (x * z - y * w) * sx,
+                2. * (y * z + x * w) * sy,
+                (1. - 2. * (x * x + y * y)) * sz,
+                tz,
+            ],
+            [0., 0., 0., 1.],
+        ))
+    }
+}
+
+/// The state of a conversion.
+struct Converter<'a> {
+    gltf: &'a Gltf,
+    buffers: Vec<Vec<u8>>,
+    dir: &'a Path,
+    // The names of the named materials made for gltf.materials.
+    material_names: Vec<String>,
+    warnings: &'a mut Vec<String>,
+    scene: Scene,
+}
+
+impl<'a> Converter<'a> {
+    fn push(&mut self, d: Directive) {
+        self.scene.push(d, Default::default());
+    }
+
+    /// Returns the values of the accessor at index, with each element's components in turn.
+    /// Normalized integers are converted to floats between 0 and 1, or -1 and 1.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), Error> {
+        let a = match self.gltf.accessors.get(index) {
+            Some(a) => a,
+            None => return invalid(format!("no accessor {}", index)),
+        };
+        if a.sparse.is_some() {
+            return invalid(format!(
+                "accessor {} is sparse, which isn't supported",
+                index
+            ));
+        }
+        let components = match a.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            kind => return invalid(format!("accessor {} has unsupported type {}", index, kind)),
+        };
+        let (size, max) = match a.component_type {
+            5120 => (1, i8::MAX as f64),
+            5121 => (1, u8::MAX as f64),
+            5122 => (2, i16::MAX as f64),
+            5123 => (2, u16::MAX as f64),
+            5125 => (4, u32::MAX as f64),
+            5126 => (4, 1.),
+            t => return invalid(format!("accessor {} has bad component type {}", index, t)),
+        };
+        let too_large = || invalid(format!("accessor {} is too large", index));
+        let len = match a.count.checked_mul(components) {
+            Some(len) => len,
+            None => return too_large(),
+        };
+        let (view_index, view) = match a.buffer_view {
+            Some(view) => match self.gltf.buffer_views.get(view) {
+                Some(v) => (view, v),
+                None => return invalid(format!("no buffer view {}", view)),
+            },
+            // Accessors without a buffer view are all zeros.
+            None => {
+                let mut values = Vec::new();
+                if values.try_reserve_exact(len).is_err() {
+                    return too_large();
+                }
+                values.resize(len, 0.);
+                return Ok((values, components));
+            }
+        };
+        let view_end = match view.byte_offset.checked_add(view.byte_length) {
+            Some(end) => end,
+            None => return invalid(format!("buffer view {} is too large", view_index)),
+        };
+        let data = match self.buffers.get(view.buffer) {
+            Some(buffer) => buffer.get(view.byte_offset..view_end).unwrap_or(&[]),
+            None => return invalid(format!("no buffer {}", view.buffer)),
+        };
+        let stride = view.byte_stride.unwrap_or(components * size);
+        if stride < components * size {
+            return invalid(format!(
+                "buffer view {} has stride {}, less than the size of accessor {}'s elements",
+                view_index, stride, index
+            ));
+        }
+        let end = stride
+            .checked_mul(a.count.saturating_sub(1))
+            .and_then(|end| end.checked_add(a.byte_offset))
+            .and_then(|end| end.checked_add(components * size));
+        match end {
+            _ if a.count == 0 => (),
+            Some(end) if end <= data.len() => (),
+            _ => return invalid(format!("accessor {} is past the end of its buffer", index)),
+        }
+        let mut values = Vec::with_capacity(len);
+        for i in 0..a.count {
+            for c in 0..components {
+                let o = a.byte_offset + i * stride + c * size;
+                let b = &data[o..o + size];
+                let v = match a.component_type {
+                    5120 => f64::from(b[0] as i8),
+                    5121 => f64::from(b[0]),
+                    5122 => f64::from(i16::from_le_bytes([b[0], b[1]])),
+                    5123 => f64::from(u16::from_le_bytes([b[0], b[1]])),
+                    5125 => f64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    _ => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                };
+                values.push(if a.normalized { (v / max).max(-1.) } else { v });
+            }
+        }
+        Ok((values, components))
+    }
+
+    /// Returns the values of the accessor at index, checking each element has n components.
+    fn vectors(&self, index: usize, n: usize) -> Result<Vec<f64>, Error> {
+        let (values, components) = self.accessor(index)?;
+        if components != n {
+            return invalid(format!(
+                "accessor {} has {} components, expected {}",
+                index, components, n
+            ));
+        }
+        Ok(values)
+    }
+
+    /// Returns the filename of the image used by texture, or None with a warning if it can't be
+    /// used.
+    fn image_file(&mut self, texture: &TextureInfo) -> Option<String> {
+        let uri = self
+            .gltf
+            .textures
+            .get(texture.index)
+            .and_then(|t| t.source)
+            .and_then(|i| self.gltf.images.get(i))
+            .and_then(|image| image.uri.as_ref());
+        match uri {
+            Some(uri) if !uri.starts_with("data:") => {
+                if texture.tex_coord != 0 {
+                    self.warnings.push(format!(
+                        "texture {} uses texture coordinates {}, only 0 is supported",
+                        texture.index, texture.tex_coord
+                    ));
+                }
+                Some(
+                    self.dir
+                        .join(percent_decode(uri))
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            }
+            _ => {
+                self.warnings.push(format!(
+                    "texture {} isn't an image file, ignoring it",
+                    texture.index
+                ));
+                None
+            }
+        }
+    }
+
+    /// Defines a named material, and its texture, for each glTF material.
+    fn materials(&mut self) {
+        let mut used = HashSet::new();
+        for (i, m) in self.gltf.materials.iter().enumerate() {
+            let name = match m.name {
+                Some(ref name) if !used.contains(name) => name.clone(),
+                _ => format!("material{}", i),
+            };
+            used.insert(name.clone());
+            let pbr = &m.pbr_metallic_roughness;
+            let [r, g, b, _] = pbr.base_color_factor;
+            let mut ps = ParamSet::default();
+            ps.add("type", Value::String(vec!["disney".to_owned()].into()));
+            let filename = pbr
+                .base_color_texture
+                .as_ref()
+                .and_then(|t| self.image_file(t));
+            match filename {
+                Some(filename) => {
+                    let texture = format!("{}-color", name);
+                    let mut tps = ParamSet::default();
+                    tps.add("filename", Value::String(vec![filename].into()));
+                    let image = Directive::Texture(
+                        texture.clone(),
+                        "spectrum".to_owned(),
+                        "imagemap".to_owned(),
+                        tps,
+                    );
+                    self.push(image);
+                    if [r, g, b] == [1., 1., 1.] {
+                        ps.add("color", Value::Texture(vec![texture].into()));
+                    } else {
+                        // The base color is the texture scaled by the factor.
+                        let scaled = format!("{}-scaled", texture);
+                        let mut sps = ParamSet::default();
+                        sps.add("tex1", Value::Texture(vec![texture].into()));
+                        sps.add("tex2", Value::RGB(vec![r, g, b].into()));
+                        self.push(Directive::Texture(
+                            scaled.clone(),
+                            "spectrum".to_owned(),
+                            "scale".to_owned(),
+                            sps,
+                        ));
+                        ps.add("color", Value::Texture(vec![scaled].into()));
+                    }
+                }
+                None => ps.add("color", Value::RGB(vec![r, g, b].into())),
+            }
+            if pbr.metallic_roughness_texture.is_some() {
+                self.warnings.push(format!(
+                    "material {}'s metallic-roughness texture isn't supported, using its factors",
+                    name
+                ));
+            }
+            ps.add("metallic", Value::Float(vec![pbr.metallic_factor].into()));
+            ps.add("roughness", Value::Float(vec![pbr.roughness_factor].into()));
+            self.push(Directive::MakeNamedMaterial(name.clone(), ps));
+            self.material_names.push(name);
+        }
+    }
+
+    /// Returns the mesh of a primitive.
+    fn mesh(&self, p: &Primitive) -> Result<TriangleMesh, Error> {
+        let position = match p.attributes.get("POSITION") {
+            Some(&position) => self.vectors(position, 3)?,
+            None => return invalid("primitive without POSITION".to_owned()),
+        };
+        let mut mesh = TriangleMesh {
+            p: position
+                .chunks(3)
+                .map(|v| Point3f {
+                    x: v[0] as Float,
+                    y: v[1] as Float,
+                    z: v[2] as Float,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        if let Some(&normal) = p.attributes.get("NORMAL") {
+            mesh.n = self
+                .vectors(normal, 3)?
+                .chunks(3)
+                .map(|v| Normal3f {
+                    x: v[0] as Float,
+                    y: v[1] as Float,
+                    z: v[2] as Float,
+                })
+                .collect();
+        }
+        if let Some(&uv) = p.attributes.get("TEXCOORD_0") {
+            mesh.uv = self
+                .vectors(uv, 2)?
+                .chunks(2)
+                .map(|v| Point2f {
+                    x: v[0] as Float,
+                    y: v[1] as Float,
+                })
+                .collect();
+        }
+        mesh.indices = match p.indices {
+            Some(indices) => self
+                .vectors(indices, 1)?
+                .into_iter()
+                .map(|i| i as usize)
+                .collect(),
+            None => (0..mesh.p.len()).collect(),
+        };
+        // is_multiple_of is too new for the toolchains this builds with.
+        #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+        if mesh.indices.len() % 3 != 0 {
+            return invalid(format!(
+                "{} indices isn't a whole number of triangles",
+                mesh.indices.len()
+            ));
+        }
+        if let Some(&i) = mesh.indices.iter().find(|&&i| i >= mesh.p.len()) {
+            return invalid(format!(
+                "vertex index {} out of range, there are {} vertices",
+                i,
+                mesh.p.len()
+            ));
+        }
+        for (name, len) in &[("NORMAL", mesh.n.len()), ("TEXCOORD_0", mesh.uv.len())] {
+            if *len != 0 && *len != mesh.p.len() {
+                return invalid(format!(
+                    "{} {} values for {} vertices",
+                    len,
+                    name,
+                    mesh.p.len()
+                ));
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn primitive(&mut self, p: &Primitive) -> Result<(), Error> {
+        if p.mode != TRIANGLES {
+            self.warnings.push(format!(
+                "ignoring primitive with mode {}, only triangles are supported",
+                p.mode
+            ));
+            return Ok(());
+        }
+        let mesh = self.mesh(p)?;
+        let material = match p.material {
+            Some(m) => match self.gltf.materials.get(m) {
+                Some(material) => Some((material, self.material_names[m].clone())),
+                None => return invalid(format!("no material {}", m)),
+            },
+            None => None,
+        };
+        let emission = material
+            .as_ref()
+            .map_or([0.; 3], |(m, _)| m.emissive_factor);
+        let emissive = emission != [0.; 3];
+        if emissive {
+            self.push(Directive::AttributeBegin);
+            let mut ps = ParamSet::default();
+            ps.add("L", Value::RGB(emission.to_vec().into()));
+            self.push(Directive::AreaLightSource("diffuse".to_owned(), ps));
+        }
+        match material {
+            Some((_, name)) => self.push(Directive::NamedMaterial(name)),
+            None => {
+                // glTF's default material.
+                let mut ps = ParamSet::default();
+                ps.add("color", Value::RGB(vec![1., 1., 1.].into()));
+                ps.add("metallic", Value::Float(vec![1.].into()));
+                ps.add("roughness", Value::Float(vec![1.].into()));
+                self.push(Directive::Material("disney".to_owned(), ps));
+            }
+        }
+        self.push(Directive::Shape(
+            "trianglemesh".to_owned(),
+            ParamSet::from(mesh),
+        ));
+        if emissive {
+            self.push(Directive::AttributeEnd);
+        }
+        Ok(())
+    }
+
+    fn light(&mut self, index: usize) -> Result<(), Error> {
+        let light = match self
+            .gltf
+            .extensions
+            .lights_punctual
+            .as_ref()
+            .and_then(|l| l.lights.get(index))
+        {
+            Some(light) => light,
+            None => return invalid(format!("no light {}", index)),
+        };
+        let color: Vec<Float> = light.color.iter().map(|c| c * light.intensity).collect();
+        // Lights shine down their node's -z axis.
+        let from = Value::Point3f(
+            vec![Point3f {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            }]
+            .into(),
+        );
+        let to = Value::Point3f(
+            vec![Point3f {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            }]
+            .into(),
+        );
+        let mut ps = ParamSet::default();
+        let name = match light.kind.as_str() {
+            "point" => {
+                ps.add("I", Value::RGB(color.into()));
+                "point"
+            }
+            "spot" => {
+                let outer = light.spot.outer_cone_angle.to_degrees();
+                let inner = light.spot.inner_cone_angle.to_degrees();
+                ps.add("I", Value::RGB(color.into()));
+                ps.add("from", from);
+                ps.add("to", to);
+                ps.add("coneangle", Value::Float(vec![outer].into()));
+                ps.add("conedeltaangle", Value::Float(vec![outer - inner].into()));
+                "spot"
+            }
+            "directional" => {
+                ps.add("L", Value::RGB(color.into()));
+                ps.add("from", from);
+                ps.add("to", to);
+                "distant"
+            }
+            kind => {
+                self.warnings.push(format!(
+                    "ignoring light {} of unknown type \"{}\"",
+                    index, kind
+                ));
+                return Ok(());
+            }
+        };
+        self.push(Directive::LightSource(name.to_owned(), ps));
+        Ok(())
+    }
+
+    /// Returns whether each node or its descendants have anything to convert, after checking
+    /// that the nodes form trees, as glTF requires, so they can be walked without checking for
+    /// cycles or nodes reached twice.
+    fn has_contents(&self) -> Result<Vec<bool>, Error> {
+        let nodes = &self.gltf.nodes;
+        let mut parents = vec![None; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            for &child in &node.children {
+                match parents.get_mut(child) {
+                    Some(Some(_)) => {
+                        return invalid(format!("node {} has more than one parent", child))
+                    }
+                    Some(parent) => *parent = Some(i),
+                    None => return invalid(format!("no node {}", child)),
+                }
+            }
+        }
+        // With at most one parent each, the nodes are trees unless following the parents of
+        // some node leads back to it.  Each node is followed from once.
+        let mut followed = vec![false; nodes.len()];
+        for i in 0..nodes.len() {
+            let mut path = HashSet::new();
+            let mut n = Some(i);
+            while let Some(j) = n.filter(|&j| !followed[j]) {
+                if !path.insert(j) {
+                    return invalid(format!("node {} is its own descendant", j));
+                }
+                n = parents[j];
+            }
+            for j in path {
+                followed[j] = true;
+            }
+        }
+        let mut has_contents = vec![false; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            if node.mesh.is_some() || node.extensions.light.is_some() {
+                let mut n = Some(i);
+                while let Some(j) = n.filter(|&j| !has_contents[j]) {
+                    has_contents[j] = true;
+                    n = parents[j];
+                }
+            }
+        }
+        Ok(has_contents)
+    }
+
+    /// Adds the node at index, and its descendants that have_contents.
+    fn node(&mut self, index: usize, has_contents: &[bool]) -> Result<(), Error> {
+        let gltf = self.gltf;
+        // None ends the attribute block of the node whose children follow it on the stack.
+        let mut stack = vec![Some(index)];
+        while let Some(index) = stack.pop() {
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    self.push(Directive::AttributeEnd);
+                    continue;
+                }
+            };
+            let node = match gltf.nodes.get(index) {
+                Some(node) => node,
+                None => return invalid(format!("no node {}", index)),
+            };
+            if !has_contents[index] {
+                continue;
+            }
+            self.push(Directive::AttributeBegin);
+            if let Some(m) = node.matrix() {
+                self.push(Directive::ConcatTransform(to_columns(&m)));
+            }
+            if let Some(ref light) = node.extensions.light {
+                self.light(light.light)?;
+            }
+            if let Some(mesh) = node.mesh {
+                let mesh = match gltf.meshes.get(mesh) {
+                    Some(mesh) => mesh,
+                    None => return invalid(format!("no mesh {}", mesh)),
+                };
+                for p in &mesh.primitives {
+                    self.primitive(p)?;
+                }
+            }
+            stack.push(None);
+            stack.extend(node.children.iter().rev().map(|&c| Some(c)));
+        }
+        Ok(())
+    }
+
+    /// Returns the first camera found under the node at index, and its camera-to-world
+    /// transform, with parent the transform of the node's parent.
+    fn find_camera(&self, index: usize, parent: Matrix4x4) -> Option<(&'a Camera, Matrix4x4)> {
+        let gltf = self.gltf;
+        let mut stack = vec![(index, parent)];
+        while let Some((index, parent)) = stack.pop() {
+            let node = match gltf.nodes.get(index) {
+                Some(node) => node,
+                None => continue,
+            };
+            let m = match node.matrix() {
+                Some(m) => parent * m,
+                None => parent,
+            };
+            if let Some(camera) = node.camera.and_then(|c| gltf.cameras.get(c)) {
+                return Some((camera, m));
+            }
+            stack.extend(node.children.iter().rev().map(|&c| (c, m)));
+        }
+        None
+    }
+
+    fn camera(&mut self, camera: &Camera, camera_to_world: Matrix4x4) {
+        let mut ps = ParamSet::default();
+        let name = match (
+            camera.kind.as_str(),
+            &camera.perspective,
+            &camera.orthographic,
+        ) {
+            ("perspective", Some(p), _) => {
+                // pbrt's field of view is of the image's shorter axis.
+                let aspect = p.aspect_ratio.unwrap_or(1.);
+                let fov = if aspect < 1. {
+                    2. * ((p.yfov / 2.).tan() * aspect).atan()
+                } else {
+                    p.yfov
+                };
+                ps.add("fov", Value::Float(vec![fov.to_degrees()].into()));
+                "perspective"
+            }
+            ("orthographic", _, Some(o)) => {
+                let window = vec![-o.xmag, o.xmag, -o.ymag, o.ymag];
+                ps.add("screenwindow", Value::Float(window.into()));
+                "orthographic"
+            }
+            (kind, _, _) => {
+                self.warnings
+                    .push(format!("ignoring camera of unknown type \"{}\"", kind));
+                return;
+            }
+        };
+        // glTF cameras look down -z, and pbrt's down +z.
+        self.push(Directive::Scale(1., 1., -1.));
+        let world_to_camera = camera_to_world.inverse();
+        self.push(Directive::ConcatTransform(to_columns(&world_to_camera)));
+        self.push(Directive::Camera(name.to_owned(), ps));
+    }
+
+    fn convert(mut self) -> Result<Scene, Error> {
+        let gltf = self.gltf;
+        let roots: Vec<usize> = match gltf.scenes.get(gltf.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => {
+                // Without scenes, every node that isn't a child is a root.
+                let children: HashSet<usize> =
+                    gltf.nodes.iter().flat_map(|n| n.children.clone()).collect();
+                (0..gltf.nodes.len())
+                    .filter(|i| !children.contains(i))
+                    .collect()
+            }
+        };
+        let has_contents = self.has_contents()?;
+        let camera = roots
+            .iter()
+            .filter_map(|&r| self.find_camera(r, Matrix4x4::identity()))
+            .next();
+        if let Some((camera, camera_to_world)) = camera {
+            self.camera(camera, camera_to_world);
+        }
+        self.push(Directive::WorldBegin);
+        self.materials();
+        for root in roots {
+            self.node(root, &has_contents)?;
+        }
+        self.push(Directive::WorldEnd);
+        Ok(self.scene)
+    }
+}
+
+// The magic number and chunk types of .glb files.
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Splits a .glb file into its JSON and binary chunks.
+fn glb_chunks(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), Error> {
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset < data.len() {
+        let (length, kind) = match (u32_at(data, offset), u32_at(data, offset + 4)) {
+            (Some(length), Some(kind)) => (length as usize, kind),
+            _ => return invalid("truncated chunk header".to_owned()),
+        };
+        let end = (offset + 8).checked_add(length);
+        let chunk = match end.and_then(|end| data.get(offset + 8..end)) {
+            Some(chunk) => chunk,
+            None => return invalid("truncated chunk".to_owned()),
+        };
+        match kind {
+            GLB_JSON if json.is_none() => json = Some(chunk),
+            GLB_BIN if bin.is_none() => bin = Some(chunk),
+            _ => (),
+        }
+        offset += 8 + chunk.len();
+    }
+    match json {
+        Some(json) => Ok((json, bin)),
+        None => invalid("no JSON chunk".to_owned()),
+    }
+}
+
+/// Converts the glTF file in data, which may be .gltf JSON or a .glb binary.  Buffers and images
+/// in other files are found in dir.  Warnings are added for anything that isn't converted.
+pub fn read(data: &[u8], dir: &Path, warnings: &mut Vec<String>) -> Result<Scene, Error> {
+    let (json, mut bin) = if data.starts_with(GLB_MAGIC) {
+        glb_chunks(data)?
+    } else {
+        (data, None)
+    };
+    let gltf: Gltf = serde_json::from_slice(json).map_err(Error::Json)?;
+    let mut buffers = Vec::new();
+    for (i, buffer) in gltf.buffers.iter().enumerate() {
+        let data = match buffer.uri {
+            // Only the first buffer may refer to the binary chunk.
+            None if i == 0 => match bin.take() {
+                Some(bin) => bin.to_vec(),
+                None => {
+                    return invalid("buffer 0 has no uri and there's no binary chunk".to_owned())
+                }
+            },
+            None => return invalid(format!("buffer {} has no uri", i)),
+            Some(ref uri) if uri.starts_with("data:") => match uri.find(";base64,") {
+                Some(start) => base64_decode(&uri[start + ";base64,".len()..])?,
+                None => return invalid(format!("buffer {} has a data URI that isn't base64", i)),
+            },
+            Some(ref uri) => read_bytes(&dir.join(percent_decode(uri)))?,
+        };
+        if data.len() < buffer.byte_length {
+            return invalid(format!(
+                "buffer {} has {} bytes, expected {}",
+                i,
+                data.len(),
+                buffer.byte_length
+            ));
+        }
+        buffers.push(data);
+    }
+    Converter {
+        gltf: &gltf,
+        buffers,
+        dir,
+        material_names: Vec::new(),
+        warnings,
+        scene: Scene::default(),
+    }
+    .convert()
+}
+
+/// Converts the .gltf or .glb file at path.  See read.
+pub fn read_file<P: AsRef<Path>>(path: P, warnings: &mut Vec<String>) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    read(&read_bytes(path)?, dir, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::lint::lint;
+    use core::parser::parse_scene;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/core/testdata/gltf")
+            .join(name)
+    }
+
+    #[test]
+    fn test_read_file() {
+        let want = parse_scene(
+            format!(
+                r#"Scale 1 1 -1
+ConcatTransform [1 0 0 0  0 1 0 0  0 0 1 0  0 0 -5 1]
+Camera "perspective" "float fov" {}
+WorldBegin
+Texture "textured-color" "spectrum" "imagemap" "string filename" "{}"
+MakeNamedMaterial "textured" "string type" "disney" "texture color" "textured-color"
+    "float metallic" 0 "float roughness" 0.5
+MakeNamedMaterial "plain" "string type" "disney" "rgb color" [1 0 0]
+    "float metallic" 1 "float roughness" 1
+AttributeBegin
+  ConcatTransform [1 0 0 0  0 1 0 0  0 0 1 0  0 0 -5 1]
+  AttributeBegin
+    ConcatTransform [2 0 0 0  0 2 0 0  0 0 2 0  0 0 0 1]
+    NamedMaterial "textured"
+    Shape "trianglemesh" "integer indices" [0 1 2] "point3 P" [0 0 0 1 0 0 0 1 0]
+        "normal N" [0 0 1 0 0 1 0 0 1] "point2 uv" [0 0 1 0 0 1]
+    NamedMaterial "plain"
+    Shape "trianglemesh" "integer indices" [0 1 2] "point3 P" [0 0 0 1 0 0 0 1 0]
+        "normal N" [0 0 1 0 0 1 0 0 1]
+  AttributeEnd
+  AttributeBegin
+    LightSource "point" "rgb I" [4 2 1]
+  AttributeEnd
+AttributeEnd
+WorldEnd
+"#,
+                FRAC_PI_4.to_degrees(),
+                testdata("checker board.png").display()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        for name in &["triangle.gltf", "triangle_bin.gltf", "triangle.glb"] {
+            let mut warnings = Vec::new();
+            let scene = read_file(testdata(name), &mut warnings).unwrap();
+            assert_eq!(scene, want, "reading {}", name);
+            assert!(warnings.is_empty(), "reading {}: {:?}", name, warnings);
+            assert_eq!(lint(&scene), vec![], "reading {}", name);
+        }
+    }
+
+    #[test]
+    fn test_node_matrix() {
+        let node = Node {
+            translation: Some([1., 2., 3.]),
+            // 90 degrees about z.
+            rotation: Some([0., 0., FRAC_PI_4.sin(), FRAC_PI_4.cos()]),
+            scale: Some([2., 2., 2.]),
+            ..Default::default()
+        };
+        let want = Matrix4x4::new(
+            [0., -2., 0., 1.],
+            [2., 0., 0., 2.],
+            [0., 0., 2., 3.],
+            [0., 0., 0., 1.],
+        );
+        assert_eq!(node.matrix(), Some(want));
+        assert_eq!(
+            from_columns(&to_columns(&want)).m,
+            want.m,
+            "columns round trip"
+        );
+        assert_eq!(Node::default().matrix(), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(base64_decode("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64_decode("AAEC/w==").unwrap(), vec![0, 1, 2, 255]);
+        assert_eq!(percent_decode("a%20b%zz"), "a b%zz");
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = vec![
+            (
+                r#"{"nodes": [{"mesh": 0}], "meshes": [{"primitives": [{"attributes": {}}]}]}"#,
+                "invalid glTF file: primitive without POSITION",
+            ),
+            (
+                r#"{"nodes": [{"children": [0], "mesh": 0}], "meshes": [{"primitives": []}],
+                    "scenes": [{"nodes": [0]}]}"#,
+                "invalid glTF file: node 0 is its own descendant",
+            ),
+            (
+                r#"{"nodes": [{"children": [1]}, {"children": [2]}, {"children": [0], "mesh": 0}],
+                    "meshes": [{"primitives": []}], "scenes": [{"nodes": [0]}]}"#,
+                "invalid glTF file: node 0 is its own descendant",
+            ),
+            (
+                r#"{"nodes": [{"children": [2]}, {"children": [2]}, {"mesh": 0}],
+                    "meshes": [{"primitives": []}]}"#,
+                "invalid glTF file: node 2 has more than one parent",
+            ),
+            (
+                r#"{"nodes": [{"mesh": 0}],
+                    "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+                    "accessors": [{"count": 18446744073709551615, "type": "VEC3",
+                                   "componentType": 5126}]}"#,
+                "invalid glTF file: accessor 0 is too large",
+            ),
+            (
+                r#"{"nodes": [{"mesh": 0}],
+                    "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+                    "accessors": [{"bufferView": 0, "count": 4611686018427387904, "type": "VEC3",
+                                   "componentType": 5126}],
+                    "bufferViews": [{"buffer": 0, "byteLength": 1}],
+                    "buffers": [{"uri": "data:application/octet-stream;base64,AA==",
+                                 "byteLength": 1}]}"#,
+                "invalid glTF file: accessor 0 is past the end of its buffer",
+            ),
+            (
+                r#"{"nodes": [{"mesh": 0}],
+                    "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+                    "accessors": [{"bufferView": 0, "count": 1, "type": "VEC3",
+                                   "componentType": 5126}],
+                    "bufferViews": [{"buffer": 0, "byteOffset": 18446744073709551615,
+                                     "byteLength": 1}],
+                    "buffers": [{"uri": "data:application/octet-stream;base64,AA==",
+                                 "byteLength": 1}]}"#,
+                "invalid glTF file: buffer view 0 is too large",
+            ),
+            (
+                r#"{"nodes": [{"mesh": 0}],
+                    "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+                    "accessors": [{"bufferView": 0, "count": 2, "type": "VEC3",
+                                   "componentType": 5126}],
+                    "bufferViews": [{"buffer": 0, "byteLength": 1, "byteStride": 0}],
+                    "buffers": [{"uri": "data:application/octet-stream;base64,AA==",
+                                 "byteLength": 1}]}"#,
+                "invalid glTF file: buffer view 0 has stride 0, less than the size of accessor \
+                 0's elements",
+            ),
+            (
+                r#"{"buffers": [{"uri": "data:application/octet-stream;base64,AA==", "byteLength": 4}]}"#,
+                "invalid glTF file: buffer 0 has 1 bytes, expected 4",
+            ),
+        ];
+        for (gltf, want) in errors {
+            match read(gltf.as_bytes(), Path::new(""), &mut Vec::new()) {
+                Err(e) => assert_eq!(e.to_string(), want),
+                Ok(scene) => panic!("expected {:?} reading {}, got {:?}", want, gltf, scene),
+            }
+        }
+
+        // A .glb whose JSON chunk claims the largest possible length.
+        let mut glb = b"glTF\x02\0\0\0\x1c\0\0\0".to_vec();
+        glb.extend(&u32::MAX.to_le_bytes());
+        glb.extend(&GLB_JSON.to_le_bytes());
+        glb.extend(b"{}\n\n");
+        let e = read(&glb, Path::new(""), &mut Vec::new()).unwrap_err();
+        assert_eq!(e.to_string(), "invalid glTF file: truncated chunk");
+    }
+}
@@ -0,0 +1,434 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Splits pbrt scene files into tokens for the parser.  Comments and whitespace are dropped,
+//! quoted strings have their escapes processed, and numbers are converted as they're read.
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+
+use core::parser::Location;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// An unquoted word: directive names, and values like `true` and `StartTime`.
+    Keyword(String),
+    /// A quoted string with the quotes removed.
+    String(String),
+    Number(f64),
+    OpenBracket,
+    CloseBracket,
+}
+
+/// A token and where it was found.  end is the location just past its last byte.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: Location,
+    pub end: Location,
+}
+
+/// Returned when the input can't be split into tokens.
+#[derive(Debug)]
+pub enum LexError {
+    /// expected is what the lexer wanted to find at location, in the same form as the parser's
+    /// syntax errors.
+    Syntax {
+        location: Location,
+        expected: String,
+    },
+    Io(io::Error),
+}
+
+impl From<io::Error> for LexError {
+    fn from(e: io::Error) -> Self {
+        LexError::Io(e)
+    }
+}
+
+/// The most text kept of a line for error messages.  Inline meshes are often written as a single
+/// line many megabytes long, and only its start is worth showing.
+const MAX_LINE_TEXT: usize = 1024;
+
+/// Lexer is an iterator over the tokens read from a BufRead.  It keeps the text of the lines it
+/// has read, for error messages, until told to forget them with forget_lines_before or
+/// forget_lines_after.
+pub struct Lexer<R> {
+    input: R,
+    location: Location,
+    /// Complete lines that haven't been forgotten, with their line numbers, in order.
+    lines: VecDeque<(usize, Vec<u8>)>,
+    /// The line being read, truncated to MAX_LINE_TEXT bytes and "...".
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    pub fn new(input: R) -> Lexer<R> {
+        Lexer {
+            input,
+            location: Location { line: 1, column: 1 },
+            lines: VecDeque::new(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Returns the location just past the last byte read.
+    pub fn location(&self) -> Location {
+        self.location
+    }
+
+    /// Drops the text of lines before line n.
+    pub fn forget_lines_before(&mut self, n: usize) {
+        while self.lines.front().is_some_and(|(l, _)| *l < n) {
+            self.lines.pop_front();
+        }
+    }
+
+    /// Drops the text of the complete lines after line n.  The line being read is kept.
+    pub fn forget_lines_after(&mut self, n: usize) {
+        while self.lines.back().is_some_and(|(l, _)| *l > n) {
+            self.lines.pop_back();
+        }
+    }
+
+    /// Returns the text of line n, without its line ending, or an empty string if it has been
+    /// forgotten.  If n is the line being read, the rest of it is read first, so this should only
+    /// be used once lexing is finished, e.g. to report an error.
+    pub fn line(&mut self, n: usize) -> String {
+        if n == self.location.line {
+            // Errors here only shorten the snippet.
+            while let Ok(Some(c)) = self.peek() {
+                if c == b'\n' {
+                    break;
+                }
+                let _ = self.bump();
+            }
+        }
+        let line = if n == self.location.line {
+            Some(&self.line)
+        } else {
+            self.lines.iter().find(|(l, _)| *l == n).map(|(_, text)| text)
+        };
+        line.map_or_else(String::new, |l| {
+            String::from_utf8_lossy(l).trim_end().to_owned()
+        })
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => return Ok(buf.first().cloned()),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn bump(&mut self) -> io::Result<Option<u8>> {
+        let c = match self.peek()? {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        self.input.consume(1);
+        if c == b'\n' {
+            let line = std::mem::take(&mut self.line);
+            self.lines.push_back((self.location.line, line));
+            self.location.line += 1;
+            self.location.column = 1;
+        } else {
+            self.location.column += 1;
+            if self.line.len() < MAX_LINE_TEXT {
+                self.line.push(c);
+            } else if self.line.len() == MAX_LINE_TEXT {
+                self.line.extend_from_slice(b"...");
+            }
+        }
+        Ok(Some(c))
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> io::Result<()> {
+        while let Some(c) = self.peek()? {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' => {
+                    self.bump()?;
+                }
+                b'#' => {
+                    while let Some(c) = self.bump()? {
+                        if c == b'\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, start: Location) -> Result<TokenKind, LexError> {
+        // Opening quote.
+        self.bump()?;
+        let mut s = Vec::new();
+        loop {
+            let location = self.location;
+            match self.bump()? {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = match self.bump()? {
+                        Some(b'b') => b'\x08',
+                        Some(b'f') => b'\x0c',
+                        Some(b'n') => b'\n',
+                        Some(b'r') => b'\r',
+                        Some(b't') => b'\t',
+                        Some(c @ b'\\') | Some(c @ b'\'') | Some(c @ b'"') => c,
+                        _ => {
+                            return Err(LexError::Syntax {
+                                location,
+                                expected: "escape sequence".to_owned(),
+                            })
+                        }
+                    };
+                    s.push(c);
+                }
+                Some(b'\n') | None => {
+                    return Err(LexError::Syntax {
+                        location,
+                        expected: format!("'\"' to close string started at {}", start),
+                    })
+                }
+                Some(c) => s.push(c),
+            }
+        }
+        Ok(TokenKind::String(String::from_utf8_lossy(&s).into_owned()))
+    }
+
+    fn word(&mut self, start: Location) -> Result<TokenKind, LexError> {
+        let mut word = Vec::new();
+        while let Some(c) = self.peek()? {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' | b'"' | b'[' | b']' | b'#' => break,
+                _ => {
+                    self.bump()?;
+                    word.push(c);
+                }
+            }
+        }
+        let word = String::from_utf8_lossy(&word);
+        match word.as_bytes()[0] {
+            b'0'..=b'9' | b'-' | b'+' | b'.' => {
+                word.parse().map(TokenKind::Number).map_err(|_| LexError::Syntax {
+                    location: start,
+                    expected: "number".to_owned(),
+                })
+            }
+            _ => Ok(TokenKind::Keyword(word.into_owned())),
+        }
+    }
+
+    fn token(&mut self) -> Result<Option<Token>, LexError> {
+        self.skip_whitespace_and_comments()?;
+        let start = self.location;
+        let kind = match self.peek()? {
+            None => return Ok(None),
+            Some(b'[') => {
+                self.bump()?;
+                TokenKind::OpenBracket
+            }
+            Some(b']') => {
+                self.bump()?;
+                TokenKind::CloseBracket
+            }
+            Some(b'"') => self.string(start)?,
+            Some(_) => self.word(start)?,
+        };
+        Ok(Some(Token {
+            kind,
+            start,
+            end: self.location,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Result<Token, LexError>> {
+        match self.token() {
+            Ok(Some(t)) => Some(Ok(t)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &[u8]) -> Vec<TokenKind> {
+        Lexer::new(input).map(|t| t.unwrap().kind).collect()
+    }
+
+    /// Returns the location and expectation of the first error lexing input.
+    fn error(input: &[u8]) -> (Location, String) {
+        for t in Lexer::new(input) {
+            match t {
+                Ok(_) => (),
+                Err(LexError::Syntax { location, expected }) => return (location, expected),
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+        }
+        panic!("no error lexing {:?}", input);
+    }
+
+    fn keyword(s: &str) -> TokenKind {
+        TokenKind::Keyword(s.to_owned())
+    }
+
+    fn string(s: &str) -> TokenKind {
+        TokenKind::String(s.to_owned())
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            kinds(&b"Shape \"sphere\" \"float radius\" [1]"[..]),
+            vec![
+                keyword("Shape"),
+                string("sphere"),
+                string("float radius"),
+                TokenKind::OpenBracket,
+                TokenKind::Number(1.),
+                TokenKind::CloseBracket,
+            ]
+        );
+        // Brackets and strings don't need surrounding whitespace.
+        assert_eq!(
+            kinds(&b"[\"a\"\"b\"]true"[..]),
+            vec![
+                TokenKind::OpenBracket,
+                string("a"),
+                string("b"),
+                TokenKind::CloseBracket,
+                keyword("true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(
+            kinds(&b"3.25 .1 0.2 3. 4 -1 +2 1e3 -2.5E-1"[..]),
+            vec![3.25, 0.1, 0.2, 3., 4., -1., 2., 1000., -0.25]
+                .into_iter()
+                .map(TokenKind::Number)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            error(&b"Translate 1.2.3"[..]),
+            (Location { line: 1, column: 11 }, "number".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            kinds(&b"a # comment\nb # comment\n"[..]),
+            vec![keyword("a"), keyword("b")]
+        );
+        // A comment on the last line doesn't need a newline.
+        assert_eq!(kinds(&b"a\nb # comment"[..]), vec![keyword("a"), keyword("b")]);
+        assert_eq!(
+            kinds(&b"[ 1 # comment\n2 3]\n"[..]),
+            vec![
+                TokenKind::OpenBracket,
+                TokenKind::Number(1.),
+                TokenKind::Number(2.),
+                TokenKind::Number(3.),
+                TokenKind::CloseBracket,
+            ]
+        );
+        // A comment can follow a token without whitespace.
+        assert_eq!(kinds(&b"a#comment"[..]), vec![keyword("a")]);
+    }
+
+    #[test]
+    fn test_strings() {
+        // '#' in a string isn't a comment.
+        assert_eq!(
+            kinds(&b"\"string filename\" \"textures/#1.png\""[..]),
+            vec![string("string filename"), string("textures/#1.png")]
+        );
+        assert_eq!(
+            kinds(&br#""a \"quoted\" \\ \t""#[..]),
+            vec![string("a \"quoted\" \\ \t")]
+        );
+        assert_eq!(
+            error(&b"Shape \"sphere"[..]),
+            (
+                Location { line: 1, column: 14 },
+                "'\"' to close string started at 1:7".to_owned()
+            )
+        );
+        assert_eq!(
+            error(&b"\"a\\q\""[..]),
+            (Location { line: 1, column: 3 }, "escape sequence".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_locations() {
+        let tokens: Vec<_> = Lexer::new(&b"WorldBegin\n  Translate 1 2 3 # c\n"[..])
+            .map(|t| {
+                let t = t.unwrap();
+                ((t.start.line, t.start.column), (t.end.line, t.end.column))
+            })
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                ((1, 1), (1, 11)),
+                ((2, 3), (2, 12)),
+                ((2, 13), (2, 14)),
+                ((2, 15), (2, 16)),
+                ((2, 17), (2, 18)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut lexer = Lexer::new(&b"a\nb # c\nd e\nf"[..]);
+        assert_eq!(lexer.by_ref().take(3).count(), 3);
+        assert_eq!(lexer.line(1), "a");
+        assert_eq!(lexer.line(2), "b # c");
+        lexer.forget_lines_before(2);
+        assert_eq!(lexer.line(1), "");
+        assert_eq!(lexer.line(2), "b # c");
+        // Line 3 is only partly read, the rest is read on demand.
+        assert_eq!(lexer.line(3), "d e");
+
+        let mut lexer = Lexer::new(&b"a\nb\nc\nd"[..]);
+        assert_eq!(lexer.by_ref().count(), 4);
+        lexer.forget_lines_after(1);
+        assert_eq!(lexer.line(1), "a");
+        assert_eq!(lexer.line(2), "");
+        assert_eq!(lexer.line(3), "");
+        assert_eq!(lexer.line(4), "d");
+
+        let long = format!("Shape {}", "1 ".repeat(MAX_LINE_TEXT));
+        let mut lexer = Lexer::new(long.as_bytes());
+        assert_eq!(lexer.by_ref().count(), MAX_LINE_TEXT + 1);
+        assert_eq!(lexer.line(1), format!("{}...", &long[..MAX_LINE_TEXT]));
+    }
+}
@@ -0,0 +1,1079 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Checks a parsed scene for mistakes pbrt-v3 would only log, or silently ignore, while rendering
+//! it: directives in the wrong block, unbalanced AttributeBegin/AttributeEnd and
+//! TransformBegin/TransformEnd, references to named materials, textures and media that haven't
+//! been defined, and parameters that are never looked up or have the wrong type.
+use std::collections::HashSet;
+use std::fmt;
+
+use core::paramset::{ParamSet, Value};
+use core::parser::{Directive, Scene, Span};
+
+/// How bad a Diagnostic is.  Errors are things pbrt-v3 reports as errors, and ignores the
+/// offending directive or parameter.  Warnings are things that are probably mistakes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found by lint, and the directive it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.span, self.severity, self.message)
+    }
+}
+
+/// Returns the problems found in scene, in the order of the directives they were found in.
+pub fn lint(scene: &Scene) -> Vec<Diagnostic> {
+    let mut linter = Linter::default();
+    for (d, span) in scene.directives.iter().zip(&scene.spans) {
+        linter.directive(d, span);
+    }
+    linter.close_all();
+    linter.diagnostics
+}
+
+/// The types a parameter can be declared with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Bool,
+    Integer,
+    Float,
+    Point3,
+    Vector3,
+    Normal,
+    String,
+    Spectrum,
+    /// A float, or the name of a float texture.
+    FloatTexture,
+    /// A spectrum, or the name of a spectrum texture.
+    SpectrumTexture,
+    /// FloatTexture or SpectrumTexture, depending on the type of the Texture being defined.
+    TextureValue,
+    /// Texture coordinates, which pbrt-v3 accepts as point2s or as pairs of floats.
+    UV,
+}
+
+impl Type {
+    /// Returns the Value::type_names that match self.  TextureValue matches either kind of texture.
+    fn type_names(self) -> &'static [&'static str] {
+        match self {
+            Type::Bool => &["bool"],
+            Type::Integer => &["integer"],
+            Type::Float => &["float"],
+            Type::Point3 => &["point3"],
+            Type::Vector3 => &["vector3"],
+            Type::Normal => &["normal"],
+            Type::String => &["string"],
+            Type::Spectrum => &["spectrum", "rgb", "xyz", "blackbody"],
+            Type::FloatTexture => &["float", "texture"],
+            Type::SpectrumTexture => &["spectrum", "rgb", "xyz", "blackbody", "texture"],
+            Type::TextureValue => &["float", "spectrum", "rgb", "xyz", "blackbody", "texture"],
+            Type::UV => &["point2", "float"],
+        }
+    }
+}
+
+type Params = &'static [(&'static str, Type)];
+
+/// Returns the parameters pbrt-v3 looks up for the implementation name of directive kw, e.g.
+/// `parameters("Shape", "sphere")`, and the types each can be declared with.  For a Texture, name
+/// is its class.  Returns None if kw has no implementation called name.
+pub fn parameters(kw: &str, name: &str) -> Option<Vec<(&'static str, &'static [&'static str])>> {
+    let table = if kw == "MakeNamedMaterial" {
+        "Material"
+    } else {
+        kw
+    };
+    known_params(table, name).map(|known| {
+        known
+            .iter()
+            .flat_map(|params| params.iter())
+            .map(|&(name, typ)| (name, typ.type_names()))
+            .collect()
+    })
+}
+
+/// Returns the parameters pbrt-v3 looks up for the implementation name of the kind of directive
+/// kw, or None if it doesn't have one by that name.
+fn known_params(kw: &str, name: &str) -> Option<&'static [Params]> {
+    use self::Type::*;
+
+    const CAMERA: Params = &[
+        ("shutteropen", Float),
+        ("shutterclose", Float),
+        ("lensradius", Float),
+        ("focaldistance", Float),
+        ("frameaspectratio", Float),
+        ("screenwindow", Float),
+    ];
+    const PERSPECTIVE: Params = &[("fov", Float), ("halffov", Float)];
+    const REALISTIC: Params = &[
+        ("lensfile", String),
+        ("aperturediameter", Float),
+        ("focusdistance", Float),
+        ("simpleweighting", Bool),
+    ];
+
+    const SAMPLER: Params = &[("pixelsamples", Integer)];
+    const HALTON: Params = &[("samplepixelcenter", Bool)];
+    const STRATIFIED: Params = &[
+        ("jitter", Bool),
+        ("xsamples", Integer),
+        ("ysamples", Integer),
+        ("dimensions", Integer),
+    ];
+    const LOW_DISCREPANCY: Params = &[("dimensions", Integer)];
+
+    const FILM: Params = &[
+        ("filename", String),
+        ("xresolution", Integer),
+        ("yresolution", Integer),
+        ("cropwindow", Float),
+        ("pixelbounds", Integer),
+        ("diagonal", Float),
+        ("scale", Float),
+        ("maxsampleluminance", Float),
+    ];
+
+    const INTEGRATOR: Params = &[("maxdepth", Integer), ("pixelbounds", Integer)];
+    const PATH: Params = &[("rrthreshold", Float), ("lightsamplestrategy", String)];
+    const BDPT: Params = &[
+        ("visualizestrategies", Bool),
+        ("visualizeweights", Bool),
+        ("lightsamplestrategy", String),
+    ];
+    const DIRECT_LIGHTING: Params = &[("strategy", String)];
+    const MLT: Params = &[
+        ("bootstrapsamples", Integer),
+        ("chains", Integer),
+        ("mutationsperpixel", Integer),
+        ("largestepprobability", Float),
+        ("sigma", Float),
+    ];
+    const SPPM: Params = &[
+        ("numiterations", Integer),
+        ("iterations", Integer),
+        ("photonsperiteration", Integer),
+        ("imagewritefrequency", Integer),
+        ("radius", Float),
+    ];
+    const AMBIENT_OCCLUSION: Params = &[("cossample", Bool), ("nsamples", Integer)];
+
+    const SHAPE: Params = &[("alpha", FloatTexture), ("shadowalpha", FloatTexture)];
+    const QUADRIC: Params = &[
+        ("radius", Float),
+        ("zmin", Float),
+        ("zmax", Float),
+        ("phimax", Float),
+    ];
+    const DISK: Params = &[
+        ("height", Float),
+        ("radius", Float),
+        ("innerradius", Float),
+        ("phimax", Float),
+    ];
+    const CONE: Params = &[("height", Float), ("radius", Float), ("phimax", Float)];
+    const HYPERBOLOID: Params = &[("p1", Point3), ("p2", Point3), ("phimax", Float)];
+    const CURVE: Params = &[
+        ("P", Point3),
+        ("basis", String),
+        ("degree", Integer),
+        ("type", String),
+        ("N", Normal),
+        ("width", Float),
+        ("width0", Float),
+        ("width1", Float),
+        ("splitdepth", Integer),
+    ];
+    const TRIANGLE_MESH: Params = &[
+        ("indices", Integer),
+        ("P", Point3),
+        ("uv", UV),
+        ("st", UV),
+        ("N", Normal),
+        ("S", Vector3),
+        ("faceIndices", Integer),
+    ];
+    const PLY_MESH: Params = &[
+        ("filename", String),
+        ("displacement", FloatTexture),
+        ("edgelength", Float),
+    ];
+    const HEIGHT_FIELD: Params = &[("nu", Integer), ("nv", Integer), ("Pz", Float)];
+    const LOOP_SUBDIV: Params = &[
+        ("nlevels", Integer),
+        ("levels", Integer),
+        ("indices", Integer),
+        ("P", Point3),
+    ];
+    const NURBS: Params = &[
+        ("nu", Integer),
+        ("nv", Integer),
+        ("uorder", Integer),
+        ("vorder", Integer),
+        ("uknots", Float),
+        ("vknots", Float),
+        ("u0", Float),
+        ("u1", Float),
+        ("v0", Float),
+        ("v1", Float),
+        ("P", Point3),
+        ("Pw", Float),
+    ];
+
+    const MATERIAL: Params = &[("bumpmap", FloatTexture)];
+    const MATTE: Params = &[("Kd", SpectrumTexture), ("sigma", FloatTexture)];
+    const PLASTIC: Params = &[
+        ("Kd", SpectrumTexture),
+        ("Ks", SpectrumTexture),
+        ("roughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const TRANSLUCENT: Params = &[
+        ("Kd", SpectrumTexture),
+        ("Ks", SpectrumTexture),
+        ("reflect", SpectrumTexture),
+        ("transmit", SpectrumTexture),
+        ("roughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const GLASS: Params = &[
+        ("Kr", SpectrumTexture),
+        ("Kt", SpectrumTexture),
+        ("eta", FloatTexture),
+        ("index", FloatTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const MIRROR: Params = &[("Kr", SpectrumTexture)];
+    const METAL: Params = &[
+        ("eta", SpectrumTexture),
+        ("k", SpectrumTexture),
+        ("roughness", FloatTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const UBER: Params = &[
+        ("Kd", SpectrumTexture),
+        ("Ks", SpectrumTexture),
+        ("Kr", SpectrumTexture),
+        ("Kt", SpectrumTexture),
+        ("roughness", FloatTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("eta", FloatTexture),
+        ("index", FloatTexture),
+        ("opacity", SpectrumTexture),
+        ("remaproughness", Bool),
+    ];
+    const SUBSTRATE: Params = &[
+        ("Kd", SpectrumTexture),
+        ("Ks", SpectrumTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const MIX: Params = &[
+        ("amount", SpectrumTexture),
+        ("namedmaterial1", String),
+        ("namedmaterial2", String),
+    ];
+    const SUBSURFACE: Params = &[
+        ("name", String),
+        ("g", Float),
+        ("mfp", SpectrumTexture),
+        ("sigma_a", SpectrumTexture),
+        ("sigma_s", SpectrumTexture),
+        ("reflectance", SpectrumTexture),
+        ("scale", Float),
+        ("eta", Float),
+        ("Kr", SpectrumTexture),
+        ("Kt", SpectrumTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const KD_SUBSURFACE: Params = &[
+        ("Kd", SpectrumTexture),
+        ("mfp", SpectrumTexture),
+        ("eta", Float),
+        ("Kr", SpectrumTexture),
+        ("Kt", SpectrumTexture),
+        ("uroughness", FloatTexture),
+        ("vroughness", FloatTexture),
+        ("remaproughness", Bool),
+    ];
+    const FOURIER: Params = &[("bsdffile", String)];
+    const HAIR: Params = &[
+        ("sigma_a", SpectrumTexture),
+        ("color", SpectrumTexture),
+        ("eumelanin", FloatTexture),
+        ("pheomelanin", FloatTexture),
+        ("eta", FloatTexture),
+        ("beta_m", FloatTexture),
+        ("beta_n", FloatTexture),
+        ("alpha", FloatTexture),
+    ];
+    const DISNEY: Params = &[
+        ("color", SpectrumTexture),
+        ("metallic", FloatTexture),
+        ("eta", FloatTexture),
+        ("roughness", FloatTexture),
+        ("speculartint", FloatTexture),
+        ("anisotropic", FloatTexture),
+        ("sheen", FloatTexture),
+        ("sheentint", FloatTexture),
+        ("clearcoat", FloatTexture),
+        ("clearcoatgloss", FloatTexture),
+        ("spectrans", FloatTexture),
+        ("scatterdistance", SpectrumTexture),
+        ("thin", Bool),
+        ("flatness", FloatTexture),
+        ("difftrans", FloatTexture),
+    ];
+
+    const LIGHT: Params = &[("scale", Spectrum)];
+    const POINT: Params = &[("I", Spectrum), ("from", Point3)];
+    const SPOT: Params = &[
+        ("I", Spectrum),
+        ("from", Point3),
+        ("to", Point3),
+        ("coneangle", Float),
+        ("conedeltaangle", Float),
+    ];
+    const GONIOMETRIC: Params = &[("I", Spectrum), ("mapname", String)];
+    const PROJECTION: Params = &[("I", Spectrum), ("fov", Float), ("mapname", String)];
+    const DISTANT: Params = &[("L", Spectrum), ("from", Point3), ("to", Point3)];
+    const INFINITE: Params = &[
+        ("L", Spectrum),
+        ("nsamples", Integer),
+        ("samples", Integer),
+        ("mapname", String),
+    ];
+    const DIFFUSE: Params = &[
+        ("L", Spectrum),
+        ("twosided", Bool),
+        ("nsamples", Integer),
+        ("samples", Integer),
+    ];
+
+    const TEXTURE: Params = &[
+        ("mapping", String),
+        ("uscale", Float),
+        ("vscale", Float),
+        ("udelta", Float),
+        ("vdelta", Float),
+        ("v1", Vector3),
+        ("v2", Vector3),
+    ];
+    const CONSTANT: Params = &[("value", TextureValue)];
+    const SCALE: Params = &[("tex1", TextureValue), ("tex2", TextureValue)];
+    const MIX_TEXTURE: Params = &[
+        ("tex1", TextureValue),
+        ("tex2", TextureValue),
+        ("amount", FloatTexture),
+    ];
+    const BILERP: Params = &[
+        ("v00", TextureValue),
+        ("v01", TextureValue),
+        ("v10", TextureValue),
+        ("v11", TextureValue),
+    ];
+    const IMAGE_MAP: Params = &[
+        ("filename", String),
+        ("maxanisotropy", Float),
+        ("trilinear", Bool),
+        ("wrap", String),
+        ("scale", Float),
+        ("gamma", Bool),
+    ];
+    const CHECKERBOARD: Params = &[
+        ("dimension", Integer),
+        ("tex1", TextureValue),
+        ("tex2", TextureValue),
+        ("aamode", String),
+    ];
+    const DOTS: Params = &[("inside", TextureValue), ("outside", TextureValue)];
+    const FBM: Params = &[("octaves", Integer), ("roughness", Float)];
+    const MARBLE: Params = &[
+        ("octaves", Integer),
+        ("roughness", Float),
+        ("scale", Float),
+        ("variation", Float),
+    ];
+    const PTEX: Params = &[("filename", String), ("gamma", Float)];
+
+    const HOMOGENEOUS: Params = &[
+        ("preset", String),
+        ("sigma_a", Spectrum),
+        ("sigma_s", Spectrum),
+        ("scale", Float),
+        ("g", Float),
+    ];
+    const HETEROGENEOUS: Params = &[
+        ("preset", String),
+        ("sigma_a", Spectrum),
+        ("sigma_s", Spectrum),
+        ("scale", Float),
+        ("g", Float),
+        ("p0", Point3),
+        ("p1", Point3),
+        ("nx", Integer),
+        ("ny", Integer),
+        ("nz", Integer),
+        ("density", Float),
+    ];
+
+    let params: &'static [Params] = match (kw, name) {
+        ("Camera", "perspective") => &[CAMERA, PERSPECTIVE],
+        ("Camera", "orthographic") | ("Camera", "environment") => &[CAMERA],
+        ("Camera", "realistic") => &[CAMERA, REALISTIC],
+        ("Sampler", "halton") => &[SAMPLER, HALTON],
+        ("Sampler", "stratified") => &[SAMPLER, STRATIFIED],
+        ("Sampler", "lowdiscrepancy") | ("Sampler", "02sequence") | ("Sampler", "maxmindist") => {
+            &[SAMPLER, LOW_DISCREPANCY]
+        }
+        ("Sampler", "sobol") | ("Sampler", "random") => &[SAMPLER],
+        ("Film", "image") => &[FILM],
+        ("Integrator", "path") | ("Integrator", "volpath") => &[INTEGRATOR, PATH],
+        ("Integrator", "bdpt") => &[INTEGRATOR, BDPT],
+        ("Integrator", "directlighting") => &[INTEGRATOR, DIRECT_LIGHTING],
+        ("Integrator", "whitted") => &[INTEGRATOR],
+        ("Integrator", "mlt") => &[INTEGRATOR, MLT],
+        ("Integrator", "sppm") => &[INTEGRATOR, SPPM],
+        ("Integrator", "ambientocclusion") => &[INTEGRATOR, AMBIENT_OCCLUSION],
+        ("Shape", "sphere") | ("Shape", "cylinder") | ("Shape", "paraboloid") => &[SHAPE, QUADRIC],
+        ("Shape", "disk") => &[SHAPE, DISK],
+        ("Shape", "cone") => &[SHAPE, CONE],
+        ("Shape", "hyperboloid") => &[SHAPE, HYPERBOLOID],
+        ("Shape", "curve") => &[SHAPE, CURVE],
+        ("Shape", "trianglemesh") => &[SHAPE, TRIANGLE_MESH],
+        ("Shape", "plymesh") => &[SHAPE, PLY_MESH],
+        ("Shape", "heightfield") => &[SHAPE, HEIGHT_FIELD],
+        ("Shape", "loopsubdiv") => &[SHAPE, LOOP_SUBDIV],
+        ("Shape", "nurbs") => &[SHAPE, NURBS],
+        ("Material", "") | ("Material", "none") => &[],
+        ("Material", "matte") => &[MATERIAL, MATTE],
+        ("Material", "plastic") => &[MATERIAL, PLASTIC],
+        ("Material", "translucent") => &[MATERIAL, TRANSLUCENT],
+        ("Material", "glass") => &[MATERIAL, GLASS],
+        ("Material", "mirror") => &[MATERIAL, MIRROR],
+        ("Material", "metal") => &[MATERIAL, METAL],
+        ("Material", "uber") => &[MATERIAL, UBER],
+        ("Material", "substrate") => &[MATERIAL, SUBSTRATE],
+        ("Material", "mix") => &[MATERIAL, MIX],
+        ("Material", "subsurface") => &[MATERIAL, SUBSURFACE],
+        ("Material", "kdsubsurface") => &[MATERIAL, KD_SUBSURFACE],
+        ("Material", "fourier") => &[MATERIAL, FOURIER],
+        ("Material", "hair") => &[MATERIAL, HAIR],
+        ("Material", "disney") => &[MATERIAL, DISNEY],
+        ("LightSource", "point") => &[LIGHT, POINT],
+        ("LightSource", "spot") => &[LIGHT, SPOT],
+        ("LightSource", "goniometric") => &[LIGHT, GONIOMETRIC],
+        ("LightSource", "projection") => &[LIGHT, PROJECTION],
+        ("LightSource", "distant") => &[LIGHT, DISTANT],
+        ("LightSource", "infinite") | ("LightSource", "exinfinite") => &[LIGHT, INFINITE],
+        ("AreaLightSource", "diffuse") => &[LIGHT, DIFFUSE],
+        ("Texture", "constant") => &[TEXTURE, CONSTANT],
+        ("Texture", "scale") => &[TEXTURE, SCALE],
+        ("Texture", "mix") => &[TEXTURE, MIX_TEXTURE],
+        ("Texture", "bilerp") => &[TEXTURE, BILERP],
+        ("Texture", "imagemap") => &[TEXTURE, IMAGE_MAP],
+        ("Texture", "checkerboard") => &[TEXTURE, CHECKERBOARD],
+        ("Texture", "dots") => &[TEXTURE, DOTS],
+        ("Texture", "fbm") | ("Texture", "wrinkled") => &[TEXTURE, FBM],
+        ("Texture", "windy") | ("Texture", "uv") => &[TEXTURE],
+        ("Texture", "marble") => &[TEXTURE, MARBLE],
+        ("Texture", "ptex") => &[TEXTURE, PTEX],
+        ("MakeNamedMedium", "homogeneous") => &[HOMOGENEOUS],
+        ("MakeNamedMedium", "heterogeneous") => &[HETEROGENEOUS],
+        _ => return None,
+    };
+    Some(params)
+}
+
+/// Returns how pbrt-v3 refers to the things directive kw creates, in error messages.
+fn kind_name(kw: &str) -> &'static str {
+    match kw {
+        "Camera" => "camera",
+        "Sampler" => "sampler",
+        "Film" => "film",
+        "Integrator" => "integrator",
+        "Shape" => "shape",
+        "LightSource" => "light",
+        "AreaLightSource" => "area light",
+        "Texture" => "texture",
+        "MakeNamedMedium" => "medium",
+        _ => "material",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    Attribute,
+    Transform,
+}
+
+/// The names defined in an attribute block.  pbrt-v3 keeps these in its graphics state, so
+/// definitions are forgotten at the AttributeEnd of the block they were made in.
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    materials: HashSet<String>,
+    float_textures: HashSet<String>,
+    spectrum_textures: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Linter {
+    diagnostics: Vec<Diagnostic>,
+    in_world: bool,
+    /// The open attribute and transform blocks, and where they were opened.
+    blocks: Vec<(Block, Span)>,
+    /// scopes[0] is the world's, and there's one more for each open attribute block.
+    scopes: Vec<Scope>,
+    /// Named media aren't part of the graphics state, so they live until the end of the scene.
+    media: HashSet<String>,
+}
+
+impl Linter {
+    fn report(&mut self, severity: Severity, span: &Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            span: span.clone(),
+            message,
+        });
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        if self.scopes.is_empty() {
+            self.scopes.push(Scope::default());
+        }
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn directive(&mut self, d: &Directive, span: &Span) {
+        match d {
+            Directive::Camera(name, ps) => {
+                self.options_only("Camera", span);
+                self.params("Camera", name, ps, span);
+            }
+            Directive::Sampler(name, ps) => {
+                self.options_only("Sampler", span);
+                self.params("Sampler", name, ps, span);
+            }
+            Directive::Integrator(name, ps) => {
+                self.options_only("Integrator", span);
+                self.params("Integrator", name, ps, span);
+            }
+            Directive::Film(name, ps) => {
+                self.options_only("Film", span);
+                self.params("Film", name, ps, span);
+            }
+            Directive::TransformTimes(..) => self.options_only("TransformTimes", span),
+            Directive::WorldBegin => {
+                self.options_only("WorldBegin", span);
+                self.in_world = true;
+                self.scopes = vec![Scope::default()];
+            }
+            Directive::WorldEnd => {
+                self.world_only("WorldEnd", span);
+                self.close_all();
+                self.in_world = false;
+            }
+            Directive::AttributeBegin => {
+                self.world_only("AttributeBegin", span);
+                self.blocks.push((Block::Attribute, span.clone()));
+                let scope = self.scope().clone();
+                self.scopes.push(scope);
+            }
+            Directive::AttributeEnd => {
+                self.world_only("AttributeEnd", span);
+                self.end(Block::Attribute, span);
+            }
+            Directive::TransformBegin => {
+                self.world_only("TransformBegin", span);
+                self.blocks.push((Block::Transform, span.clone()));
+            }
+            Directive::TransformEnd => {
+                self.world_only("TransformEnd", span);
+                self.end(Block::Transform, span);
+            }
+            Directive::LightSource(name, ps) => {
+                self.world_only("LightSource", span);
+                self.params("LightSource", name, ps, span);
+            }
+            Directive::AreaLightSource(name, ps) => {
+                self.world_only("AreaLightSource", span);
+                self.params("AreaLightSource", name, ps, span);
+            }
+            Directive::Material(name, ps) => {
+                self.world_only("Material", span);
+                self.params("Material", name, ps, span);
+            }
+            Directive::MakeNamedMaterial(name, ps) => {
+                self.world_only("MakeNamedMaterial", span);
+                match ps.get("type") {
+                    Some(Value::String(typ)) if !typ.0.is_empty() => {
+                        self.params("MakeNamedMaterial", &typ.0[0], ps, span)
+                    }
+                    _ => self.report(
+                        Severity::Error,
+                        span,
+                        format!("MakeNamedMaterial \"{}\" has no \"string type\"", name),
+                    ),
+                }
+                if !self.scope().materials.insert(name.clone()) {
+                    self.report(
+                        Severity::Warning,
+                        span,
+                        format!("named material \"{}\" redefined", name),
+                    );
+                }
+            }
+            Directive::NamedMaterial(name) => {
+                self.world_only("NamedMaterial", span);
+                self.named_material(name, span);
+            }
+            Directive::MakeNamedMedium(name, ps) => {
+                match ps.get("type") {
+                    Some(Value::String(typ)) if !typ.0.is_empty() => {
+                        self.params("MakeNamedMedium", &typ.0[0], ps, span)
+                    }
+                    _ => self.report(
+                        Severity::Error,
+                        span,
+                        format!("MakeNamedMedium \"{}\" has no \"string type\"", name),
+                    ),
+                }
+                self.media.insert(name.clone());
+            }
+            Directive::MediumInterface(inside, outside) => {
+                for name in &[inside, outside] {
+                    if !name.is_empty() && !self.media.contains(*name) {
+                        self.report(
+                            Severity::Error,
+                            span,
+                            format!("undefined medium \"{}\"", name),
+                        );
+                    }
+                }
+            }
+            Directive::Shape(name, ps) => {
+                self.world_only("Shape", span);
+                self.params("Shape", name, ps, span);
+            }
+            Directive::Texture(name, typ, class, ps) => {
+                self.world_only("Texture", span);
+                let value = match typ.as_str() {
+                    "float" => Type::FloatTexture,
+                    "spectrum" | "color" => Type::SpectrumTexture,
+                    _ => {
+                        self.report(
+                            Severity::Error,
+                            span,
+                            format!("texture type \"{}\" unknown", typ),
+                        );
+                        return;
+                    }
+                };
+                self.check_params("Texture", class, ps, value, span);
+                let scope = self.scope();
+                if value == Type::FloatTexture {
+                    scope.float_textures.insert(name.clone());
+                } else {
+                    scope.spectrum_textures.insert(name.clone());
+                }
+            }
+            Directive::LookAt(..)
+            | Directive::Translate(..)
+            | Directive::Scale(..)
+            | Directive::Rotate(..)
+            | Directive::Identity
+            | Directive::Transform(_)
+            | Directive::ConcatTransform(_)
+            | Directive::CoordinateSystem(_)
+            | Directive::CoordSysTransform(_)
+            | Directive::ActiveTransform(_)
+            | Directive::Include(_)
+            | Directive::Import(_) => (),
+        }
+    }
+
+    fn options_only(&mut self, kw: &str, span: &Span) {
+        if self.in_world {
+            self.report(
+                Severity::Error,
+                span,
+                format!("{} is only allowed before WorldBegin", kw),
+            );
+        }
+    }
+
+    fn world_only(&mut self, kw: &str, span: &Span) {
+        if !self.in_world {
+            self.report(
+                Severity::Error,
+                span,
+                format!("{} is only allowed between WorldBegin and WorldEnd", kw),
+            );
+        }
+    }
+
+    /// Closes the innermost open block of kind block, reporting an error if there isn't one or
+    /// if another block opened after it is still open.
+    fn end(&mut self, block: Block, span: &Span) {
+        let (begin, end) = match block {
+            Block::Attribute => ("AttributeBegin", "AttributeEnd"),
+            Block::Transform => ("TransformBegin", "TransformEnd"),
+        };
+        let i = match self.blocks.iter().rposition(|(b, _)| *b == block) {
+            Some(i) => i,
+            None => {
+                self.report(
+                    Severity::Error,
+                    span,
+                    format!("{} without a matching {}", end, begin),
+                );
+                return;
+            }
+        };
+        let (_, opened) = self.blocks.remove(i);
+        if let Some((inner, inner_span)) = self.blocks.get(i).cloned() {
+            let inner = match inner {
+                Block::Attribute => "AttributeBegin",
+                Block::Transform => "TransformBegin",
+            };
+            self.report(
+                Severity::Error,
+                span,
+                format!(
+                    "{} closes the {} at {} before the {} at {} is closed",
+                    end, begin, opened, inner, inner_span
+                ),
+            );
+        }
+        if block == Block::Attribute {
+            // The attribute blocks are closed in order, so the innermost one's scope is last.
+            self.scopes.pop();
+        }
+    }
+
+    /// Reports and forgets the blocks still open at WorldEnd or the end of the scene.
+    fn close_all(&mut self) {
+        for (block, span) in std::mem::take(&mut self.blocks) {
+            let (begin, end) = match block {
+                Block::Attribute => ("AttributeBegin", "AttributeEnd"),
+                Block::Transform => ("TransformBegin", "TransformEnd"),
+            };
+            self.report(
+                Severity::Warning,
+                &span,
+                format!("{} has no matching {}", begin, end),
+            );
+        }
+        self.scopes.truncate(1);
+    }
+
+    fn named_material(&mut self, name: &str, span: &Span) {
+        if !self.scope().materials.contains(name) {
+            self.report(
+                Severity::Error,
+                span,
+                format!("undefined named material \"{}\"", name),
+            );
+        }
+    }
+
+    fn params(&mut self, kw: &str, name: &str, ps: &ParamSet, span: &Span) {
+        self.check_params(kw, name, ps, Type::FloatTexture, span)
+    }
+
+    /// Checks the parameters of the implementation name of directive kw.  texture_value is what
+    /// Type::TextureValue parameters resolve to.
+    fn check_params(
+        &mut self,
+        kw: &str,
+        name: &str,
+        ps: &ParamSet,
+        texture_value: Type,
+        span: &Span,
+    ) {
+        // MakeNamedMaterial's "string type" names a Material.
+        let table = if kw == "MakeNamedMaterial" {
+            "Material"
+        } else {
+            kw
+        };
+        let known = match known_params(table, name) {
+            Some(known) => known,
+            None => {
+                self.report(
+                    Severity::Error,
+                    span,
+                    format!("{} \"{}\" unknown", kind_name(kw), name),
+                );
+                return;
+            }
+        };
+        let is_named = kw == "MakeNamedMaterial" || kw == "MakeNamedMedium";
+        for item in ps.items() {
+            let found = item.values.type_name();
+            if is_named && item.name == "type" {
+                continue;
+            }
+            let typ = known
+                .iter()
+                .flat_map(|params| params.iter())
+                .find(|(n, _)| *n == item.name)
+                .map(|&(_, t)| {
+                    if t == Type::TextureValue {
+                        texture_value
+                    } else {
+                        t
+                    }
+                });
+            let typ = match typ {
+                Some(typ) => typ,
+                None => {
+                    self.report(
+                        Severity::Warning,
+                        span,
+                        format!("unused parameter \"{} {}\"", found, item.name),
+                    );
+                    continue;
+                }
+            };
+            let expected = typ.type_names();
+            if !expected.contains(&found) {
+                self.report(
+                    Severity::Error,
+                    span,
+                    format!(
+                        "parameter \"{}\" has type {}, expected {}",
+                        item.name,
+                        found,
+                        one_of(expected)
+                    ),
+                );
+                continue;
+            }
+            match &item.values {
+                Value::Texture(textures) => {
+                    for texture in &textures.0 {
+                        self.texture_reference(texture, typ, span);
+                    }
+                }
+                Value::String(names) if name == "mix" && item.name.starts_with("namedmaterial") => {
+                    for name in &names.0 {
+                        self.named_material(name, span);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn texture_reference(&mut self, texture: &str, typ: Type, span: &Span) {
+        let (defined, kind) = if typ == Type::FloatTexture {
+            (self.scope().float_textures.contains(texture), "float")
+        } else {
+            (self.scope().spectrum_textures.contains(texture), "spectrum")
+        };
+        if !defined {
+            self.report(
+                Severity::Error,
+                span,
+                format!("undefined {} texture \"{}\"", kind, texture),
+            );
+        }
+    }
+}
+
+/// Formats names as "a", "a or b", "a, b or c" and so on.
+fn one_of(names: &[&str]) -> String {
+    match names.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::parser::parse_scene;
+
+    /// Returns the severity, line and message of each diagnostic for input.
+    fn diagnostics(input: &str) -> Vec<(Severity, usize, String)> {
+        let scene = parse_scene(input.as_bytes()).unwrap();
+        lint(&scene)
+            .into_iter()
+            .map(|d| (d.severity, d.span.start.line, d.message))
+            .collect()
+    }
+
+    fn error(line: usize, message: &str) -> (Severity, usize, String) {
+        (Severity::Error, line, message.to_owned())
+    }
+
+    fn warning(line: usize, message: &str) -> (Severity, usize, String) {
+        (Severity::Warning, line, message.to_owned())
+    }
+
+    #[test]
+    fn test_clean() {
+        let input = r#"LookAt 0 0 5  0 0 0  0 1 0
+Camera "perspective" "float fov" 45
+Sampler "halton" "integer pixelsamples" 16
+Film "image" "string filename" "out.exr" "integer xresolution" 400
+MakeNamedMedium "fog" "string type" "homogeneous" "rgb sigma_s" [.1 .1 .1]
+MediumInterface "" "fog"
+WorldBegin
+Texture "checks" "spectrum" "checkerboard" "rgb tex1" [1 0 0] "rgb tex2" [0 0 1]
+Texture "bumps" "float" "fbm" "integer octaves" 4
+MakeNamedMaterial "red" "string type" "matte" "texture Kd" "checks"
+AttributeBegin
+  TransformBegin
+    Translate 0 1 0
+  TransformEnd
+  NamedMaterial "red"
+  Material "plastic" "texture bumpmap" "bumps" "float roughness" .1
+  Shape "trianglemesh" "integer indices" [0 1 2] "point P" [0 0 0 1 0 0 0 1 0]
+    "float uv" [0 0 1 0 0 1]
+AttributeEnd
+WorldEnd
+"#;
+        assert_eq!(diagnostics(input), vec![]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let input = r#"Shape "sphere"
+WorldBegin
+Camera "perspective"
+AttributeBegin
+  TransformBegin
+AttributeEnd
+  TransformEnd
+TransformEnd
+AttributeBegin
+WorldEnd
+"#;
+        assert_eq!(
+            diagnostics(input),
+            vec![
+                error(1, "Shape is only allowed between WorldBegin and WorldEnd"),
+                error(3, "Camera is only allowed before WorldBegin"),
+                error(
+                    6,
+                    "AttributeEnd closes the AttributeBegin at 4:1 before the TransformBegin at \
+                     5:3 is closed"
+                ),
+                error(8, "TransformEnd without a matching TransformBegin"),
+                warning(9, "AttributeBegin has no matching AttributeEnd"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undefined_names() {
+        let input = r#"MediumInterface "fog" ""
+WorldBegin
+AttributeBegin
+  Texture "checks" "spectrum" "checkerboard"
+  MakeNamedMaterial "red" "string type" "matte" "texture Kd" "checks"
+  NamedMaterial "red"
+AttributeEnd
+NamedMaterial "red"
+Material "matte" "texture Kd" "checks"
+Texture "f" "float" "constant" "float value" 1
+Material "matte" "texture Kd" "f" "texture sigma" "f"
+Material "mix" "string namedmaterial1" "red" "string namedmaterial2" "red"
+MakeNamedMaterial "red" "string type" "mirror"
+MakeNamedMaterial "red" "string type" "mirror"
+MakeNamedMaterial "nothing"
+WorldEnd
+"#;
+        assert_eq!(
+            diagnostics(input),
+            vec![
+                error(1, "undefined medium \"fog\""),
+                error(8, "undefined named material \"red\""),
+                error(9, "undefined spectrum texture \"checks\""),
+                error(11, "undefined spectrum texture \"f\""),
+                error(12, "undefined named material \"red\""),
+                error(12, "undefined named material \"red\""),
+                warning(14, "named material \"red\" redefined"),
+                error(15, "MakeNamedMaterial \"nothing\" has no \"string type\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let input = r#"Camera "perspective" "integer fov" 45 "float fvo" 45
+Sampler "bogus"
+WorldBegin
+LightSource "point" "color I" [1 1 1] "blackbody scale" [5500 1]
+Shape "sphere" "float radius" 1 "float alpha" .5 "texture shadowalpha" "a"
+Texture "t" "float" "scale" "rgb tex1" [1 1 1]
+Texture "u" "colour" "scale"
+WorldEnd
+"#;
+        assert_eq!(
+            diagnostics(input),
+            vec![
+                error(1, "parameter \"fov\" has type integer, expected float"),
+                warning(1, "unused parameter \"float fvo\""),
+                error(2, "sampler \"bogus\" unknown"),
+                error(5, "undefined float texture \"a\""),
+                error(
+                    6,
+                    "parameter \"tex1\" has type rgb, expected float or texture"
+                ),
+                error(7, "texture type \"colour\" unknown"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let scene = parse_scene(&b"WorldBegin\nCamera \"perspective\""[..]).unwrap();
+        assert_eq!(
+            lint(&scene)[0].to_string(),
+            "2:1: error: Camera is only allowed before WorldBegin"
+        );
+    }
+}
@@ -0,0 +1,1836 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Converts Mitsuba 0.6 and Mitsuba 2 XML scene files to Scenes.
+//!
+//! The sensor becomes the Camera, and its film and sampler the Film and Sampler.  In the world
+//! block, bsdfs become materials, emitters become lights, and each shape becomes a shape in an
+//! AttributeBegin/AttributeEnd block with its transform, material and area light.  Mitsuba 0.6's
+//! camelCase parameter names and Mitsuba 2's snake_case ones are both understood, and `$name`
+//! references to `<default>` values are expanded.
+//!
+//! Plugins, parameters and elements without a pbrt equivalent are reported as warnings.
+//!
+//! Only available with the mitsuba feature.
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::f32::consts::SQRT_2;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use roxmltree::{Document, Node};
+
+use core::geometry::{cross, Point2f, Point3f, Vector3f};
+use core::obj;
+use core::paramset::{ParamSet, Value};
+use core::parser::{Directive, Scene};
+use core::pbrt::Float;
+use core::ply::TriangleMesh;
+use core::transform::{Matrix4x4, Transform};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be read.
+    Io(PathBuf, io::Error),
+    /// The included file given, or the scene file if there's none, isn't well-formed XML.
+    Xml(Option<PathBuf>, roxmltree::Error),
+    /// An element that can't be converted, in the included file given, or the scene file if
+    /// there's none.
+    Invalid {
+        file: Option<PathBuf>,
+        line: u32,
+        why: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Xml(Some(path), e) => write!(f, "{}: {}", path.display(), e),
+            Error::Xml(None, e) => write!(f, "{}", e),
+            Error::Invalid {
+                file: Some(file),
+                line,
+                why,
+            } => write!(f, "{}:{}: {}", file.display(), line, why),
+            Error::Invalid {
+                file: None,
+                line,
+                why,
+            } => write!(f, "line {}: {}", line, why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Returns name in lower case without underscores, so Mitsuba 0.6's "toWorld" and Mitsuba 2's
+/// "to_world" are the same.
+fn key(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn line(node: Node) -> u32 {
+    node.document().text_pos_at(node.range().start).row
+}
+
+fn float_value(v: Float) -> Value {
+    Value::Float(vec![v].into())
+}
+
+fn gray(v: Float) -> Value {
+    Value::RGB(vec![v, v, v].into())
+}
+
+fn point_value(p: [Float; 3]) -> Value {
+    Value::Point3f(
+        vec![Point3f {
+            x: p[0],
+            y: p[1],
+            z: p[2],
+        }]
+        .into(),
+    )
+}
+
+fn string_value(s: &str) -> Value {
+    Value::String(vec![s.to_owned()].into())
+}
+
+/// Returns the values of m for ConcatTransform, which lists the columns in turn.
+fn to_columns(m: &Matrix4x4) -> [Float; 16] {
+    let mut a = [0.; 16];
+    for (i, v) in a.iter_mut().enumerate() {
+        *v = m.m[i % 4][i / 4];
+    }
+    a
+}
+
+fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Mitsuba's named indices of refraction.
+const IORS: &[(&str, Float)] = &[
+    ("vacuum", 1.0),
+    ("helium", 1.000_036),
+    ("hydrogen", 1.000_132),
+    ("air", 1.000_277),
+    ("carbon dioxide", 1.000_45),
+    ("water", 1.333),
+    ("acetone", 1.36),
+    ("ethanol", 1.361),
+    ("carbon tetrachloride", 1.461),
+    ("glycerol", 1.4729),
+    ("benzene", 1.501),
+    ("silicone oil", 1.520_45),
+    ("bromine", 1.661),
+    ("water ice", 1.31),
+    ("fused quartz", 1.458),
+    ("pyrex", 1.470),
+    ("acrylic glass", 1.49),
+    ("polypropylene", 1.49),
+    ("bk7", 1.5046),
+    ("sodium chloride", 1.544),
+    ("amber", 1.55),
+    ("pet", 1.575),
+    ("diamond", 2.419),
+];
+
+// RGB approximations of the eta and k of Mitsuba's most used named conductors.
+const CONDUCTORS: &[(&str, [Float; 3], [Float; 3])] = &[
+    ("Ag", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ("Al", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("Au", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+    ("Cu", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+];
+
+/// A plugin element like `<bsdf type="diffuse">`.  Its children are marked as they're used, so
+/// the rest can be reported.
+struct Plugin<'a, 'i: 'a> {
+    node: Node<'a, 'i>,
+    children: Vec<Node<'a, 'i>>,
+    used: Vec<bool>,
+}
+
+impl<'a, 'i> Plugin<'a, 'i> {
+    fn new(node: Node<'a, 'i>) -> Plugin<'a, 'i> {
+        let children: Vec<_> = node.children().filter(|c| c.is_element()).collect();
+        Plugin {
+            node,
+            used: vec![false; children.len()],
+            children,
+        }
+    }
+
+    /// Returns the plugin's type, e.g. "diffuse".
+    fn kind(&self) -> &'a str {
+        self.node.attribute("type").unwrap_or("")
+    }
+
+    /// Returns how the plugin is described in messages, e.g. `bsdf "diffuse"`.
+    fn describe(&self) -> String {
+        format!("{} \"{}\"", self.node.tag_name().name(), self.kind())
+    }
+
+    /// Returns the child called name, e.g. `<float name="fov" .../>` for "fov".
+    fn prop(&mut self, name: &str) -> Option<Node<'a, 'i>> {
+        let name = key(name);
+        let i = self
+            .children
+            .iter()
+            .position(|c| c.attribute("name").map(key).as_ref() == Some(&name))?;
+        self.used[i] = true;
+        Some(self.children[i])
+    }
+
+    /// Returns the unused children with one of the tags, e.g. a shape's `<bsdf>`.
+    fn objects(&mut self, tags: &[&str]) -> Vec<Node<'a, 'i>> {
+        let mut objects = Vec::new();
+        for (c, used) in self.children.iter().zip(self.used.iter_mut()) {
+            if !*used && tags.contains(&c.tag_name().name()) {
+                *used = true;
+                objects.push(*c);
+            }
+        }
+        objects
+    }
+
+    /// Returns the first unused child with one of the tags.  Any others are left to be reported.
+    fn object(&mut self, tags: &[&str]) -> Option<Node<'a, 'i>> {
+        let i = self
+            .children
+            .iter()
+            .zip(&self.used)
+            .position(|(c, &used)| !used && tags.contains(&c.tag_name().name()))?;
+        self.used[i] = true;
+        Some(self.children[i])
+    }
+
+    /// Marks the children called any of names as used, for parameters that don't change what's
+    /// rendered.
+    fn skip(&mut self, names: &[&str]) {
+        for name in names {
+            self.prop(name);
+        }
+    }
+}
+
+/// The state of a conversion.
+struct Converter<'w> {
+    dir: PathBuf,
+    // The included files being read, innermost last.
+    files: Vec<PathBuf>,
+    defaults: HashMap<String, String>,
+    // The directives before and after WorldBegin.
+    options: Vec<Directive>,
+    world: Vec<Directive>,
+    sensor: bool,
+    integrator: bool,
+    // The ids of the things defined so far.
+    textures: HashSet<String>,
+    // Named materials' types and parameters, for bsdfs that refer to them.
+    materials: HashMap<String, (String, ParamSet)>,
+    media: HashSet<String>,
+    groups: HashMap<String, Vec<Directive>>,
+    // The number of names made up so far.
+    names: usize,
+    warnings: &'w mut Vec<String>,
+}
+
+impl<'w> Converter<'w> {
+    fn invalid<T>(&self, node: Node, why: String) -> Result<T, Error> {
+        Err(Error::Invalid {
+            file: self.files.last().cloned(),
+            line: line(node),
+            why,
+        })
+    }
+
+    fn warn(&mut self, node: Node, why: String) {
+        let warning = format!("line {}: {}", line(node), why);
+        self.warnings.push(match self.files.last() {
+            Some(file) => format!("{}: {}", file.display(), warning),
+            None => warning,
+        });
+    }
+
+    /// Reports the children of p that weren't used.
+    fn finish(&mut self, p: Plugin) {
+        let what = p.describe();
+        for (c, used) in p.children.iter().zip(p.used) {
+            if used {
+                continue;
+            }
+            let why = match c.attribute("name") {
+                Some(name) => format!(
+                    "ignoring {} parameter \"{}\", which has no pbrt equivalent",
+                    what, name
+                ),
+                None => format!(
+                    "ignoring {} in {}, which has no pbrt equivalent",
+                    c.tag_name().name(),
+                    what
+                ),
+            };
+            self.warn(*c, why);
+        }
+    }
+
+    fn unsupported(&mut self, p: &Plugin) {
+        let why = format!("ignoring {}, which has no pbrt equivalent", p.describe());
+        self.warn(p.node, why);
+    }
+
+    /// Returns a new name starting with prefix, for something without an id.
+    fn new_name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    /// Returns the path of a file named in the scene.
+    fn resolve(&self, filename: &str) -> String {
+        self.dir.join(filename).to_string_lossy().into_owned()
+    }
+
+    /// Returns the attribute called name, with references to defaults expanded.
+    fn attr(&self, node: Node, name: &str) -> Option<String> {
+        let value = node.attribute(name)?;
+        if !value.contains('$') {
+            return Some(value.to_owned());
+        }
+        // Longest first, so $spp doesn't replace the start of $spp2.
+        let mut names: Vec<&String> = self.defaults.keys().collect();
+        names.sort_by_key(|n| std::cmp::Reverse(n.len()));
+        let mut value = value.to_owned();
+        for name in names {
+            value = value.replace(&format!("${}", name), &self.defaults[name]);
+        }
+        Some(value)
+    }
+
+    fn required(&self, node: Node, name: &str) -> Result<String, Error> {
+        match self.attr(node, name) {
+            Some(value) => Ok(value),
+            None => self.invalid(
+                node,
+                format!("<{}> without a {} attribute", node.tag_name().name(), name),
+            ),
+        }
+    }
+
+    /// Parses the numbers in s, separated by commas or spaces.
+    fn numbers(&self, node: Node, s: &str) -> Result<Vec<Float>, Error> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|n| !n.is_empty())
+            .map(|n| match n.parse() {
+                Ok(n) => Ok(n),
+                Err(_) => self.invalid(node, format!("bad number \"{}\"", n)),
+            })
+            .collect()
+    }
+
+    /// Parses the three numbers in the attribute called name.
+    fn triple(&self, node: Node, name: &str) -> Result<[Float; 3], Error> {
+        match self.numbers(node, &self.required(node, name)?)?[..] {
+            [x, y, z] => Ok([x, y, z]),
+            _ => self.invalid(node, format!("expected 3 numbers in {}", name)),
+        }
+    }
+
+    fn number(&self, node: Node) -> Result<Float, Error> {
+        match self.numbers(node, &self.required(node, "value")?)?[..] {
+            [v] => Ok(v),
+            _ => self.invalid(node, "expected a number".to_owned()),
+        }
+    }
+
+    /// Returns the x, y and z of a point, vector or transform operation, which are given as
+    /// attributes, defaulting to default, or as one or three numbers in its value.
+    fn xyz(&self, node: Node, default: Float) -> Result<[Float; 3], Error> {
+        if node.attribute("value").is_some() {
+            return match self.numbers(node, &self.required(node, "value")?)?[..] {
+                [v] => Ok([v, v, v]),
+                [x, y, z] => Ok([x, y, z]),
+                _ => self.invalid(node, "expected 1 or 3 numbers".to_owned()),
+            };
+        }
+        let mut v = [default; 3];
+        for (v, name) in v.iter_mut().zip(&["x", "y", "z"]) {
+            if let Some(s) = self.attr(node, name) {
+                match self.numbers(node, &s)?[..] {
+                    [n] => *v = n,
+                    _ => return self.invalid(node, format!("expected a number in {}", name)),
+                }
+            }
+        }
+        Ok(v)
+    }
+
+    /// Warns that the parameter node is given as something other than what's expected.
+    fn wrong_type(&mut self, p: &Plugin, node: Node, expected: &str) {
+        let why = format!(
+            "ignoring {} parameter \"{}\": a <{}> has no pbrt equivalent, only {}",
+            p.describe(),
+            node.attribute("name").unwrap_or(""),
+            node.tag_name().name(),
+            expected
+        );
+        self.warn(node, why);
+    }
+
+    fn float(&mut self, p: &mut Plugin, name: &str) -> Result<Option<Float>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        match node.tag_name().name() {
+            "float" | "integer" => self.number(node).map(Some),
+            _ => {
+                self.wrong_type(p, node, "a number");
+                Ok(None)
+            }
+        }
+    }
+
+    fn int(&mut self, p: &mut Plugin, name: &str) -> Result<Option<i64>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if node.tag_name().name() != "integer" {
+            self.wrong_type(p, node, "an integer");
+            return Ok(None);
+        }
+        let value = self.required(node, "value")?;
+        match value.trim().parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => self.invalid(node, format!("bad integer \"{}\"", value)),
+        }
+    }
+
+    fn string(&mut self, p: &mut Plugin, name: &str) -> Result<Option<String>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if node.tag_name().name() != "string" {
+            self.wrong_type(p, node, "a string");
+            return Ok(None);
+        }
+        self.required(node, "value").map(Some)
+    }
+
+    fn point(&mut self, p: &mut Plugin, name: &str) -> Result<Option<[Float; 3]>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        match node.tag_name().name() {
+            "point" | "vector" => self.xyz(node, 0.).map(Some),
+            _ => {
+                self.wrong_type(p, node, "a point or vector");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the spectrum parameter called name as an rgb, spectrum or blackbody value, or as a
+    /// texture, which is defined first.
+    fn spectrum(&mut self, p: &mut Plugin, name: &str) -> Result<Option<Value>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let v = match node.tag_name().name() {
+            "float" | "integer" => gray(self.number(node)?),
+            "rgb" | "srgb" => {
+                let value = self.required(node, "value")?;
+                let hex = value.trim().trim_start_matches('#');
+                let mut rgb = if value.trim().starts_with('#') && hex.len() == 6 {
+                    let channel = |i| u8::from_str_radix(&hex[i..i + 2], 16);
+                    match (channel(0), channel(2), channel(4)) {
+                        (Ok(r), Ok(g), Ok(b)) => vec![r, g, b]
+                            .into_iter()
+                            .map(|c| Float::from(c) / 255.)
+                            .collect(),
+                        _ => return self.invalid(node, format!("bad color \"{}\"", value)),
+                    }
+                } else {
+                    self.numbers(node, &value)?
+                };
+                if rgb.len() == 1 {
+                    rgb = vec![rgb[0]; 3];
+                }
+                if rgb.len() != 3 {
+                    return self.invalid(node, "expected 1 or 3 numbers".to_owned());
+                }
+                if node.tag_name().name() == "srgb" || value.trim().starts_with('#') {
+                    rgb = rgb.into_iter().map(srgb_to_linear).collect();
+                }
+                Value::RGB(rgb.into())
+            }
+            "spectrum" => {
+                if let Some(filename) = self.attr(node, "filename") {
+                    Value::SpectrumFile(vec![self.resolve(&filename)].into())
+                } else {
+                    let value = self.required(node, "value")?;
+                    if value.contains(':') {
+                        let mut pairs = Vec::new();
+                        for pair in value.split(',').filter(|p| !p.trim().is_empty()) {
+                            match self.numbers(node, &pair.replace(':', " "))?[..] {
+                                [lambda, v] => pairs.extend_from_slice(&[lambda, v]),
+                                _ => {
+                                    return self.invalid(
+                                        node,
+                                        format!("bad wavelength:value pair \"{}\"", pair),
+                                    )
+                                }
+                            }
+                        }
+                        Value::SampledSpectrum(pairs.into())
+                    } else {
+                        gray(self.number(node)?)
+                    }
+                }
+            }
+            "blackbody" => {
+                let t = self.required(node, "temperature")?;
+                let t = self.numbers(node, t.trim().trim_end_matches(['k', 'K']))?;
+                let scale = match self.attr(node, "scale") {
+                    Some(s) => self.numbers(node, &s)?,
+                    None => vec![1.],
+                };
+                match (&t[..], &scale[..]) {
+                    (&[t], &[scale]) => Value::Blackbody(vec![t, scale].into()),
+                    _ => return self.invalid(node, "bad blackbody".to_owned()),
+                }
+            }
+            "texture" => match self.texture(node)? {
+                Some(texture) => Value::Texture(vec![texture].into()),
+                None => return Ok(None),
+            },
+            "ref" => {
+                let id = self.required(node, "id")?;
+                if !self.textures.contains(&id) {
+                    return self.invalid(node, format!("no texture \"{}\"", id));
+                }
+                Value::Texture(vec![id].into())
+            }
+            _ => {
+                self.wrong_type(p, node, "a spectrum or texture");
+                return Ok(None);
+            }
+        };
+        Ok(Some(v))
+    }
+
+    /// Adds the spectrum parameter called name to ps as param, if it's given.
+    fn add_spectrum(
+        &mut self,
+        p: &mut Plugin,
+        ps: &mut ParamSet,
+        name: &str,
+        param: &str,
+    ) -> Result<(), Error> {
+        if let Some(v) = self.spectrum(p, name)? {
+            ps.add(param, v);
+        }
+        Ok(())
+    }
+
+    /// Adds the number parameter called name to ps as param, if it's given.
+    fn add_float(
+        &mut self,
+        p: &mut Plugin,
+        ps: &mut ParamSet,
+        name: &str,
+        param: &str,
+    ) -> Result<(), Error> {
+        if let Some(v) = self.float(p, name)? {
+            ps.add(param, float_value(v));
+        }
+        Ok(())
+    }
+
+    /// Returns the transform parameter called name, e.g. "toWorld".
+    fn transform(&mut self, p: &mut Plugin, name: &str) -> Result<Option<Matrix4x4>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if node.tag_name().name() != "transform" {
+            self.wrong_type(p, node, "a transform");
+            return Ok(None);
+        }
+        let mut m = Matrix4x4::identity();
+        for op in node.children().filter(|c| c.is_element()) {
+            let t = match op.tag_name().name() {
+                "translate" => {
+                    let [x, y, z] = self.xyz(op, 0.)?;
+                    Transform::translate(&Vector3f::new(x, y, z)).matrix()
+                }
+                "scale" => {
+                    let [x, y, z] = self.xyz(op, 1.)?;
+                    Transform::scale(x, y, z).matrix()
+                }
+                "rotate" => {
+                    let [x, y, z] = self.xyz(op, 0.)?;
+                    let angle = self.numbers(op, &self.required(op, "angle")?)?;
+                    match angle[..] {
+                        [angle] => Transform::rotate(angle, &Vector3f::new(x, y, z)).matrix(),
+                        _ => return self.invalid(op, "expected a number in angle".to_owned()),
+                    }
+                }
+                "matrix" => match self.numbers(op, &self.required(op, "value")?)?[..] {
+                    [a, b, c, d, e, f, g, h, i, j, k, l, m, n, o, p] => {
+                        Matrix4x4::new([a, b, c, d], [e, f, g, h], [i, j, k, l], [m, n, o, p])
+                    }
+                    [a, b, c, d, e, f, g, h, i] => Matrix4x4::new(
+                        [a, b, c, 0.],
+                        [d, e, f, 0.],
+                        [g, h, i, 0.],
+                        [0., 0., 0., 1.],
+                    ),
+                    _ => return self.invalid(op, "expected 9 or 16 numbers".to_owned()),
+                },
+                "lookat" => {
+                    let [ox, oy, oz] = self.triple(op, "origin")?;
+                    let [tx, ty, tz] = self.triple(op, "target")?;
+                    let [ux, uy, uz] = match op.attribute("up") {
+                        Some(_) => self.triple(op, "up")?,
+                        None => [0., 1., 0.],
+                    };
+                    // Mitsuba's lookat is pbrt's LookAt's camera to world transform.
+                    Transform::look_at(
+                        &Point3f {
+                            x: ox,
+                            y: oy,
+                            z: oz,
+                        },
+                        &Point3f {
+                            x: tx,
+                            y: ty,
+                            z: tz,
+                        },
+                        &Vector3f::new(ux, uy, uz),
+                    )
+                    .matrix_inverse()
+                }
+                tag => {
+                    let why = format!("ignoring <{}>, which has no pbrt equivalent", tag);
+                    self.warn(op, why);
+                    continue;
+                }
+            };
+            m = t * m;
+        }
+        Ok(Some(m))
+    }
+
+    /// Adds pbrt's uv mapping parameters for the texture p to ps.  Mitsuba's uv coordinates are
+    /// multiplied by scale after its own scale and offset are applied.
+    fn uv_mapping(&mut self, p: &mut Plugin, ps: &mut ParamSet, scale: Float) -> Result<(), Error> {
+        let uvscale = self.float(p, "uvscale")?.unwrap_or(1.);
+        let uscale = self.float(p, "uscale")?.unwrap_or(uvscale) * scale;
+        let vscale = self.float(p, "vscale")?.unwrap_or(uvscale) * scale;
+        let udelta = self.float(p, "uoffset")?.unwrap_or(0.) * scale;
+        let vdelta = self.float(p, "voffset")?.unwrap_or(0.) * scale;
+        for &(name, v, default) in &[
+            ("uscale", uscale, 1.),
+            ("vscale", vscale, 1.),
+            ("udelta", udelta, 0.),
+            ("vdelta", vdelta, 0.),
+        ] {
+            if v != default {
+                ps.add(name, float_value(v));
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines the spectrum texture element node, and returns its name, or None if it has no pbrt
+    /// equivalent.
+    fn texture(&mut self, node: Node) -> Result<Option<String>, Error> {
+        let mut p = Plugin::new(node);
+        let mut ps = ParamSet::default();
+        let class = match p.kind() {
+            "bitmap" => {
+                let filename = match self.string(&mut p, "filename")? {
+                    Some(filename) => filename,
+                    None => return self.invalid(node, "bitmap without a filename".to_owned()),
+                };
+                ps.add("filename", string_value(&self.resolve(&filename)));
+                if let Some(wrap) = self.string(&mut p, "wrapMode")? {
+                    let wrap = match wrap.as_str() {
+                        "repeat" => "repeat",
+                        "clamp" => "clamp",
+                        "zero" | "black" => "black",
+                        _ => {
+                            let why = format!(
+                                "wrap mode \"{}\" has no pbrt equivalent, using \"repeat\"",
+                                wrap
+                            );
+                            self.warn(node, why);
+                            "repeat"
+                        }
+                    };
+                    ps.add("wrap", string_value(wrap));
+                }
+                self.uv_mapping(&mut p, &mut ps, 1.)?;
+                "imagemap"
+            }
+            "checkerboard" => {
+                let tex1 = self
+                    .spectrum(&mut p, "color0")?
+                    .unwrap_or_else(|| gray(0.4));
+                let tex2 = self
+                    .spectrum(&mut p, "color1")?
+                    .unwrap_or_else(|| gray(0.2));
+                ps.add("tex1", tex1);
+                ps.add("tex2", tex2);
+                // Mitsuba's checks are half as big as pbrt's.
+                self.uv_mapping(&mut p, &mut ps, 2.)?;
+                "checkerboard"
+            }
+            _ => {
+                self.unsupported(&p);
+                return Ok(None);
+            }
+        };
+        let name = match self.attr(node, "id") {
+            Some(id) => id,
+            None => self.new_name("texture"),
+        };
+        self.finish(p);
+        self.world.push(Directive::Texture(
+            name.clone(),
+            "spectrum".to_owned(),
+            class.to_owned(),
+            ps,
+        ));
+        self.textures.insert(name.clone());
+        Ok(Some(name))
+    }
+
+    /// Returns the index of refraction called name, which may be a number or a material's name.
+    fn ior(&mut self, p: &mut Plugin, name: &str, default: Float) -> Result<Float, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(default),
+        };
+        match node.tag_name().name() {
+            "float" => self.number(node),
+            "string" => {
+                let value = self.required(node, "value")?;
+                match IORS.iter().find(|&&(name, _)| name == value.to_lowercase()) {
+                    Some(&(_, ior)) => Ok(ior),
+                    None => {
+                        self.invalid(node, format!("unknown index of refraction \"{}\"", value))
+                    }
+                }
+            }
+            _ => {
+                self.wrong_type(p, node, "a number or material name");
+                Ok(default)
+            }
+        }
+    }
+
+    /// Adds the anisotropic roughness of the rough bsdf p to ps.
+    fn roughness(&mut self, p: &mut Plugin, ps: &mut ParamSet) -> Result<(), Error> {
+        if let Some(distribution) = self.string(p, "distribution")? {
+            if distribution != "ggx" {
+                let why = format!(
+                    "{}'s {} distribution is approximated with pbrt's Trowbridge-Reitz (GGX)",
+                    p.describe(),
+                    distribution
+                );
+                self.warn(p.node, why);
+            }
+        }
+        let alpha = self.float(p, "alpha")?.unwrap_or(0.1);
+        let u = self.float(p, "alphaU")?.unwrap_or(alpha);
+        let v = self.float(p, "alphaV")?.unwrap_or(alpha);
+        ps.add("uroughness", float_value(u));
+        ps.add("vroughness", float_value(v));
+        ps.add("remaproughness", Value::Bool(vec![false].into()));
+        Ok(())
+    }
+
+    /// Returns the pbrt material for the bsdf element node, defining the textures and named
+    /// materials it uses first, or None if it has no pbrt equivalent.
+    fn material(&mut self, node: Node) -> Result<Option<(String, ParamSet)>, Error> {
+        let mut p = Plugin::new(node);
+        let mut ps = ParamSet::default();
+        let kind = p.kind();
+        let name = match kind {
+            "diffuse" | "roughdiffuse" => {
+                self.add_spectrum(&mut p, &mut ps, "reflectance", "Kd")?;
+                if kind == "roughdiffuse" {
+                    // Mitsuba's Oren-Nayar sigma is alpha / sqrt(2) radians.
+                    let alpha = self.float(&mut p, "alpha")?.unwrap_or(0.2);
+                    ps.add("sigma", float_value((alpha / SQRT_2).to_degrees()));
+                }
+                "matte"
+            }
+            "dielectric" | "roughdielectric" | "thindielectric" => {
+                let int_ior = self.ior(&mut p, "intIOR", 1.5046)?;
+                let ext_ior = self.ior(&mut p, "extIOR", 1.000_277)?;
+                ps.add("eta", float_value(int_ior / ext_ior));
+                self.add_spectrum(&mut p, &mut ps, "specularReflectance", "Kr")?;
+                self.add_spectrum(&mut p, &mut ps, "specularTransmittance", "Kt")?;
+                if kind == "roughdielectric" {
+                    self.roughness(&mut p, &mut ps)?;
+                } else if kind == "thindielectric" {
+                    let why = format!("{} is approximated with glass", p.describe());
+                    self.warn(node, why);
+                }
+                "glass"
+            }
+            "conductor"
+                if p.prop("material").and_then(|m| m.attribute("value")) == Some("none") =>
+            {
+                self.add_spectrum(&mut p, &mut ps, "specularReflectance", "Kr")?;
+                "mirror"
+            }
+            "conductor" | "roughconductor" => {
+                let material = self
+                    .string(&mut p, "material")?
+                    .unwrap_or_else(|| "Cu".to_owned());
+                let eta = self.spectrum(&mut p, "eta")?;
+                let k = self.spectrum(&mut p, "k")?;
+                let rgb = |v: &[Float; 3]| Value::RGB(v.to_vec().into());
+                let named = CONDUCTORS.iter().find(|c| c.0 == material);
+                if named.is_none() && (eta.is_none() || k.is_none()) {
+                    let why = format!(
+                        "conductor material \"{}\" has no pbrt equivalent, using copper",
+                        material
+                    );
+                    self.warn(node, why);
+                }
+                let (named_eta, named_k) =
+                    named.map_or((None, None), |c| (Some(rgb(&c.1)), Some(rgb(&c.2))));
+                if let Some(eta) = eta.or(named_eta) {
+                    ps.add("eta", eta);
+                }
+                if let Some(k) = k.or(named_k) {
+                    ps.add("k", k);
+                }
+                if kind == "roughconductor" {
+                    self.roughness(&mut p, &mut ps)?;
+                } else {
+                    ps.add("roughness", float_value(0.));
+                    ps.add("remaproughness", Value::Bool(vec![false].into()));
+                }
+                "metal"
+            }
+            "plastic" | "roughplastic" => {
+                self.add_spectrum(&mut p, &mut ps, "diffuseReflectance", "Kd")?;
+                // Mitsuba's specular reflectance defaults to 1, and pbrt's to 0.25.
+                let ks = self
+                    .spectrum(&mut p, "specularReflectance")?
+                    .unwrap_or_else(|| gray(1.));
+                ps.add("Ks", ks);
+                let alpha = match kind {
+                    "roughplastic" => self.float(&mut p, "alpha")?.unwrap_or(0.1),
+                    _ => 0.,
+                };
+                ps.add("roughness", float_value(alpha));
+                ps.add("remaproughness", Value::Bool(vec![false].into()));
+                "plastic"
+            }
+            "twosided" | "mask" | "bumpmap" | "normalmap" => {
+                // pbrt's materials are two-sided, and the others' effects are reported as
+                // unused parameters.
+                let nested = p.object(&["bsdf", "ref"]);
+                self.finish(p);
+                return match nested {
+                    Some(nested) => self.nested_material(nested),
+                    None => self.invalid(node, format!("{} without a nested bsdf", kind)),
+                };
+            }
+            "blendbsdf" | "mixturebsdf" => {
+                let amount = if kind == "blendbsdf" {
+                    self.spectrum(&mut p, "weight")?
+                } else {
+                    let weights = self.string(&mut p, "weights")?.unwrap_or_default();
+                    match self.numbers(node, &weights)?[..] {
+                        [w1, w2] if w1 + w2 > 0. => Some(gray(w2 / (w1 + w2))),
+                        _ => None,
+                    }
+                };
+                let bsdfs = p.objects(&["bsdf", "ref"]);
+                let (amount, first, second) = match (amount, &bsdfs[..]) {
+                    (Some(amount), &[first, second]) => (amount, first, second),
+                    _ => {
+                        let why = format!(
+                            "{} only has a pbrt equivalent with two bsdfs and their weights",
+                            p.describe()
+                        );
+                        self.warn(node, why);
+                        return Ok(None);
+                    }
+                };
+                match (self.named_material(first)?, self.named_material(second)?) {
+                    (Some(first), Some(second)) => {
+                        // pbrt's amount is the weight of namedmaterial1, and Mitsuba's weight is
+                        // that of the second bsdf.
+                        ps.add("amount", amount);
+                        ps.add("namedmaterial1", string_value(&second));
+                        ps.add("namedmaterial2", string_value(&first));
+                    }
+                    _ => return Ok(None),
+                }
+                "mix"
+            }
+            "null" => "none",
+            "principled" => {
+                self.add_spectrum(&mut p, &mut ps, "baseColor", "color")?;
+                for &(name, param) in &[
+                    ("metallic", "metallic"),
+                    ("roughness", "roughness"),
+                    ("anisotropic", "anisotropic"),
+                    ("specTint", "speculartint"),
+                    ("sheen", "sheen"),
+                    ("sheenTint", "sheentint"),
+                    ("clearcoat", "clearcoat"),
+                    ("clearcoatGloss", "clearcoatgloss"),
+                    ("specTrans", "spectrans"),
+                    ("eta", "eta"),
+                ] {
+                    self.add_float(&mut p, &mut ps, name, param)?;
+                }
+                "disney"
+            }
+            _ => {
+                self.unsupported(&p);
+                return Ok(None);
+            }
+        };
+        self.finish(p);
+        Ok(Some((name.to_owned(), ps)))
+    }
+
+    /// Returns the material for a bsdf or ref element nested in another element.
+    fn nested_material(&mut self, node: Node) -> Result<Option<(String, ParamSet)>, Error> {
+        if node.tag_name().name() == "bsdf" {
+            return self.material(node);
+        }
+        let id = self.named_material(node)?;
+        Ok(id.map(|id| self.materials[&id].clone()))
+    }
+
+    /// Returns the name of the named material for the bsdf or ref element node, defining it first
+    /// if it's a bsdf, or None if it has no pbrt equivalent.
+    fn named_material(&mut self, node: Node) -> Result<Option<String>, Error> {
+        if node.tag_name().name() == "ref" {
+            let id = self.required(node, "id")?;
+            if !self.materials.contains_key(&id) {
+                return self.invalid(node, format!("no bsdf \"{}\"", id));
+            }
+            return Ok(Some(id));
+        }
+        let (name, mut ps) = match self.material(node)? {
+            Some(material) => material,
+            None => return Ok(None),
+        };
+        let id = match self.attr(node, "id") {
+            Some(id) => id,
+            None => self.new_name("material"),
+        };
+        self.materials
+            .insert(id.clone(), (name.clone(), ps.clone()));
+        ps.add("type", string_value(&name));
+        self.world
+            .push(Directive::MakeNamedMaterial(id.clone(), ps));
+        Ok(Some(id))
+    }
+
+    /// Returns the directive setting the material of the shape p, if it has a bsdf, defining the
+    /// textures and named materials it uses first.
+    fn shape_material(&mut self, p: &mut Plugin) -> Result<Option<Directive>, Error> {
+        let node = match p.object(&["bsdf", "ref"]) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        if node.tag_name().name() == "ref" || node.attribute("id").is_some() {
+            return Ok(self.named_material(node)?.map(Directive::NamedMaterial));
+        }
+        Ok(self
+            .material(node)?
+            .map(|(name, ps)| Directive::Material(name, ps)))
+    }
+
+    /// Defines the medium element node, and returns its name, or None if it has no pbrt
+    /// equivalent.
+    fn medium(&mut self, node: Node) -> Result<Option<String>, Error> {
+        let mut p = Plugin::new(node);
+        if p.kind() != "homogeneous" {
+            self.unsupported(&p);
+            return Ok(None);
+        }
+        let mut ps = ParamSet::default();
+        ps.add("type", string_value("homogeneous"));
+        self.add_spectrum(&mut p, &mut ps, "sigmaA", "sigma_a")?;
+        self.add_spectrum(&mut p, &mut ps, "sigmaS", "sigma_s")?;
+        self.add_float(&mut p, &mut ps, "scale", "scale")?;
+        if let Some(phase) = p.object(&["phase"]) {
+            let mut phase = Plugin::new(phase);
+            match phase.kind() {
+                "isotropic" => (),
+                "hg" => self.add_float(&mut phase, &mut ps, "g", "g")?,
+                _ => self.unsupported(&phase),
+            }
+            self.finish(phase);
+        }
+        let id = match self.attr(node, "id") {
+            Some(id) => id,
+            None => self.new_name("medium"),
+        };
+        self.finish(p);
+        self.world.push(Directive::MakeNamedMedium(id.clone(), ps));
+        self.media.insert(id.clone());
+        Ok(Some(id))
+    }
+
+    /// Returns the name of the medium parameter called name, e.g. a shape's "interior", defining
+    /// it first if it's nested.
+    fn medium_param(&mut self, p: &mut Plugin, name: &str) -> Result<Option<String>, Error> {
+        let node = match p.prop(name) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        match node.tag_name().name() {
+            "medium" => self.medium(node),
+            "ref" => {
+                let id = self.required(node, "id")?;
+                if !self.media.contains(&id) {
+                    return self.invalid(node, format!("no medium \"{}\"", id));
+                }
+                Ok(Some(id))
+            }
+            _ => {
+                self.wrong_type(p, node, "a medium");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the area light of the shape p, if it has an emitter.
+    fn area_light(&mut self, p: &mut Plugin) -> Result<Option<Directive>, Error> {
+        let mut e = match p.object(&["emitter"]) {
+            Some(node) => Plugin::new(node),
+            None => return Ok(None),
+        };
+        if e.kind() != "area" {
+            self.unsupported(&e);
+            return Ok(None);
+        }
+        let mut ps = ParamSet::default();
+        self.add_spectrum(&mut e, &mut ps, "radiance", "L")?;
+        self.finish(e);
+        Ok(Some(Directive::AreaLightSource("diffuse".to_owned(), ps)))
+    }
+
+    /// Adds the shape element node, in its own attribute block.
+    fn shape(&mut self, node: Node) -> Result<(), Error> {
+        let p = Plugin::new(node);
+        if p.kind() == "shapegroup" {
+            return self.shapegroup(node, p);
+        }
+        if let Some(block) = self.shape_block(node, p)? {
+            self.world.extend(block);
+        }
+        Ok(())
+    }
+
+    /// Returns the attribute block making the shape p, whose element is node, or None if it has
+    /// no pbrt equivalent.  The materials, textures and media it uses are defined first.
+    fn shape_block(&mut self, node: Node, mut p: Plugin) -> Result<Option<Vec<Directive>>, Error> {
+        let kind = p.kind();
+        let to_world = self.transform(&mut p, "toWorld")?;
+        // The directives making the shape, in object space.
+        let mut shape = Vec::new();
+        match kind {
+            "obj" => {
+                let filename = match self.string(&mut p, "filename")? {
+                    Some(filename) => self.resolve(&filename),
+                    None => return self.invalid(node, "obj without a filename".to_owned()),
+                };
+                let mut warnings = Vec::new();
+                let directives = match obj::read_file(&filename, &mut warnings) {
+                    Ok(directives) => directives,
+                    Err(e) => return self.invalid(node, format!("{}: {}", filename, e)),
+                };
+                for warning in warnings {
+                    self.warn(node, format!("{}: {}", filename, warning));
+                }
+                // Mitsuba doesn't use the .obj file's materials.
+                shape.extend(
+                    directives
+                        .into_iter()
+                        .filter(|d| matches!(d, Directive::Shape(..))),
+                );
+            }
+            "ply" => {
+                let filename = match self.string(&mut p, "filename")? {
+                    Some(filename) => self.resolve(&filename),
+                    None => return self.invalid(node, "ply without a filename".to_owned()),
+                };
+                let mut ps = ParamSet::default();
+                ps.add("filename", string_value(&filename));
+                shape.push(Directive::Shape("plymesh".to_owned(), ps));
+            }
+            "sphere" => {
+                if let Some([x, y, z]) = self.point(&mut p, "center")? {
+                    shape.push(Directive::Translate(x, y, z));
+                }
+                let mut ps = ParamSet::default();
+                self.add_float(&mut p, &mut ps, "radius", "radius")?;
+                shape.push(Directive::Shape("sphere".to_owned(), ps));
+            }
+            "cylinder" => {
+                let p0 = self.point(&mut p, "p0")?.unwrap_or([0., 0., 0.]);
+                let p1 = self.point(&mut p, "p1")?.unwrap_or([0., 0., 1.]);
+                let d = Vector3f::new(p1[0] - p0[0], p1[1] - p0[1], p1[2] - p0[2]);
+                let length = d.length();
+                if length == 0. {
+                    return self.invalid(node, "cylinder with p0 equal to p1".to_owned());
+                }
+                // pbrt's cylinders are along z, so map z to p1 - p0.
+                let w = d.normalize();
+                let u = if w.x.abs() > w.y.abs() {
+                    Vector3f::new(-w.z, 0., w.x).normalize()
+                } else {
+                    Vector3f::new(0., w.z, -w.y).normalize()
+                };
+                let v = cross(&w, &u);
+                let frame = Matrix4x4::new(
+                    [u.x, v.x, w.x, p0[0]],
+                    [u.y, v.y, w.y, p0[1]],
+                    [u.z, v.z, w.z, p0[2]],
+                    [0., 0., 0., 1.],
+                );
+                shape.push(Directive::ConcatTransform(to_columns(&frame)));
+                let mut ps = ParamSet::default();
+                self.add_float(&mut p, &mut ps, "radius", "radius")?;
+                ps.add("zmin", float_value(0.));
+                ps.add("zmax", float_value(length));
+                shape.push(Directive::Shape("cylinder".to_owned(), ps));
+            }
+            "disk" => shape.push(Directive::Shape("disk".to_owned(), ParamSet::default())),
+            "rectangle" => {
+                let mesh = TriangleMesh {
+                    indices: vec![0, 1, 2, 0, 2, 3],
+                    p: [[-1., -1.], [1., -1.], [1., 1.], [-1., 1.]]
+                        .iter()
+                        .map(|&[x, y]| Point3f { x, y, z: 0. })
+                        .collect(),
+                    uv: [[0., 0.], [1., 0.], [1., 1.], [0., 1.]]
+                        .iter()
+                        .map(|&[x, y]| Point2f { x, y })
+                        .collect(),
+                    ..Default::default()
+                };
+                shape.push(Directive::Shape(
+                    "trianglemesh".to_owned(),
+                    ParamSet::from(mesh),
+                ));
+            }
+            "cube" => {
+                // Vertex i is at -1 or 1 in x, y and z as bits 0, 1 and 2 of i are clear or set.
+                let coord = |i: usize, bit: usize| if i & (1 << bit) == 0 { -1. } else { 1. };
+                let mesh = TriangleMesh {
+                    indices: vec![
+                        0, 4, 6, 0, 6, 2, 1, 3, 7, 1, 7, 5, 0, 1, 5, 0, 5, 4, 2, 6, 7, 2, 7, 3, 0,
+                        2, 3, 0, 3, 1, 4, 5, 7, 4, 7, 6,
+                    ],
+                    p: (0..8)
+                        .map(|i| Point3f {
+                            x: coord(i, 0),
+                            y: coord(i, 1),
+                            z: coord(i, 2),
+                        })
+                        .collect(),
+                    ..Default::default()
+                };
+                shape.push(Directive::Shape(
+                    "trianglemesh".to_owned(),
+                    ParamSet::from(mesh),
+                ));
+            }
+            "instance" => {
+                let group = match p.object(&["ref"]) {
+                    Some(group) => group,
+                    None => return self.invalid(node, "instance without a ref".to_owned()),
+                };
+                let id = self.required(group, "id")?;
+                match self.groups.get(&id) {
+                    Some(group) => shape.extend(group.iter().cloned()),
+                    None => return self.invalid(group, format!("no shapegroup \"{}\"", id)),
+                }
+            }
+            _ => {
+                self.unsupported(&p);
+                return Ok(None);
+            }
+        }
+        let interior = self.medium_param(&mut p, "interior")?;
+        let exterior = self.medium_param(&mut p, "exterior")?;
+        let material = self.shape_material(&mut p)?;
+        let area_light = self.area_light(&mut p)?;
+        self.finish(p);
+
+        let mut block = vec![Directive::AttributeBegin];
+        if let Some(m) = to_world {
+            block.push(Directive::ConcatTransform(to_columns(&m)));
+        }
+        if interior.is_some() || exterior.is_some() {
+            block.push(Directive::MediumInterface(
+                interior.unwrap_or_default(),
+                exterior.unwrap_or_default(),
+            ));
+        }
+        block.extend(material);
+        block.extend(area_light);
+        block.extend(shape);
+        block.push(Directive::AttributeEnd);
+        Ok(Some(block))
+    }
+
+    /// Converts the shapes of a shapegroup, to be added by instances of it.  The materials and
+    /// textures they use are defined once, where the group is, whether or not it's instanced.
+    fn shapegroup(&mut self, node: Node, mut p: Plugin) -> Result<(), Error> {
+        let id = self.required(node, "id")?;
+        let mut group = Vec::new();
+        for shape in p.objects(&["shape"]) {
+            let child = Plugin::new(shape);
+            if child.kind() == "shapegroup" {
+                self.shapegroup(shape, child)?;
+            } else if let Some(block) = self.shape_block(shape, child)? {
+                group.extend(block);
+            }
+        }
+        self.finish(p);
+        self.groups.insert(id, group);
+        Ok(())
+    }
+
+    /// Adds the light for the emitter element node, which isn't in a shape.
+    fn emitter(&mut self, node: Node) -> Result<(), Error> {
+        let mut p = Plugin::new(node);
+        let to_world = self.transform(&mut p, "toWorld")?;
+        let mut ps = ParamSet::default();
+        let kind = p.kind();
+        let name = match kind {
+            "point" => {
+                self.add_spectrum(&mut p, &mut ps, "intensity", "I")?;
+                if let Some(position) = self.point(&mut p, "position")? {
+                    ps.add("from", point_value(position));
+                }
+                "point"
+            }
+            "spot" => {
+                self.add_spectrum(&mut p, &mut ps, "intensity", "I")?;
+                let cutoff = self.float(&mut p, "cutoffAngle")?.unwrap_or(20.);
+                let beam_width = self.float(&mut p, "beamWidth")?.unwrap_or(cutoff * 0.75);
+                // Mitsuba's spot lights shine down z.
+                ps.add("from", point_value([0., 0., 0.]));
+                ps.add("to", point_value([0., 0., 1.]));
+                ps.add("coneangle", float_value(cutoff));
+                ps.add("conedeltaangle", float_value(cutoff - beam_width));
+                "spot"
+            }
+            "directional" => {
+                self.add_spectrum(&mut p, &mut ps, "irradiance", "L")?;
+                let direction = self.point(&mut p, "direction")?.unwrap_or([0., 0., 1.]);
+                ps.add("from", point_value([0., 0., 0.]));
+                ps.add("to", point_value(direction));
+                "distant"
+            }
+            "constant" => {
+                self.add_spectrum(&mut p, &mut ps, "radiance", "L")?;
+                "infinite"
+            }
+            "envmap" => {
+                let filename = match self.string(&mut p, "filename")? {
+                    Some(filename) => filename,
+                    None => return self.invalid(node, "envmap without a filename".to_owned()),
+                };
+                ps.add("mapname", string_value(&self.resolve(&filename)));
+                if let Some(scale) = self.float(&mut p, "scale")? {
+                    ps.add("L", gray(scale));
+                }
+                "infinite"
+            }
+            _ => {
+                self.unsupported(&p);
+                return Ok(());
+            }
+        };
+        self.finish(p);
+        let block = to_world.is_some() || kind == "envmap";
+        if block {
+            self.world.push(Directive::AttributeBegin);
+        }
+        if let Some(m) = to_world {
+            self.world.push(Directive::ConcatTransform(to_columns(&m)));
+        }
+        if kind == "envmap" {
+            // Mitsuba's environment maps have y up, and pbrt's z.
+            self.world.push(Directive::Rotate(-90., 1., 0., 0.));
+        }
+        self.world.push(Directive::LightSource(name.to_owned(), ps));
+        if block {
+            self.world.push(Directive::AttributeEnd);
+        }
+        Ok(())
+    }
+
+    /// Returns pbrt's field of view, which is of the image's shorter axis, for the perspective
+    /// sensor p of an image with the aspect ratio given.
+    fn fov(&mut self, p: &mut Plugin, aspect: Float) -> Result<Float, Error> {
+        let axis = self.string(p, "fovAxis")?.unwrap_or_else(|| "x".to_owned());
+        let (fov, axis) = match self.float(p, "fov")? {
+            Some(fov) => (fov, axis),
+            None => {
+                // A focal length for 35mm film, which gives the field of view of its diagonal.
+                let focal_length = self
+                    .string(p, "focalLength")?
+                    .unwrap_or_else(|| "50mm".to_owned());
+                let f = match self.numbers(p.node, focal_length.trim_end_matches("mm"))?[..] {
+                    [f] => f,
+                    _ => {
+                        let why = format!("bad focal length \"{}\"", focal_length);
+                        return self.invalid(p.node, why);
+                    }
+                };
+                let diagonal = (36. * 36. + 24. * 24. as Float).sqrt();
+                (
+                    (2. * (diagonal / (2. * f)).atan()).to_degrees(),
+                    "diagonal".to_owned(),
+                )
+            }
+        };
+        let t = (fov.to_radians() / 2.).tan();
+        let (tx, ty) = match axis.as_str() {
+            "x" => (t, t / aspect),
+            "y" => (t * aspect, t),
+            "diagonal" => {
+                let d = (1. + aspect * aspect).sqrt();
+                (t * aspect / d, t / d)
+            }
+            "smaller" if aspect > 1. => (t * aspect, t),
+            "smaller" => (t, t / aspect),
+            "larger" if aspect > 1. => (t, t / aspect),
+            "larger" => (t * aspect, t),
+            _ => return self.invalid(p.node, format!("unknown fov axis \"{}\"", axis)),
+        };
+        Ok((2. * tx.min(ty).atan()).to_degrees())
+    }
+
+    /// Adds the Film for the film element node, and returns its width and height.
+    fn film(&mut self, node: Node) -> Result<(i64, i64), Error> {
+        let mut p = Plugin::new(node);
+        let width = self.int(&mut p, "width")?.unwrap_or(768);
+        let height = self.int(&mut p, "height")?.unwrap_or(576);
+        let mut ps = ParamSet::default();
+        ps.add("xresolution", Value::Int(vec![width].into()));
+        ps.add("yresolution", Value::Int(vec![height].into()));
+        let x = self.int(&mut p, "cropOffsetX")?.unwrap_or(0);
+        let y = self.int(&mut p, "cropOffsetY")?.unwrap_or(0);
+        let crop_width = self.int(&mut p, "cropWidth")?.unwrap_or(width - x);
+        let crop_height = self.int(&mut p, "cropHeight")?.unwrap_or(height - y);
+        if (x, y, crop_width, crop_height) != (0, 0, width, height) {
+            let (w, h) = (width as Float, height as Float);
+            let window = vec![
+                x as Float / w,
+                (x + crop_width) as Float / w,
+                y as Float / h,
+                (y + crop_height) as Float / h,
+            ];
+            ps.add("cropwindow", Value::Float(window.into()));
+        }
+        // The image file's format doesn't change what's rendered.
+        p.skip(&["fileFormat", "pixelFormat", "componentFormat", "banner"]);
+        self.finish(p);
+        self.options.push(Directive::Film("image".to_owned(), ps));
+        Ok((width, height))
+    }
+
+    /// Adds the Sampler for the sampler element node.
+    fn sampler(&mut self, node: Node) -> Result<(), Error> {
+        let mut p = Plugin::new(node);
+        let name = match p.kind() {
+            "independent" => "random",
+            "stratified" => "stratified",
+            "ldsampler" => "lowdiscrepancy",
+            "halton" => "halton",
+            "sobol" => "sobol",
+            _ => {
+                self.unsupported(&p);
+                return Ok(());
+            }
+        };
+        let count = self.int(&mut p, "sampleCount")?.unwrap_or(4);
+        let mut ps = ParamSet::default();
+        if name == "stratified" {
+            let n = (count as Float).sqrt().ceil() as i64;
+            ps.add("xsamples", Value::Int(vec![n].into()));
+            ps.add("ysamples", Value::Int(vec![n].into()));
+        } else {
+            ps.add("pixelsamples", Value::Int(vec![count].into()));
+        }
+        self.finish(p);
+        self.options.push(Directive::Sampler(name.to_owned(), ps));
+        Ok(())
+    }
+
+    /// Adds the Camera, Film and Sampler for the sensor element node.
+    fn sensor(&mut self, node: Node) -> Result<(), Error> {
+        if self.sensor {
+            self.warn(node, "ignoring all but the first sensor".to_owned());
+            return Ok(());
+        }
+        self.sensor = true;
+        let mut p = Plugin::new(node);
+        let to_world = self
+            .transform(&mut p, "toWorld")?
+            .unwrap_or_else(Matrix4x4::identity);
+        let (width, height) = match p.object(&["film"]) {
+            Some(film) => self.film(film)?,
+            None => (768, 576),
+        };
+        if let Some(sampler) = p.object(&["sampler"]) {
+            self.sampler(sampler)?;
+        }
+        let mut ps = ParamSet::default();
+        let kind = p.kind();
+        let name = match kind {
+            "perspective" | "thinlens" => {
+                let fov = self.fov(&mut p, width as Float / height as Float)?;
+                ps.add("fov", float_value(fov));
+                if kind == "thinlens" {
+                    self.add_float(&mut p, &mut ps, "apertureRadius", "lensradius")?;
+                    self.add_float(&mut p, &mut ps, "focusDistance", "focaldistance")?;
+                }
+                "perspective"
+            }
+            "orthographic" => "orthographic",
+            _ => {
+                self.unsupported(&p);
+                return Ok(());
+            }
+        };
+        self.finish(p);
+        // Mitsuba's camera space has x to the left of the image, and pbrt's to the right.
+        self.options.push(Directive::Scale(-1., 1., 1.));
+        self.options
+            .push(Directive::ConcatTransform(to_columns(&to_world.inverse())));
+        self.options.push(Directive::Camera(name.to_owned(), ps));
+        Ok(())
+    }
+
+    /// Adds the Integrator for the integrator element node.
+    fn integrator(&mut self, node: Node) -> Result<(), Error> {
+        if self.integrator {
+            self.warn(node, "ignoring all but the first integrator".to_owned());
+            return Ok(());
+        }
+        self.integrator = true;
+        let mut p = Plugin::new(node);
+        let name = match p.kind() {
+            "path" => "path",
+            "volpath" | "volpath_simple" => "volpath",
+            "direct" => "directlighting",
+            "bdpt" => "bdpt",
+            "mlt" | "pssmlt" => "mlt",
+            "sppm" => "sppm",
+            "ao" => "ambientocclusion",
+            _ => {
+                self.unsupported(&p);
+                return Ok(());
+            }
+        };
+        let mut ps = ParamSet::default();
+        match self.int(&mut p, "maxDepth")? {
+            Some(-1) => {
+                let why = "unlimited maxDepth has no pbrt equivalent, using pbrt's default";
+                self.warn(node, why.to_owned());
+            }
+            // Mitsuba counts the segment to the camera, so its 1 is pbrt's 0, only emitted light.
+            Some(depth) => ps.add("maxdepth", Value::Int(vec![(depth - 1).max(0)].into())),
+            None => (),
+        }
+        if let Some(samples) = self.int(&mut p, "shadingSamples")? {
+            ps.add("nsamples", Value::Int(vec![samples].into()));
+        }
+        self.finish(p);
+        self.options
+            .push(Directive::Integrator(name.to_owned(), ps));
+        Ok(())
+    }
+
+    /// Converts the children of the scene element root, or of an included file's.
+    fn scene(&mut self, root: Node) -> Result<(), Error> {
+        if root.tag_name().name() != "scene" {
+            let why = format!("expected <scene>, found <{}>", root.tag_name().name());
+            return self.invalid(root, why);
+        }
+        for node in root.children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "default" => {
+                    let name = self.required(node, "name")?;
+                    let value = self.required(node, "value")?;
+                    self.defaults.entry(name).or_insert(value);
+                }
+                "include" => self.include(node)?,
+                "integrator" => self.integrator(node)?,
+                "sensor" => self.sensor(node)?,
+                "emitter" => self.emitter(node)?,
+                "shape" => self.shape(node)?,
+                "bsdf" => {
+                    self.named_material(node)?;
+                }
+                "texture" => {
+                    self.texture(node)?;
+                }
+                "medium" => {
+                    self.medium(node)?;
+                }
+                tag => {
+                    let why = format!("ignoring <{}>, which has no pbrt equivalent", tag);
+                    self.warn(node, why);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, node: Node) -> Result<(), Error> {
+        let path = self.dir.join(self.required(node, "filename")?);
+        if self.files.contains(&path) {
+            return self.invalid(node, format!("{} includes itself", path.display()));
+        }
+        let text = fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        let doc = Document::parse(&text).map_err(|e| Error::Xml(Some(path.clone()), e))?;
+        self.files.push(path);
+        let result = self.scene(doc.root_element());
+        self.files.pop();
+        result
+    }
+}
+
+/// Converts the Mitsuba scene in text.  Files it names are found in dir.  Warnings are added for
+/// anything that has no pbrt equivalent.
+pub fn read(text: &str, dir: &Path, warnings: &mut Vec<String>) -> Result<Scene, Error> {
+    let doc = Document::parse(text).map_err(|e| Error::Xml(None, e))?;
+    let mut c = Converter {
+        dir: dir.to_path_buf(),
+        files: Vec::new(),
+        defaults: HashMap::new(),
+        options: Vec::new(),
+        world: Vec::new(),
+        sensor: false,
+        integrator: false,
+        textures: HashSet::new(),
+        materials: HashMap::new(),
+        media: HashSet::new(),
+        groups: HashMap::new(),
+        names: 0,
+        warnings,
+    };
+    c.scene(doc.root_element())?;
+    let mut scene = Scene::default();
+    let directives = c
+        .options
+        .into_iter()
+        .chain(Some(Directive::WorldBegin))
+        .chain(c.world)
+        .chain(Some(Directive::WorldEnd));
+    for d in directives {
+        scene.push(d, Default::default());
+    }
+    Ok(scene)
+}
+
+/// Converts the Mitsuba scene file at path.  See read.
+pub fn read_file<P: AsRef<Path>>(path: P, warnings: &mut Vec<String>) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    read(&text, dir, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::lint::lint;
+    use core::parser::parse_scene;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/core/testdata/mitsuba")
+            .join(name)
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut warnings = Vec::new();
+        let scene = read_file(testdata("scene.xml"), &mut warnings).unwrap();
+        let t = (60f32.to_radians() / 2.).tan();
+        let want = parse_scene(
+            format!(
+                r#"Integrator "path" "integer maxdepth" 5
+Film "image" "integer xresolution" 400 "integer yresolution" 200
+Sampler "random" "integer pixelsamples" 64
+Scale -1 1 1
+ConcatTransform [-1 0 0 0  0 1 0 0  0 0 -1 0  0 0 5 1]
+Camera "perspective" "float fov" {}
+WorldBegin
+MakeNamedMaterial "white" "string type" "matte" "rgb Kd" [0.8 0.8 0.8]
+Texture "checks" "spectrum" "checkerboard" "rgb tex1" [1 0 0] "rgb tex2" [0.2 0.2 0.2]
+    "float uscale" 8 "float vscale" 8
+LightSource "point" "blackbody I" [3000 1] "point from" [0 4 0]
+AttributeBegin
+  ConcatTransform [2 0 0 0  0 2 0 0  0 0 2 0  0 3 0 1]
+  NamedMaterial "white"
+  AreaLightSource "diffuse" "rgb L" [5 5 5]
+  Shape "trianglemesh" "integer indices" [0 1 2 0 2 3] "point P" [-1 -1 0 1 -1 0 1 1 0 -1 1 0]
+      "point2 uv" [0 0 1 0 1 1 0 1]
+AttributeEnd
+AttributeBegin
+  Material "plastic" "texture Kd" "checks" "rgb Ks" [1 1 1] "float roughness" 0.2
+      "bool remaproughness" "false"
+  Translate 0 1 0
+  Shape "sphere" "float radius" 0.5
+AttributeEnd
+AttributeBegin
+  Material "glass" "float eta" 1.333
+  Shape "sphere"
+AttributeEnd
+WorldEnd
+"#,
+                (2. * (t / 2.).atan()).to_degrees()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(scene, want);
+        assert_eq!(lint(&scene), vec![]);
+        assert_eq!(
+            warnings,
+            vec![
+                "line 19: ignoring rfilter in film \"hdrfilm\", which has no pbrt equivalent"
+                    .to_owned(),
+                format!(
+                    "{}: line 8: ignoring emitter \"sunsky\", which has no pbrt equivalent",
+                    testdata("lights.xml").display()
+                ),
+                "line 51: ignoring bsdf \"roughplastic\" parameter \"nonlinear\", which has no \
+                 pbrt equivalent"
+                    .to_owned(),
+                "line 64: ignoring shape \"hair\", which has no pbrt equivalent".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mitsuba2() {
+        let mut warnings = Vec::new();
+        let scene = read_file(testdata("scene2.xml"), &mut warnings).unwrap();
+        let cube = testdata("../obj/cube.obj");
+        let mut obj_warnings = Vec::new();
+        let cube_shapes = obj::read_file(&cube, &mut obj_warnings)
+            .unwrap()
+            .into_iter()
+            .filter(|d| matches!(d, Directive::Shape(..)));
+        let t = (45f32.to_radians() / 2.).tan();
+        let rotate = to_columns(&Transform::rotate(90., &Vector3f::new(0., 1., 0.)).matrix());
+        let rotate: Vec<String> = rotate.iter().map(|v| v.to_string()).collect();
+        let parse = |text: String| parse_scene(text.as_bytes()).unwrap().directives;
+        let mut want = parse(format!(
+            r#"Integrator "volpath"
+Film "image" "integer xresolution" 128 "integer yresolution" 128
+Sampler "stratified" "integer xsamples" 4 "integer ysamples" 4
+Scale -1 1 1
+ConcatTransform [1 0 0 0  0 1 0 0  0 0 1 0  0 0 0 1]
+Camera "perspective" "float fov" {} "float lensradius" 0.1 "float focaldistance" 4
+WorldBegin
+MakeNamedMedium "fog" "string type" "homogeneous" "rgb sigma_a" [0.1 0.1 0.1]
+    "rgb sigma_s" [0.5 0.5 0.5] "float g" 0.7
+AttributeBegin
+  ConcatTransform [{}]
+  AttributeBegin
+    Material "metal" "rgb eta" [0.143 0.374 1.442] "rgb k" [3.983 2.385 1.603]
+        "float roughness" 0 "bool remaproughness" "false"
+"#,
+            (2. * t.atan()).to_degrees(),
+            rotate.join(" ")
+        ));
+        want.extend(cube_shapes);
+        want.extend(parse(format!(
+            r#"  AttributeEnd
+AttributeEnd
+AttributeBegin
+  MediumInterface "fog" ""
+  ConcatTransform [0 0 1 0  0 -1 0 0  1 0 0 0  1 0 0 1]
+  Shape "cylinder" "float zmin" 0 "float zmax" 2
+AttributeEnd
+AttributeBegin
+  Rotate -90 1 0 0
+  LightSource "infinite" "string mapname" "{}"
+AttributeEnd
+WorldEnd
+"#,
+            testdata("sky.exr").display()
+        )));
+        assert_eq!(scene.directives, want);
+        let mut want_warnings = vec![
+            "line 5: unlimited maxDepth has no pbrt equivalent, using pbrt's default".to_owned(),
+        ];
+        want_warnings.extend(
+            obj_warnings
+                .iter()
+                .map(|w| format!("line 32: {}: {}", cube.display(), w)),
+        );
+        assert_eq!(warnings, want_warnings);
+    }
+
+    #[test]
+    fn test_shapegroup() {
+        let xml = r#"<scene>
+            <shape type="shapegroup" id="balls">
+                <shape type="sphere"><bsdf type="diffuse" id="red"/></shape>
+            </shape>
+            <shape type="shapegroup" id="unused">
+                <shape type="sphere"><bsdf type="diffuse" id="white"/></shape>
+            </shape>
+            <shape type="instance"><ref id="balls"/></shape>
+            <shape type="instance"><ref id="balls"/></shape>
+            <shape type="sphere"><ref id="white"/></shape>
+        </scene>"#;
+        let scene = read(xml, Path::new(""), &mut Vec::new()).unwrap();
+        // Named materials are defined once each, before any instances.
+        let want = parse_scene(
+            &br#"WorldBegin
+MakeNamedMaterial "red" "string type" "matte"
+MakeNamedMaterial "white" "string type" "matte"
+AttributeBegin
+  AttributeBegin
+    NamedMaterial "red"
+    Shape "sphere"
+  AttributeEnd
+AttributeEnd
+AttributeBegin
+  AttributeBegin
+    NamedMaterial "red"
+    Shape "sphere"
+  AttributeEnd
+AttributeEnd
+AttributeBegin
+  NamedMaterial "white"
+  Shape "sphere"
+AttributeEnd
+WorldEnd
+"#[..],
+        )
+        .unwrap();
+        let world = scene.directives.iter().position(|d| *d == Directive::WorldBegin).unwrap();
+        assert_eq!(&scene.directives[world..], &want.directives[..]);
+    }
+
+    #[test]
+    fn test_fov() {
+        let fov = |params: &str, width: i64, height: i64| {
+            let xml = format!(
+                r#"<scene><sensor type="perspective">{}
+                    <film type="hdrfilm">
+                        <integer name="width" value="{}"/><integer name="height" value="{}"/>
+                    </film>
+                </sensor></scene>"#,
+                params, width, height
+            );
+            let scene = read(&xml, Path::new(""), &mut Vec::new()).unwrap();
+            match &scene.directives[3] {
+                Directive::Camera(_, ps) => ps.find_one_float("fov", 0.).unwrap(),
+                d => panic!("expected Camera, found {:?}", d),
+            }
+        };
+        let fov90 = r#"<float name="fov" value="90"/>"#;
+        let axis = |axis| format!(r#"{}<string name="fovAxis" value="{}"/>"#, fov90, axis);
+        let tests = vec![
+            (fov90.to_owned(), 200, 100, 2. * 0.5f32.atan()),
+            (axis("y"), 200, 100, FRAC_PI_2),
+            (axis("y"), 100, 200, 2. * 0.5f32.atan()),
+            (axis("smaller"), 100, 200, FRAC_PI_2),
+            (axis("larger"), 100, 200, 2. * 0.5f32.atan()),
+            (axis("diagonal"), 300, 400, 2. * 0.6f32.atan()),
+            // A 50mm lens's field of view of the 24mm side of 35mm film.
+            (String::new(), 360, 240, 2. * (12. / 50f32).atan()),
+        ];
+        for (params, width, height, want) in tests {
+            let got = fov(&params, width, height);
+            assert!(
+                (got - want.to_degrees()).abs() < 1e-3,
+                "{} at {}x{}: got {}, want {}",
+                params,
+                width,
+                height,
+                got,
+                want.to_degrees()
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = vec![
+            (
+                r#"<scene><shape type="ply"/></scene>"#,
+                "line 1: ply without a filename",
+            ),
+            (
+                r#"<scene><shape type="sphere"><ref id="nope"/></shape></scene>"#,
+                "line 1: no bsdf \"nope\"",
+            ),
+            (
+                r#"<notscene/>"#,
+                "line 1: expected <scene>, found <notscene>",
+            ),
+            (
+                "<scene>\n<sensor type=\"perspective\"><float name=\"fov\" value=\"wide\"/>\
+                 </sensor></scene>",
+                "line 2: bad number \"wide\"",
+            ),
+        ];
+        for (xml, want) in errors {
+            match read(xml, Path::new(""), &mut Vec::new()) {
+                Err(e) => assert_eq!(e.to_string(), want),
+                Ok(scene) => panic!("expected {:?} reading {}, got {:?}", want, xml, scene),
+            }
+        }
+    }
+}
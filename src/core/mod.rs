@@ -12,15 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 pub mod api;
+pub mod builder;
 pub mod error;
 pub mod geometry;
+#[cfg(feature = "gltf")]
+pub mod gltf;
 pub mod interaction;
+pub mod lexer;
 pub mod light;
+pub mod lint;
 pub mod medium;
+#[cfg(feature = "mitsuba")]
+pub mod mitsuba;
+pub mod obj;
 pub mod paramset;
+pub mod parser;
 pub mod pbrt;
+pub mod ply;
+pub mod quaternion;
 pub mod spectrum;
 pub mod texture;
 pub mod transform;
-
-mod parser;
+pub mod upgrade;
+pub mod writer;
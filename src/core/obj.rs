@@ -0,0 +1,518 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Converts Wavefront .obj files, and the .mtl files of materials they use, to directives for a
+//! scene's world block.
+//!
+//! Each group of faces becomes a trianglemesh shape, with polygons split into triangles.  Each
+//! material becomes a named matte, plastic or uber material, depending on whether it's specular
+//! or transparent, and `usemtl` becomes NamedMaterial.  Diffuse texture maps become imagemap
+//! textures.
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use core::geometry::{Normal3f, Point2f, Point3f};
+use core::paramset::{ParamSet, Value};
+use core::parser::Directive;
+use core::pbrt::Float;
+use core::ply::TriangleMesh;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// A line that can't be converted, in the .mtl file given, or the .obj file if there's none.
+    Syntax {
+        mtl: Option<PathBuf>,
+        line: usize,
+        why: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Syntax {
+                mtl: Some(mtl),
+                line,
+                why,
+            } => write!(f, "{}:{}: {}", mtl.display(), line, why),
+            Error::Syntax {
+                mtl: None,
+                line,
+                why,
+            } => write!(f, "line {}: {}", line, why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Parses the first n of args as floats.
+fn floats(args: &[&str], n: usize) -> Result<Vec<Float>, String> {
+    if args.len() < n {
+        return Err(format!("expected {} numbers, found {}", n, args.len()));
+    }
+    args[..n]
+        .iter()
+        .map(|a| a.parse().map_err(|_| format!("bad number \"{}\"", a)))
+        .collect()
+}
+
+/// The parts of a .mtl material that are converted.
+#[derive(Debug, Default)]
+struct Mtl {
+    kd: Option<Vec<Float>>,
+    ks: Option<Vec<Float>>,
+    ns: Option<Float>,
+    map_kd: Option<String>,
+    d: Option<Float>,
+}
+
+impl Mtl {
+    /// Returns the directives defining the material name, and its texture if it has one.
+    fn directives(&self, name: &str) -> Vec<Directive> {
+        let mut ds = Vec::new();
+        let mut ps = ParamSet::default();
+        if let Some(ref filename) = self.map_kd {
+            let texture = format!("{}-Kd", name);
+            let mut tps = ParamSet::default();
+            tps.add("filename", Value::String(vec![filename.clone()].into()));
+            ds.push(Directive::Texture(
+                texture.clone(),
+                "spectrum".to_owned(),
+                "imagemap".to_owned(),
+                tps,
+            ));
+            ps.add("Kd", Value::Texture(vec![texture].into()));
+        } else if let Some(ref kd) = self.kd {
+            ps.add("Kd", Value::RGB(kd.clone().into()));
+        }
+        let specular = self
+            .ks
+            .as_ref()
+            .is_some_and(|ks| ks.iter().any(|&c| c > 0.));
+        let opacity = self.d.filter(|&d| d < 1.);
+        let class = match (opacity, specular) {
+            (Some(_), _) => "uber",
+            (None, true) => "plastic",
+            (None, false) => "matte",
+        };
+        if class != "matte" {
+            if let Some(ref ks) = self.ks {
+                ps.add("Ks", Value::RGB(ks.clone().into()));
+            }
+            if let Some(ns) = self.ns {
+                // The Beckmann roughness with about the same highlight as the Phong exponent.
+                let roughness = (2. / (ns.max(0.) + 2.)).sqrt();
+                ps.add("roughness", Value::Float(vec![roughness].into()));
+                ps.add("remaproughness", Value::Bool(vec![false].into()));
+            }
+        }
+        if let Some(d) = opacity {
+            ps.add("opacity", Value::RGB(vec![d, d, d].into()));
+        }
+        ps.add("type", Value::String(vec![class.to_owned()].into()));
+        ds.push(Directive::MakeNamedMaterial(name.to_owned(), ps));
+        ds
+    }
+}
+
+/// Reads the materials in a .mtl file, returning the directives that define them.  dir is where
+/// the .mtl file is, relative to the .obj file, and texture maps are made relative to it too.
+fn read_mtl<R: BufRead>(
+    r: R,
+    dir: &Path,
+    materials: &mut HashSet<String>,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Directive>, (usize, String)> {
+    let mut mtls: Vec<(String, Mtl)> = Vec::new();
+    let mut unsupported = HashSet::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line.map_err(|e| (i + 1, e.to_string()))?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((k, _)) if k.starts_with('#') => continue,
+            Some((k, args)) => (*k, args),
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = args.join(" ");
+            materials.insert(name.clone());
+            mtls.push((name, Mtl::default()));
+            continue;
+        }
+        let mtl = match mtls.last_mut() {
+            Some((_, mtl)) => mtl,
+            None => return Err((i + 1, format!("\"{}\" before newmtl", keyword))),
+        };
+        let err = |why| (i + 1, why);
+        match keyword {
+            "Kd" => mtl.kd = Some(floats(args, 3).map_err(err)?),
+            "Ks" => mtl.ks = Some(floats(args, 3).map_err(err)?),
+            "Ns" => mtl.ns = Some(floats(args, 1).map_err(err)?[0]),
+            "d" => mtl.d = Some(floats(args, 1).map_err(err)?[0]),
+            "Tr" => mtl.d = Some(1. - floats(args, 1).map_err(err)?[0]),
+            // Options like "-bm 1" come before the file name.
+            "map_Kd" => match args.last() {
+                Some(filename) => {
+                    mtl.map_kd = Some(dir.join(filename).to_string_lossy().into_owned())
+                }
+                None => return Err(err("map_Kd without a file name".to_owned())),
+            },
+            _ => {
+                if unsupported.insert(keyword.to_owned()) {
+                    warnings.push(format!(
+                        "ignoring unsupported material statement \"{}\"",
+                        keyword
+                    ));
+                }
+            }
+        }
+    }
+    Ok(mtls
+        .iter()
+        .flat_map(|(name, mtl)| mtl.directives(name))
+        .collect())
+}
+
+/// Builds the mesh of a group of faces.  Each distinct combination of position, texture
+/// coordinate and normal indices in the .obj file is a vertex of the mesh.
+#[derive(Default)]
+struct MeshBuilder {
+    mesh: TriangleMesh,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, obj: &Obj, key: (usize, Option<usize>, Option<usize>)) -> usize {
+        let mesh = &mut self.mesh;
+        *self.vertices.entry(key).or_insert_with(|| {
+            let (p, uv, n) = key;
+            mesh.p.push(obj.p[p]);
+            if let Some(uv) = uv {
+                mesh.uv.push(obj.uv[uv]);
+            }
+            if let Some(n) = n {
+                mesh.n.push(obj.n[n]);
+            }
+            mesh.p.len() - 1
+        })
+    }
+}
+
+/// The state of the conversion of a .obj file.
+#[derive(Default)]
+struct Obj {
+    p: Vec<Point3f>,
+    uv: Vec<Point2f>,
+    n: Vec<Normal3f>,
+    materials: HashSet<String>,
+    // The material used by the faces being read, and by the last shape output.
+    material: Option<String>,
+    shape_material: Option<String>,
+    group: MeshBuilder,
+    directives: Vec<Directive>,
+}
+
+/// Returns the index of the element of a list of len elements that an .obj index refers to.
+/// Positive indices count from 1, and negative ones back from the end.
+fn index(s: &str, len: usize) -> Result<usize, String> {
+    let i: i64 = s.parse().map_err(|_| format!("bad index \"{}\"", s))?;
+    let index = if i < 0 { len as i64 + i } else { i - 1 };
+    if index < 0 || index >= len as i64 {
+        return Err(format!("index {} out of range, there are {}", i, len));
+    }
+    Ok(index as usize)
+}
+
+impl Obj {
+    fn face(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() < 3 {
+            return Err(format!("face with {} vertices", args.len()));
+        }
+        let mut face = Vec::with_capacity(args.len());
+        for arg in args {
+            let mut parts = arg.split('/');
+            let p = index(parts.next().unwrap_or(""), self.p.len())?;
+            let uv = match parts.next() {
+                Some("") | None => None,
+                Some(uv) => Some(index(uv, self.uv.len())?),
+            };
+            let n = match parts.next() {
+                Some("") | None => None,
+                Some(n) => Some(index(n, self.n.len())?),
+            };
+            let mut group = ::std::mem::take(&mut self.group);
+            face.push(group.vertex(self, (p, uv, n)));
+            self.group = group;
+        }
+        // Split the polygon into a fan of triangles.
+        for i in 1..face.len() - 1 {
+            self.group
+                .mesh
+                .indices
+                .extend(&[face[0], face[i], face[i + 1]]);
+        }
+        Ok(())
+    }
+
+    /// Outputs the shape made of the faces read since the last one, if there are any.
+    fn end_group(&mut self) {
+        let mut mesh = ::std::mem::take(&mut self.group).mesh;
+        if mesh.indices.is_empty() {
+            return;
+        }
+        // Normals and texture coordinates are only kept if every vertex has them.
+        if mesh.n.len() != mesh.p.len() {
+            mesh.n.clear();
+        }
+        if mesh.uv.len() != mesh.p.len() {
+            mesh.uv.clear();
+        }
+        if self.material != self.shape_material {
+            if let Some(ref material) = self.material {
+                self.directives
+                    .push(Directive::NamedMaterial(material.clone()));
+            }
+            self.shape_material = self.material.clone();
+        }
+        self.directives.push(Directive::Shape(
+            "trianglemesh".to_owned(),
+            ParamSet::from(mesh),
+        ));
+    }
+}
+
+/// Converts the .obj file read from r.  .mtl files named by mtllib are read from dir.  Warnings
+/// are added for anything that isn't converted.
+pub fn read<R: BufRead>(
+    r: R,
+    dir: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Directive>, Error> {
+    let mut obj = Obj::default();
+    let mut unsupported = HashSet::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let syntax = |why| Error::Syntax {
+            mtl: None,
+            line: i + 1,
+            why,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((k, _)) if k.starts_with('#') => continue,
+            Some((k, args)) => (*k, args),
+            None => continue,
+        };
+        match keyword {
+            "v" => {
+                let v = floats(args, 3).map_err(syntax)?;
+                obj.p.push(Point3f {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "vt" => {
+                let v = floats(args, 2).map_err(syntax)?;
+                obj.uv.push(Point2f { x: v[0], y: v[1] });
+            }
+            "vn" => {
+                let v = floats(args, 3).map_err(syntax)?;
+                obj.n.push(Normal3f {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "f" => obj.face(args).map_err(syntax)?,
+            "g" | "o" => obj.end_group(),
+            "usemtl" => {
+                obj.end_group();
+                let name = args.join(" ");
+                if !obj.materials.contains(&name) {
+                    warnings.push(format!(
+                        "line {}: material \"{}\" isn't defined",
+                        i + 1,
+                        name
+                    ));
+                }
+                obj.material = Some(name);
+            }
+            "mtllib" => {
+                for mtllib in args {
+                    let path = dir.join(mtllib);
+                    let f = match File::open(&path) {
+                        Ok(f) => f,
+                        Err(e) => {
+                            warnings.push(format!("{}: {}", path.display(), e));
+                            continue;
+                        }
+                    };
+                    let mtl_dir = Path::new(mtllib).parent().unwrap_or_else(|| Path::new(""));
+                    let mut mtl_warnings = Vec::new();
+                    let ds = read_mtl(
+                        BufReader::new(f),
+                        mtl_dir,
+                        &mut obj.materials,
+                        &mut mtl_warnings,
+                    )
+                    .map_err(|(line, why)| Error::Syntax {
+                        mtl: Some(path.clone()),
+                        line,
+                        why,
+                    })?;
+                    for warning in mtl_warnings {
+                        warnings.push(format!("{}: {}", path.display(), warning));
+                    }
+                    obj.directives.extend(ds);
+                }
+            }
+            // Smoothing groups only matter to normals, which are given or not.
+            "s" => (),
+            _ => {
+                if unsupported.insert(keyword.to_owned()) {
+                    warnings.push(format!(
+                        "line {}: ignoring unsupported statement \"{}\"",
+                        i + 1,
+                        keyword
+                    ));
+                }
+            }
+        }
+    }
+    obj.end_group();
+    Ok(obj.directives)
+}
+
+/// Converts the .obj file at path.  See read.
+pub fn read_file<P: AsRef<Path>>(
+    path: P,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Directive>, Error> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    read(BufReader::new(File::open(path)?), dir, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::lint::lint;
+    use core::parser::{parse_scene, Scene};
+    use core::writer::Writer;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/core/testdata/obj")
+            .join(name)
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut warnings = Vec::new();
+        let directives = read_file(testdata("cube.obj"), &mut warnings).unwrap();
+        let mut w = Writer::new(Vec::new());
+        for d in &directives {
+            w.write(d).unwrap();
+        }
+        let got = String::from_utf8(w.into_inner()).unwrap();
+        let want = parse_scene(
+            br#"Texture "wood-Kd" "spectrum" "imagemap" "string filename" "textures/wood.png"
+MakeNamedMaterial "wood" "string type" "matte" "texture Kd" "wood-Kd"
+MakeNamedMaterial "shiny" "string type" "plastic" "rgb Kd" [0.8 0.1 0.1]
+    "rgb Ks" [0.5 0.5 0.5] "float roughness" 0.5 "bool remaproughness" "false"
+MakeNamedMaterial "glass" "string type" "uber" "rgb Kd" [1 1 1] "rgb opacity" [0.25 0.25 0.25]
+NamedMaterial "wood"
+Shape "trianglemesh" "integer indices" [0 1 2 0 2 3]
+    "point3 P" [0 0 0 1 0 0 1 1 0 0 1 0]
+    "point2 uv" [0 0 1 0 1 1 0 1]
+NamedMaterial "shiny"
+Shape "trianglemesh" "integer indices" [0 1 2 0 2 3 0 3 4]
+    "point3 P" [0 0 1 1 0 1 1 1 1 0.5 1.5 1 0 1 1]
+    "normal N" [0 0 1 0 0 1 0 0 1 0 0 1 0 0 1]
+Shape "trianglemesh" "integer indices" [0 1 2] "point3 P" [0 0 0 1 0 0 0 0 1]
+"#,
+        )
+        .unwrap();
+        assert_eq!(parse_scene(got.as_bytes()).unwrap(), want);
+        assert_eq!(
+            warnings,
+            vec![
+                format!(
+                    "{}: ignoring unsupported material statement \"illum\"",
+                    testdata("cube.mtl").display()
+                ),
+                "line 19: ignoring unsupported statement \"l\"".to_owned(),
+            ]
+        );
+
+        // The conversion should make a scene without mistakes.
+        let mut scene = Scene::default();
+        scene.push(Directive::WorldBegin, Default::default());
+        for d in directives {
+            scene.push(d, Default::default());
+        }
+        scene.push(Directive::WorldEnd, Default::default());
+        assert_eq!(lint(&scene), vec![]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = vec![
+            ("v 0 0\n", "line 1: expected 3 numbers, found 2"),
+            (
+                "v 0 0 0\nf 1 2 3\n",
+                "line 2: index 2 out of range, there are 1",
+            ),
+            ("v 0 0 0\nf 1 1\n", "line 2: face with 2 vertices"),
+            ("vt a 0\n", "line 1: bad number \"a\""),
+        ];
+        for (obj, want) in errors {
+            match read(obj.as_bytes(), Path::new(""), &mut Vec::new()) {
+                Err(e) => assert_eq!(e.to_string(), want),
+                Ok(ds) => panic!("expected {:?} reading {:?}, got {:?}", want, obj, ds),
+            }
+        }
+    }
+
+    #[test]
+    fn test_negative_indices() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nusemtl missing\n";
+        let mut warnings = Vec::new();
+        let ds = read(obj.as_bytes(), Path::new(""), &mut warnings).unwrap();
+        match ds.as_slice() {
+            [Directive::Shape(name, ps)] => {
+                assert_eq!(name, "trianglemesh");
+                assert_eq!(ps.get("indices"), Some(&Value::Int(vec![0, 1, 2].into())));
+            }
+            ds => panic!("expected one shape, got {:?}", ds),
+        }
+        assert_eq!(
+            warnings,
+            vec!["line 5: material \"missing\" isn't defined".to_owned()]
+        );
+    }
+}
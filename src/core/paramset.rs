@@ -13,6 +13,7 @@
 // limitations under the License.
 use std::cell::RefCell;
 use std::collections;
+use std::error;
 use std::fmt;
 use std::str::FromStr;
 
@@ -22,6 +23,7 @@ use core::spectrum::Spectrum;
 use core::texture::Texture;
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ParamList<T>(pub Vec<T>);
 
 impl<T> From<Vec<T>> for ParamList<T> {
@@ -48,41 +50,194 @@ where
     }
 }
 
+/// The values of a parameter.  With the serde feature, a Value is serialized as its type and
+/// values, e.g. `{"type": "rgb", "values": [1, 0, 0]}`.  The types are named as in scene files,
+/// except for the spectra given by file name ("spectrum_file") and as Spectrum ("spectrum_xyz").
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "values")
+)]
 pub enum Value {
+    #[cfg_attr(feature = "serde", serde(rename = "bool"))]
     Bool(ParamList<bool>),
+    #[cfg_attr(feature = "serde", serde(rename = "float"))]
     Float(ParamList<Float>),
+    #[cfg_attr(feature = "serde", serde(rename = "integer"))]
     Int(ParamList<i64>),
+    #[cfg_attr(feature = "serde", serde(rename = "point2"))]
     Point2f(ParamList<Point2f>),
+    #[cfg_attr(feature = "serde", serde(rename = "vector2"))]
     Vector2f(ParamList<Vector2f>),
+    #[cfg_attr(feature = "serde", serde(rename = "point3"))]
     Point3f(ParamList<Point3f>),
+    #[cfg_attr(feature = "serde", serde(rename = "vector3"))]
     Vector3f(ParamList<Vector3f>),
+    #[cfg_attr(feature = "serde", serde(rename = "normal"))]
     Normal3f(ParamList<Normal3f>),
+    #[cfg_attr(feature = "serde", serde(rename = "spectrum_xyz"))]
     Spectrum(ParamList<Spectrum>),
+    #[cfg_attr(feature = "serde", serde(rename = "string"))]
     String(ParamList<String>),
+    #[cfg_attr(feature = "serde", serde(rename = "texture"))]
     Texture(ParamList<String>),
     // TODO(wathiede): make a generic 'Spectrum' type?
+    #[cfg_attr(feature = "serde", serde(rename = "rgb"))]
     RGB(ParamList<Float>),
+    #[cfg_attr(feature = "serde", serde(rename = "xyz"))]
+    XYZ(ParamList<Float>),
+    #[cfg_attr(feature = "serde", serde(rename = "blackbody"))]
     Blackbody(ParamList<Float>),
+    /// Wavelength (nm) and value pairs, flattened.
+    #[cfg_attr(feature = "serde", serde(rename = "spectrum"))]
+    SampledSpectrum(ParamList<Float>),
+    /// Names of .spd files holding wavelength and value pairs, or in pbrt-v4, of named spectra
+    /// like "metal-Ag-eta".
+    #[cfg_attr(feature = "serde", serde(rename = "spectrum_file"))]
+    SpectrumFile(ParamList<String>),
 }
 
+impl Value {
+    /// Returns the name used for this type of value in scene files.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Float(_) => "float",
+            Value::Int(_) => "integer",
+            Value::Point2f(_) => "point2",
+            Value::Vector2f(_) => "vector2",
+            Value::Point3f(_) => "point3",
+            Value::Vector3f(_) => "vector3",
+            Value::Normal3f(_) => "normal",
+            Value::Spectrum(_) | Value::SampledSpectrum(_) | Value::SpectrumFile(_) => "spectrum",
+            Value::String(_) => "string",
+            Value::Texture(_) => "texture",
+            Value::RGB(_) => "rgb",
+            Value::XYZ(_) => "xyz",
+            Value::Blackbody(_) => "blackbody",
+        }
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::Bool(pl) => pl.0.is_empty(),
+            Value::Float(pl)
+            | Value::RGB(pl)
+            | Value::XYZ(pl)
+            | Value::Blackbody(pl)
+            | Value::SampledSpectrum(pl) => pl.0.is_empty(),
+            Value::Int(pl) => pl.0.is_empty(),
+            Value::Point2f(pl) => pl.0.is_empty(),
+            Value::Vector2f(pl) => pl.0.is_empty(),
+            Value::Point3f(pl) => pl.0.is_empty(),
+            Value::Vector3f(pl) => pl.0.is_empty(),
+            Value::Normal3f(pl) => pl.0.is_empty(),
+            Value::Spectrum(pl) => pl.0.is_empty(),
+            Value::String(pl) | Value::Texture(pl) | Value::SpectrumFile(pl) => pl.0.is_empty(),
+        }
+    }
+}
+
+/// Returned by the ParamSet lookups when a parameter can't be returned as the type asked for.
 #[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The parameter has a different type than the one looked up.
+    WrongType {
+        name: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The parameter is a spectrum that can't be converted to a Spectrum yet.
+    UnsupportedSpectrum { name: String, found: &'static str },
+}
+
+impl Error {
+    fn wrong_type(name: &str, expected: &'static str, found: &Value) -> Error {
+        Error::WrongType {
+            name: name.to_owned(),
+            expected,
+            found: found.type_name(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::WrongType {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "parameter \"{}\" has type {}, expected {}",
+                name, found, expected
+            ),
+            Error::UnsupportedSpectrum { name, found } => write!(
+                f,
+                "parameter \"{}\" has type {}, which isn't supported yet",
+                name, found
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A named parameter.  With the serde feature, it's serialized as its name alongside the fields
+/// of its Value, e.g. `{"name": "Kd", "type": "rgb", "values": [1, 0, 0]}`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ParamSetItem {
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub values: Value,
+    #[cfg_attr(feature = "serde", serde(skip))]
     looked_up: RefCell<bool>,
 }
 
 impl ParamSetItem {
     pub fn new(name: &str, values: &Value) -> ParamSetItem {
+        ParamSetItem::from_values(name, values.clone())
+    }
+
+    /// Like new, but takes values instead of copying them.
+    pub fn from_values(name: &str, values: Value) -> ParamSetItem {
         ParamSetItem {
             name: String::from(name),
-            values: values.clone(),
+            values,
             looked_up: RefCell::new(false),
         }
     }
 }
 
+/// find!(find_xs, find_one_x, Variant, T, type_name) creates find_xs, which returns a slice of
+/// the values of a parameter of type Value::Variant, and find_one_x, which returns the first
+/// value or a default.
+macro_rules! find {
+    ($find:ident, $find_one:ident, $variant:ident, $t:ty, $type_name:expr) => {
+        #[doc = concat!("Returns the values of the ", $type_name, " parameter name, or None if it isn't set.")]
+        pub fn $find(&self, name: &str) -> Result<Option<&[$t]>, Error> {
+            match self.lookup(name) {
+                Some(Value::$variant(pl)) => Ok(Some(&pl.0)),
+                Some(v) => Err(Error::wrong_type(name, $type_name, v)),
+                None => Ok(None),
+            }
+        }
+
+        #[doc = concat!("Returns the first value of the ", $type_name, " parameter name, or default if it isn't set.")]
+        pub fn $find_one(&self, name: &str, default: $t) -> Result<$t, Error> {
+            Ok(self
+                .$find(name)?
+                .and_then(|vs| vs.first())
+                .cloned()
+                .unwrap_or(default))
+        }
+    };
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct ParamSet {
     values: collections::HashMap<String, ParamSetItem>,
@@ -109,14 +264,115 @@ impl ParamSet {
         })
     }
 
-    pub fn find_one_string(&self, name: &str, default: &str) -> String {
-        match self.find(name) {
-            Some(Value::String(pl)) => pl.0.first().map_or(default.into(), |v| v.clone()),
-            None => default.into(),
-            _ => panic!("still working on it"),
+    /// Returns the value of parameter name and marks it used.
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.values.get(name).map(|psi| {
+            *psi.looked_up.borrow_mut() = true;
+            &psi.values
+        })
+    }
+
+    find!(find_bools, find_one_bool, Bool, bool, "bool");
+    find!(find_floats, find_one_float, Float, Float, "float");
+    find!(find_ints, find_one_int, Int, i64, "integer");
+    find!(find_point2fs, find_one_point2f, Point2f, Point2f, "point2");
+    find!(find_vector2fs, find_one_vector2f, Vector2f, Vector2f, "vector2");
+    find!(find_point3fs, find_one_point3f, Point3f, Point3f, "point3");
+    find!(find_vector3fs, find_one_vector3f, Vector3f, Vector3f, "vector3");
+    find!(find_normal3fs, find_one_normal3f, Normal3f, Normal3f, "normal");
+
+    /// Returns the values of the string parameter name, or None if it isn't set.
+    pub fn find_strings(&self, name: &str) -> Result<Option<&[String]>, Error> {
+        match self.lookup(name) {
+            Some(Value::String(pl)) => Ok(Some(&pl.0)),
+            Some(v) => Err(Error::wrong_type(name, "string", v)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the first value of the string parameter name, or default if it isn't set.
+    pub fn find_one_string<'a>(&'a self, name: &str, default: &'a str) -> Result<&'a str, Error> {
+        Ok(self
+            .find_strings(name)?
+            .and_then(|vs| vs.first())
+            .map_or(default, |s| s.as_str()))
+    }
+
+    /// Returns the name of the texture bound to parameter name, or None if it isn't set.
+    pub fn find_texture(&self, name: &str) -> Result<Option<&str>, Error> {
+        match self.lookup(name) {
+            Some(Value::Texture(pl)) => Ok(pl.0.first().map(|s| s.as_str())),
+            Some(v) => Err(Error::wrong_type(name, "texture", v)),
+            None => Ok(None),
         }
     }
 
+    /// Returns the values of the spectrum parameter name, or None if it isn't set.  rgb and xyz
+    /// values are converted, other spectrum types are not yet supported.
+    pub fn find_spectra(&self, name: &str) -> Result<Option<Vec<Spectrum>>, Error> {
+        self.find_n_spectra(name, usize::MAX)
+    }
+
+    /// Returns the first value of the spectrum parameter name, or default if it isn't set.
+    pub fn find_one_spectrum(&self, name: &str, default: Spectrum) -> Result<Spectrum, Error> {
+        Ok(self
+            .find_n_spectra(name, 1)?
+            .and_then(|vs| vs.into_iter().next())
+            .unwrap_or(default))
+    }
+
+    /// Like find_spectra, but only converts the first n values.
+    fn find_n_spectra(&self, name: &str, n: usize) -> Result<Option<Vec<Spectrum>>, Error> {
+        let unsupported = |v: &Value| Error::UnsupportedSpectrum {
+            name: name.to_owned(),
+            found: v.type_name(),
+        };
+        match self.lookup(name) {
+            Some(Value::Spectrum(pl)) => Ok(Some(pl.0.iter().take(n).cloned().collect())),
+            Some(Value::RGB(pl)) => Ok(Some(
+                pl.0.chunks_exact(3)
+                    .take(n)
+                    .map(|c| Spectrum::from_rgb([c[0], c[1], c[2]]))
+                    .collect(),
+            )),
+            Some(Value::XYZ(pl)) => Ok(Some(
+                pl.0.chunks_exact(3)
+                    .take(n)
+                    .map(|c| Spectrum::from_xyz([c[0], c[1], c[2]]))
+                    .collect(),
+            )),
+            Some(v @ Value::Blackbody(_))
+            | Some(v @ Value::SampledSpectrum(_))
+            | Some(v @ Value::SpectrumFile(_)) => Err(unsupported(v)),
+            Some(v) => Err(Error::wrong_type(name, "spectrum", v)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the values of parameter name without marking it used.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name).map(|psi| &psi.values)
+    }
+
+    /// Removes parameter name, returning its values.
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.values.remove(name).map(|psi| psi.values)
+    }
+
+    /// Renames parameter from to to, if it's set.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(values) = self.remove(from) {
+            self.add(to, values);
+        }
+    }
+
+    /// Returns the parameters sorted by name, without marking them used.
+    pub fn items(&self) -> Vec<&ParamSetItem> {
+        let mut items: Vec<_> = self.values.values().collect();
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+        items
+    }
+
     pub fn report_unused(&self) -> bool {
         let mut unused = false;
         info!("report_unused");
@@ -132,11 +388,26 @@ impl ParamSet {
     }
 }
 
+/// A ParamSet is serialized as a list of its items, sorted by name.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ParamSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.items())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ParamSet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<ParamSetItem>::deserialize(deserializer).map(ParamSet::from)
+    }
+}
+
 impl From<Vec<ParamSetItem>> for ParamSet {
     fn from(psis: Vec<ParamSetItem>) -> Self {
         let mut ps: ParamSet = Default::default();
-        for psi in &psis {
-            ps.add(&psi.name, psi.values.clone())
+        for psi in psis {
+            ps.add(&psi.name, psi.values)
         }
         ps
     }
@@ -214,10 +485,84 @@ mod tests {
             ParamSetItem::new("test3", &Value::String(ParamList(vec![]))),
         ].into();
 
-        let test2: String = "one".to_owned();
-        assert_eq!(ps.find_one_string("test2", "one"), test2);
+        assert_eq!(ps.find_one_string("test2", "three"), Ok("one"));
+        assert_eq!(ps.find_one_string("test3", "three"), Ok("three"));
+        assert_eq!(ps.find_one_string("test4", "three"), Ok("three"));
+        assert_eq!(ps.find_one_float("test1", 0.), Ok(1.));
+        assert_eq!(ps.find_floats("test1"), Ok(Some(&[1., 2.][..])));
+        assert_eq!(ps.find_floats("test4"), Ok(None));
 
         // let test3: String = "one".to_owned();
         // assert_eq!(ps.find("test3").unwrap_or("one").first(), test3);
     }
+
+    #[test]
+    fn test_param_set_find_types() {
+        let p = Point3f {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        };
+        let ps: ParamSet = vec![
+            ParamSetItem::new("b", &Value::Bool(vec![true].into())),
+            ParamSetItem::new("i", &Value::Int(vec![1, 2, 3].into())),
+            ParamSetItem::new("p", &Value::Point3f(vec![p].into())),
+            ParamSetItem::new(
+                "n",
+                &Value::Normal3f(
+                    vec![Normal3f {
+                        x: 0.,
+                        y: 0.,
+                        z: 1.,
+                    }].into(),
+                ),
+            ),
+            ParamSetItem::new("rgb", &Value::RGB(vec![1., 1., 1.].into())),
+            ParamSetItem::new("xyz", &Value::XYZ(vec![0.5, 0.25, 0.125, 1., 1., 1.].into())),
+            ParamSetItem::new("bb", &Value::Blackbody(vec![3000., 1.].into())),
+            ParamSetItem::new("tex", &Value::Texture(vec!["checks".to_owned()].into())),
+        ].into();
+
+        assert_eq!(ps.find_one_bool("b", false), Ok(true));
+        assert_eq!(ps.find_one_int("i", 0), Ok(1));
+        assert_eq!(ps.find_ints("i"), Ok(Some(&[1, 2, 3][..])));
+        assert_eq!(ps.find_one_point3f("p", Point3f { x: 0., y: 0., z: 0. }), Ok(p));
+        assert_eq!(ps.find_one_normal3f("n", Normal3f { x: 0., y: 0., z: 0. }).unwrap().z, 1.);
+        assert_eq!(ps.find_texture("tex"), Ok(Some("checks")));
+        assert_eq!(ps.find_texture("notex"), Ok(None));
+
+        let white = ps.find_one_spectrum("rgb", Spectrum::from_xyz([0.; 3])).unwrap();
+        assert!((white.y - 1.).abs() < 1e-5);
+        assert_eq!(
+            ps.find_one_spectrum("xyz", Spectrum::from_xyz([0.; 3])),
+            Ok(Spectrum::from_xyz([0.5, 0.25, 0.125]))
+        );
+        assert_eq!(
+            ps.find_spectra("xyz"),
+            Ok(Some(vec![
+                Spectrum::from_xyz([0.5, 0.25, 0.125]),
+                Spectrum::from_xyz([1., 1., 1.]),
+            ]))
+        );
+        assert_eq!(
+            ps.find_one_spectrum("bb", Spectrum::from_xyz([0.; 3])),
+            Err(Error::UnsupportedSpectrum {
+                name: "bb".to_owned(),
+                found: "blackbody",
+            })
+        );
+
+        let err = ps.find_one_float("i", 0.).unwrap_err();
+        assert_eq!(
+            err,
+            Error::WrongType {
+                name: "i".to_owned(),
+                expected: "float",
+                found: "integer",
+            }
+        );
+        assert_eq!(err.to_string(), "parameter \"i\" has type integer, expected float");
+        assert!(ps.find_texture("rgb").is_err());
+        assert!(ps.find_one_spectrum("i", Spectrum::from_xyz([0.; 3])).is_err());
+    }
 }
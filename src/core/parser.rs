@@ -11,38 +11,124 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::str;
-use std::str::FromStr;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 extern crate nom;
-use self::nom::{alphanumeric, digit, space, IResult};
+use self::nom::{ErrorKind, IResult};
 
-extern crate regex;
-
-use core::geometry::Point3f;
+use core::geometry::{Normal3f, Point2f, Point3f, Vector2f, Vector3f};
+use core::lexer::{LexError, Lexer, Token, TokenKind};
 use core::paramset::{ParamList, ParamSet, ParamSetItem, Value};
 use core::pbrt::Float;
 
-#[derive(PartialEq, Debug)]
+/// A position in a scene file.  Lines and columns start at 1, columns count bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The region of a scene file a directive was parsed from.  file is None when the scene wasn't
+/// read from a file, and the default Span is used for directives that weren't parsed at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub file: Option<Arc<PathBuf>>,
+    pub start: Location,
+    pub end: Location,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.start.line) {
+            (Some(file), 0) => write!(f, "{}", file.display()),
+            (Some(file), _) => write!(f, "{}:{}", file.display(), self.start),
+            (None, 0) => write!(f, "<unknown>"),
+            (None, _) => write!(f, "{}", self.start),
+        }
+    }
+}
+
+/// A syntax error in a scene, with enough context to show the user where it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub file: Option<PathBuf>,
+    pub location: Location,
+    /// The text of the line containing the error.
+    pub line: String,
+    /// What the parser expected to find at location, e.g. "number after Translate".
+    pub expected: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        writeln!(f, "{}: expected {}", self.location, self.expected)?;
+        writeln!(f, "{}", self.line)?;
+        // Keep tabs so the caret lines up with the snippet however the terminal expands them.
+        let indent: String = self
+            .line
+            .bytes()
+            .take(self.location.column.saturating_sub(1))
+            .map(|b| if b == b'\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}^", indent)
+    }
+}
+
+#[derive(Debug)]
 pub enum Error {
-    NomError(nom::Err),
-    NomIncomplete(nom::Needed),
+    Syntax(SyntaxError),
+    /// The file could not be read.
+    Io(PathBuf, io::Error),
+    /// A file was included, directly or indirectly, from itself.  The chain of files that lead
+    /// back to the first repeated file is provided, outermost first.
+    IncludeCycle(Vec<PathBuf>),
+    /// An error in the file included by the directive at the given span.
+    Included(Span, Box<Error>),
 }
 
-// TODO(wathiede): why does this result in:
-//      expected at least 2 type arguments
-// impl From<IResult> for Error {
-//     fn from(err: IResult) -> Error {
-//         match err {
-//             IResult::Error(e) => Error::NomError(e),
-//             IResult::Incomplete(n) => Error::NomIncomplete(n),
-//         }
-//     }
-// }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax(e) => write!(f, "{}", e),
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::IncludeCycle(chain) => {
+                write!(f, "include cycle: ")?;
+                let mut it = chain.iter();
+                if let Some(p) = it.next() {
+                    write!(f, "{}", p.display())?;
+                }
+                for p in it {
+                    write!(f, " -> {}", p.display())?;
+                }
+                Ok(())
+            }
+            Error::Included(span, e) => write!(f, "{}\n  included from {}", e, span),
+        }
+    }
+}
+
+impl error::Error for Error {}
 
+/// A scene file directive.  With the serde feature, directives are serialized with serde's
+/// default enum encoding, e.g. `{"Translate": [1, 0, 0]}` or `"WorldBegin"`.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Directive {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     // TODO(wathiede): convert to 3 x Vector3f?
     LookAt(
         Float, Float, Float, // eye xyz
@@ -58,7 +144,15 @@ pub enum Directive {
     AttributeBegin,
     AttributeEnd,
     LightSource(String, ParamSet),
+    AreaLightSource(String, ParamSet),
     Material(String, ParamSet),
+    MakeNamedMaterial(String, ParamSet),
+    NamedMaterial(String),
+    MakeNamedMedium(String, ParamSet),
+    MediumInterface(
+        String, // inside
+        String, // outside
+    ),
     Shape(String, ParamSet),
     Translate(Float, Float, Float),
     Scale(Float, Float, Float),
@@ -69,257 +163,514 @@ pub enum Directive {
         String, // class
         ParamSet,
     ),
+    Identity,
+    Transform([Float; 16]),
+    ConcatTransform([Float; 16]),
+    CoordinateSystem(String),
+    CoordSysTransform(String),
+    TransformBegin,
+    TransformEnd,
+    ActiveTransform(ActiveTransform),
+    TransformTimes(Float, Float),
+    Include(String),
+    Import(String),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Which of the start and end time transforms subsequent transform directives modify.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ActiveTransform {
+    All,
+    StartTime,
+    EndTime,
+}
+
+/// A parsed scene.  spans[i] is where directives[i] was parsed from.  Spans are bookkeeping and
+/// are ignored when comparing scenes.
+#[derive(Debug, Clone, Default)]
 pub struct Scene {
     pub directives: Vec<Directive>,
+    pub spans: Vec<Span>,
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(quoted_name<&str>,
-   map_res!(
-       delimited!(tag!("\""), alphanumeric, tag!("\"")),
-       str::from_utf8
-   )
-);
+impl Scene {
+    pub fn push(&mut self, directive: Directive, span: Span) {
+        self.directives.push(directive);
+        self.spans.push(span);
+    }
+}
 
-fn bool(input: &[u8]) -> IResult<&[u8], bool> {
-    flat_map!(
-        input,
-        recognize!(alt!(tag!("true") | tag!("false"))),
-        parse_to!(bool)
-    )
+impl PartialEq for Scene {
+    fn eq(&self, rhs: &Scene) -> bool {
+        self.directives == rhs.directives
+    }
 }
 
-// number is a superset of nom::double! that includes '1' and '1.'
-#[cfg_attr(feature = "cargo-clippy", allow(cyclomatic_complexity))]
-fn number(input: &[u8]) -> IResult<&[u8], Float> {
-    flat_map!(
-        input,
-        recognize!(tuple!(
-            opt!(alt!(tag!("+") | tag!("-"))),
-            alt!(
-                complete!(delimited!(opt!(digit), tag!("."), digit))
-                    | complete!(preceded!(digit, tag!(".")))
-                    | digit
-            ),
-            opt!(complete!(tuple!(
-                alt!(tag!("e") | tag!("E")),
-                opt!(alt!(tag!("+") | tag!("-"))),
-                digit
-            )))
-        )),
-        parse_to!(Float)
-    )
+impl From<Vec<Directive>> for Scene {
+    fn from(directives: Vec<Directive>) -> Self {
+        let spans = vec![Default::default(); directives.len()];
+        Scene { directives, spans }
+    }
 }
 
-fn integer(input: &[u8]) -> IResult<&[u8], i64> {
-    flat_map!(
-        input,
-        recognize!(tuple!(opt!(alt!(tag!("+") | tag!("-"))), complete!(digit))),
-        parse_to!(i64)
-    )
+/// With the serde feature, a Scene is serialized as `{"directives": [...]}`.  Spans aren't
+/// serialized, so deserialized scenes have default spans.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Scene {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("Scene", 1)?;
+        s.serialize_field("directives", &self.directives)?;
+        s.end()
+    }
 }
 
-fn strip_comment(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    // TODO(wathiede): handle "#" if necessary.
-    let re = regex::bytes::Regex::new(r"#.*\n").unwrap();
-    let res = re.replace_all(input, &b"\n"[..]);
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Scene {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Scene")]
+        struct Directives {
+            directives: Vec<Directive>,
+        }
+        Directives::deserialize(deserializer).map(|s| Scene::from(s.directives))
+    }
+}
 
-    IResult::Done(&b""[..], Vec::from(res))
+/// Expected is the custom nom error produced when a parser fails.  remaining is the number of
+/// tokens left at the point of failure, which is converted to a Location for the user.
+#[derive(Debug, Clone, PartialEq)]
+struct Expected {
+    what: String,
+    remaining: usize,
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_bool<Value>,
-    do_parse!(
-        values: alt!(
-            ws!(delimited!(tag!("["), many1!(bool), tag!("]")))
-            | ws!(many1!(bool))
-        ) >>
-        (Value::Bool(ParamList(values)))
-    )
-);
+/// What the parsers read: a token, or the bracketed values of a parameter, which Directives
+/// converts as they're read so that the tokens of long lists, like the vertices of inline meshes,
+/// needn't be kept.  The values are moved into the parsed directive afterwards, see
+/// Directives::parse_next.
+#[derive(Debug)]
+enum Item {
+    Token(Token),
+    Values {
+        values: Value,
+        start: Location,
+        end: Location,
+    },
+}
 
-named!(
-    parse_point3f<Point3f>,
-    ws!(do_parse!(
-        x: number >> y: number >> z: number >> (Point3f { x, y, z })
-    ))
-);
+impl Item {
+    /// Returns the kind of token, or None for values.
+    fn kind(&self) -> Option<&TokenKind> {
+        match self {
+            Item::Token(t) => Some(&t.kind),
+            Item::Values { .. } => None,
+        }
+    }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_point<Value>,
-    ws!(
-        do_parse!(
-                tag!("[") >>
-                points: many1!(parse_point3f) >>
-                tag!("]") >>
-            (Value::Point3f(points.into()))
-        )
-    )
+    fn start(&self) -> Location {
+        match self {
+            Item::Token(t) => t.start,
+            Item::Values { start, .. } => *start,
+        }
+    }
+
+    fn end(&self) -> Location {
+        match self {
+            Item::Token(t) => t.end,
+            Item::Values { end, .. } => *end,
+        }
+    }
+}
+
+/// Returns the kind of the first item of input, if it's a token.
+fn first(input: &[Item]) -> Option<&TokenKind> {
+    input.first().and_then(Item::kind)
+}
+
+type PResult<'a, O> = IResult<&'a [Item], O, Expected>;
+
+fn fail<'a, O>(input: &'a [Item], what: &str) -> PResult<'a, O> {
+    IResult::Error(ErrorKind::Custom(Expected {
+        what: what.to_owned(),
+        remaining: input.len(),
+    }))
+}
+
+/// expect!(what, parser) runs parser and, if it fails without consuming any tokens, replaces the
+/// error with one naming what should have been at the current position.  Errors further into the
+/// input are more precise and are kept.
+macro_rules! expect (
+    ($i:expr, $what:expr, $submac:ident!( $($args:tt)* )) => (
+        {
+            let i_ = $i;
+            match $submac!(i_, $($args)*) {
+                IResult::Error(ErrorKind::Custom(ref e)) if e.remaining < i_.len() => {
+                    IResult::Error(ErrorKind::Custom(e.clone()))
+                }
+                IResult::Error(_) | IResult::Incomplete(_) => IResult::Error(ErrorKind::Custom(
+                    Expected {
+                        what: $what.to_string(),
+                        remaining: i_.len(),
+                    },
+                )),
+                IResult::Done(i, o) => IResult::Done(i, o),
+            }
+        }
+    );
+    ($i:expr, $what:expr, $f:expr) => (
+        expect!($i, $what, call!($f))
+    );
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_float<Value>,
+/// keyword matches the unquoted word kw.
+fn keyword<'a>(input: &'a [Item], kw: &str) -> PResult<'a, ()> {
+    match first(input) {
+        Some(TokenKind::Keyword(k)) if k == kw => IResult::Done(&input[1..], ()),
+        _ => fail(input, kw),
+    }
+}
+
+fn open_bracket(input: &[Item]) -> PResult<'_, ()> {
+    match first(input) {
+        Some(TokenKind::OpenBracket) => IResult::Done(&input[1..], ()),
+        _ => fail(input, "'['"),
+    }
+}
+
+fn close_bracket(input: &[Item]) -> PResult<'_, ()> {
+    match first(input) {
+        Some(TokenKind::CloseBracket) => IResult::Done(&input[1..], ()),
+        _ => fail(input, "']'"),
+    }
+}
+
+fn number(input: &[Item]) -> PResult<'_, Float> {
+    match first(input) {
+        Some(&TokenKind::Number(n)) => IResult::Done(&input[1..], n as Float),
+        _ => fail(input, "number"),
+    }
+}
+
+fn integer(input: &[Item]) -> PResult<'_, i64> {
+    match first(input) {
+        Some(&TokenKind::Number(n)) if n.fract() == 0. => IResult::Done(&input[1..], n as i64),
+        _ => fail(input, "integer"),
+    }
+}
+
+fn string(input: &[Item]) -> PResult<'_, String> {
+    match first(input) {
+        Some(TokenKind::String(s)) => IResult::Done(&input[1..], s.clone()),
+        _ => fail(input, "quoted string"),
+    }
+}
+
+/// bool accepts true and false with or without quotes.
+fn bool(input: &[Item]) -> PResult<'_, bool> {
+    match first(input) {
+        Some(TokenKind::Keyword(s)) | Some(TokenKind::String(s)) if s == "true" => {
+            IResult::Done(&input[1..], true)
+        }
+        Some(TokenKind::Keyword(s)) | Some(TokenKind::String(s)) if s == "false" => {
+            IResult::Done(&input[1..], false)
+        }
+        _ => fail(input, "true or false"),
+    }
+}
+
+#[rustfmt::skip]
+named!(
+    point2f<&[Item], Point2f, Expected>,
     do_parse!(
-        values: alt!(
-            ws!(delimited!(tag!("["), many1!(number), tag!("]")))
-            | ws!(many1!(number))
-        ) >>
-        (Value::Float(ParamList(values)))
+        x: number >>
+        y: number >>
+        (Point2f { x, y })
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_rgb<Value>,
+#[rustfmt::skip]
+named!(
+    vector2f<&[Item], Vector2f, Expected>,
     do_parse!(
-        values: alt!(
-            ws!(delimited!(tag!("["), many1!(number), tag!("]")))
-            | ws!(many1!(number))
-        ) >>
-        (Value::RGB(ParamList(values)))
+        x: number >>
+        y: number >>
+        (Vector2f { x, y })
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_blackbody<Value>,
+#[rustfmt::skip]
+named!(
+    point3f<&[Item], Point3f, Expected>,
     do_parse!(
-        values: alt!(
-            ws!(delimited!(tag!("["), many1!(number), tag!("]")))
-            | ws!(many1!(number))
-        ) >>
-        (Value::Blackbody(ParamList(values)))
+        x: number >>
+        y: number >>
+        z: number >>
+        (Point3f { x, y, z })
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_integer<Value>,
+#[rustfmt::skip]
+named!(
+    vector3f<&[Item], Vector3f, Expected>,
     do_parse!(
-        values: alt!(
-            ws!(delimited!(tag!("["), many1!(integer), tag!("]")))
-            | ws!(many1!(integer))
-        ) >>
-        (Value::Int(ParamList(values)))
+        x: number >>
+        y: number >>
+        z: number >>
+        (Vector3f { x, y, z })
     )
 );
 
+#[rustfmt::skip]
 named!(
-    ascii<String>,
-    map_res!(
-        map_res!(
-            delimited!(tag!("\""), take_until!("\""), tag!("\"")),
-            str::from_utf8
-        ),
-        String::from_str
+    normal3f<&[Item], Normal3f, Expected>,
+    do_parse!(
+        x: number >>
+        y: number >>
+        z: number >>
+        (Normal3f { x, y, z })
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_string<Value>,
-    alt!(
-        do_parse!(
-            values: ws!(delimited!(tag!("["), many1!(ascii), tag!("]"))) >>
-            (Value::String(ParamList(values)))
-        ) |
-        do_parse!(
-            value: ws!(ascii) >>
-            (Value::String(ParamList(vec![value])))
-        )
-    )
-);
+/// list parses either a bracketed list of values or unbracketed values using parser.  decl is the
+/// parameter's `"type name"` declaration, for error messages.
+fn list<'a, O>(
+    input: &'a [Item],
+    parser: fn(&'a [Item]) -> PResult<'a, O>,
+    what: &str,
+    decl: &str,
+) -> PResult<'a, Vec<O>> {
+    if let IResult::Done(i, _) = open_bracket(input) {
+        return do_parse!(
+            i,
+            values: many1!(parser)
+                >> expect!(format!("{} or ']' in \"{}\"", what, decl), close_bracket)
+                >> (values)
+        );
+    }
+    many1!(input, parser)
+}
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set_item_values_texture<Value>,
-    alt!(
-        do_parse!(
-            values: ws!(delimited!(tag!("["), many1!(ascii), tag!("]"))) >>
-            (Value::Texture(ParamList(values)))
-        ) |
-        do_parse!(
-            value: ws!(ascii) >>
-            (Value::Texture(ParamList(vec![value])))
-        )
-    )
-);
+/// string_list is like list, but without brackets only a single string is taken, since the next
+/// string may be the declaration of another parameter.
+fn string_list<'a>(input: &'a [Item], decl: &str) -> PResult<'a, Vec<String>> {
+    if let IResult::Done(..) = open_bracket(input) {
+        return list(input, string, "quoted string", decl);
+    }
+    map!(input, string, |s| vec![s])
+}
 
-fn param_set_item_values<'a, 'b>(input: &'a [u8], psi_type: &'b [u8]) -> IResult<&'a [u8], Value> {
-    match psi_type {
-        b"bool" => param_set_item_values_bool(input),
-        b"float" => param_set_item_values_float(input),
-        b"integer" => param_set_item_values_integer(input),
-        b"string" => param_set_item_values_string(input),
-        b"point" => param_set_item_values_point(input),
-        b"rgb" => param_set_item_values_rgb(input),
-        b"texture" => param_set_item_values_texture(input),
-        b"blackbody" => param_set_item_values_blackbody(input),
-        _ => panic!(format!(
-            "unhandled param_set_item {:?}",
-            str::from_utf8(psi_type).unwrap()
-        )),
+/// The parameter types understood by param_set_item_values.
+const PARAM_TYPES: &[&str] = &[
+    "bool", "integer", "float", "point2", "vector2", "point", "point3", "vector", "vector3",
+    "normal", "normal3", "rgb", "color", "xyz", "blackbody", "spectrum", "string", "texture",
+];
+
+/// spectrum values are either wavelength/value pairs, or the name of a .spd file holding them.
+fn spectrum<'a>(input: &'a [Item], decl: &str) -> PResult<'a, Value> {
+    let strings = if let IResult::Done(i, _) = open_bracket(input) {
+        i
+    } else {
+        input
+    };
+    if let Some(TokenKind::String(_)) = first(strings) {
+        return map!(input, call!(string_list, decl), |v| {
+            Value::SpectrumFile(ParamList(v))
+        });
+    }
+    match list(input, number, "number", decl) {
+        IResult::Done(rest, ref v) if v.len() % 2 != 0 => {
+            // Point at where the missing value belongs: the closing bracket, if any, or the
+            // token after the values.
+            let at = if strings.len() < input.len() {
+                &input[input.len() - rest.len() - 1..]
+            } else {
+                rest
+            };
+            fail(
+                at,
+                &format!("wavelength/value pairs for parameter \"{}\"", decl),
+            )
+        }
+        res => res.map(|v| Value::SampledSpectrum(ParamList(v))),
     }
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(
-    param_set_item<ParamSetItem>,
-    do_parse!(
-        tag!("\"") >>
-        typ: alphanumeric >>
-        space >>
-        name: map_res!(alphanumeric, str::from_utf8) >>
-        tag!("\"") >>
-        values: call!(param_set_item_values, typ) >>
-        (ParamSetItem::new(name, &values))
+/// Parses the values for a parameter of type typ, which must be one of PARAM_TYPES.
+fn param_set_item_values<'a>(input: &'a [Item], typ: &str, decl: &str) -> PResult<'a, Value> {
+    match typ {
+        "bool" => map!(input, call!(list, bool, "true or false", decl), |v| {
+            Value::Bool(ParamList(v))
+        }),
+        "float" => map!(input, call!(list, number, "number", decl), |v| {
+            Value::Float(ParamList(v))
+        }),
+        "integer" => map!(input, call!(list, integer, "integer", decl), |v| {
+            Value::Int(ParamList(v))
+        }),
+        "string" => map!(input, call!(string_list, decl), |v| {
+            Value::String(ParamList(v))
+        }),
+        "point2" => map!(input, call!(list, point2f, "number", decl), |v| {
+            Value::Point2f(ParamList(v))
+        }),
+        "vector2" => map!(input, call!(list, vector2f, "number", decl), |v| {
+            Value::Vector2f(ParamList(v))
+        }),
+        "point" | "point3" => map!(input, call!(list, point3f, "number", decl), |v| {
+            Value::Point3f(ParamList(v))
+        }),
+        "vector" | "vector3" => map!(input, call!(list, vector3f, "number", decl), |v| {
+            Value::Vector3f(ParamList(v))
+        }),
+        "normal" | "normal3" => map!(input, call!(list, normal3f, "number", decl), |v| {
+            Value::Normal3f(ParamList(v))
+        }),
+        "rgb" | "color" => map!(input, call!(list, number, "number", decl), |v| {
+            Value::RGB(ParamList(v))
+        }),
+        "xyz" => map!(input, call!(list, number, "number", decl), |v| {
+            Value::XYZ(ParamList(v))
+        }),
+        "texture" => map!(input, call!(string_list, decl), |v| {
+            Value::Texture(ParamList(v))
+        }),
+        "blackbody" => map!(input, call!(list, number, "number", decl), |v| {
+            Value::Blackbody(ParamList(v))
+        }),
+        "spectrum" => spectrum(input, decl),
+        _ => unreachable!("unhandled parameter type {:?}", typ),
+    }
+}
+
+/// Parses a parameter: its `"type name"` declaration followed by its values.
+fn param_set_item(input: &[Item]) -> PResult<'_, ParamSetItem> {
+    let decl = match first(input) {
+        Some(TokenKind::String(decl)) => decl,
+        _ => return fail(input, "parameter"),
+    };
+    let mut words = decl.split_whitespace();
+    let (typ, name) = match (words.next(), words.next(), words.next()) {
+        (Some(typ), Some(name), None) => (typ, name),
+        _ => return fail(input, "parameter declaration of the form \"type name\""),
+    };
+    if !PARAM_TYPES.contains(&typ) {
+        return fail(
+            input,
+            &format!(
+                "parameter type ({}) in \"{}\"",
+                PARAM_TYPES.join(", "),
+                decl
+            ),
+        );
+    }
+    // Lists Directives converted are left empty here, and moved in once the directive's parsed.
+    if let Some(Item::Values { .. }) = input.get(1) {
+        if let Some((empty, _)) = packed_values(decl) {
+            return IResult::Done(&input[2..], ParamSetItem::from_values(name, empty));
+        }
+    }
+    map!(
+        &input[1..],
+        expect!(
+            format!("value for parameter \"{}\"", decl),
+            call!(param_set_item_values, typ, decl)
+        ),
+        |values| ParamSetItem::from_values(name, values)
     )
-);
+}
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-named!(param_set<Vec<ParamSetItem>>,
-    many0!(param_set_item)
-);
+/// param_set parses parameters until the next token isn't a quoted string.  Unlike many0!, an
+/// error in a parameter is returned instead of ending the list.
+fn param_set(input: &[Item]) -> PResult<'_, Vec<ParamSetItem>> {
+    let mut items = Vec::new();
+    let mut input = input;
+    while let Some(TokenKind::String(_)) = first(input) {
+        match param_set_item(input) {
+            IResult::Done(i, item) => {
+                items.push(item);
+                input = i;
+            }
+            IResult::Error(e) => return IResult::Error(e),
+            IResult::Incomplete(n) => return IResult::Incomplete(n),
+        }
+    }
+    IResult::Done(input, items)
+}
+
+/// Returns the parameter declared by decl, e.g. "float fov", with values converted exactly as if
+/// they'd followed decl in a scene file.  If decl isn't a valid declaration or values don't suit
+/// its type, returns what was expected instead.
+pub fn parse_param(decl: &str, values: Vec<TokenKind>) -> Result<ParamSetItem, String> {
+    let token = |kind| {
+        Item::Token(Token {
+            kind,
+            start: Location::default(),
+            end: Location::default(),
+        })
+    };
+    let mut tokens = vec![
+        token(TokenKind::String(decl.to_owned())),
+        token(TokenKind::OpenBracket),
+    ];
+    tokens.extend(values.into_iter().map(token));
+    tokens.push(token(TokenKind::CloseBracket));
+    match param_set_item(&tokens) {
+        IResult::Done([], item) => Ok(item),
+        IResult::Error(ErrorKind::Custom(e)) => Err(e.what),
+        _ => Err(format!("values for parameter \"{}\"", decl)),
+    }
+}
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    look_at<Directive>,
-    ws!(
-        do_parse!(
-            tag!("LookAt") >>
-            ex: number >>
-            ey: number >>
-            ez: number >>
-            lx: number >>
-            ly: number >>
-            lz: number >>
-            ux: number >>
-            uy: number >>
-            uz: number >>
-            (Directive::LookAt(ex, ey, ez, lx, ly, lz, ux, uy, uz))
-        )
+    look_at<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "LookAt") >>
+        ex: expect!("number after LookAt", number) >>
+        ey: expect!("number after LookAt", number) >>
+        ez: expect!("number after LookAt", number) >>
+        lx: expect!("number after LookAt", number) >>
+        ly: expect!("number after LookAt", number) >>
+        lz: expect!("number after LookAt", number) >>
+        ux: expect!("number after LookAt", number) >>
+        uy: expect!("number after LookAt", number) >>
+        uz: expect!("number after LookAt", number) >>
+        (Directive::LookAt(ex, ey, ez, lx, ly, lz, ux, uy, uz))
     )
 );
 
-named!(
-    world_begin<Directive>,
-    ws!(do_parse!(tag!("WorldBegin") >> (Directive::WorldBegin)))
-);
-named!(
-    world_end<Directive>,
-    ws!(do_parse!(tag!("WorldEnd") >> (Directive::WorldEnd)))
-);
+/// $name is the nom parser created, and $tag is the tag to look for and the Directive returned,
+/// which takes no arguments.
+macro_rules! directive_no_args {
+    ($name:tt, $tag:tt) => {
+        named!(
+            $name<&[Item], Directive, Expected>,
+            do_parse!(call!(keyword, stringify!($tag)) >> (Directive::$tag))
+        );
+    };
+}
+
+directive_no_args!(world_begin, WorldBegin);
+directive_no_args!(world_end, WorldEnd);
+directive_no_args!(attribute_begin, AttributeBegin);
+directive_no_args!(attribute_end, AttributeEnd);
+directive_no_args!(identity, Identity);
+directive_no_args!(transform_begin, TransformBegin);
+directive_no_args!(transform_end, TransformEnd);
 
 /// $name is the nom parser created, and $tag is the tag to look for and the Directive type
 /// returned.
 macro_rules! directive_param_set {
     ($name:tt, $tag:tt) => {
         named!(
-            $name<Directive>,
-            ws!(do_parse!(
-                tag!(stringify!($tag))
-                    >> name: quoted_name
+            $name<&[Item], Directive, Expected>,
+            do_parse!(
+                call!(keyword, stringify!($tag))
+                    >> name: expect!(concat!("quoted name after ", stringify!($tag)), string)
                     >> ps: param_set
-                    >> (Directive::$tag(name.into(), ps.into()))
-            ))
+                    >> (Directive::$tag(name, ps.into()))
+            )
         );
     };
 }
@@ -328,212 +679,736 @@ directive_param_set!(sampler, Sampler);
 directive_param_set!(integrator, Integrator);
 directive_param_set!(film, Film);
 directive_param_set!(light_source, LightSource);
+directive_param_set!(area_light_source, AreaLightSource);
 directive_param_set!(material, Material);
+directive_param_set!(make_named_material, MakeNamedMaterial);
+directive_param_set!(make_named_medium, MakeNamedMedium);
 directive_param_set!(shape, Shape);
 directive_param_set!(camera, Camera);
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    scale<Directive>,
-    ws!(
-        do_parse!(
-            tag!("Scale") >>
-            x: number >>
-            y: number >>
-            z: number >>
-            (Directive::Scale(x, y, z))
-        )
+    scale<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Scale") >>
+        x: expect!("number after Scale", number) >>
+        y: expect!("number after Scale", number) >>
+        z: expect!("number after Scale", number) >>
+        (Directive::Scale(x, y, z))
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    rotate<Directive>,
-    ws!(
-        do_parse!(
-            tag!("Rotate") >>
-            angle: number >>
-            x: number >>
-            y: number >>
-            z: number >>
-            (Directive::Rotate(angle, x, y, z))
-        )
+    rotate<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Rotate") >>
+        angle: expect!("number after Rotate", number) >>
+        x: expect!("number after Rotate", number) >>
+        y: expect!("number after Rotate", number) >>
+        z: expect!("number after Rotate", number) >>
+        (Directive::Rotate(angle, x, y, z))
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    translate<Directive>,
-    ws!(
-        do_parse!(
-            tag!("Translate") >>
-            x: number >>
-            y: number >>
-            z: number >>
-            (Directive::Translate(x, y, z))
-        )
+    translate<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Translate") >>
+        x: expect!("number after Translate", number) >>
+        y: expect!("number after Translate", number) >>
+        z: expect!("number after Translate", number) >>
+        (Directive::Translate(x, y, z))
     )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    texture<Directive>,
-    ws!(
-        do_parse!(
-            tag!("Texture") >>
-            name: quoted_name >>
-            typ: quoted_name >>
-            class: quoted_name >>
-            ps: param_set >>
-            (Directive::Texture(name.into(), typ.into(), class.into(), ps.into()))
-        )
+    texture<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Texture") >>
+        name: expect!("quoted texture name after Texture", string) >>
+        typ: expect!("quoted texture type after Texture", string) >>
+        class: expect!("quoted texture class after Texture", string) >>
+        ps: param_set >>
+        (Directive::Texture(name, typ, class, ps.into()))
     )
 );
 
+#[rustfmt::skip]
 named!(
-    attribute_begin<Directive>,
-    ws!(do_parse!(
-        tag!("AttributeBegin") >> (Directive::AttributeBegin)
-    ))
+    matrix<&[Item], [Float; 16], Expected>,
+    do_parse!(
+        open_bracket >>
+        m: many_m_n!(16, 16, number) >>
+        close_bracket >>
+        ({
+            let mut a = [0.; 16];
+            a.copy_from_slice(&m);
+            a
+        })
+    )
 );
 
+#[rustfmt::skip]
 named!(
-    attribute_end<Directive>,
-    ws!(do_parse!(tag!("AttributeEnd") >> (Directive::AttributeEnd)))
+    transform<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Transform") >>
+        m: expect!("[ 16 numbers ] after Transform", matrix) >>
+        (Directive::Transform(m))
+    )
 );
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 named!(
-    directives<Vec<Directive>>,
-    dbg_dmp!(many1!(
-        alt!(
-            film
-            | shape
-            | scale
-            | rotate
-            | camera
-            | texture
-            | sampler
-            | look_at
-            | material
-            | translate
-            | world_end
-            | integrator
-            | world_begin
-            | light_source
-            | attribute_end
-            | attribute_begin
-        )
+    concat_transform<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "ConcatTransform") >>
+        m: expect!("[ 16 numbers ] after ConcatTransform", matrix) >>
+        (Directive::ConcatTransform(m))
+    )
+);
+
+#[rustfmt::skip]
+named!(
+    coordinate_system<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "CoordinateSystem") >>
+        name: expect!("quoted name after CoordinateSystem", string) >>
+        (Directive::CoordinateSystem(name))
     )
+);
+
+#[rustfmt::skip]
+named!(
+    named_material<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "NamedMaterial") >>
+        name: expect!("quoted name after NamedMaterial", string) >>
+        (Directive::NamedMaterial(name))
     )
 );
 
-pub fn parse_scene(input: &[u8]) -> Result<Scene, Error> {
-    match strip_comment(input) {
-        // TODO(wathiede): Implement From for IResult and use ? operator here.
-        IResult::Done(_, i) => match parse_scene_macro(&i) {
-            IResult::Done(_, scene) => Ok(scene),
-            IResult::Error(e) => Err(Error::NomError(e)),
-            IResult::Incomplete(n) => Err(Error::NomIncomplete(n)),
-        },
-        IResult::Error(e) => Err(Error::NomError(e)),
-        IResult::Incomplete(n) => Err(Error::NomIncomplete(n)),
+/// opt_string matches an optional quoted string.
+fn opt_string(input: &[Item]) -> PResult<'_, Option<String>> {
+    match string(input) {
+        IResult::Done(rest, s) => IResult::Done(rest, Some(s)),
+        _ => IResult::Done(input, None),
     }
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
+// MediumInterface takes the inside and outside medium names, or a single name used for both.
+#[rustfmt::skip]
 named!(
-    parse_scene_macro<Scene>,
-    ws!(
-        do_parse!(
-            directives: directives >>
-            (Scene{directives})
-        )
+    medium_interface<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "MediumInterface") >>
+        inside: expect!("quoted name after MediumInterface", string) >>
+        outside: call!(opt_string) >>
+        (Directive::MediumInterface(inside.clone(), outside.unwrap_or(inside)))
     )
 );
 
-#[cfg(test)]
-mod tests {
-    use std::fmt::Debug;
+#[rustfmt::skip]
+named!(
+    coord_sys_transform<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "CoordSysTransform") >>
+        name: expect!("quoted name after CoordSysTransform", string) >>
+        (Directive::CoordSysTransform(name))
+    )
+);
+
+fn active_transform_type(input: &[Item]) -> PResult<'_, ActiveTransform> {
+    let which = match first(input) {
+        Some(TokenKind::Keyword(k)) if k == "All" => ActiveTransform::All,
+        Some(TokenKind::Keyword(k)) if k == "StartTime" => ActiveTransform::StartTime,
+        Some(TokenKind::Keyword(k)) if k == "EndTime" => ActiveTransform::EndTime,
+        _ => return fail(input, "All, StartTime or EndTime"),
+    };
+    IResult::Done(&input[1..], which)
+}
+
+#[rustfmt::skip]
+named!(
+    active_transform<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "ActiveTransform") >>
+        which: expect!("All, StartTime or EndTime after ActiveTransform", active_transform_type) >>
+        (Directive::ActiveTransform(which))
+    )
+);
+
+#[rustfmt::skip]
+named!(
+    transform_times<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "TransformTimes") >>
+        start: expect!("number after TransformTimes", number) >>
+        end: expect!("number after TransformTimes", number) >>
+        (Directive::TransformTimes(start, end))
+    )
+);
+
+#[rustfmt::skip]
+named!(
+    include<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Include") >>
+        path: expect!("quoted filename after Include", string) >>
+        (Directive::Include(path))
+    )
+);
+
+#[rustfmt::skip]
+named!(
+    import<&[Item], Directive, Expected>,
+    do_parse!(
+        call!(keyword, "Import") >>
+        path: expect!("quoted filename after Import", string) >>
+        (Directive::Import(path))
+    )
+);
+
+type DirectiveParser = for<'a> fn(&'a [Item]) -> PResult<'a, Directive>;
+
+/// Returns the parser for the directive named kw.
+fn directive_parser(kw: &str) -> Option<DirectiveParser> {
+    let parser: DirectiveParser = match kw {
+        "LookAt" => look_at,
+        "Camera" => camera,
+        "Sampler" => sampler,
+        "Integrator" => integrator,
+        "Film" => film,
+        "WorldBegin" => world_begin,
+        "WorldEnd" => world_end,
+        "AttributeBegin" => attribute_begin,
+        "AttributeEnd" => attribute_end,
+        "LightSource" => light_source,
+        "AreaLightSource" => area_light_source,
+        "Material" => material,
+        "MakeNamedMaterial" => make_named_material,
+        "NamedMaterial" => named_material,
+        "MakeNamedMedium" => make_named_medium,
+        "MediumInterface" => medium_interface,
+        "Shape" => shape,
+        "Translate" => translate,
+        "Scale" => scale,
+        "Rotate" => rotate,
+        "Texture" => texture,
+        "Identity" => identity,
+        "Transform" => transform,
+        "ConcatTransform" => concat_transform,
+        "CoordinateSystem" => coordinate_system,
+        "CoordSysTransform" => coord_sys_transform,
+        "TransformBegin" => transform_begin,
+        "TransformEnd" => transform_end,
+        "ActiveTransform" => active_transform,
+        "TransformTimes" => transform_times,
+        "Include" => include,
+        "Import" => import,
+        _ => return None,
+    };
+    Some(parser)
+}
+
+/// directive parses the directive named by the keyword at the start of input.
+fn directive(input: &[Item]) -> PResult<'_, Directive> {
+    match first(input) {
+        Some(TokenKind::Keyword(kw)) => match directive_parser(kw) {
+            Some(parser) => parser(input),
+            None => fail(input, "directive"),
+        },
+        _ => fail(input, "directive"),
+    }
+}
+
+/// Returns an empty list of values for a parameter declared as decl, and how many numbers make up
+/// each value, if Directives converts bracketed lists of its type as they're read.
+fn packed_values(decl: &str) -> Option<(Value, usize)> {
+    let mut words = decl.split_whitespace();
+    let typ = match (words.next(), words.next(), words.next()) {
+        (Some(typ), Some(_), None) => typ,
+        _ => return None,
+    };
+    Some(match typ {
+        "float" => (Value::Float(ParamList(Vec::new())), 1),
+        "integer" => (Value::Int(ParamList(Vec::new())), 1),
+        "point2" => (Value::Point2f(ParamList(Vec::new())), 2),
+        "vector2" => (Value::Vector2f(ParamList(Vec::new())), 2),
+        "point" | "point3" => (Value::Point3f(ParamList(Vec::new())), 3),
+        "vector" | "vector3" => (Value::Vector3f(ParamList(Vec::new())), 3),
+        "normal" | "normal3" => (Value::Normal3f(ParamList(Vec::new())), 3),
+        "rgb" | "color" => (Value::RGB(ParamList(Vec::new())), 1),
+        "xyz" => (Value::XYZ(ParamList(Vec::new())), 1),
+        "blackbody" => (Value::Blackbody(ParamList(Vec::new())), 1),
+        "spectrum" => (Value::SampledSpectrum(ParamList(Vec::new())), 1),
+        _ => return None,
+    })
+}
+
+/// Appends the value made of the numbers n to values, which came from packed_values.
+fn push_value(values: &mut Value, n: &[f64]) {
+    let f = |i: usize| n[i] as Float;
+    match values {
+        Value::Float(pl)
+        | Value::RGB(pl)
+        | Value::XYZ(pl)
+        | Value::Blackbody(pl)
+        | Value::SampledSpectrum(pl) => pl.0.push(f(0)),
+        Value::Int(pl) => pl.0.push(n[0] as i64),
+        Value::Point2f(pl) => pl.0.push(Point2f { x: f(0), y: f(1) }),
+        Value::Vector2f(pl) => pl.0.push(Vector2f { x: f(0), y: f(1) }),
+        Value::Point3f(pl) => pl.0.push(Point3f {
+            x: f(0),
+            y: f(1),
+            z: f(2),
+        }),
+        Value::Vector3f(pl) => pl.0.push(Vector3f {
+            x: f(0),
+            y: f(1),
+            z: f(2),
+        }),
+        Value::Normal3f(pl) => pl.0.push(Normal3f {
+            x: f(0),
+            y: f(1),
+            z: f(2),
+        }),
+        _ => unreachable!("{} values aren't packed", values.type_name()),
+    }
+}
+
+/// Moves the values Directives converted out of items, which d was parsed from, into the
+/// parameters param_set_item left empty for them.  Items are taken last first, so that as in
+/// ParamSet, the last of the parameters with the same name is the one kept.
+fn move_values(mut d: Directive, mut items: Vec<Item>) -> Directive {
+    let ps = match d {
+        Directive::Camera(_, ref mut ps)
+        | Directive::Sampler(_, ref mut ps)
+        | Directive::Integrator(_, ref mut ps)
+        | Directive::Film(_, ref mut ps)
+        | Directive::LightSource(_, ref mut ps)
+        | Directive::AreaLightSource(_, ref mut ps)
+        | Directive::Material(_, ref mut ps)
+        | Directive::MakeNamedMaterial(_, ref mut ps)
+        | Directive::MakeNamedMedium(_, ref mut ps)
+        | Directive::Shape(_, ref mut ps)
+        | Directive::Texture(_, _, _, ref mut ps) => ps,
+        _ => return d,
+    };
+    while let Some(item) = items.pop() {
+        let values = match item {
+            Item::Values { values, .. } => values,
+            Item::Token(_) => continue,
+        };
+        let name = match items.last().and_then(Item::kind) {
+            Some(TokenKind::String(decl)) => decl.split_whitespace().nth(1).unwrap_or_default(),
+            _ => continue,
+        };
+        if ps.get(name).is_some_and(Value::is_empty) {
+            ps.add(name, values);
+        }
+    }
+    d
+}
+
+/// Directives is an iterator over the directives read from a scene.  Only the tokens of the
+/// directive being parsed are held in memory, and bracketed lists of numbers are converted to
+/// parameter values as they're read, so scenes of any size can be read.  `Include` and
+/// `Import` directives are returned as is, see FileDirectives to have them expanded.
+///
+/// Iteration stops after the first error.
+pub struct Directives<R> {
+    lexer: Lexer<io::BufReader<R>>,
+    file: Option<Arc<PathBuf>>,
+    /// Items read but not yet parsed.  After the first directive, items[0] is always the name of
+    /// the next directive.
+    items: Vec<Item>,
+    done: bool,
+}
+
+impl<R: io::Read> Directives<R> {
+    /// Reads directives from input.  file is the name of the file being read, used in spans and
+    /// errors.
+    pub fn new(input: R, file: Option<PathBuf>) -> Directives<R> {
+        Directives {
+            lexer: Lexer::new(io::BufReader::new(input)),
+            file: file.map(Arc::new),
+            items: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn syntax_error(&mut self, location: Location, expected: String) -> Error {
+        Error::Syntax(SyntaxError {
+            file: self.file.as_ref().map(|f| f.as_ref().clone()),
+            location,
+            line: self.lexer.line(location.line),
+            expected,
+        })
+    }
+
+    /// Returns the next token, or None at the end of the input.
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        match self.lexer.next() {
+            None => Ok(None),
+            Some(Ok(t)) => Ok(Some(t)),
+            Some(Err(LexError::Syntax { location, expected })) => {
+                Err(self.syntax_error(location, expected))
+            }
+            Some(Err(LexError::Io(e))) => {
+                let path = self.file.as_ref().map_or_else(PathBuf::new, |f| f.to_path_buf());
+                Err(Error::Io(path, e))
+            }
+        }
+    }
+
+    /// Reads tokens until the start of the directive after the next one, or the end of input.
+    /// Returns how many of the buffered items belong to the next directive.
+    fn fill(&mut self) -> Result<usize, Error> {
+        while let Some(t) = self.next_token()? {
+            let packed = match (self.items.last().and_then(Item::kind), &t.kind) {
+                (Some(TokenKind::String(decl)), TokenKind::OpenBracket) => {
+                    packed_values(decl).map(|(values, size)| (decl.clone(), values, size))
+                }
+                _ => None,
+            };
+            if let Some((decl, values, size)) = packed {
+                self.pack(t, &decl, values, size)?;
+                continue;
+            }
+            let next_directive = match t.kind {
+                TokenKind::Keyword(ref kw) => {
+                    !self.items.is_empty() && directive_parser(kw).is_some()
+                }
+                _ => false,
+            };
+            self.items.push(Item::Token(t));
+            if next_directive {
+                return Ok(self.items.len() - 1);
+            }
+        }
+        Ok(self.items.len())
+    }
+
+    /// Reads the rest of the list opened by open, converting its numbers into values, which
+    /// packed_values returned for decl, and buffers them as a single item.  Lists that
+    /// start with a string are buffered as usual.  Errors are the ones the parser would report
+    /// for the same tokens.
+    fn pack(&mut self, open: Token, decl: &str, mut values: Value, size: usize) -> Result<(), Error> {
+        let what = match values {
+            Value::Int(_) => "integer",
+            _ => "number",
+        };
+        let mut count = 0;
+        // The numbers of the value being read, and where it started.
+        let mut group = [0.; 3];
+        let mut len = 0;
+        let mut group_start = open.start;
+        loop {
+            let t = self.next_token()?;
+            match t.as_ref().map(|t| &t.kind) {
+                Some(TokenKind::String(_)) if count == 0 && len == 0 => {
+                    // Spectra can be named by file.
+                    self.items.push(Item::Token(open));
+                    self.items.extend(t.map(Item::Token));
+                    return Ok(());
+                }
+                Some(&TokenKind::Number(n)) if what == "number" || n.fract() == 0. => {
+                    if len == 0 {
+                        group_start = t.as_ref().unwrap().start;
+                    }
+                    group[len] = n;
+                    len += 1;
+                    if len == size {
+                        push_value(&mut values, &group[..size]);
+                        count += 1;
+                        len = 0;
+                        // Errors can only be reported at the '[' or from here on.
+                        self.lexer.forget_lines_after(open.start.line);
+                    }
+                }
+                Some(TokenKind::CloseBracket) if count > 0 && len == 0 => {
+                    let close = t.unwrap();
+                    if let Value::SampledSpectrum(_) = values {
+                        if count % 2 != 0 {
+                            let expected =
+                                format!("wavelength/value pairs for parameter \"{}\"", decl);
+                            return Err(self.syntax_error(close.start, expected));
+                        }
+                    }
+                    self.items.push(Item::Values {
+                        values,
+                        start: open.start,
+                        end: close.end,
+                    });
+                    return Ok(());
+                }
+                _ => {
+                    let (location, expected) = if count == 0 {
+                        (open.start, format!("value for parameter \"{}\"", decl))
+                    } else {
+                        let location = match t {
+                            _ if len > 0 => group_start,
+                            Some(t) => t.start,
+                            None => self.lexer.location(),
+                        };
+                        (location, format!("{} or ']' in \"{}\"", what, decl))
+                    };
+                    return Err(self.syntax_error(location, expected));
+                }
+            }
+        }
+    }
+
+    fn parse_next(&mut self) -> Result<Option<(Directive, Span)>, Error> {
+        let n = self.fill()?;
+        if n == 0 {
+            return Ok(None);
+        }
+        // The text of earlier lines is no longer needed for errors.
+        self.lexer.forget_lines_before(self.items[0].start().line);
+        let (location, expected) = match directive(&self.items[..n]) {
+            IResult::Done(rest, d) => {
+                if rest.is_empty() {
+                    let span = Span {
+                        file: self.file.clone(),
+                        start: self.items[0].start(),
+                        end: self.items[n - 1].end(),
+                    };
+                    let items: Vec<_> = self.items.drain(..n).collect();
+                    return Ok(Some((move_values(d, items), span)));
+                }
+                (rest[0].start(), "directive".to_owned())
+            }
+            IResult::Error(ErrorKind::Custom(e)) => {
+                let location = match self.items.get(n - e.remaining) {
+                    Some(item) => item.start(),
+                    None => self.lexer.location(),
+                };
+                (location, e.what)
+            }
+            IResult::Error(_) | IResult::Incomplete(_) => {
+                (self.items[0].start(), "directive".to_owned())
+            }
+        };
+        Err(self.syntax_error(location, expected))
+    }
+}
 
-    use nom::IResult;
+impl<R: io::Read> Iterator for Directives<R> {
+    type Item = Result<(Directive, Span), Error>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.parse_next();
+        if let Ok(None) | Err(_) = res {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
+/// Parses the scene in input.  `Include` and `Import` directives are returned as is, see
+/// parse_file to have them replaced by the contents of the files they name.
+pub fn parse_scene(input: &[u8]) -> Result<Scene, Error> {
+    collect_scene(Directives::new(input, None))
+}
+
+fn collect_scene<I>(directives: I) -> Result<Scene, Error>
+where
+    I: Iterator<Item = Result<(Directive, Span), Error>>,
+{
+    let mut scene = Scene::default();
+    for d in directives {
+        let (d, span) = d?;
+        scene.push(d, span);
+    }
+    Ok(scene)
+}
+
+/// A file being read by FileDirectives.
+struct IncludedFile {
+    directives: Directives<fs::File>,
+    /// The canonical path of the file, for finding include cycles.
+    canonical: PathBuf,
+    /// The directory relative paths in the file are resolved against.
+    dir: PathBuf,
+    /// The span of the `Include` or `Import` that named this file, None for the top level file.
+    included_at: Option<Span>,
+    /// Whether this file was imported, and so needs an `AttributeEnd` once it's finished.
+    imported: bool,
+}
+
+/// FileDirectives is an iterator over the directives in a scene file, with `Include` and `Import`
+/// directives replaced by the contents of the files they name.  Relative paths are resolved
+/// against the directory of the file containing the directive.  `Import` behaves like `Include`,
+/// but wraps the imported directives in an attribute block so the named materials and textures it
+/// defines stay local to it.
+///
+/// Files are read as the directives are needed, so a scene can be executed while it's parsed.
+/// Iteration stops after the first error.
+pub struct FileDirectives {
+    stack: Vec<IncludedFile>,
+}
+
+impl FileDirectives {
+    /// Opens the scene file at path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileDirectives, Error> {
+        let mut fd = FileDirectives { stack: Vec::new() };
+        fd.push(path.as_ref(), None, false)?;
+        Ok(fd)
+    }
+
+    fn push(&mut self, path: &Path, included_at: Option<Span>, imported: bool) -> Result<(), Error> {
+        let io_error = |e| Error::Io(path.to_path_buf(), e);
+        let canonical = path.canonicalize().map_err(io_error)?;
+        if self.stack.iter().any(|f| f.canonical == canonical) {
+            let mut chain: Vec<_> = self.stack.iter().map(|f| f.canonical.clone()).collect();
+            chain.push(canonical);
+            return Err(Error::IncludeCycle(chain));
+        }
+        let file = fs::File::open(path).map_err(io_error)?;
+        self.stack.push(IncludedFile {
+            directives: Directives::new(file, Some(path.to_path_buf())),
+            canonical,
+            dir: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            included_at,
+            imported,
+        });
+        Ok(())
+    }
+
+    fn parse_next(&mut self) -> Result<Option<(Directive, Span)>, Error> {
+        loop {
+            let next = match self.stack.last_mut() {
+                Some(f) => f.directives.next(),
+                None => return Ok(None),
+            };
+            match next {
+                Some(Ok((Directive::Include(name), span))) => {
+                    let path = self.stack.last().unwrap().dir.join(name);
+                    self.push(&path, Some(span.clone()), false)
+                        .map_err(|e| Error::Included(span, Box::new(e)))?;
+                }
+                Some(Ok((Directive::Import(name), span))) => {
+                    let path = self.stack.last().unwrap().dir.join(name);
+                    self.push(&path, Some(span.clone()), true)
+                        .map_err(|e| Error::Included(span.clone(), Box::new(e)))?;
+                    return Ok(Some((Directive::AttributeBegin, span)));
+                }
+                Some(Ok(d)) => return Ok(Some(d)),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    let f = self.stack.pop().unwrap();
+                    if f.imported {
+                        return Ok(Some((Directive::AttributeEnd, f.included_at.unwrap())));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for FileDirectives {
+    type Item = Result<(Directive, Span), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parse_next() {
+            Ok(d) => d.map(Ok),
+            Err(mut e) => {
+                // Report which directives included the file with the error, and stop.
+                for f in self.stack.drain(..).rev() {
+                    if let Some(span) = f.included_at {
+                        e = Error::Included(span, Box::new(e));
+                    }
+                }
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Parses the scene in the file at path, replacing `Include` and `Import` directives with the
+/// contents of the files they name.  See FileDirectives for details, and to execute directives as
+/// they're parsed.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Scene, Error> {
+    collect_scene(FileDirectives::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
 
-    #[allow(dead_code)]
-    fn dump<O>(res: &IResult<&[u8], O>)
-    where
-        O: Debug,
-    {
-        match res {
-            &IResult::Done(ref i, ref o) => println!("i: {:?} | o: {:?}", str::from_utf8(i), o),
-            &IResult::Error(ref e) => panic!("e: {:#?}", e),
-            &IResult::Incomplete(n) => panic!("need: {:?}", n),
+    /// Lexes input and runs parser over all of its tokens.
+    fn parse<O>(parser: for<'a> fn(&'a [Item]) -> PResult<'a, O>, input: &[u8]) -> O {
+        let tokens: Vec<_> = Lexer::new(input).map(|t| Item::Token(t.unwrap())).collect();
+        match parser(&tokens) {
+            IResult::Done(rest, o) => {
+                assert!(rest.is_empty(), "unparsed tokens: {:?}", rest);
+                o
+            }
+            IResult::Error(e) => panic!("parse failed: {:?}", e),
+            IResult::Incomplete(n) => panic!("need: {:?}", n),
+        }
+    }
+
+    /// Lexes input and parses it as the values of a parameter of type typ.
+    fn values(typ: &str, input: &[u8]) -> Value {
+        let tokens: Vec<_> = Lexer::new(input).map(|t| Item::Token(t.unwrap())).collect();
+        match param_set_item_values(&tokens, typ, typ) {
+            IResult::Done(&[], v) => v,
+            res => panic!("parse failed: {:?}", res),
         }
     }
 
+    fn directives(input: &[u8]) -> Vec<Directive> {
+        parse_scene(input).unwrap().directives
+    }
+
     #[test]
-    fn test_strip_comment() {
-        assert_eq!(
-            strip_comment(&b"a # comment\nb # comment\n"[..]),
-            IResult::Done(&b""[..], Vec::from(&b"a \nb \n"[..]))
-        );
-        assert_eq!(
-            strip_comment(&b"a # comment\nb\n"[..]),
-            IResult::Done(&b""[..], Vec::from(&b"a \nb\n"[..]))
-        );
-        assert_eq!(
-            strip_comment(&b"a\nb\n"[..]),
-            IResult::Done(&b""[..], Vec::from(&b"a\nb\n"[..]))
-        );
+    fn test_number_comment_number() {
         assert_eq!(
-            strip_comment(&b"a\nb # comment\n"[..]),
-            IResult::Done(&b""[..], Vec::from(&b"a\nb \n"[..]))
+            values("float", &b"[ 1 # comment\n2 3]\n"[..]),
+            Value::Float(ParamList(vec![1., 2., 3.]))
         );
     }
 
     #[test]
-    fn test_number() {
-        assert_eq!(number(&b"3.14"[..]), IResult::Done(&b""[..], 3.14));
-        assert_eq!(number(&b".1"[..]), IResult::Done(&b""[..], 0.1));
-        assert_eq!(number(&b"0.2"[..]), IResult::Done(&b""[..], 0.2));
-        assert_eq!(number(&b"3."[..]), IResult::Done(&b""[..], 3.));
-        assert_eq!(number(&b"4"[..]), IResult::Done(&b""[..], 4.));
+    fn test_integer() {
+        assert_eq!(parse(integer, &b"-3"[..]), -3);
+        let tokens: Vec<_> = Lexer::new(&b"1.5"[..]).map(|t| Item::Token(t.unwrap())).collect();
+        assert!(integer(&tokens).is_err());
     }
 
     #[test]
-    fn test_number_comment_number() {
-        if let IResult::Done(_, input) = strip_comment(&b"[ 1 # comment\n2 3]\n"[..]) {
-            let ref res = param_set_item_values_float(&input);
-            assert_eq!(
-                res,
-                &IResult::Done(&b""[..], Value::Float(ParamList(vec![1., 2., 3.])))
-            );
-        };
+    fn test_bool() {
+        assert_eq!(parse(bool, &b"true"[..]), true);
+        assert_eq!(parse(bool, &b"\"false\""[..]), false);
     }
 
     #[test]
     fn test_param_set_item_values_point() {
         let input = &b"[.1 .2 .3  .4 .5 .6]\n"[..];
-        let ref res = param_set_item_values_point(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                Value::Point3f(
-                    vec![
-                        Point3f {
-                            x: 0.1,
-                            y: 0.2,
-                            z: 0.3,
-                        },
-                        Point3f {
-                            x: 0.4,
-                            y: 0.5,
-                            z: 0.6,
-                        },
-                    ].into()
-                )
+            values("point", input),
+            Value::Point3f(
+                vec![
+                    Point3f {
+                        x: 0.1,
+                        y: 0.2,
+                        z: 0.3,
+                    },
+                    Point3f {
+                        x: 0.4,
+                        y: 0.5,
+                        z: 0.6,
+                    },
+                ].into()
             )
         );
     }
@@ -541,116 +1416,123 @@ mod tests {
     #[test]
     fn test_param_set_item_values_float() {
         let input = &b"[.4 .45 .5]\n"[..];
-        let ref res = param_set_item_values_float(&input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Float(ParamList(vec![0.4, 0.45, 0.5])))
-        );
+        assert_eq!(values("float", input), Value::Float(ParamList(vec![0.4, 0.45, 0.5])));
 
         let input = &b"1 2. -3.0"[..];
-        let ref res = param_set_item_values_float(input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Float(ParamList(vec![1., 2., -3.])))
-        );
+        assert_eq!(values("float", input), Value::Float(ParamList(vec![1., 2., -3.])));
 
         let input = &b"[  1 2 3]"[..];
-        let ref res = param_set_item_values_float(input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Float(ParamList(vec![1., 2., 3.])))
-        );
+        assert_eq!(values("float", input), Value::Float(ParamList(vec![1., 2., 3.])));
     }
 
     #[test]
     fn test_param_set_item_values_integer() {
         let input = &b"[-1 2 3]\n"[..];
-        let ref res = param_set_item_values_integer(&input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Int(ParamList(vec![-1, 2, 3])))
-        );
+        assert_eq!(values("integer", input), Value::Int(ParamList(vec![-1, 2, 3])));
 
         let input = &b"[400]\n"[..];
-        let ref res = param_set_item_values_integer(&input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Int(ParamList(vec![400])))
-        );
+        assert_eq!(values("integer", input), Value::Int(ParamList(vec![400])));
     }
 
     #[test]
     fn test_param_set_item_values_string() {
         let input = &b"[\"foo\"]\n"[..];
-        let ref res = param_set_item_values_string(&input);
+        assert_eq!(values("string", input), Value::String(ParamList(vec!["foo".to_owned()])));
+
+        let input = &b"\"foo\"\n"[..];
+        assert_eq!(values("string", input), Value::String(ParamList(vec!["foo".to_owned()])));
+    }
+
+    #[test]
+    fn test_param_set_item_values_texture() {
+        let input = &b"[\"foo\"]\n"[..];
+        assert_eq!(values("texture", input), Value::Texture(ParamList(vec!["foo".to_owned()])));
+
+        let input = &b"\"foo\"\n"[..];
+        assert_eq!(values("texture", input), Value::Texture(ParamList(vec!["foo".to_owned()])));
+    }
+
+    #[test]
+    fn test_param_set_item_values_geometric() {
+        assert_eq!(
+            values("point2", &b"[0 1 2 3]"[..]),
+            Value::Point2f(ParamList(vec![
+                Point2f { x: 0., y: 1. },
+                Point2f { x: 2., y: 3. },
+            ]))
+        );
         assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::String(ParamList(vec!["foo".to_owned()])))
+            values("vector2", &b"[0 1]"[..]),
+            Value::Vector2f(ParamList(vec![Vector2f { x: 0., y: 1. }]))
         );
+        let p = Value::Point3f(ParamList(vec![Point3f {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        }]));
+        assert_eq!(values("point", &b"[1 2 3]"[..]), p);
+        assert_eq!(values("point3", &b"[1 2 3]"[..]), p);
+        let v = Value::Vector3f(ParamList(vec![Vector3f::new(1., 2., 3.)]));
+        assert_eq!(values("vector", &b"[1 2 3]"[..]), v);
+        assert_eq!(values("vector3", &b"[1 2 3]"[..]), v);
+        let n = Value::Normal3f(ParamList(vec![Normal3f {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        }]));
+        assert_eq!(values("normal", &b"[0 0 1]"[..]), n);
+        assert_eq!(values("normal3", &b"[0 0 1]"[..]), n);
+    }
 
-        let input = &b"\"foo\"\n"[..];
-        let ref res = param_set_item_values_string(&input);
+    #[test]
+    fn test_param_set_item_values_spectra() {
+        let rgb = Value::RGB(ParamList(vec![0.1, 0.2, 0.3]));
+        assert_eq!(values("rgb", &b"[.1 .2 .3]"[..]), rgb);
+        assert_eq!(values("color", &b"[.1 .2 .3]"[..]), rgb);
+        assert_eq!(
+            values("xyz", &b"[.1 .2 .3]"[..]),
+            Value::XYZ(ParamList(vec![0.1, 0.2, 0.3]))
+        );
         assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::String(ParamList(vec!["foo".to_owned()])))
+            values("spectrum", &b"[300 .3  400 .6]"[..]),
+            Value::SampledSpectrum(ParamList(vec![300., 0.3, 400., 0.6]))
         );
-    }
-
-    #[test]
-    fn test_param_set_item_values_texture() {
-        let input = &b"[\"foo\"]\n"[..];
-        let ref res = param_set_item_values_texture(&input);
         assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Texture(ParamList(vec!["foo".to_owned()])))
+            values("spectrum", &b"\"spds/metals/Au.eta.spd\""[..]),
+            Value::SpectrumFile(ParamList(vec!["spds/metals/Au.eta.spd".to_owned()]))
         );
-
-        let input = &b"\"foo\"\n"[..];
-        let ref res = param_set_item_values_texture(&input);
         assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Value::Texture(ParamList(vec!["foo".to_owned()])))
+            values("spectrum", &b"[ \"Au.eta.spd\" ]"[..]),
+            Value::SpectrumFile(ParamList(vec!["Au.eta.spd".to_owned()]))
         );
     }
 
     #[test]
     fn test_param_set_item_float() {
         let input = &b"\"float foo\" [ 0 1 2 ]"[..];
-        let ref res = param_set_item(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                ParamSetItem::new("foo", &Value::Float(ParamList(vec![0., 1., 2.])))
-            )
+            parse(param_set_item, input),
+            ParamSetItem::new("foo", &Value::Float(ParamList(vec![0., 1., 2.])))
         );
     }
 
     #[test]
     fn test_param_set_item_integer() {
         let input = &b"\"integer foo\" [400]"[..];
-        let ref res = param_set_item(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                ParamSetItem::new("foo", &Value::Int(ParamList(vec![400])))
-            )
+            parse(param_set_item, input),
+            ParamSetItem::new("foo", &Value::Int(ParamList(vec![400])))
         );
     }
 
     #[test]
     fn test_param_set_item_bool() {
         let input = &b"\"bool foo\" [true false true false]"[..];
-        let ref res = param_set_item(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                ParamSetItem::new(
-                    "foo",
-                    &Value::Bool(ParamList(vec![true, false, true, false]))
-                )
+            parse(param_set_item, input),
+            ParamSetItem::new(
+                "foo",
+                &Value::Bool(ParamList(vec![true, false, true, false]))
             )
         );
     }
@@ -660,55 +1542,43 @@ mod tests {
         let input = &b"\"bool foo\" [true false true false]
 \"integer bar\" [ 0 1 2 ]
 "[..];
-        let ref res = param_set(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    ParamSetItem::new(
-                        "foo",
-                        &Value::Bool(ParamList(vec![true, false, true, false])),
-                    ),
-                    ParamSetItem::new("bar", &Value::Int(ParamList(vec![0, 1, 2]))),
-                ]
-            )
+            parse(param_set, input),
+            vec![
+                ParamSetItem::new(
+                    "foo",
+                    &Value::Bool(ParamList(vec![true, false, true, false])),
+                ),
+                ParamSetItem::new("bar", &Value::Int(ParamList(vec![0, 1, 2]))),
+            ]
         );
 
         let input = &b"\"integer xresolution\" [400] \"integer yresolution\" [200]
 \"string filename\" \"simple.png\"
 "[..];
-        let ref res = param_set(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    ParamSetItem::new("xresolution", &Value::Int(ParamList(vec![400]))),
-                    ParamSetItem::new("yresolution", &Value::Int(ParamList(vec![200]))),
-                    ParamSetItem::new(
-                        "filename",
-                        &Value::String(ParamList(vec!["simple.png".to_owned()])),
-                    ),
-                ]
-            )
+            parse(param_set, input),
+            vec![
+                ParamSetItem::new("xresolution", &Value::Int(ParamList(vec![400]))),
+                ParamSetItem::new("yresolution", &Value::Int(ParamList(vec![200]))),
+                ParamSetItem::new(
+                    "filename",
+                    &Value::String(ParamList(vec!["simple.png".to_owned()])),
+                ),
+            ]
         );
         let input = &b"\"string filename\" \"simple.png\"
 \"integer xresolution\" [400] \"integer yresolution\" [200]"[..];
-        let ref res = param_set(input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    ParamSetItem::new(
-                        "filename",
-                        &Value::String(ParamList(vec!["simple.png".to_owned()])),
-                    ),
-                    ParamSetItem::new("xresolution", &Value::Int(ParamList(vec![400]))),
-                    ParamSetItem::new("yresolution", &Value::Int(ParamList(vec![200]))),
-                ]
-            )
+            parse(param_set, input),
+            vec![
+                ParamSetItem::new(
+                    "filename",
+                    &Value::String(ParamList(vec!["simple.png".to_owned()])),
+                ),
+                ParamSetItem::new("xresolution", &Value::Int(ParamList(vec![400]))),
+                ParamSetItem::new("yresolution", &Value::Int(ParamList(vec![200]))),
+            ]
         );
     }
 
@@ -718,92 +1588,64 @@ mod tests {
 .5 .5 0  # look at point
 0 0 1    # up vector
 "[..];
-        if let IResult::Done(_, input) = strip_comment(input) {
-            let ref mut res = look_at(&input);
-            assert_eq!(
-                res,
-                &IResult::Done(
-                    &b""[..],
-                    Directive::LookAt(3., 4., 1.5, 0.5, 0.5, 0., 0., 0., 1.)
-                )
-            );
-        };
+        assert_eq!(
+            parse(look_at, input),
+            Directive::LookAt(3., 4., 1.5, 0.5, 0.5, 0., 0., 0., 1.)
+        );
     }
 
     #[test]
     fn test_camera() {
         let input = &b"Camera \"perspective\" \"float fov\" 45"[..];
-        if let IResult::Done(_, input) = strip_comment(input) {
-            let ref mut res = camera(&input);
-            assert_eq!(
-                res,
-                &IResult::Done(
-                    &b""[..],
-                    Directive::Camera(
-                        "perspective".into(),
-                        vec![ParamSetItem::new("fov", &Value::Float(vec![45.].into()))].into()
-                    ),
-                )
-            );
-        };
+        assert_eq!(
+            parse(camera, input),
+            Directive::Camera(
+                "perspective".into(),
+                vec![ParamSetItem::new("fov", &Value::Float(vec![45.].into()))].into()
+            ),
+        );
     }
 
     #[test]
     fn test_sampler() {
         let input = &b"Sampler \"halton\" \"integer pixelsamples\" 128"[..];
-        if let IResult::Done(_, input) = strip_comment(input) {
-            let ref mut res = sampler(&input);
-            assert_eq!(
-                res,
-                &IResult::Done(
-                    &b""[..],
-                    Directive::Sampler(
-                        "halton".into(),
-                        vec![ParamSetItem::new(
-                            "pixelsamples",
-                            &Value::Int(vec![128].into()),
-                        )].into()
-                    )
-                )
-            );
-        };
+        assert_eq!(
+            parse(sampler, input),
+            Directive::Sampler(
+                "halton".into(),
+                vec![ParamSetItem::new(
+                    "pixelsamples",
+                    &Value::Int(vec![128].into()),
+                )].into()
+            )
+        );
     }
 
     #[test]
     fn test_integrator() {
         let input = &b"Integrator \"path\""[..];
-        if let IResult::Done(_, input) = strip_comment(input) {
-            let ref mut res = integrator(&input);
-            assert_eq!(
-                res,
-                &IResult::Done(
-                    &b""[..],
-                    Directive::Integrator("path".into(), vec![].into())
-                )
-            );
-        };
+        assert_eq!(
+            parse(integrator, input),
+            Directive::Integrator("path".into(), vec![].into())
+        );
     }
 
     #[test]
     fn test_film() {
         let input = &b"Film \"image\" \"string filename\" \"simple.png\"
 \"integer xresolution\" [400] \"integer yresolution\" [200]"[..];
-        let ref mut res = film(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                Directive::Film(
-                    "image".into(),
-                    vec![
-                        ParamSetItem::new(
-                            "filename",
-                            &Value::String(vec!["simple.png".to_owned()].into()),
-                        ),
-                        ParamSetItem::new("xresolution", &Value::Int(vec![400].into())),
-                        ParamSetItem::new("yresolution", &Value::Int(vec![200].into())),
-                    ].into()
-                )
+            parse(film, input),
+            Directive::Film(
+                "image".into(),
+                vec![
+                    ParamSetItem::new(
+                        "filename",
+                        &Value::String(vec!["simple.png".to_owned()].into()),
+                    ),
+                    ParamSetItem::new("xresolution", &Value::Int(vec![400].into())),
+                    ParamSetItem::new("yresolution", &Value::Int(vec![200].into())),
+                ].into()
             )
         );
     }
@@ -812,148 +1654,530 @@ mod tests {
     fn test_attribute() {
         let input =
             &b"AttributeBegin\n  LightSource \"infinite\" \"rgb L\" [.4 .45 .5]\nAttributeEnd"[..];
-        let ref mut res = directives(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    Directive::AttributeBegin,
-                    Directive::LightSource(
-                        "infinite".into(),
-                        vec![ParamSetItem::new(
-                            "L",
-                            &Value::RGB(ParamList(vec![0.4, 0.45, 0.5])),
-                        )].into(),
-                    ),
-                    Directive::AttributeEnd,
-                ]
-            )
+            directives(input),
+            vec![
+                Directive::AttributeBegin,
+                Directive::LightSource(
+                    "infinite".into(),
+                    vec![ParamSetItem::new(
+                        "L",
+                        &Value::RGB(ParamList(vec![0.4, 0.45, 0.5])),
+                    )].into(),
+                ),
+                Directive::AttributeEnd,
+            ]
         );
 
         let input = &b"AttributeBegin\n  Material \"mirror\"\nAttributeEnd"[..];
-        let ref mut res = directives(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    Directive::AttributeBegin,
-                    Directive::Material("mirror".into(), vec![].into()),
-                    Directive::AttributeEnd,
-                ]
-            )
+            directives(input),
+            vec![
+                Directive::AttributeBegin,
+                Directive::Material("mirror".into(), vec![].into()),
+                Directive::AttributeEnd,
+            ]
         );
 
         let input = &b"AttributeBegin\n  Shape \"sphere\" \"float radius\" 1\nAttributeEnd"[..];
-        let ref mut res = directives(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    Directive::AttributeBegin,
-                    Directive::Shape(
-                        "sphere".into(),
-                        vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
-                    ),
-                    Directive::AttributeEnd,
-                ].into(),
-            )
+            directives(input),
+            vec![
+                Directive::AttributeBegin,
+                Directive::Shape(
+                    "sphere".into(),
+                    vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
+                ),
+                Directive::AttributeEnd,
+            ],
         );
 
         let input = &b"AttributeBegin\n  Material \"mirror\"\n  Shape \"sphere\" \"float radius\" 1\nAttributeEnd"[..];
-        let ref mut res = directives(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                vec![
-                    Directive::AttributeBegin,
-                    Directive::Material("mirror".into(), vec![].into()),
-                    Directive::Shape(
-                        "sphere".into(),
-                        vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
-                    ),
-                    Directive::AttributeEnd,
-                ].into(),
-            )
+            directives(input),
+            vec![
+                Directive::AttributeBegin,
+                Directive::Material("mirror".into(), vec![].into()),
+                Directive::Shape(
+                    "sphere".into(),
+                    vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
+                ),
+                Directive::AttributeEnd,
+            ],
         );
     }
 
     #[test]
     fn test_light_source() {
         let input = &b"LightSource \"infinite\" \"rgb L\" [.4 .45 .5]"[..];
-        let ref mut res = light_source(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                Directive::LightSource(
-                    "infinite".into(),
-                    vec![ParamSetItem::new(
-                        "L",
-                        &Value::RGB(ParamList(vec![0.4, 0.45, 0.5])),
-                    )].into()
-                )
+            parse(light_source, input),
+            Directive::LightSource(
+                "infinite".into(),
+                vec![ParamSetItem::new(
+                    "L",
+                    &Value::RGB(ParamList(vec![0.4, 0.45, 0.5])),
+                )].into()
             )
         );
     }
 
+    #[test]
+    fn test_named_materials() {
+        let input = &b"MakeNamedMaterial \"gold\" \"string type\" \"metal\"
+AttributeBegin
+  NamedMaterial \"gold\"
+  AreaLightSource \"diffuse\" \"rgb L\" [1 1 1]
+AttributeEnd"[..];
+        assert_eq!(
+            directives(input),
+            vec![
+                Directive::MakeNamedMaterial(
+                    "gold".into(),
+                    vec![ParamSetItem::new(
+                        "type",
+                        &Value::String(vec!["metal".to_owned()].into()),
+                    )].into(),
+                ),
+                Directive::AttributeBegin,
+                Directive::NamedMaterial("gold".into()),
+                Directive::AreaLightSource(
+                    "diffuse".into(),
+                    vec![ParamSetItem::new("L", &Value::RGB(vec![1., 1., 1.].into()))].into(),
+                ),
+                Directive::AttributeEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_media() {
+        let input = &b"MakeNamedMedium \"fog\" \"string type\" \"homogeneous\"
+MediumInterface \"fog\" \"\"
+MediumInterface \"fog\"
+WorldBegin"[..];
+        assert_eq!(
+            directives(input),
+            vec![
+                Directive::MakeNamedMedium(
+                    "fog".into(),
+                    vec![ParamSetItem::new(
+                        "type",
+                        &Value::String(vec!["homogeneous".to_owned()].into()),
+                    )].into(),
+                ),
+                Directive::MediumInterface("fog".into(), "".into()),
+                Directive::MediumInterface("fog".into(), "fog".into()),
+                Directive::WorldBegin,
+            ]
+        );
+    }
+
     #[test]
     fn test_material() {
         let input = &b"Material \"mirror\""[..];
-        let ref mut res = material(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                Directive::Material("mirror".into(), vec![].into()),
-            )
+            parse(material, input),
+            Directive::Material("mirror".into(), vec![].into()),
         );
     }
 
     #[test]
     fn test_shape() {
         let input = &b"Shape \"sphere\" \"float radius\" 1"[..];
-        let ref mut res = shape(&input);
         assert_eq!(
-            res,
-            &IResult::Done(
-                &b""[..],
-                Directive::Shape(
-                    "sphere".into(),
-                    vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
-                ),
-            )
+            parse(shape, input),
+            Directive::Shape(
+                "sphere".into(),
+                vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
+            ),
         );
     }
 
     #[test]
     fn test_translate() {
         let input = &b"Translate 0 0 -1"[..];
-        let ref mut res = translate(&input);
-        assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Directive::Translate(0., 0., -1.))
-        );
+        assert_eq!(parse(translate, input), Directive::Translate(0., 0., -1.));
     }
 
     #[test]
     fn test_scale() {
         let input = &b"Scale 0 0 -1"[..];
-        let ref mut res = scale(&input);
-        assert_eq!(res, &IResult::Done(&b""[..], Directive::Scale(0., 0., -1.)));
+        assert_eq!(parse(scale, input), Directive::Scale(0., 0., -1.));
     }
 
     #[test]
     fn test_rotate() {
         let input = &b"Rotate 30 0 0 -1"[..];
-        let ref mut res = rotate(&input);
+        assert_eq!(parse(rotate, input), Directive::Rotate(30., 0., 0., -1.));
+    }
+
+    #[test]
+    fn test_transform() {
+        let input = &b"Transform [1 0 0 0  0 1 0 0  0 0 1 0  2 3 4 1]"[..];
+        assert_eq!(
+            parse(transform, input),
+            Directive::Transform([
+                1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 2., 3., 4., 1.,
+            ])
+        );
+
+        let input = &b"ConcatTransform [ 2 0 0 0 0 2 0 0 0 0 2 0 0 0 0 1 ]"[..];
+        assert_eq!(
+            parse(concat_transform, input),
+            Directive::ConcatTransform([
+                2., 0., 0., 0., 0., 2., 0., 0., 0., 0., 2., 0., 0., 0., 0., 1.,
+            ])
+        );
+
+        // Too few values.
+        let input = &b"Transform [1 0 0 0]"[..];
+        let tokens: Vec<_> = Lexer::new(input).map(|t| Item::Token(t.unwrap())).collect();
+        assert!(transform(&tokens).is_err());
+    }
+
+    #[test]
+    fn test_transform_directives() {
+        let input = &b"Identity
+TransformTimes 0 1.5
+TransformBegin
+  ActiveTransform StartTime
+  Translate 1 0 0
+  ActiveTransform EndTime
+  Translate 2 0 0
+  ActiveTransform All
+  CoordinateSystem \"mycs\"
+TransformEnd
+CoordSysTransform \"mycs\"
+Transform [1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1]
+"[..];
+        assert_eq!(
+            directives(input),
+            vec![
+                Directive::Identity,
+                Directive::TransformTimes(0., 1.5),
+                Directive::TransformBegin,
+                Directive::ActiveTransform(ActiveTransform::StartTime),
+                Directive::Translate(1., 0., 0.),
+                Directive::ActiveTransform(ActiveTransform::EndTime),
+                Directive::Translate(2., 0., 0.),
+                Directive::ActiveTransform(ActiveTransform::All),
+                Directive::CoordinateSystem("mycs".into()),
+                Directive::TransformEnd,
+                Directive::CoordSysTransform("mycs".into()),
+                Directive::Transform([
+                    1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1.,
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let input = &b"Include \"geometry/foo.pbrt\"\nImport \"bar.pbrt\""[..];
+        assert_eq!(
+            directives(input),
+            vec![
+                Directive::Include("geometry/foo.pbrt".into()),
+                Directive::Import("bar.pbrt".into()),
+            ]
+        );
+    }
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/core/testdata")
+            .join(name)
+    }
+
+    #[test]
+    fn test_parse_file_include() {
+        let scene = parse_file(testdata("include/main.pbrt")).unwrap();
+        assert_eq!(
+            scene.directives,
+            vec![
+                Directive::WorldBegin,
+                // From geometry/shapes.pbrt.
+                Directive::Shape(
+                    "sphere".into(),
+                    vec![ParamSetItem::new("radius", &Value::Float(vec![1.].into()))].into(),
+                ),
+                // From geometry/material.pbrt, included by geometry/shapes.pbrt.
+                Directive::Material("mirror".into(), vec![].into()),
+                Directive::AttributeBegin,
+                // From geometry/material.pbrt, imported by main.pbrt.
+                Directive::Material("mirror".into(), vec![].into()),
+                Directive::AttributeEnd,
+                Directive::WorldEnd,
+            ]
+        );
+    }
+
+    /// Returns the error from the innermost included file.
+    fn innermost(e: &Error) -> &Error {
+        match e {
+            Error::Included(_, e) => innermost(e),
+            e => e,
+        }
+    }
+
+    #[test]
+    fn test_parse_file_include_cycle() {
+        let err = parse_file(testdata("include/cycle_a.pbrt")).unwrap_err();
+        match innermost(&err) {
+            Error::IncludeCycle(chain) => {
+                let names: Vec<_> = chain
+                    .iter()
+                    .map(|p| p.file_name().unwrap().to_str().unwrap())
+                    .collect();
+                assert_eq!(names, vec!["cycle_a.pbrt", "cycle_b.pbrt", "cycle_a.pbrt"]);
+            }
+            e => panic!("expected include cycle, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse_file_include_missing() {
+        let err = parse_file(testdata("include/missing.pbrt")).unwrap_err();
+        match *innermost(&err) {
+            Error::Io(ref path, _) => assert!(path.ends_with("include/does-not-exist.pbrt")),
+            ref e => panic!("expected io error, got {:?}", e),
+        }
+        assert!(err.to_string().contains("does-not-exist.pbrt"));
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = &b"WorldBegin
+  Translate 1 2 3  # comment
+  Shape \"sphere\"
+    \"float radius\" 1
+WorldEnd"[..];
+        let scene = parse_scene(input).unwrap();
+        let spans: Vec<_> = scene
+            .spans
+            .iter()
+            .map(|s| ((s.start.line, s.start.column), (s.end.line, s.end.column)))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ((1, 1), (1, 11)),
+                ((2, 3), (2, 18)),
+                ((3, 3), (4, 21)),
+                ((5, 1), (5, 9)),
+            ]
+        );
+        assert_eq!(scene.spans[1].to_string(), "2:3");
+    }
+
+    /// A reader that fails, to show how much input has been read.
+    struct Broken;
+
+    impl io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    #[test]
+    fn test_directives_streaming() {
+        let input = io::Read::chain(&b"WorldBegin\nTranslate 1 2 3\n"[..], Broken);
+        let mut it = Directives::new(input, None);
+        // The first directive is returned before the rest of the input is needed.
+        let (d, span) = it.next().unwrap().unwrap();
+        assert_eq!(d, Directive::WorldBegin);
+        assert_eq!(span.start, Location { line: 1, column: 1 });
+        // Translate could be followed by more numbers, so the read error is hit.
+        match it.next() {
+            Some(Err(Error::Io(..))) => (),
+            res => panic!("expected io error, got {:?}", res),
+        }
+        assert!(it.next().is_none());
+
+        let mut it = Directives::new(&b"WorldBegin\nTranslate 1 x 3\nWorldEnd"[..], None);
+        assert_eq!(it.next().unwrap().unwrap().0, Directive::WorldBegin);
+        match it.next() {
+            Some(Err(Error::Syntax(ref e))) => {
+                assert_eq!(e.location, Location { line: 2, column: 13 });
+                assert_eq!(e.line, "Translate 1 x 3");
+            }
+            res => panic!("expected syntax error, got {:?}", res),
+        }
+        assert!(it.next().is_none());
+    }
+
+    #[test]
+    fn test_packed_values() {
+        let input = &b"Shape \"trianglemesh\"
+  \"point P\" [0 0 0
+             1 0 0
+             0 1 0]
+  \"integer indices\" [0 1 2] \"float radius\" 1
+  \"spectrum eta\" [\"Au.eta.spd\"] \"spectrum k\" [300 .3 400 .6]
+WorldEnd"[..];
+        let mut it = Directives::new(input, None);
+        let (d, span) = it.next().unwrap().unwrap();
+        assert_eq!(
+            d,
+            Directive::Shape(
+                "trianglemesh".into(),
+                vec![
+                    ParamSetItem::new(
+                        "P",
+                        &Value::Point3f(ParamList(vec![
+                            Point3f::new(0., 0., 0.),
+                            Point3f::new(1., 0., 0.),
+                            Point3f::new(0., 1., 0.),
+                        ])),
+                    ),
+                    ParamSetItem::new("indices", &Value::Int(ParamList(vec![0, 1, 2]))),
+                    ParamSetItem::new("radius", &Value::Float(ParamList(vec![1.]))),
+                    ParamSetItem::new(
+                        "eta",
+                        &Value::SpectrumFile(ParamList(vec!["Au.eta.spd".to_owned()])),
+                    ),
+                    ParamSetItem::new(
+                        "k",
+                        &Value::SampledSpectrum(ParamList(vec![300., 0.3, 400., 0.6])),
+                    ),
+                ]
+                .into()
+            )
+        );
+        assert_eq!(span.end, Location { line: 6, column: 61 });
+        // The text of the lines inside the list of points was dropped as it was read.
+        assert_eq!(it.lexer.line(1), "Shape \"trianglemesh\"");
+        assert_eq!(it.lexer.line(2), "  \"point P\" [0 0 0");
+        assert_eq!(it.lexer.line(3), "");
+        assert_eq!(it.lexer.line(4), "             0 1 0]");
+
+        // Errors inside lists are the same as for other values, and show their line.
+        let e = syntax_error(&b"Shape \"trianglemesh\" \"point P\" [0 0 0\n1 0 0\n0 1 x]"[..]);
+        assert_eq!(e.location, Location { line: 3, column: 1 });
+        assert_eq!(e.expected, "number or ']' in \"point P\"");
+        assert_eq!(e.line, "0 1 x]");
+        let e = syntax_error(&b"Shape \"trianglemesh\" \"integer indices\" [\n0 1 2.5\n]"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 5 });
+        assert_eq!(e.expected, "integer or ']' in \"integer indices\"");
+        let e = syntax_error(&b"Shape \"trianglemesh\" \"point P\" [0 0\n]"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 32 });
+        assert_eq!(e.expected, "value for parameter \"point P\"");
+        assert_eq!(e.line, "Shape \"trianglemesh\" \"point P\" [0 0");
+        let e = syntax_error(&b"Shape \"trianglemesh\" \"point P\" [0 0\n0 1]"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 3 });
+        assert_eq!(e.expected, "number or ']' in \"point P\"");
+        let e = syntax_error(&b"Shape \"trianglemesh\" \"float radius\" [1 2"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 41 });
+        assert_eq!(e.expected, "number or ']' in \"float radius\"");
+
+        // As for other parameters, the last of the same name is kept.
+        let input = &b"Shape \"sphere\" \"float radius\" [1] \"float radius\" [2]
+Shape \"sphere\" \"float radius\" [1] \"float radius\" 2
+Shape \"sphere\" \"float radius\" 1 \"float radius\" [2]"[..];
+        for d in Directives::new(input, None) {
+            assert_eq!(
+                d.unwrap().0,
+                Directive::Shape(
+                    "sphere".into(),
+                    vec![ParamSetItem::new("radius", &Value::Float(ParamList(vec![2.])))].into()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_directives() {
+        let mut it = FileDirectives::open(testdata("include/bad.pbrt")).unwrap();
+        // Directives before the error are returned as they're parsed.
+        assert_eq!(it.next().unwrap().unwrap().0, Directive::WorldBegin);
+        match it.next() {
+            Some(Err(Error::Included(..))) => (),
+            res => panic!("expected included error, got {:?}", res),
+        }
+        assert!(it.next().is_none());
+    }
+
+    fn syntax_error(input: &[u8]) -> SyntaxError {
+        match parse_scene(input) {
+            Err(Error::Syntax(e)) => e,
+            res => panic!("expected syntax error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let e = syntax_error(&b"WorldBegin\n  Translate 1 x 3\nWorldEnd\n"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 15 });
+        assert_eq!(e.expected, "number after Translate");
         assert_eq!(
-            res,
-            &IResult::Done(&b""[..], Directive::Rotate(30., 0., 0., -1.))
+            e.to_string(),
+            "2:15: expected number after Translate\n  Translate 1 x 3\n              ^"
         );
+
+        // Running out of input.
+        let e = syntax_error(&b"Rotate 90 0 1"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 14 });
+        assert_eq!(e.expected, "number after Rotate");
+
+        let e = syntax_error(&b"WorldBegin\nBogus 1 2 3\n"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 1 });
+        assert_eq!(e.expected, "directive");
+
+        let e = syntax_error(&b"Camera perspective"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 8 });
+        assert_eq!(e.expected, "quoted name after Camera");
+
+        // The snippet is the original line, comments included.
+        let e = syntax_error(&b"Film \"image\" # comment\n  \"integer xresolution\" [ x ]"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 25 });
+        assert_eq!(e.expected, "value for parameter \"integer xresolution\"");
+        assert_eq!(e.line, "  \"integer xresolution\" [ x ]");
+
+        let e = syntax_error(&b"Transform [ 1 2 3 ]"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 11 });
+        assert_eq!(e.expected, "[ 16 numbers ] after Transform");
+
+        let e = syntax_error(&b"Shape \"sphere\" \"float radius\" [ 1 x ]"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 35 });
+        assert_eq!(e.expected, "number or ']' in \"float radius\"");
+
+        let e = syntax_error(&b"Material \"matte\" \"colour Kd\" [1 1 1]"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 18 });
+        assert!(e.expected.starts_with("parameter type (bool, integer, float,"));
+        assert!(e.expected.ends_with(") in \"colour Kd\""));
+
+        let e = syntax_error(&b"Material \"metal\" \"spectrum eta\" [300 .3 400]"[..]);
+        assert_eq!(e.location, Location { line: 1, column: 44 });
+        assert_eq!(e.expected, "wavelength/value pairs for parameter \"spectrum eta\"");
+
+        // Errors from the lexer are reported the same way.
+        let e = syntax_error(&b"WorldBegin\nShape \"sphere\n"[..]);
+        assert_eq!(e.location, Location { line: 2, column: 14 });
+        assert_eq!(e.expected, "'\"' to close string started at 2:7");
+    }
+
+    #[test]
+    fn test_parse_file_errors() {
+        let err = parse_file(testdata("include/bad.pbrt")).unwrap_err();
+        match err {
+            Error::Included(ref span, ref e) => {
+                assert!(span.file.as_ref().unwrap().ends_with("include/bad.pbrt"));
+                assert_eq!(span.start, Location { line: 2, column: 1 });
+                match **e {
+                    Error::Syntax(ref e) => {
+                        assert!(e.file.as_ref().unwrap().ends_with("geometry/bad.pbrt"));
+                        assert_eq!(e.location, Location { line: 1, column: 11 });
+                    }
+                    ref e => panic!("expected syntax error, got {:?}", e),
+                }
+            }
+            ref e => panic!("expected included error, got {:?}", e),
+        }
+        let msg = err.to_string();
+        assert!(msg.contains("geometry/bad.pbrt:1:11: expected number after Translate"));
+        assert!(msg.contains("included from "));
+        assert!(msg.contains("include/bad.pbrt:2:1"));
     }
 
     #[test]
@@ -1077,9 +2301,37 @@ mod tests {
                 Directive::AttributeEnd,
                 Directive::WorldEnd,
             ],
+            ..Default::default()
         };
         assert_eq!(res.unwrap(), want);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let scene = parse_scene(&include_bytes!("testdata/scene1.pbrt")[..]).unwrap();
+        let json = serde_json::to_string(&scene).unwrap();
+        let got: Scene = serde_json::from_str(&json).unwrap();
+        assert_eq!(got, scene);
+        assert_eq!(got.spans.len(), got.directives.len());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json() {
+        let scene = parse_scene(
+            br#"Translate 1 0 0
+WorldBegin
+Material "matte" "rgb Kd" [0.5 0.5 0.5] "texture bumpmap" "bumps"
+Shape "disk" "point3 P" [0 0 1]
+"#,
+        )
+        .unwrap();
+        let want = r#"{"directives":[{"Translate":[1.0,0.0,0.0]},"WorldBegin",{"Material":["matte",[{"name":"Kd","type":"rgb","values":[0.5,0.5,0.5]},{"name":"bumpmap","type":"texture","values":["bumps"]}]]},{"Shape":["disk",[{"name":"P","type":"point3","values":[{"x":0.0,"y":0.0,"z":1.0}]}]]}]}"#;
+        assert_eq!(serde_json::to_string(&scene).unwrap(), want);
+        let got: Scene = serde_json::from_str(want).unwrap();
+        assert_eq!(got, scene);
+    }
 }
 /*
 Shape "trianglemesh"
@@ -13,8 +13,13 @@
 // limitations under the License.
 // Set this type alias to modify all floats in pbrt to be 32 or 64-bit.
 use std::f32;
+use std::mem;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
 pub type Float = f32;
 pub const EPSILON: Float = f32::EPSILON;
+/// The largest relative error from rounding a result to the nearest Float, half of EPSILON.
+pub const MACHINE_EPSILON: Float = f32::EPSILON * 0.5;
 // Set this type alias to modify all ints in pbrt to be 32 or 64-bit.
 pub type Int = i32;
 
@@ -27,6 +32,273 @@ pub struct Options {
     pub image_file: String,
 }
 
+/// Returns γn, which bounds the relative error (1 ± ε)^n of n rounded floating-point operations.
+pub fn gamma(n: Int) -> Float {
+    let n = n as Float * MACHINE_EPSILON;
+    n / (1. - n)
+}
+
+/// Returns the smallest Float greater than v, or v if it's infinity.
+pub fn next_float_up(v: Float) -> Float {
+    if v.is_infinite() && v > 0. {
+        return v;
+    }
+    // -0 and 0 have different bits, but the float after both is the smallest positive one.
+    let v = if v == 0. { 0. } else { v };
+    let bits = v.to_bits();
+    Float::from_bits(if v >= 0. { bits + 1 } else { bits - 1 })
+}
+
+/// Returns the largest Float less than v, or v if it's negative infinity.
+pub fn next_float_down(v: Float) -> Float {
+    if v.is_infinite() && v < 0. {
+        return v;
+    }
+    let v = if v == 0. { -0. } else { v };
+    let bits = v.to_bits();
+    Float::from_bits(if v > 0. { bits - 1 } else { bits + 1 })
+}
+
+/// A Float with an interval, [lower_bound, upper_bound], that's guaranteed to contain the value
+/// that would have been computed in exact arithmetic.  Each operation rounds the bounds of its
+/// result outwards, so the interval stays conservative through a whole computation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EFloat {
+    v: Float,
+    low: Float,
+    high: Float,
+}
+
+impl EFloat {
+    /// Returns v with an absolute error of at most err.
+    pub fn new(v: Float, err: Float) -> EFloat {
+        if err == 0. {
+            EFloat { v, low: v, high: v }
+        } else {
+            EFloat {
+                v,
+                low: next_float_down(v - err),
+                high: next_float_up(v + err),
+            }
+        }
+    }
+
+    /// Returns the value as computed in floating-point.
+    pub fn value(&self) -> Float {
+        self.v
+    }
+
+    pub fn lower_bound(&self) -> Float {
+        self.low
+    }
+
+    pub fn upper_bound(&self) -> Float {
+        self.high
+    }
+
+    /// Returns a bound on the distance between the value and the exact result.
+    pub fn absolute_error(&self) -> Float {
+        next_float_up((self.high - self.v).abs().max((self.v - self.low).abs()))
+    }
+
+    pub fn sqrt(&self) -> EFloat {
+        EFloat {
+            v: self.v.sqrt(),
+            low: next_float_down(self.low.sqrt()),
+            high: next_float_up(self.high.sqrt()),
+        }
+    }
+
+    pub fn abs(&self) -> EFloat {
+        if self.low >= 0. {
+            *self
+        } else if self.high <= 0. {
+            -*self
+        } else {
+            EFloat {
+                v: self.v.abs(),
+                low: 0.,
+                high: self.high.max(-self.low),
+            }
+        }
+    }
+}
+
+impl From<Float> for EFloat {
+    /// Returns v, which is exact.
+    fn from(v: Float) -> EFloat {
+        EFloat::new(v, 0.)
+    }
+}
+
+impl From<EFloat> for Float {
+    fn from(ef: EFloat) -> Float {
+        ef.v
+    }
+}
+
+impl Add for EFloat {
+    type Output = EFloat;
+
+    fn add(self, rhs: EFloat) -> EFloat {
+        EFloat {
+            v: self.v + rhs.v,
+            low: next_float_down(self.low + rhs.low),
+            high: next_float_up(self.high + rhs.high),
+        }
+    }
+}
+
+impl Sub for EFloat {
+    type Output = EFloat;
+
+    fn sub(self, rhs: EFloat) -> EFloat {
+        EFloat {
+            v: self.v - rhs.v,
+            low: next_float_down(self.low - rhs.high),
+            high: next_float_up(self.high - rhs.low),
+        }
+    }
+}
+
+/// Returns the smallest and largest of the products or quotients of the bounds.
+fn min_max(values: [Float; 4]) -> (Float, Float) {
+    let min = values.iter().cloned().fold(Float::INFINITY, Float::min);
+    let max = values.iter().cloned().fold(Float::NEG_INFINITY, Float::max);
+    (min, max)
+}
+
+impl Mul for EFloat {
+    type Output = EFloat;
+
+    fn mul(self, rhs: EFloat) -> EFloat {
+        let (low, high) = min_max([
+            self.low * rhs.low,
+            self.high * rhs.low,
+            self.low * rhs.high,
+            self.high * rhs.high,
+        ]);
+        EFloat {
+            v: self.v * rhs.v,
+            low: next_float_down(low),
+            high: next_float_up(high),
+        }
+    }
+}
+
+impl Div for EFloat {
+    type Output = EFloat;
+
+    fn div(self, rhs: EFloat) -> EFloat {
+        if rhs.low < 0. && rhs.high > 0. {
+            // Dividing by an interval that contains zero could give anything.
+            return EFloat {
+                v: self.v / rhs.v,
+                low: Float::NEG_INFINITY,
+                high: Float::INFINITY,
+            };
+        }
+        let (low, high) = min_max([
+            self.low / rhs.low,
+            self.high / rhs.low,
+            self.low / rhs.high,
+            self.high / rhs.high,
+        ]);
+        EFloat {
+            v: self.v / rhs.v,
+            low: next_float_down(low),
+            high: next_float_up(high),
+        }
+    }
+}
+
+impl Neg for EFloat {
+    type Output = EFloat;
+
+    fn neg(self) -> EFloat {
+        EFloat {
+            v: -self.v,
+            low: -self.high,
+            high: -self.low,
+        }
+    }
+}
+
+impl Add<Float> for EFloat {
+    type Output = EFloat;
+
+    fn add(self, rhs: Float) -> EFloat {
+        self + EFloat::from(rhs)
+    }
+}
+
+impl Sub<Float> for EFloat {
+    type Output = EFloat;
+
+    fn sub(self, rhs: Float) -> EFloat {
+        self - EFloat::from(rhs)
+    }
+}
+
+impl Mul<Float> for EFloat {
+    type Output = EFloat;
+
+    fn mul(self, rhs: Float) -> EFloat {
+        self * EFloat::from(rhs)
+    }
+}
+
+impl Mul<EFloat> for Float {
+    type Output = EFloat;
+
+    fn mul(self, rhs: EFloat) -> EFloat {
+        EFloat::from(self) * rhs
+    }
+}
+
+impl Div<Float> for EFloat {
+    type Output = EFloat;
+
+    fn div(self, rhs: Float) -> EFloat {
+        self / EFloat::from(rhs)
+    }
+}
+
+/// Solves a t^2 + b t + c = 0, returning its roots with the smaller first, or None if they're
+/// complex.  The discriminant is computed in f64, and the roots in the form that avoids
+/// cancellation, so the intervals are tight enough for ray-shape intersection.
+pub fn quadratic(a: EFloat, b: EFloat, c: EFloat) -> Option<(EFloat, EFloat)> {
+    let (av, bv, cv) = (f64::from(a.v), f64::from(b.v), f64::from(c.v));
+    let discrim = bv * bv - 4. * av * cv;
+    if discrim < 0. {
+        return None;
+    }
+    let root_discrim = discrim.sqrt();
+    let root_discrim = EFloat::new(
+        root_discrim as Float,
+        MACHINE_EPSILON * root_discrim as Float,
+    );
+    let q = if b.v < 0. {
+        -0.5 * (b - root_discrim)
+    } else {
+        -0.5 * (b + root_discrim)
+    };
+    let (mut t0, mut t1) = if q.low <= 0. && q.high >= 0. {
+        // q is only about zero when b and the discriminant are, so the roots are about the double
+        // root -b / 2a, and computing them directly has nothing to cancel.
+        (
+            -0.5 * (b + root_discrim) / a,
+            -0.5 * (b - root_discrim) / a,
+        )
+    } else {
+        (q / a, c / q)
+    };
+    if t0.low > t1.low {
+        mem::swap(&mut t0, &mut t1);
+    }
+    Some((t0, t1))
+}
+
 //const PI: Float = 3.14159265358979323846;
 //const INV_PI: Float = 0.31830988618379067154;
 //const INV2_PI: Float = 0.15915494309189533577;
@@ -34,3 +306,143 @@ pub struct Options {
 //const PI_OVER2: Float = 1.57079632679489661923;
 //const PI_OVER4: Float = 0.78539816339744830961;
 //const SQRT2: Float = 1.41421356237309504880;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_next_float() {
+        assert_eq!(next_float_up(1.), 1. + EPSILON);
+        assert_eq!(next_float_down(1.), 1. - EPSILON / 2.);
+        assert_eq!(next_float_up(-1.), -1. + EPSILON / 2.);
+        assert_eq!(next_float_up(0.), Float::from_bits(1));
+        assert_eq!(next_float_up(-0.), Float::from_bits(1));
+        assert_eq!(next_float_down(0.), -Float::from_bits(1));
+        assert_eq!(next_float_up(Float::MAX), Float::INFINITY);
+        assert_eq!(next_float_up(Float::INFINITY), Float::INFINITY);
+        assert_eq!(next_float_down(Float::NEG_INFINITY), Float::NEG_INFINITY);
+        assert_eq!(next_float_down(Float::INFINITY), Float::MAX);
+    }
+
+    #[test]
+    fn test_gamma() {
+        assert_eq!(gamma(0), 0.);
+        for n in 1..100 {
+            let bound = (1. + f64::from(MACHINE_EPSILON)).powi(n) - 1.;
+            assert!(f64::from(gamma(n)) >= bound, "gamma({})", n);
+        }
+    }
+
+    #[test]
+    fn test_efloat() {
+        let ef = EFloat::new(2., 0.5);
+        assert_eq!(ef.value(), 2.);
+        assert!(ef.lower_bound() < 1.5 && ef.upper_bound() > 2.5);
+        assert!(ef.absolute_error() > 0.5);
+        assert_eq!(EFloat::from(3.).upper_bound(), 3.);
+        assert_eq!(Float::from(ef * 2.), 4.);
+        assert_eq!((-ef).upper_bound(), -ef.lower_bound());
+        let straddle = EFloat::new(0.5, 1.);
+        assert_eq!(straddle.abs().lower_bound(), 0.);
+        assert_eq!((ef / straddle).upper_bound(), Float::INFINITY);
+        assert!(quadratic(EFloat::from(1.), EFloat::from(0.), EFloat::from(1.)).is_none());
+        let (t0, t1) = quadratic(EFloat::from(1.), EFloat::from(0.), EFloat::from(0.)).unwrap();
+        for t in &[t0, t1] {
+            assert_eq!(t.value(), 0.);
+            assert!(t.lower_bound() <= 0. && t.upper_bound() >= 0. && t.absolute_error() < 1e-40);
+        }
+    }
+
+    /// Returns an EFloat, and a value in its interval to stand for the exact value it
+    /// approximates.
+    fn efloat() -> impl Strategy<Value = (EFloat, f64)> {
+        let err = prop_oneof![Just(0.), 0f32..1e-3, 0f32..10.];
+        (-1e4f32..1e4, err, 0f64..=1.).prop_map(|(v, err, t)| {
+            let ef = EFloat::new(v, err);
+            let (low, high) = (f64::from(ef.lower_bound()), f64::from(ef.upper_bound()));
+            (ef, low + t * (high - low))
+        })
+    }
+
+    fn contains(ef: &EFloat, exact: f64) -> bool {
+        f64::from(ef.lower_bound()) <= exact && exact <= f64::from(ef.upper_bound())
+    }
+
+    proptest! {
+        #[test]
+        fn next_float_is_adjacent(v in any::<f32>().prop_filter("finite", |v| v.is_finite())) {
+            let (up, down) = (next_float_up(v), next_float_down(v));
+            prop_assert!(up > v && down < v);
+            prop_assert_eq!(next_float_down(up), v);
+            prop_assert_eq!(next_float_up(down), v);
+        }
+
+        #[test]
+        fn efloat_ops_contain_exact((a, pa) in efloat(), (b, pb) in efloat()) {
+            prop_assert!(contains(&a, pa));
+            prop_assert!(contains(&(a + b), pa + pb));
+            prop_assert!(contains(&(a - b), pa - pb));
+            prop_assert!(contains(&(a * b), pa * pb));
+            prop_assert!(contains(&(-a), -pa));
+            prop_assert!(contains(&a.abs(), pa.abs()));
+            if pb != 0. {
+                prop_assert!(contains(&(a / b), pa / pb));
+            }
+            if a.lower_bound() >= 0. {
+                prop_assert!(contains(&a.sqrt(), pa.sqrt()));
+            }
+            prop_assert!(f64::from(a.absolute_error()) >= (pa - f64::from(a.value())).abs());
+        }
+
+        #[test]
+        fn efloat_expressions_contain_exact(
+            (start, p_start) in efloat(),
+            steps in prop::collection::vec((0..4usize, efloat()), 1..20),
+        ) {
+            let (mut ef, mut exact) = (start, p_start);
+            for (op, (operand, p_operand)) in steps {
+                match op {
+                    0 => {
+                        ef = ef + operand;
+                        exact += p_operand;
+                    }
+                    1 => {
+                        ef = ef - operand;
+                        exact -= p_operand;
+                    }
+                    2 => {
+                        ef = ef * operand;
+                        exact *= p_operand;
+                    }
+                    _ if p_operand != 0. => {
+                        ef = ef / operand;
+                        exact /= p_operand;
+                    }
+                    _ => (),
+                }
+                prop_assert!(contains(&ef, exact), "{:?} doesn't contain {}", ef, exact);
+            }
+        }
+
+        #[test]
+        fn quadratic_roots_contain_exact(a in -100i32..100, b in -100i32..100, c in -100i32..100) {
+            prop_assume!(a != 0);
+            let (a, b, c) = (f64::from(a), f64::from(b), f64::from(c));
+            let roots = quadratic((a as Float).into(), (b as Float).into(), (c as Float).into());
+            let discrim = b * b - 4. * a * c;
+            prop_assert_eq!(roots.is_some(), discrim >= 0.);
+            if let Some((t0, t1)) = roots {
+                let mut r0 = (-b - discrim.sqrt()) / (2. * a);
+                let mut r1 = (-b + discrim.sqrt()) / (2. * a);
+                if r0 > r1 {
+                    mem::swap(&mut r0, &mut r1);
+                }
+                prop_assert!(contains(&t0, r0), "{:?} doesn't contain {}", t0, r0);
+                prop_assert!(contains(&t1, r1), "{:?} doesn't contain {}", t1, r1);
+            }
+        }
+    }
+}
@@ -0,0 +1,844 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Reads the PLY files referenced by the plymesh shape, in ASCII or binary form, as triangle
+//! meshes.
+//!
+//! Vertex positions, normals and texture coordinates are read, along with the faces' vertex
+//! indices.  Quads are split into two triangles.  Anything else in the file is skipped, with a
+//! warning.
+//!
+//! Meshes can also be written, to move the inline meshes of trianglemesh shapes to PLY files.
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use core::geometry::{Normal3f, Point2f, Point3f};
+use core::paramset::{self, ParamSet, Value};
+use core::pbrt::Float;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file isn't a PLY file that can be read as a mesh, for the reason given.
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Invalid(why) => write!(f, "invalid PLY file: {}", why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+fn invalid<T>(why: String) -> Result<T, Error> {
+    Err(Error::Invalid(why))
+}
+
+/// A mesh of triangles, as the trianglemesh shape describes them.  Each three indices are the
+/// vertices of a triangle.  n and uv are either empty or hold a value for each vertex in p.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub indices: Vec<usize>,
+    pub p: Vec<Point3f>,
+    pub n: Vec<Normal3f>,
+    pub uv: Vec<Point2f>,
+}
+
+/// Converts the mesh to the parameters of a trianglemesh shape.
+impl From<TriangleMesh> for ParamSet {
+    fn from(mesh: TriangleMesh) -> ParamSet {
+        join_trianglemesh(mesh, ParamSet::default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, Error> {
+        Ok(match name {
+            "char" | "int8" => Scalar::Char,
+            "uchar" | "uint8" => Scalar::UChar,
+            "short" | "int16" => Scalar::Short,
+            "ushort" | "uint16" => Scalar::UShort,
+            "int" | "int32" => Scalar::Int,
+            "uint" | "uint32" => Scalar::UInt,
+            "float" | "float32" => Scalar::Float,
+            "double" | "float64" => Scalar::Double,
+            _ => return invalid(format!("unknown property type \"{}\"", name)),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// A list of values, preceded by their count.  The types are the count's and the values'.
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    /// Returns the index of the scalar property called name.
+    fn scalar(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| match p {
+            Property::Scalar(n, _) => n == name,
+            _ => false,
+        })
+    }
+
+    /// Returns the index of the list property called name.
+    fn list(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| match p {
+            Property::List(n, _, _) => n == name,
+            _ => false,
+        })
+    }
+
+    /// Adds a warning for each property whose index isn't in used.
+    fn warn_unused(&self, used: &[usize], warnings: &mut Vec<String>) {
+        for (i, p) in self.properties.iter().enumerate() {
+            if !used.contains(&i) {
+                let name = match p {
+                    Property::Scalar(n, _) | Property::List(n, _, _) => n,
+                };
+                warnings.push(format!(
+                    "ignoring unsupported {} property \"{}\"",
+                    self.name, name
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+fn read_header<R: BufRead>(r: &mut R) -> Result<Header, Error> {
+    let mut buf = Vec::new();
+    let mut next_line = |r: &mut R| -> Result<String, Error> {
+        buf.clear();
+        if r.read_until(b'\n', &mut buf)? == 0 {
+            return invalid("missing end_header".to_owned());
+        }
+        Ok(String::from_utf8_lossy(&buf).trim().to_owned())
+    };
+    if next_line(r)? != "ply" {
+        return invalid("missing \"ply\" at the start of the file".to_owned());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line(r)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => (),
+            ["format", f, _version] => {
+                format = Some(match *f {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return invalid(format!("unknown format \"{}\"", f)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .or_else(|_| invalid(format!("bad element count \"{}\"", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", ..] => {
+                let property = match &words[1..] {
+                    ["list", count, item, name] => Property::List(
+                        name.to_string(),
+                        Scalar::parse(count)?,
+                        Scalar::parse(item)?,
+                    ),
+                    [ty, name] => Property::Scalar(name.to_string(), Scalar::parse(ty)?),
+                    _ => return invalid(format!("bad property \"{}\"", line)),
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return invalid(format!("property before any element \"{}\"", line)),
+                }
+            }
+            _ => return invalid(format!("unexpected header line \"{}\"", line)),
+        }
+    }
+    match format {
+        Some(format) => Ok(Header { format, elements }),
+        None => invalid("missing format".to_owned()),
+    }
+}
+
+/// Where the values of the elements are read from, after the header.
+trait Source {
+    fn read(&mut self, ty: Scalar) -> Result<f64, Error>;
+}
+
+struct Ascii<'a>(SplitWhitespace<'a>);
+
+impl<'a> Source for Ascii<'a> {
+    fn read(&mut self, _ty: Scalar) -> Result<f64, Error> {
+        match self.0.next() {
+            Some(word) => word
+                .parse()
+                .or_else(|_| invalid(format!("bad number \"{}\"", word))),
+            None => invalid("unexpected end of data".to_owned()),
+        }
+    }
+}
+
+struct Binary<R> {
+    r: R,
+    big_endian: bool,
+}
+
+macro_rules! read_binary {
+    ($self:ident, $t:ty) => {{
+        let mut b = [0u8; ::std::mem::size_of::<$t>()];
+        $self.r.read_exact(&mut b)?;
+        if $self.big_endian {
+            <$t>::from_be_bytes(b) as f64
+        } else {
+            <$t>::from_le_bytes(b) as f64
+        }
+    }};
+}
+
+impl<R: Read> Binary<R> {
+    fn read_scalar(&mut self, ty: Scalar) -> io::Result<f64> {
+        Ok(match ty {
+            Scalar::Char => read_binary!(self, i8),
+            Scalar::UChar => read_binary!(self, u8),
+            Scalar::Short => read_binary!(self, i16),
+            Scalar::UShort => read_binary!(self, u16),
+            Scalar::Int => read_binary!(self, i32),
+            Scalar::UInt => read_binary!(self, u32),
+            Scalar::Float => read_binary!(self, f32),
+            Scalar::Double => read_binary!(self, f64),
+        })
+    }
+}
+
+impl<R: Read> Source for Binary<R> {
+    fn read(&mut self, ty: Scalar) -> Result<f64, Error> {
+        self.read_scalar(ty).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Invalid("unexpected end of data".to_owned()),
+            _ => Error::Io(e),
+        })
+    }
+}
+
+/// Reads one instance of element into row: a value for each scalar property, and all the values
+/// of each list property.
+fn read_row<S: Source>(
+    element: &Element,
+    src: &mut S,
+    row: &mut Vec<Vec<f64>>,
+) -> Result<(), Error> {
+    row.resize(element.properties.len(), Vec::new());
+    for (p, values) in element.properties.iter().zip(row.iter_mut()) {
+        values.clear();
+        match *p {
+            Property::Scalar(_, ty) => values.push(src.read(ty)?),
+            Property::List(_, count, ty) => {
+                for _ in 0..src.read(count)? as usize {
+                    values.push(src.read(ty)?);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The names texture coordinates are given under by various exporters.
+const UV_NAMES: [(&str, &str); 4] = [
+    ("u", "v"),
+    ("s", "t"),
+    ("texture_u", "texture_v"),
+    ("texture_s", "texture_t"),
+];
+
+fn read_vertices<S: Source>(
+    element: &Element,
+    src: &mut S,
+    mesh: &mut TriangleMesh,
+    warnings: &mut Vec<String>,
+) -> Result<(), Error> {
+    let p = match (
+        element.scalar("x"),
+        element.scalar("y"),
+        element.scalar("z"),
+    ) {
+        (Some(x), Some(y), Some(z)) => [x, y, z],
+        _ => return invalid("vertex element without x, y and z properties".to_owned()),
+    };
+    let n = match (
+        element.scalar("nx"),
+        element.scalar("ny"),
+        element.scalar("nz"),
+    ) {
+        (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+        _ => None,
+    };
+    let uv = UV_NAMES
+        .iter()
+        .filter_map(|&(u, v)| match (element.scalar(u), element.scalar(v)) {
+            (Some(u), Some(v)) => Some([u, v]),
+            _ => None,
+        })
+        .next();
+    let mut used = p.to_vec();
+    used.extend(n.iter().flatten());
+    used.extend(uv.iter().flatten());
+    element.warn_unused(&used, warnings);
+
+    let mut row = Vec::new();
+    for _ in 0..element.count {
+        read_row(element, src, &mut row)?;
+        let value = |i: usize| row[i][0] as Float;
+        mesh.p.push(Point3f {
+            x: value(p[0]),
+            y: value(p[1]),
+            z: value(p[2]),
+        });
+        if let Some(n) = n {
+            mesh.n.push(Normal3f {
+                x: value(n[0]),
+                y: value(n[1]),
+                z: value(n[2]),
+            });
+        }
+        if let Some(uv) = uv {
+            mesh.uv.push(Point2f {
+                x: value(uv[0]),
+                y: value(uv[1]),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn read_faces<S: Source>(
+    element: &Element,
+    src: &mut S,
+    mesh: &mut TriangleMesh,
+    warnings: &mut Vec<String>,
+) -> Result<(), Error> {
+    let vi = match element
+        .list("vertex_indices")
+        .or_else(|| element.list("vertex_index"))
+    {
+        Some(vi) => vi,
+        None => return invalid("face element without a vertex_indices list".to_owned()),
+    };
+    element.warn_unused(&[vi], warnings);
+
+    let mut row = Vec::new();
+    let mut skipped = 0;
+    for _ in 0..element.count {
+        read_row(element, src, &mut row)?;
+        let mut face = Vec::with_capacity(4);
+        for &i in &row[vi] {
+            if i < 0. {
+                return invalid(format!("negative vertex index {}", i));
+            }
+            face.push(i as usize);
+        }
+        match face.len() {
+            3 => mesh.indices.extend(face),
+            4 => mesh
+                .indices
+                .extend(&[face[0], face[1], face[2], face[0], face[2], face[3]]),
+            _ => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warnings.push(format!(
+            "ignoring {} faces that aren't triangles or quads",
+            skipped
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the mesh in a PLY file from r.  Warnings are added for anything in the file that isn't
+/// part of the mesh.
+pub fn read<R: BufRead>(mut r: R, warnings: &mut Vec<String>) -> Result<TriangleMesh, Error> {
+    let header = read_header(&mut r)?;
+    let mut text = String::new();
+    let big_endian = match header.format {
+        Format::Ascii => {
+            r.read_to_string(&mut text)?;
+            let mut src = Ascii(text.split_whitespace());
+            return read_elements(&header, &mut src, warnings);
+        }
+        Format::BinaryLittleEndian => false,
+        Format::BinaryBigEndian => true,
+    };
+    read_elements(&header, &mut Binary { r, big_endian }, warnings)
+}
+
+/// Reads the mesh in the PLY file at path.  See read.
+pub fn read_file<P: AsRef<Path>>(
+    path: P,
+    warnings: &mut Vec<String>,
+) -> Result<TriangleMesh, Error> {
+    read(BufReader::new(File::open(path)?), warnings)
+}
+
+fn read_elements<S: Source>(
+    header: &Header,
+    src: &mut S,
+    warnings: &mut Vec<String>,
+) -> Result<TriangleMesh, Error> {
+    let mut mesh = TriangleMesh::default();
+    let mut have_vertices = false;
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                read_vertices(element, src, &mut mesh, warnings)?;
+                have_vertices = true;
+            }
+            "face" => read_faces(element, src, &mut mesh, warnings)?,
+            _ => {
+                warnings.push(format!("ignoring unsupported element \"{}\"", element.name));
+                let mut row = Vec::new();
+                for _ in 0..element.count {
+                    read_row(element, src, &mut row)?;
+                }
+            }
+        }
+    }
+    if !have_vertices {
+        return invalid("no vertex element".to_owned());
+    }
+    if let Some(&i) = mesh.indices.iter().find(|&&i| i >= mesh.p.len()) {
+        return invalid(format!(
+            "vertex index {} out of range, there are {} vertices",
+            i,
+            mesh.p.len()
+        ));
+    }
+    Ok(mesh)
+}
+
+// The parameters of a trianglemesh shape that describe its mesh.
+const MESH_PARAMS: [&str; 5] = ["indices", "P", "N", "uv", "st"];
+
+/// Splits the parameters of a trianglemesh shape into the mesh they describe, and the rest, which
+/// a plymesh shape reading the mesh from a file takes as well.  Texture coordinates may be given
+/// as point2s or as pairs of floats, named "uv" or "st".
+pub fn split_trianglemesh(ps: &ParamSet) -> Result<(TriangleMesh, ParamSet), String> {
+    if ps.get("S").is_some() {
+        return Err("tangents (\"S\") can't be written to PLY files".to_owned());
+    }
+    let err = |e: paramset::Error| e.to_string();
+    let indices = ps
+        .find_ints("indices")
+        .map_err(err)?
+        .ok_or("trianglemesh without \"indices\"")?;
+    let p = ps
+        .find_point3fs("P")
+        .map_err(err)?
+        .ok_or("trianglemesh without \"P\"")?
+        .to_vec();
+    let n = ps
+        .find_normal3fs("N")
+        .map_err(err)?
+        .map_or(vec![], <[_]>::to_vec);
+    let mut uv = Vec::new();
+    for name in &["uv", "st"] {
+        uv = match ps.get(name) {
+            Some(Value::Point2f(uvs)) => uvs.0.clone(),
+            Some(Value::Float(vs)) if vs.0.len() % 2 == 0 => {
+                vs.0.chunks_exact(2)
+                    .map(|c| Point2f { x: c[0], y: c[1] })
+                    .collect()
+            }
+            Some(Value::Float(_)) => {
+                return Err(format!("odd number of values in \"float {}\"", name))
+            }
+            Some(v) => {
+                return Err(format!(
+                    "parameter \"{}\" has type {}, expected point2 or float",
+                    name,
+                    v.type_name()
+                ))
+            }
+            None => continue,
+        };
+        break;
+    }
+
+    if indices.len() % 3 != 0 {
+        return Err(format!(
+            "{} indices isn't a whole number of triangles",
+            indices.len()
+        ));
+    }
+    if let Some(&i) = indices.iter().find(|&&i| i < 0 || i as usize >= p.len()) {
+        return Err(format!(
+            "vertex index {} out of range, there are {} vertices",
+            i,
+            p.len()
+        ));
+    }
+    for (name, len) in &[("N", n.len()), ("uv", uv.len())] {
+        if *len != 0 && *len != p.len() {
+            return Err(format!(
+                "{} values of \"{}\" for {} vertices",
+                len,
+                name,
+                p.len()
+            ));
+        }
+    }
+
+    let mut rest = ParamSet::default();
+    for item in ps.items() {
+        if !MESH_PARAMS.contains(&item.name.as_str()) {
+            rest.add(&item.name, item.values.clone());
+        }
+    }
+    let mesh = TriangleMesh {
+        indices: indices.iter().map(|&i| i as usize).collect(),
+        p,
+        n,
+        uv,
+    };
+    Ok((mesh, rest))
+}
+
+/// The inverse of split_trianglemesh: adds the parameters describing mesh to ps, replacing any
+/// already there.
+pub fn join_trianglemesh(mesh: TriangleMesh, mut ps: ParamSet) -> ParamSet {
+    let indices: Vec<i64> = mesh.indices.into_iter().map(|i| i as i64).collect();
+    ps.add("indices", Value::Int(indices.into()));
+    ps.add("P", Value::Point3f(mesh.p.into()));
+    if !mesh.n.is_empty() {
+        ps.add("N", Value::Normal3f(mesh.n.into()));
+    }
+    if !mesh.uv.is_empty() {
+        ps.add("uv", Value::Point2f(mesh.uv.into()));
+    }
+    ps
+}
+
+/// Writes mesh to w as a binary little-endian PLY file.
+pub fn write<W: Write>(mut w: W, mesh: &TriangleMesh) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "element vertex {}", mesh.p.len())?;
+    let mut properties = vec!["x", "y", "z"];
+    if !mesh.n.is_empty() {
+        properties.extend(&["nx", "ny", "nz"]);
+    }
+    if !mesh.uv.is_empty() {
+        properties.extend(&["u", "v"]);
+    }
+    for name in properties {
+        writeln!(w, "property float {}", name)?;
+    }
+    writeln!(w, "element face {}", mesh.indices.len() / 3)?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for (i, p) in mesh.p.iter().enumerate() {
+        let mut values = vec![p.x, p.y, p.z];
+        if let Some(n) = mesh.n.get(i) {
+            values.extend(&[n.x, n.y, n.z]);
+        }
+        if let Some(uv) = mesh.uv.get(i) {
+            values.extend(&[uv.x, uv.y]);
+        }
+        for v in values {
+            w.write_all(&v.to_le_bytes())?;
+        }
+    }
+    for triangle in mesh.indices.chunks(3) {
+        w.write_all(&[3])?;
+        for &i in triangle {
+            w.write_all(&(i as i32).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes mesh to a PLY file at path.  See write.
+pub fn write_file<P: AsRef<Path>>(path: P, mesh: &TriangleMesh) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(&mut w, mesh)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "ply
+format ascii 1.0
+comment made by hand
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float u
+property float v
+property uchar red
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0  0 0 1  0 0  255
+1 0 0  0 0 1  1 0  255
+1 1 0  0 0 1  1 1  255
+0 1 0  0 0 1  0 1  255
+4 0 1 2 3
+";
+
+    fn quad() -> TriangleMesh {
+        let p = |x, y| Point3f { x, y, z: 0. };
+        let n = Normal3f {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        let uv = |x, y| Point2f { x, y };
+        TriangleMesh {
+            indices: vec![0, 1, 2, 0, 2, 3],
+            p: vec![p(0., 0.), p(1., 0.), p(1., 1.), p(0., 1.)],
+            n: vec![n; 4],
+            uv: vec![uv(0., 0.), uv(1., 0.), uv(1., 1.), uv(0., 1.)],
+        }
+    }
+
+    /// Returns QUAD in binary form, with the header's format replaced by format, and floats and
+    /// ints encoded by float and int.
+    fn binary(format: &str, float: fn(f32) -> [u8; 4], int: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let header_end = QUAD.find("end_header\n").unwrap() + "end_header\n".len();
+        let mut data = QUAD[..header_end].replace("ascii", format).into_bytes();
+        for line in QUAD[header_end..].lines() {
+            let words: Vec<f32> = line
+                .split_whitespace()
+                .map(|w| w.parse().unwrap())
+                .collect();
+            if words.len() == 5 {
+                // A face: a uchar count, then int indices.
+                data.push(words[0] as u8);
+                for &i in &words[1..] {
+                    data.extend(&int(i as i32));
+                }
+            } else {
+                // A vertex: eight floats, then a uchar.
+                for &v in &words[..8] {
+                    data.extend(&float(v));
+                }
+                data.push(words[8] as u8);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut warnings = Vec::new();
+        let mesh = read(QUAD.as_bytes(), &mut warnings).unwrap();
+        assert_eq!(mesh, quad());
+        assert_eq!(
+            warnings,
+            vec!["ignoring unsupported vertex property \"red\"".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_binary() {
+        let le = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let be = binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        for data in &[le, be] {
+            let mut warnings = Vec::new();
+            let mesh = read(&data[..], &mut warnings).unwrap();
+            assert_eq!(mesh, quad());
+            assert_eq!(warnings.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_param_set() {
+        let ps = ParamSet::from(quad());
+        let names: Vec<&str> = ps.items().iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["N", "P", "indices", "uv"]);
+        assert_eq!(
+            ps.get("indices"),
+            Some(&Value::Int(vec![0, 1, 2, 0, 2, 3].into()))
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        let mut data = Vec::new();
+        write(&mut data, &quad()).unwrap();
+        let mut warnings = Vec::new();
+        assert_eq!(read(&data[..], &mut warnings).unwrap(), quad());
+        assert!(warnings.is_empty());
+
+        let mesh = TriangleMesh {
+            n: vec![],
+            uv: vec![],
+            ..quad()
+        };
+        data.clear();
+        write(&mut data, &mesh).unwrap();
+        assert_eq!(read(&data[..], &mut warnings).unwrap(), mesh);
+    }
+
+    #[test]
+    fn test_split_trianglemesh() {
+        let mut ps = ParamSet::from(quad());
+        ps.remove("uv");
+        let st: Vec<Float> = quad().uv.iter().flat_map(|uv| vec![uv.x, uv.y]).collect();
+        ps.add("st", Value::Float(st.into()));
+        ps.add("alpha", Value::Float(vec![0.5].into()));
+        let (mesh, rest) = split_trianglemesh(&ps).unwrap();
+        assert_eq!(mesh, quad());
+        let mut want = ParamSet::default();
+        want.add("alpha", Value::Float(vec![0.5].into()));
+        assert_eq!(rest, want);
+
+        ps.add("indices", Value::Int(vec![0, 1, 4].into()));
+        assert_eq!(
+            split_trianglemesh(&ps).unwrap_err(),
+            "vertex index 4 out of range, there are 4 vertices"
+        );
+        ps.add("indices", Value::Int(vec![0, 1].into()));
+        assert_eq!(
+            split_trianglemesh(&ps).unwrap_err(),
+            "2 indices isn't a whole number of triangles"
+        );
+    }
+
+    #[test]
+    fn test_join_trianglemesh() {
+        let mut ps = ParamSet::default();
+        ps.add("alpha", Value::Texture(vec!["mask".to_owned()].into()));
+        ps.add("P", Value::Point3f(vec![Point3f::new(0., 0., 0.)].into()));
+        let ps = join_trianglemesh(quad(), ps);
+        let names: Vec<&str> = ps.items().iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["N", "P", "alpha", "indices", "uv"]);
+        assert_eq!(ps.get("P"), ParamSet::from(quad()).get("P"));
+        assert_eq!(split_trianglemesh(&ps).unwrap().0, quad());
+    }
+
+    #[test]
+    fn test_faces() {
+        let ply = "ply
+format ascii 1.0
+element vertex 3
+property double x
+property double y
+property double z
+element face 2
+property list uchar uint vertex_index
+property uchar flags
+end_header
+0 0 0 1 0 0 0 1 0
+3 2 1 0 7
+2 0 1 7
+";
+        let mut warnings = Vec::new();
+        let mesh = read(ply.as_bytes(), &mut warnings).unwrap();
+        assert_eq!(mesh.indices, vec![2, 1, 0]);
+        assert!(mesh.n.is_empty() && mesh.uv.is_empty());
+        assert_eq!(
+            warnings,
+            vec![
+                "ignoring unsupported face property \"flags\"".to_owned(),
+                "ignoring 1 faces that aren't triangles or quads".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = vec![
+            ("obj\n", "missing \"ply\" at the start of the file"),
+            ("ply\nformat ascii 1.0\n", "missing end_header"),
+            ("ply\nelement vertex 0\nend_header\n", "missing format"),
+            (
+                "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\n\
+                 property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+                 end_header\n0 0 0\n3 0 1 2\n",
+                "vertex index 1 out of range, there are 1 vertices",
+            ),
+            (
+                "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+                 property float z\nend_header\n0 0 0\n",
+                "unexpected end of data",
+            ),
+            (
+                "ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n",
+                "unknown property type \"half\"",
+            ),
+        ];
+        for (ply, want) in errors {
+            match read(ply.as_bytes(), &mut Vec::new()) {
+                Err(Error::Invalid(why)) => assert_eq!(why, want),
+                r => panic!("expected {:?} reading {:?}, got {:?}", want, ply, r),
+            }
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Quaternions, which represent rotations compactly and can be interpolated smoothly between
+//! them.
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use core::geometry::{self, Vector3f};
+use core::pbrt::Float;
+use core::transform::{Matrix4x4, Transform};
+
+/// The quaternion w + v.x i + v.y j + v.z k.  Unit quaternions represent rotations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub v: Vector3f,
+    pub w: Float,
+}
+
+impl Default for Quaternion {
+    /// Returns the identity rotation.
+    fn default() -> Quaternion {
+        Quaternion {
+            v: Vector3f::default(),
+            w: 1.,
+        }
+    }
+}
+
+impl Quaternion {
+    pub fn new(v: Vector3f, w: Float) -> Quaternion {
+        Quaternion { v, w }
+    }
+
+    /// Returns the rotation of theta degrees about axis, like Transform::rotate's.
+    pub fn from_axis_angle(theta: Float, axis: &Vector3f) -> Quaternion {
+        let (sin, cos) = (theta.to_radians() / 2.).sin_cos();
+        Quaternion {
+            v: axis.normalize() * sin,
+            w: cos,
+        }
+    }
+
+    /// Returns the angle in degrees, in [0, 360], and the unit axis of the rotation, for a
+    /// Rotate directive.  The axis is arbitrary for the identity.  The quaternion must be
+    /// normalized.
+    pub fn axis_angle(&self) -> (Float, Vector3f) {
+        let sin = self.v.length();
+        if sin == 0. {
+            return (0., Vector3f::new(1., 0., 0.));
+        }
+        let theta = 2. * sin.atan2(self.w);
+        (theta.to_degrees(), self.v / sin)
+    }
+
+    /// Returns the rotation in the upper-left 3x3 of m, which must be a rotation matrix.
+    pub fn from_matrix(m: &Matrix4x4) -> Quaternion {
+        let m = &m.m;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0. {
+            // Compute w from the matrix trace, then the vector part.
+            let s = (trace + 1.).sqrt();
+            let w = s / 2.;
+            let s = 0.5 / s;
+            Quaternion {
+                v: Vector3f::new(
+                    (m[2][1] - m[1][2]) * s,
+                    (m[0][2] - m[2][0]) * s,
+                    (m[1][0] - m[0][1]) * s,
+                ),
+                w,
+            }
+        } else {
+            // Compute the largest of x, y and z, then the remaining components.
+            const NEXT: [usize; 3] = [1, 2, 0];
+            let mut i = 0;
+            if m[1][1] > m[0][0] {
+                i = 1;
+            }
+            if m[2][2] > m[i][i] {
+                i = 2;
+            }
+            let j = NEXT[i];
+            let k = NEXT[j];
+            let mut s = ((m[i][i] - (m[j][j] + m[k][k])) + 1.).sqrt();
+            let mut q = [0.; 3];
+            q[i] = s * 0.5;
+            if s != 0. {
+                s = 0.5 / s;
+            }
+            q[j] = (m[j][i] + m[i][j]) * s;
+            q[k] = (m[k][i] + m[i][k]) * s;
+            Quaternion {
+                v: Vector3f::new(q[0], q[1], q[2]),
+                w: (m[k][j] - m[j][k]) * s,
+            }
+        }
+    }
+
+    /// Returns the rotation matrix of the quaternion, which must be normalized.
+    pub fn to_matrix(&self) -> Matrix4x4 {
+        let (x, y, z, w) = (self.v.x, self.v.y, self.v.z, self.w);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (x * w, y * w, z * w);
+        Matrix4x4::new(
+            [1. - 2. * (yy + zz), 2. * (xy - wz), 2. * (xz + wy), 0.],
+            [2. * (xy + wz), 1. - 2. * (xx + zz), 2. * (yz - wx), 0.],
+            [2. * (xz - wy), 2. * (yz + wx), 1. - 2. * (xx + yy), 0.],
+            [0., 0., 0., 1.],
+        )
+    }
+
+    /// Returns the rotation as a Transform.  The quaternion must be normalized.
+    pub fn to_transform(&self) -> Transform {
+        let m = self.to_matrix();
+        Transform::new(m, m.transpose())
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        *self / dot(self, self).sqrt()
+    }
+
+    /// Returns the quaternion with its vector part negated, which for a unit quaternion is the
+    /// inverse rotation.
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion {
+            v: -self.v,
+            w: self.w,
+        }
+    }
+
+    /// Returns v rotated by the quaternion, which must be normalized.
+    pub fn rotate(&self, v: &Vector3f) -> Vector3f {
+        (*self * Quaternion::new(*v, 0.) * self.conjugate()).v
+    }
+}
+
+impl From<Matrix4x4> for Quaternion {
+    fn from(m: Matrix4x4) -> Quaternion {
+        Quaternion::from_matrix(&m)
+    }
+}
+
+impl From<Transform> for Quaternion {
+    /// Returns the rotation of t, which must be a rotation.
+    fn from(t: Transform) -> Quaternion {
+        Quaternion::from_matrix(&t.matrix())
+    }
+}
+
+impl From<Quaternion> for Matrix4x4 {
+    fn from(q: Quaternion) -> Matrix4x4 {
+        q.to_matrix()
+    }
+}
+
+impl From<Quaternion> for Transform {
+    fn from(q: Quaternion) -> Transform {
+        q.to_transform()
+    }
+}
+
+impl Add for Quaternion {
+    type Output = Quaternion;
+
+    fn add(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            v: self.v + rhs.v,
+            w: self.w + rhs.w,
+        }
+    }
+}
+
+impl Sub for Quaternion {
+    type Output = Quaternion;
+
+    fn sub(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            v: self.v - rhs.v,
+            w: self.w - rhs.w,
+        }
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Quaternion;
+
+    fn neg(self) -> Quaternion {
+        Quaternion {
+            v: -self.v,
+            w: -self.w,
+        }
+    }
+}
+
+impl AddAssign for Quaternion {
+    fn add_assign(&mut self, rhs: Quaternion) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Quaternion {
+    fn sub_assign(&mut self, rhs: Quaternion) {
+        *self = *self - rhs;
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// Returns the Hamilton product.  For rotations, it's the rotation by rhs followed by self,
+    /// like the product of their Transforms.
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            v: rhs.v * self.w + self.v * rhs.w + geometry::cross(&self.v, &rhs.v),
+            w: self.w * rhs.w - geometry::dot(&self.v, &rhs.v),
+        }
+    }
+}
+
+impl Mul<Float> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, f: Float) -> Quaternion {
+        Quaternion {
+            v: self.v * f,
+            w: self.w * f,
+        }
+    }
+}
+
+impl Mul<Quaternion> for Float {
+    type Output = Quaternion;
+
+    fn mul(self, q: Quaternion) -> Quaternion {
+        q * self
+    }
+}
+
+impl MulAssign<Float> for Quaternion {
+    fn mul_assign(&mut self, f: Float) {
+        *self = *self * f;
+    }
+}
+
+impl Div<Float> for Quaternion {
+    type Output = Quaternion;
+
+    fn div(self, f: Float) -> Quaternion {
+        Quaternion {
+            v: self.v / f,
+            w: self.w / f,
+        }
+    }
+}
+
+impl DivAssign<Float> for Quaternion {
+    fn div_assign(&mut self, f: Float) {
+        *self = *self / f;
+    }
+}
+
+/// Returns the four dimensional dot product of q1 and q2.  For unit quaternions, it's the cosine
+/// of half the angle between their rotations.
+pub fn dot(q1: &Quaternion, q2: &Quaternion) -> Float {
+    q1.v.x * q2.v.x + q1.v.y * q2.v.y + q1.v.z * q2.v.z + q1.w * q2.w
+}
+
+/// Spherically interpolates between the unit quaternions q1 and q2, rotating at a constant
+/// angular speed from q1 when t is 0 to q2 when it's 1.
+pub fn slerp(t: Float, q1: &Quaternion, q2: &Quaternion) -> Quaternion {
+    let cos_theta = dot(q1, q2);
+    if cos_theta > 0.9995 {
+        // They're nearly parallel, where linear interpolation is accurate and slerp's isn't.
+        ((1. - t) * *q1 + t * *q2).normalize()
+    } else {
+        let theta = cos_theta.clamp(-1., 1.).acos();
+        let theta_p = theta * t;
+        let q_perp = (*q2 - *q1 * cos_theta).normalize();
+        *q1 * theta_p.cos() + q_perp * theta_p.sin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::geometry::Point3f;
+
+    fn assert_close(got: &Transform, want: &Transform) {
+        let (g, w) = (got.matrix().m, want.matrix().m);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((g[i][j] - w[i][j]).abs() < 1e-5, "{:?} != {:?}", got, want);
+            }
+        }
+    }
+
+    #[test]
+    fn test_matrix_round_trip() {
+        for &(theta, axis) in &[
+            (0., Vector3f::new(1., 0., 0.)),
+            (90., Vector3f::new(0., 1., 0.)),
+            (179., Vector3f::new(1., 0., 0.)),
+            (-120., Vector3f::new(1., 2., 3.)),
+            (200., Vector3f::new(0., 0., 1.)),
+        ] {
+            let r = Transform::rotate(theta, &axis);
+            let q = Quaternion::from_matrix(&r.matrix());
+            assert!((dot(&q, &q) - 1.).abs() < 1e-5, "{:?}", q);
+            assert_close(&q.to_transform(), &r);
+        }
+    }
+
+    #[test]
+    fn test_slerp() {
+        let q1 = Quaternion::default();
+        let q2 = Quaternion::from_matrix(&Transform::rotate_z(90.).matrix());
+        assert_eq!(slerp(0., &q1, &q2), q1);
+        let end = slerp(1., &q1, &q2);
+        assert!((dot(&end, &q2) - 1.).abs() < 1e-6);
+        // Halfway is a rotation of 45 degrees.
+        let half = slerp(0.5, &q1, &q2);
+        assert_close(&half.to_transform(), &Transform::rotate_z(45.));
+        let p = half
+            .to_transform()
+            .transform_point(&Point3f::new(1., 0., 0.));
+        let s = 0.5f32.sqrt();
+        assert!(p.distance(&Point3f::new(s, s, 0.)) < 1e-6);
+        // Nearly parallel quaternions are interpolated linearly.
+        let q3 = Quaternion::from_matrix(&Transform::rotate_z(1.).matrix());
+        assert_close(
+            &slerp(0.5, &q1, &q3).to_transform(),
+            &Transform::rotate_z(0.5),
+        );
+    }
+
+    #[test]
+    fn test_axis_angle() {
+        let axis = Vector3f::new(1., 2., 3.);
+        let q = Quaternion::from_axis_angle(70., &axis);
+        assert_close(&q.to_transform(), &Transform::rotate(70., &axis));
+        let (theta, a) = q.axis_angle();
+        assert!((theta - 70.).abs() < 1e-4);
+        assert!((a - axis.normalize()).length() < 1e-6);
+        let (theta, _) = Quaternion::default().axis_angle();
+        assert_eq!(theta, 0.);
+    }
+
+    #[test]
+    fn test_product() {
+        let x = Quaternion::from_axis_angle(90., &Vector3f::new(1., 0., 0.));
+        let z = Quaternion::from_axis_angle(90., &Vector3f::new(0., 0., 1.));
+        // Rotations compose like Transforms, without the gimbal lock of Euler angles.
+        let zx = z * x;
+        assert_close(
+            &Transform::from(zx),
+            &(Transform::rotate_z(90.) * Transform::rotate_x(90.)),
+        );
+        let v = Vector3f::new(0., 1., 0.);
+        assert!((zx.rotate(&v) - Vector3f::new(0., 0., 1.)).length() < 1e-6);
+        assert!((x.rotate(&v) - Vector3f::new(0., 0., 1.)).length() < 1e-6);
+        let identity = x * x.conjugate();
+        assert!((dot(&identity, &Quaternion::default()) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut q = Quaternion::new(Vector3f::new(1., 2., 3.), 4.);
+        assert_eq!(dot(&q, &q), 30.);
+        q += Quaternion::new(Vector3f::new(1., 0., 0.), 1.);
+        assert_eq!(q, Quaternion::new(Vector3f::new(2., 2., 3.), 5.));
+        q -= Quaternion::new(Vector3f::new(0., 2., 0.), 0.);
+        q *= 2.;
+        assert_eq!(q, Quaternion::new(Vector3f::new(4., 0., 6.), 10.));
+        q /= 2.;
+        assert_eq!(-q, Quaternion::new(Vector3f::new(-2., 0., -3.), -5.));
+        let n = q.normalize();
+        assert!((dot(&n, &n) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let r = Transform::rotate(-30., &Vector3f::new(0., 1., 1.));
+        let q = Quaternion::from(r);
+        assert_close(&Transform::from(q), &r);
+        assert_eq!(Quaternion::from(r.matrix()), q);
+        assert_eq!(Matrix4x4::from(q), q.to_matrix());
+    }
+}
@@ -16,8 +16,28 @@ use core::pbrt::Float;
 // TODO(wathiede): this is really wrong, but it's a placeholder so code should compile.  See
 // chapter 5 section 1-3.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Spectrum {
     pub x: Float,
     pub y: Float,
     pub z: Float,
 }
+
+impl Spectrum {
+    pub fn from_xyz(xyz: [Float; 3]) -> Spectrum {
+        Spectrum {
+            x: xyz[0],
+            y: xyz[1],
+            z: xyz[2],
+        }
+    }
+
+    /// Converts linear sRGB to a Spectrum, using the same matrix as pbrt's RGBToXYZ.
+    pub fn from_rgb(rgb: [Float; 3]) -> Spectrum {
+        Spectrum {
+            x: 0.412_453 * rgb[0] + 0.357_580 * rgb[1] + 0.180_423 * rgb[2],
+            y: 0.212_671 * rgb[0] + 0.715_160 * rgb[1] + 0.072_169 * rgb[2],
+            z: 0.019_334 * rgb[0] + 0.119_193 * rgb[1] + 0.950_227 * rgb[2],
+        }
+    }
+}
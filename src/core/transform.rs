@@ -11,11 +11,13 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::f32::consts::PI;
 use std::fmt;
-use std::ops::Mul;
+use std::ops::{Add, Mul, Sub};
 
-use core::geometry::Vector3f;
-use core::pbrt::{Float, EPSILON};
+use core::geometry::{cross, dot, Bounds3f, Normal3f, Point3f, Ray, RayDifferential, Vector3f};
+use core::pbrt::{gamma, Float, EPSILON};
+use core::quaternion::{self, Quaternion};
 
 #[derive(Default, Clone, Copy)]
 /// The matrix m is stored in row-major form, so element m[i][j] corresponds to mi , j , where i is
@@ -178,13 +180,24 @@ impl PartialEq for Matrix4x4 {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Transform {
     m: Matrix4x4,
     m_inv: Matrix4x4,
 }
 
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
 impl Transform {
+    /// Returns the transform with matrix m, whose inverse must be m_inv.
+    pub fn new(m: Matrix4x4, m_inv: Matrix4x4) -> Transform {
+        Transform { m, m_inv }
+    }
+
     /// Returns a new transform with m and m_inv set to identity.
     pub fn identity() -> Transform {
         Transform {
@@ -252,6 +265,51 @@ impl Transform {
         }
     }
 
+    /// rotate_x generates a Transform for the rotation of theta (in degrees) about the x axis.
+    pub fn rotate_x(theta: Float) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new(
+            [1., 0., 0., 0.],
+            [0., cos_theta, -sin_theta, 0.],
+            [0., sin_theta, cos_theta, 0.],
+            [0., 0., 0., 1.],
+        );
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// rotate_y generates a Transform for the rotation of theta (in degrees) about the y axis.
+    pub fn rotate_y(theta: Float) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new(
+            [cos_theta, 0., sin_theta, 0.],
+            [0., 1., 0., 0.],
+            [-sin_theta, 0., cos_theta, 0.],
+            [0., 0., 0., 1.],
+        );
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// rotate_z generates a Transform for the rotation of theta (in degrees) about the z axis.
+    pub fn rotate_z(theta: Float) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new(
+            [cos_theta, -sin_theta, 0., 0.],
+            [sin_theta, cos_theta, 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        );
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
     pub fn scale(sx: Float, sy: Float, sz: Float) -> Transform {
         Transform {
             m: Matrix4x4 {
@@ -273,6 +331,83 @@ impl Transform {
         }
     }
 
+    /// look_at generates the world to camera Transform for a camera positioned at pos, pointed at
+    /// look, with up orienting the camera along the viewing direction.
+    pub fn look_at(pos: &Point3f, look: &Point3f, up: &Vector3f) -> Transform {
+        let dir = (*look - *pos).normalize();
+        let right = cross(&up.normalize(), &dir);
+        if right.length() == 0. {
+            error!(
+                "\"up\" vector ({}, {}, {}) and viewing direction ({}, {}, {}) passed to look_at \
+                 are pointing in the same direction.  Using the identity transformation.",
+                up.x, up.y, up.z, dir.x, dir.y, dir.z
+            );
+            return Transform::identity();
+        }
+        let right = right.normalize();
+        let new_up = cross(&dir, &right);
+        // Initialize the columns of the camera to world matrix with the camera's basis vectors and
+        // position.
+        let camera_to_world = Matrix4x4::new(
+            [right.x, new_up.x, dir.x, pos.x],
+            [right.y, new_up.y, dir.y, pos.y],
+            [right.z, new_up.z, dir.z, pos.z],
+            [0., 0., 0., 1.],
+        );
+        Transform {
+            m: camera_to_world.inverse(),
+            m_inv: camera_to_world,
+        }
+    }
+
+    /// orthographic generates the projection of an orthographic camera, which keeps x and y and
+    /// maps z in [z_near, z_far] to [0, 1].
+    pub fn orthographic(z_near: Float, z_far: Float) -> Transform {
+        Transform::scale(1., 1., (z_far - z_near).recip())
+            * Transform::translate(&Vector3f::new(0., 0., -z_near))
+    }
+
+    /// perspective generates the projection of a perspective camera with a field of view of fov
+    /// degrees.  Points are divided by their z, scaled so that the field of view spans [-1, 1] in
+    /// x and y, and z in [n, f] is mapped to [0, 1].
+    pub fn perspective(fov: Float, n: Float, f: Float) -> Transform {
+        let persp = Matrix4x4::new(
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., f / (f - n), -f * n / (f - n)],
+            [0., 0., 1., 0.],
+        );
+        let inv_tan_ang = (fov.to_radians() / 2.).tan().recip();
+        Transform::scale(inv_tan_ang, inv_tan_ang, 1.) * Transform::from(persp)
+    }
+
+    /// Returns whether the transform is exactly the identity.
+    pub fn is_identity(&self) -> bool {
+        self.m.m == Matrix4x4::identity().m
+    }
+
+    /// Returns whether the transform changes the length of any of the coordinate axes, beyond
+    /// a small tolerance.
+    pub fn has_scale(&self) -> bool {
+        let not_one = |v: Vector3f| {
+            let l2 = self.transform_vector(&v).length_squared();
+            !(0.999..=1.001).contains(&l2)
+        };
+        not_one(Vector3f::new(1., 0., 0.))
+            || not_one(Vector3f::new(0., 1., 0.))
+            || not_one(Vector3f::new(0., 0., 1.))
+    }
+
+    /// Returns whether the transform changes a left-handed coordinate system into a right-handed
+    /// one or vice versa, which is when the determinant of its upper-left 3x3 is negative.
+    pub fn swaps_handedness(&self) -> bool {
+        let m = &self.m.m;
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        det < 0.
+    }
+
     pub fn matrix(self) -> Matrix4x4 {
         self.m
     }
@@ -280,6 +415,178 @@ impl Transform {
     pub fn matrix_inverse(self) -> Matrix4x4 {
         self.m_inv
     }
+
+    /// Applies the transform to the point p, dividing through by the homogeneous weight if it
+    /// isn't one.
+    pub fn transform_point(&self, p: &Point3f) -> Point3f {
+        let m = &self.m.m;
+        let (x, y, z) = (p.x, p.y, p.z);
+        let xp = m[0][0] * x + m[0][1] * y + m[0][2] * z + m[0][3];
+        let yp = m[1][0] * x + m[1][1] * y + m[1][2] * z + m[1][3];
+        let zp = m[2][0] * x + m[2][1] * y + m[2][2] * z + m[2][3];
+        let wp = m[3][0] * x + m[3][1] * y + m[3][2] * z + m[3][3];
+        if wp == 1. {
+            Point3f::new(xp, yp, zp)
+        } else {
+            Point3f::new(xp, yp, zp) / wp
+        }
+    }
+
+    /// Like transform_point, but also returns a conservative bound on the absolute error of each
+    /// component of the result, from rounding in the computation.  Only affine transforms are
+    /// accounted for; the homogeneous divide isn't.
+    pub fn transform_point_with_error(&self, p: &Point3f) -> (Point3f, Vector3f) {
+        let m = &self.m.m;
+        let (x, y, z) = (p.x, p.y, p.z);
+        let abs_sum = |r: usize| {
+            (m[r][0] * x).abs() + (m[r][1] * y).abs() + (m[r][2] * z).abs() + m[r][3].abs()
+        };
+        let error = Vector3f::new(abs_sum(0), abs_sum(1), abs_sum(2)) * gamma(3);
+        (self.transform_point(p), error)
+    }
+
+    /// Like transform_point_with_error, for a point p that already has absolute error p_error:
+    /// the returned bound includes p_error carried through the transform.
+    pub fn transform_point_propagating_error(
+        &self,
+        p: &Point3f,
+        p_error: &Vector3f,
+    ) -> (Point3f, Vector3f) {
+        let m = &self.m.m;
+        let (x, y, z) = (p.x, p.y, p.z);
+        let abs_err = |r: usize| {
+            (gamma(3) + 1.)
+                * (m[r][0].abs() * p_error.x
+                    + m[r][1].abs() * p_error.y
+                    + m[r][2].abs() * p_error.z)
+                + gamma(3)
+                    * ((m[r][0] * x).abs()
+                        + (m[r][1] * y).abs()
+                        + (m[r][2] * z).abs()
+                        + m[r][3].abs())
+        };
+        let error = Vector3f::new(abs_err(0), abs_err(1), abs_err(2));
+        (self.transform_point(p), error)
+    }
+
+    /// Applies the transform to the vector v, which, unlike a point, isn't translated.
+    pub fn transform_vector(&self, v: &Vector3f) -> Vector3f {
+        let m = &self.m.m;
+        let (x, y, z) = (v.x, v.y, v.z);
+        Vector3f::new(
+            m[0][0] * x + m[0][1] * y + m[0][2] * z,
+            m[1][0] * x + m[1][1] * y + m[1][2] * z,
+            m[2][0] * x + m[2][1] * y + m[2][2] * z,
+        )
+    }
+
+    /// Like transform_vector, but also returns a conservative bound on the absolute error of each
+    /// component of the result.
+    pub fn transform_vector_with_error(&self, v: &Vector3f) -> (Vector3f, Vector3f) {
+        let m = &self.m.m;
+        let (x, y, z) = (v.x, v.y, v.z);
+        let abs_sum = |r: usize| (m[r][0] * x).abs() + (m[r][1] * y).abs() + (m[r][2] * z).abs();
+        let error = Vector3f::new(abs_sum(0), abs_sum(1), abs_sum(2)) * gamma(3);
+        (self.transform_vector(v), error)
+    }
+
+    /// Like transform_vector_with_error, for a vector v that already has absolute error v_error.
+    pub fn transform_vector_propagating_error(
+        &self,
+        v: &Vector3f,
+        v_error: &Vector3f,
+    ) -> (Vector3f, Vector3f) {
+        let m = &self.m.m;
+        let (x, y, z) = (v.x, v.y, v.z);
+        let abs_err = |r: usize| {
+            (gamma(3) + 1.)
+                * (m[r][0].abs() * v_error.x
+                    + m[r][1].abs() * v_error.y
+                    + m[r][2].abs() * v_error.z)
+                + gamma(3) * ((m[r][0] * x).abs() + (m[r][1] * y).abs() + (m[r][2] * z).abs())
+        };
+        let error = Vector3f::new(abs_err(0), abs_err(1), abs_err(2));
+        (self.transform_vector(v), error)
+    }
+
+    /// Applies the transform to the surface normal n.  Normals are transformed by the inverse
+    /// transpose, so that they stay perpendicular to transformed tangents.  The result isn't
+    /// normalized.
+    pub fn transform_normal(&self, n: &Normal3f) -> Normal3f {
+        let m_inv = &self.m_inv.m;
+        let (x, y, z) = (n.x, n.y, n.z);
+        Normal3f::new(
+            m_inv[0][0] * x + m_inv[1][0] * y + m_inv[2][0] * z,
+            m_inv[0][1] * x + m_inv[1][1] * y + m_inv[2][1] * z,
+            m_inv[0][2] * x + m_inv[1][2] * y + m_inv[2][2] * z,
+        )
+    }
+
+    /// Applies the transform to r.  The transformed origin is moved forward along the ray by its
+    /// rounding error, so that the ray can't start on the wrong side of the surface it left, and
+    /// t_max is shortened to match.
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        let (o, o_error) = self.transform_point_with_error(&r.o);
+        self.offset_ray(r, o, self.transform_vector(&r.d), &o_error)
+    }
+
+    /// Like transform_ray, but also returns bounds on the absolute error of the transformed
+    /// origin and direction.
+    pub fn transform_ray_with_error(&self, r: &Ray) -> (Ray, Vector3f, Vector3f) {
+        let (o, o_error) = self.transform_point_with_error(&r.o);
+        let (d, d_error) = self.transform_vector_with_error(&r.d);
+        (self.offset_ray(r, o, d, &o_error), o_error, d_error)
+    }
+
+    /// Like transform_ray_with_error, for a ray whose origin and direction already have absolute
+    /// errors o_error and d_error.
+    pub fn transform_ray_propagating_error(
+        &self,
+        r: &Ray,
+        o_error: &Vector3f,
+        d_error: &Vector3f,
+    ) -> (Ray, Vector3f, Vector3f) {
+        let (o, o_error) = self.transform_point_propagating_error(&r.o, o_error);
+        let (d, d_error) = self.transform_vector_propagating_error(&r.d, d_error);
+        (self.offset_ray(r, o, d, &o_error), o_error, d_error)
+    }
+
+    /// Returns r with origin o and direction d, with o moved to the edge of its error bounds in
+    /// the direction of d.
+    fn offset_ray(&self, r: &Ray, mut o: Point3f, d: Vector3f, o_error: &Vector3f) -> Ray {
+        let length_squared = d.length_squared();
+        let mut t_max = r.t_max;
+        if length_squared > 0. {
+            let dt = dot(&d.abs(), o_error) / length_squared;
+            o += d * dt;
+            t_max -= dt;
+        }
+        Ray {
+            o,
+            d,
+            t_max,
+            ..r.clone()
+        }
+    }
+
+    /// Applies the transform to the main ray of r and, if it has them, to its differentials.
+    pub fn transform_ray_differential(&self, r: &RayDifferential) -> RayDifferential {
+        RayDifferential {
+            ray: self.transform_ray(&r.ray),
+            has_differentials: r.has_differentials,
+            rx_origin: self.transform_point(&r.rx_origin),
+            ry_origin: self.transform_point(&r.ry_origin),
+            rx_direction: self.transform_vector(&r.rx_direction),
+            ry_direction: self.transform_vector(&r.ry_direction),
+        }
+    }
+
+    /// Returns the bounds of the transformed corners of b.
+    pub fn transform_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        (0..8).fold(Bounds3f::default(), |bounds, corner| {
+            bounds.union_point(&self.transform_point(&b.corner(corner)))
+        })
+    }
 }
 
 impl From<Matrix4x4> for Transform {
@@ -294,9 +601,10 @@ impl From<Matrix4x4> for Transform {
 impl Mul<Transform> for Transform {
     type Output = Transform;
     fn mul(self, rhs: Transform) -> Transform {
+        // (AB)^-1 = B^-1 A^-1
         Transform {
             m: self.m * rhs.m,
-            m_inv: self.m_inv * rhs.m_inv,
+            m_inv: rhs.m_inv * self.m_inv,
         }
     }
 }
@@ -305,11 +613,367 @@ impl<'a, 'b> Mul<&'b mut Transform> for &'a mut Transform {
     fn mul(self, rhs: &'b mut Transform) -> Transform {
         Transform {
             m: self.m * rhs.m,
-            m_inv: self.m_inv * rhs.m_inv,
+            m_inv: rhs.m_inv * self.m_inv,
         }
     }
 }
 
+/// A transform that varies over time, from start_transform at start_time to end_transform at
+/// end_time.  Rather than interpolating the matrices, which would shrink a rotating object midway,
+/// each is decomposed into a translation, a rotation and a scale, and those are interpolated.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    start_transform: Transform,
+    end_transform: Transform,
+    start_time: Float,
+    end_time: Float,
+    actually_animated: bool,
+    has_rotation: bool,
+    t: [Vector3f; 2],
+    r: [Quaternion; 2],
+    s: [Matrix4x4; 2],
+}
+
+impl AnimatedTransform {
+    pub fn new(
+        start_transform: &Transform,
+        start_time: Float,
+        end_transform: &Transform,
+        end_time: Float,
+    ) -> AnimatedTransform {
+        let (t0, r0, s0) = AnimatedTransform::decompose(&start_transform.m);
+        let (t1, mut r1, s1) = AnimatedTransform::decompose(&end_transform.m);
+        // q and -q are the same rotation; pick the one that rotates the short way round.
+        if quaternion::dot(&r0, &r1) < 0. {
+            r1 = -r1;
+        }
+        AnimatedTransform {
+            start_transform: *start_transform,
+            end_transform: *end_transform,
+            start_time,
+            end_time,
+            actually_animated: start_transform != end_transform,
+            has_rotation: quaternion::dot(&r0, &r1) < 0.9995,
+            t: [t0, t1],
+            r: [r0, r1],
+            s: [s0, s1],
+        }
+    }
+
+    /// Splits m into its translation T, rotation R and remaining scale and shear S, so that
+    /// m = T R S.  R is found by polar decomposition, averaging the matrix with its inverse
+    /// transpose until it converges.
+    pub fn decompose(m: &Matrix4x4) -> (Vector3f, Quaternion, Matrix4x4) {
+        let t = Vector3f::new(m.m[0][3], m.m[1][3], m.m[2][3]);
+        let mut m = *m;
+        for i in 0..3 {
+            m.m[i][3] = 0.;
+            m.m[3][i] = 0.;
+        }
+        m.m[3][3] = 1.;
+
+        let mut r = m;
+        for _ in 0..100 {
+            let r_it = r.transpose().inverse();
+            let mut r_next = Matrix4x4::default();
+            for i in 0..4 {
+                for j in 0..4 {
+                    r_next.m[i][j] = 0.5 * (r.m[i][j] + r_it.m[i][j]);
+                }
+            }
+            let mut norm: Float = 0.;
+            for i in 0..3 {
+                let n = (0..3).map(|j| (r.m[i][j] - r_next.m[i][j]).abs()).sum();
+                norm = norm.max(n);
+            }
+            r = r_next;
+            if norm <= 0.0001 {
+                break;
+            }
+        }
+        let s = r.inverse() * m;
+        (t, Quaternion::from_matrix(&r), s)
+    }
+
+    /// Returns the transform at time, which is start_transform before start_time and
+    /// end_transform after end_time.
+    pub fn interpolate(&self, time: Float) -> Transform {
+        if !self.actually_animated || time <= self.start_time {
+            return self.start_transform;
+        }
+        if time >= self.end_time {
+            return self.end_transform;
+        }
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let trans = self.t[0] * (1. - dt) + self.t[1] * dt;
+        let rotate = quaternion::slerp(dt, &self.r[0], &self.r[1]);
+        let mut scale = Matrix4x4::default();
+        for i in 0..4 {
+            for j in 0..4 {
+                scale.m[i][j] = (1. - dt) * self.s[0].m[i][j] + dt * self.s[1].m[i][j];
+            }
+        }
+        Transform::translate(&trans) * rotate.to_transform() * Transform::from(scale)
+    }
+
+    /// Applies the transform at r.time to r.
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        self.interpolate(r.time).transform_ray(r)
+    }
+
+    /// Applies the transform at r.time to r and its differentials.
+    pub fn transform_ray_differential(&self, r: &RayDifferential) -> RayDifferential {
+        self.interpolate(r.time).transform_ray_differential(r)
+    }
+
+    pub fn transform_point(&self, time: Float, p: &Point3f) -> Point3f {
+        self.interpolate(time).transform_point(p)
+    }
+
+    pub fn transform_vector(&self, time: Float, v: &Vector3f) -> Vector3f {
+        self.interpolate(time).transform_vector(v)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.actually_animated
+    }
+
+    pub fn has_scale(&self) -> bool {
+        self.start_transform.has_scale() || self.end_transform.has_scale()
+    }
+
+    /// Returns the bounds of b as it moves over the whole time range.
+    pub fn motion_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        if !self.actually_animated {
+            return self.start_transform.transform_bounds(b);
+        }
+        if !self.has_rotation {
+            // Without rotation, the box moves in straight lines.
+            return self
+                .start_transform
+                .transform_bounds(b)
+                .union(&self.end_transform.transform_bounds(b));
+        }
+        (0..8).fold(Bounds3f::default(), |bounds, corner| {
+            bounds.union(&self.bound_point_motion(&b.corner(corner)))
+        })
+    }
+
+    /// Returns the bounds of p as it moves over the whole time range.  The extremes of each
+    /// coordinate are at the ends of the range or where its derivative is zero, which are found
+    /// by interval search.
+    pub fn bound_point_motion(&self, p: &Point3f) -> Bounds3f {
+        let mut bounds = Bounds3f::new(
+            self.start_transform.transform_point(p),
+            self.end_transform.transform_point(p),
+        );
+        if !self.actually_animated {
+            return bounds;
+        }
+        // For dt in [0, 1] the point is at T(dt) + R(dt) S(dt) p, where T and S are linear in dt.
+        // slerp's rotation is a cos(theta dt) + b sin(theta dt), whose matrix is quadratic in
+        // those, so R(dt) = R_1 + R_cos cos(2 theta dt) + R_sin sin(2 theta dt).
+        let cos_theta = quaternion::dot(&self.r[0], &self.r[1]);
+        let theta = cos_theta.clamp(-1., 1.).acos();
+        let a = self.r[0];
+        let b = (self.r[1] - self.r[0] * cos_theta).normalize();
+        let r_1 = |v: &Vector3f| (rotate(&a, v) + rotate(&b, v)) * 0.5;
+        let r_cos = |v: &Vector3f| (rotate(&a, v) - rotate(&b, v)) * 0.5;
+        let r_sin = |v: &Vector3f| (rotate(&(a + b), v) - rotate(&(a - b), v)) * 0.25;
+        let s_0 = apply_linear(&self.s[0], p);
+        let s_d = apply_linear(&self.s[1], p) - s_0;
+        // The point is k0 + k1 dt + (k2 + k3 dt) cos(2 theta dt) + (k4 + k5 dt) sin(2 theta dt).
+        let k1 = self.t[1] - self.t[0] + r_1(&s_d);
+        let (k2, k3) = (r_cos(&s_0), r_cos(&s_d));
+        let (k4, k5) = (r_sin(&s_0), r_sin(&s_d));
+        for i in 0..3 {
+            // Its derivative is c1 + (c2 + c3 dt) cos(2 theta dt) + (c4 + c5 dt) sin(2 theta dt).
+            let c = [
+                k1[i],
+                k3[i] + 2. * theta * k4[i],
+                2. * theta * k5[i],
+                k5[i] - 2. * theta * k2[i],
+                -2. * theta * k3[i],
+            ];
+            let mut zeros = Vec::new();
+            interval_find_zeros(&c, theta, Interval::new(0., 1.), &mut zeros, 8);
+            for dt in zeros {
+                let time = self.start_time + dt * (self.end_time - self.start_time);
+                bounds = bounds.union_point(&self.transform_point(time, p));
+            }
+        }
+        bounds
+    }
+}
+
+/// Returns v rotated by q, computed as a homogeneous quadratic in q's components so that it's
+/// correct for unit quaternions and can be split by linearity for any others.
+fn rotate(q: &Quaternion, v: &Vector3f) -> Vector3f {
+    let (x, y, z, w) = (q.v.x, q.v.y, q.v.z, q.w);
+    let m = [
+        [
+            w * w + x * x - y * y - z * z,
+            2. * (x * y - w * z),
+            2. * (x * z + w * y),
+        ],
+        [
+            2. * (x * y + w * z),
+            w * w - x * x + y * y - z * z,
+            2. * (y * z - w * x),
+        ],
+        [
+            2. * (x * z - w * y),
+            2. * (y * z + w * x),
+            w * w - x * x - y * y + z * z,
+        ],
+    ];
+    Vector3f::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+/// Returns the upper-left 3x3 of m applied to p.
+fn apply_linear(m: &Matrix4x4, p: &Point3f) -> Vector3f {
+    Transform::new(*m, *m).transform_vector(&Vector3f::from(*p))
+}
+
+/// A closed range of values, for bounding a function over a range of its argument.
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    low: Float,
+    high: Float,
+}
+
+impl Interval {
+    fn new(v0: Float, v1: Float) -> Interval {
+        Interval {
+            low: v0.min(v1),
+            high: v0.max(v1),
+        }
+    }
+
+    fn point(v: Float) -> Interval {
+        Interval { low: v, high: v }
+    }
+
+    /// Returns the range of sin over the interval, which must be within [0, 2 pi].
+    fn sin(self) -> Interval {
+        let mut i = Interval::new(self.low.sin(), self.high.sin());
+        if self.low < PI / 2. && self.high > PI / 2. {
+            i.high = 1.;
+        }
+        if self.low < 1.5 * PI && self.high > 1.5 * PI {
+            i.low = -1.;
+        }
+        i
+    }
+
+    /// Returns the range of cos over the interval, which must be within [0, 2 pi].
+    fn cos(self) -> Interval {
+        let mut i = Interval::new(self.low.cos(), self.high.cos());
+        if self.low < PI && self.high > PI {
+            i.low = -1.;
+        }
+        i
+    }
+}
+
+impl Add for Interval {
+    type Output = Interval;
+
+    fn add(self, rhs: Interval) -> Interval {
+        Interval {
+            low: self.low + rhs.low,
+            high: self.high + rhs.high,
+        }
+    }
+}
+
+impl Sub for Interval {
+    type Output = Interval;
+
+    fn sub(self, rhs: Interval) -> Interval {
+        Interval {
+            low: self.low - rhs.high,
+            high: self.high - rhs.low,
+        }
+    }
+}
+
+impl Mul for Interval {
+    type Output = Interval;
+
+    fn mul(self, rhs: Interval) -> Interval {
+        let products = [
+            self.low * rhs.low,
+            self.high * rhs.low,
+            self.low * rhs.high,
+            self.high * rhs.high,
+        ];
+        Interval {
+            low: products.iter().cloned().fold(Float::INFINITY, Float::min),
+            high: products
+                .iter()
+                .cloned()
+                .fold(Float::NEG_INFINITY, Float::max),
+        }
+    }
+}
+
+/// Appends to zeros the values of t in t_interval where
+/// c[0] + (c[1] + c[2] t) cos(2 theta t) + (c[3] + c[4] t) sin(2 theta t) is zero.  The interval
+/// is bisected depth times, discarding the halves where the function's range excludes zero, and
+/// then the zero in each remaining piece is refined with Newton's method.
+fn interval_find_zeros(
+    c: &[Float; 5],
+    theta: Float,
+    t_interval: Interval,
+    zeros: &mut Vec<Float>,
+    depth: usize,
+) {
+    let two_theta_t = Interval::point(2. * theta) * t_interval;
+    let range = Interval::point(c[0])
+        + (Interval::point(c[1]) + Interval::point(c[2]) * t_interval) * two_theta_t.cos()
+        + (Interval::point(c[3]) + Interval::point(c[4]) * t_interval) * two_theta_t.sin();
+    if range.low > 0. || range.high < 0. || range.low == range.high {
+        return;
+    }
+    if depth > 0 {
+        let mid = (t_interval.low + t_interval.high) * 0.5;
+        interval_find_zeros(
+            c,
+            theta,
+            Interval::new(t_interval.low, mid),
+            zeros,
+            depth - 1,
+        );
+        interval_find_zeros(
+            c,
+            theta,
+            Interval::new(mid, t_interval.high),
+            zeros,
+            depth - 1,
+        );
+        return;
+    }
+    let mut t = (t_interval.low + t_interval.high) * 0.5;
+    for _ in 0..4 {
+        let (sin, cos) = (2. * theta * t).sin_cos();
+        let f = c[0] + (c[1] + c[2] * t) * cos + (c[3] + c[4] * t) * sin;
+        let f_prime = (c[2] + 2. * (c[3] + c[4] * t) * theta) * cos
+            + (c[4] - 2. * (c[1] + c[2] * t) * theta) * sin;
+        if f == 0. || f_prime == 0. {
+            break;
+        }
+        t -= f / f_prime;
+    }
+    if t >= t_interval.low - 1e-3 && t < t_interval.high + 1e-3 {
+        zeros.push(t);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -352,4 +1016,344 @@ mod tests {
         );
         assert_eq!(t.m * m1, m2);
     }
+
+    #[test]
+    fn test_look_at() {
+        let origin = Point3f {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        let up = Vector3f::new(0., 1., 0.);
+        // Looking down +z from the origin is the canonical camera space.
+        let t = Transform::look_at(
+            &origin,
+            &Point3f {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            &up,
+        );
+        assert_eq!(t, Transform::identity());
+
+        // Moving the eye only adds the inverse translation.
+        let t = Transform::look_at(
+            &Point3f {
+                x: 1.,
+                y: 2.,
+                z: 3.,
+            },
+            &Point3f {
+                x: 1.,
+                y: 2.,
+                z: 10.,
+            },
+            &up,
+        );
+        assert_eq!(t, Transform::translate(&Vector3f::new(-1., -2., -3.)));
+
+        // Degenerate up vector falls back to identity.
+        let t = Transform::look_at(
+            &origin,
+            &Point3f {
+                x: 0.,
+                y: 5.,
+                z: 0.,
+            },
+            &up,
+        );
+        assert_eq!(t, Transform::identity());
+    }
+
+    #[test]
+    fn test_transform_ray() {
+        let t = Transform::translate(&Vector3f::new(1., 2., 3.)) * Transform::scale(2., 2., 2.);
+        let p = Point3f::new(1., 1., 1.);
+        let v = Vector3f::new(0., 1., 0.);
+        assert_eq!(t.transform_point(&p), Point3f::new(3., 4., 5.));
+        assert_eq!(t.transform_vector(&v), Vector3f::new(0., 2., 0.));
+
+        let r = Ray::new(p, v, 10., 0.25, None);
+        let tr = t.transform_ray(&r);
+        // The origin is pushed forward by its error bound, and t_max shortened to match.
+        let (_, o_error) = t.transform_point_with_error(&p);
+        let dt = o_error.y / 2.;
+        assert!(dt > 0.);
+        assert_eq!(tr.o, Point3f::new(3., 4., 5.) + tr.d * dt);
+        assert_eq!(tr.d, Vector3f::new(0., 2., 0.));
+        assert_eq!(tr.t_max, 10. - dt);
+        assert_eq!(tr.time, 0.25);
+        let (tr2, o_error2, d_error) = t.transform_ray_with_error(&r);
+        assert_eq!(tr2.o, tr.o);
+        assert_eq!(o_error2, o_error);
+        assert_eq!(d_error, Vector3f::new(0., 2. * gamma(3), 0.));
+
+        let mut rd = RayDifferential::from(r);
+        rd.has_differentials = true;
+        rd.rx_origin = Point3f::new(2., 1., 1.);
+        rd.ry_direction = Vector3f::new(1., 0., 0.);
+        let trd = t.transform_ray_differential(&rd);
+        assert!(trd.has_differentials);
+        assert_eq!(trd.o, tr.o);
+        assert_eq!(trd.rx_origin, Point3f::new(5., 4., 5.));
+        assert_eq!(trd.ry_direction, Vector3f::new(2., 0., 0.));
+    }
+
+    #[test]
+    fn test_transform_projective_point() {
+        let t = Transform::from(Matrix4x4::new(
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 1., 0.],
+        ));
+        assert_eq!(
+            t.transform_point(&Point3f::new(2., 4., 2.)),
+            Point3f::new(1., 2., 1.)
+        );
+    }
+
+    #[test]
+    fn test_mul_inverse() {
+        let t = Transform::translate(&Vector3f::new(1., 2., 3.))
+            * Transform::rotate(30., &Vector3f::new(1., 1., 0.))
+            * Transform::scale(2., 3., 4.);
+        assert_eq!(t.matrix() * t.matrix_inverse(), Matrix4x4::identity());
+        assert_eq!(t.matrix_inverse(), t.matrix().inverse());
+    }
+
+    #[test]
+    fn test_rotate_axes() {
+        let axis = |x, y, z| Vector3f::new(x, y, z);
+        for &theta in &[0., 30., -90., 200.] {
+            assert_eq!(
+                Transform::rotate_x(theta),
+                Transform::rotate(theta, &axis(1., 0., 0.))
+            );
+            assert_eq!(
+                Transform::rotate_y(theta),
+                Transform::rotate(theta, &axis(0., 1., 0.))
+            );
+            assert_eq!(
+                Transform::rotate_z(theta),
+                Transform::rotate(theta, &axis(0., 0., 1.))
+            );
+        }
+        let p = Transform::rotate_z(90.).transform_point(&Point3f::new(1., 0., 0.));
+        assert!(p.distance(&Point3f::new(0., 1., 0.)) < 1e-6);
+    }
+
+    #[test]
+    fn test_projections() {
+        let close = |a: Point3f, b: Point3f| a.distance(&b) < 1e-5;
+        let t = Transform::orthographic(1., 11.);
+        assert!(close(
+            t.transform_point(&Point3f::new(2., 3., 1.)),
+            Point3f::new(2., 3., 0.)
+        ));
+        assert!(close(
+            t.transform_point(&Point3f::new(2., 3., 6.)),
+            Point3f::new(2., 3., 0.5)
+        ));
+        assert_eq!(t.matrix_inverse(), t.matrix().inverse());
+
+        let t = Transform::perspective(90., 1., 100.);
+        assert!(close(
+            t.transform_point(&Point3f::new(1., 0., 1.)),
+            Point3f::new(1., 0., 0.)
+        ));
+        assert!(close(
+            t.transform_point(&Point3f::new(0., -100., 100.)),
+            Point3f::new(0., -1., 1.)
+        ));
+        assert!(close(
+            t.transform_point(&Point3f::new(5., 5., 10.)),
+            Point3f::new(0.5, 0.5, t.transform_point(&Point3f::new(0., 0., 10.)).z)
+        ));
+        assert_eq!(t.matrix_inverse(), t.matrix().inverse());
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert!(Transform::identity().is_identity());
+        assert!(!Transform::translate(&Vector3f::new(0., 0., 1e-7)).is_identity());
+        assert!(!Transform::identity().has_scale());
+        assert!(!Transform::rotate(45., &Vector3f::new(1., 2., 3.)).has_scale());
+        assert!(Transform::scale(1., 1., 2.).has_scale());
+        assert!(!Transform::identity().swaps_handedness());
+        assert!(!Transform::scale(-1., -1., 1.).swaps_handedness());
+        assert!(Transform::scale(-1., 1., 1.).swaps_handedness());
+    }
+
+    #[test]
+    fn test_transform_normal() {
+        let t = Transform::scale(1., 4., 1.) * Transform::rotate_z(30.);
+        // The normal of the plane containing the tangents u and v.
+        let (u, v) = (Vector3f::new(1., 1., 0.), Vector3f::new(0., 1., 1.));
+        let n = Normal3f::from(cross(&u, &v));
+        let (u, v, n) = (
+            t.transform_vector(&u),
+            t.transform_vector(&v),
+            t.transform_normal(&n),
+        );
+        assert!(dot(&n, &u).abs() < 1e-5);
+        assert!(dot(&n, &v).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_transform_bounds() {
+        let b = Bounds3f::new(Point3f::new(0., 0., 0.), Point3f::new(2., 1., 1.));
+        let tb = Transform::rotate_z(90.).transform_bounds(&b);
+        let want = Bounds3f::new(Point3f::new(-1., 0., 0.), Point3f::new(0., 2., 1.));
+        assert!(tb.p_min.distance(&want.p_min) < 1e-6, "{:?}", tb);
+        assert!(tb.p_max.distance(&want.p_max) < 1e-6, "{:?}", tb);
+    }
+
+    #[test]
+    fn test_error_bounds() {
+        let transforms = [
+            Transform::translate(&Vector3f::new(0.1, -3.3, 1e3)),
+            Transform::rotate(37., &Vector3f::new(0.3, -1., 0.7)),
+            Transform::scale(1.1, 0.3, -7.7) * Transform::rotate_y(11.),
+        ];
+        let points = [
+            Point3f::new(0.1, 0.2, 0.3),
+            Point3f::new(-1e4, 3.7, 1e-3),
+            Point3f::new(123.456, -0.001, 7.),
+        ];
+        for t in &transforms {
+            let m = t.matrix().m;
+            let exact = |p: &Point3f, w: f64| {
+                let (x, y, z) = (f64::from(p.x), f64::from(p.y), f64::from(p.z));
+                let row = |r: usize| {
+                    f64::from(m[r][0]) * x
+                        + f64::from(m[r][1]) * y
+                        + f64::from(m[r][2]) * z
+                        + f64::from(m[r][3]) * w
+                };
+                [row(0), row(1), row(2)]
+            };
+            for p in &points {
+                let (tp, p_error) = t.transform_point_with_error(p);
+                let (tv, v_error) = t.transform_vector_with_error(&Vector3f::from(*p));
+                let (want_p, want_v) = (exact(p, 1.), exact(p, 0.));
+                for i in 0..3 {
+                    assert!((f64::from(tp[i]) - want_p[i]).abs() <= f64::from(p_error[i]));
+                    assert!((f64::from(tv[i]) - want_v[i]).abs() <= f64::from(v_error[i]));
+                }
+                // Starting with an error makes the bound larger.
+                let (_, more) = t.transform_point_propagating_error(p, &p_error);
+                let (_, more_v) =
+                    t.transform_vector_propagating_error(&Vector3f::from(*p), &v_error);
+                for i in 0..3 {
+                    assert!(more[i] >= p_error[i]);
+                    assert!(more_v[i] >= v_error[i]);
+                }
+            }
+        }
+    }
+
+    fn assert_matrix_close(got: &Matrix4x4, want: &Matrix4x4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(
+                    (got.m[i][j] - want.m[i][j]).abs() < 1e-5,
+                    "{:?} != {:?}",
+                    got,
+                    want
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompose() {
+        let m = (Transform::translate(&Vector3f::new(1., 2., 3.))
+            * Transform::rotate(30., &Vector3f::new(1., 1., 0.))
+            * Transform::scale(2., 3., 4.))
+        .matrix();
+        let (t, r, s) = AnimatedTransform::decompose(&m);
+        assert_eq!(t, Vector3f::new(1., 2., 3.));
+        assert_matrix_close(&s, &Transform::scale(2., 3., 4.).matrix());
+        assert_matrix_close(
+            &r.to_matrix(),
+            &Transform::rotate(30., &Vector3f::new(1., 1., 0.)).matrix(),
+        );
+        assert_matrix_close(&(Transform::translate(&t).matrix() * r.to_matrix() * s), &m);
+    }
+
+    #[test]
+    fn test_animated_transform() {
+        let start = Transform::identity();
+        let end = Transform::translate(&Vector3f::new(10., 0., 0.))
+            * Transform::rotate_z(90.)
+            * Transform::scale(3., 3., 3.);
+        let at = AnimatedTransform::new(&start, 1., &end, 3.);
+        assert!(at.is_animated());
+        assert!(at.has_scale());
+        let p = Point3f::new(1., 0., 0.);
+        assert_eq!(at.transform_point(0., &p), p);
+        assert_eq!(at.transform_point(1., &p), p);
+        assert_eq!(at.transform_point(3., &p), end.transform_point(&p));
+        assert_eq!(at.transform_point(4., &p), end.transform_point(&p));
+        // Halfway, the point is rotated by 45 degrees and scaled by 2, not shrunk by
+        // interpolating the rotation matrices.
+        let s = 2. * 0.5f32.sqrt();
+        let mid = at.transform_point(2., &p);
+        assert!(
+            mid.distance(&Point3f::new(5. + s, s, 0.)) < 1e-5,
+            "{:?}",
+            mid
+        );
+        let v = at.transform_vector(2., &Vector3f::new(1., 0., 0.));
+        assert!((v - Vector3f::new(s, s, 0.)).length() < 1e-5, "{:?}", v);
+
+        let ray = Ray::new(p, Vector3f::new(0., 1., 0.), 1., 2., None);
+        let moved = at.transform_ray(&ray);
+        assert!(moved.o.distance(&mid) < 1e-5);
+        assert!((moved.d - Vector3f::new(-s, s, 0.)).length() < 1e-5);
+        let moved = at.transform_ray_differential(&RayDifferential::from(ray));
+        assert!(moved.o.distance(&mid) < 1e-5);
+
+        let still = AnimatedTransform::new(&end, 0., &end, 1.);
+        assert!(!still.is_animated());
+        assert_eq!(still.transform_point(0.5, &p), end.transform_point(&p));
+    }
+
+    #[test]
+    fn test_motion_bounds() {
+        let b = Bounds3f::new(Point3f::new(-1., 0.5, -1.), Point3f::new(1., 2., 1.));
+        let cases = [
+            // Translation only.
+            (
+                Transform::identity(),
+                Transform::translate(&Vector3f::new(3., -1., 2.)),
+            ),
+            // Rotation, where the corners sweep out arcs beyond the ends' bounds.
+            (Transform::identity(), Transform::rotate_z(150.)),
+            (
+                Transform::rotate(-40., &Vector3f::new(1., 1., 1.)),
+                Transform::translate(&Vector3f::new(1., 2., 3.))
+                    * Transform::rotate(100., &Vector3f::new(0., 1., 1.))
+                    * Transform::scale(0.5, 2., 1.),
+            ),
+        ];
+        for (start, end) in &cases {
+            let at = AnimatedTransform::new(start, 0., end, 1.);
+            let bounds = at.motion_bounds(&b);
+            let mut sampled = Bounds3f::default();
+            for i in 0..=1000 {
+                let t = at.interpolate(i as Float / 1000.);
+                sampled = sampled.union(&t.transform_bounds(&b));
+            }
+            // The bounds contain the box at every time, and are no bigger than needed.
+            for i in 0..3 {
+                assert!(bounds.p_min[i] <= sampled.p_min[i] + 1e-4, "{:?}", bounds);
+                assert!(bounds.p_max[i] >= sampled.p_max[i] - 1e-4, "{:?}", bounds);
+                assert!(bounds.p_min[i] >= sampled.p_min[i] - 1e-2, "{:?}", bounds);
+                assert!(bounds.p_max[i] <= sampled.p_max[i] + 1e-2, "{:?}", bounds);
+            }
+        }
+    }
 }
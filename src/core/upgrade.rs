@@ -0,0 +1,665 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Rewrites pbrt-v3 directives in pbrt-v4 syntax, following the rules of pbrt-v4's own
+//! `--upgrade`.  Materials, samplers, integrators, films and cameras that were renamed get their
+//! new names, renamed parameters are renamed, and spectrum valued light `"scale"`s and blackbody
+//! scales are folded into v4's float `"scale"`; v3 had no light `"power"`, so one is dropped.
+//! Anything without a v4 equivalent is reported as a Warning; it's dropped if it's a parameter,
+//! or left as is otherwise.
+use std::fmt;
+
+use core::geometry::Point2f;
+use core::paramset::{ParamList, ParamSet, Value};
+use core::parser::{Directive, Scene, Span};
+use core::pbrt::Float;
+
+/// Something that couldn't be translated to pbrt-v4, and where it was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+/// Collects the warnings for the directive at span.
+struct Warnings<'a> {
+    span: &'a Span,
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl<'a> Warnings<'a> {
+    fn warn(&mut self, message: String) {
+        self.warnings.push(Warning {
+            span: self.span.clone(),
+            message,
+        });
+    }
+}
+
+/// Returns d, read from span, rewritten in pbrt-v4 syntax.  Anything that can't be translated is
+/// added to warnings.
+pub fn upgrade(d: Directive, span: &Span, warnings: &mut Vec<Warning>) -> Directive {
+    let w = &mut Warnings { span, warnings };
+    match d {
+        Directive::Camera(name, mut ps) => {
+            let name = upgrade_camera(name, &mut ps);
+            Directive::Camera(name, ps)
+        }
+        Directive::Sampler(name, ps) => Directive::Sampler(upgrade_sampler(name), ps),
+        Directive::Integrator(name, mut ps) => {
+            let name = upgrade_integrator(name, &mut ps, w);
+            Directive::Integrator(name, ps)
+        }
+        Directive::Film(name, mut ps) => {
+            let name = upgrade_film(name, &mut ps, w);
+            Directive::Film(name, ps)
+        }
+        Directive::LightSource(name, mut ps) => {
+            upgrade_light(&mut ps, w);
+            Directive::LightSource(name, ps)
+        }
+        Directive::AreaLightSource(name, mut ps) => {
+            upgrade_light(&mut ps, w);
+            Directive::AreaLightSource(name, ps)
+        }
+        Directive::Material(typ, mut ps) => {
+            let typ = upgrade_material(typ, &mut ps, w);
+            Directive::Material(typ, ps)
+        }
+        Directive::MakeNamedMaterial(name, mut ps) => {
+            let typ = match ps.remove("type") {
+                Some(Value::String(ParamList(ref types))) if types.len() == 1 => types[0].clone(),
+                _ => {
+                    w.warn(format!("MakeNamedMaterial \"{}\" has no \"string type\"", name));
+                    return Directive::MakeNamedMaterial(name, ps);
+                }
+            };
+            let typ = upgrade_material(typ, &mut ps, w);
+            ps.add("type", Value::String(vec![typ].into()));
+            Directive::MakeNamedMaterial(name, ps)
+        }
+        Directive::Texture(name, typ, class, mut ps) => {
+            // "color" was a synonym for "spectrum".
+            let typ = if typ == "color" {
+                "spectrum".to_owned()
+            } else {
+                typ
+            };
+            upgrade_texture(&class, &mut ps);
+            Directive::Texture(name, typ, class, ps)
+        }
+        Directive::Shape(name, mut ps) => {
+            upgrade_shape(&name, &mut ps, w);
+            Directive::Shape(name, ps)
+        }
+        // pbrt-v4 deprecates transform blocks in favor of attribute blocks.
+        Directive::TransformBegin => Directive::AttributeBegin,
+        Directive::TransformEnd => Directive::AttributeEnd,
+        d => d,
+    }
+}
+
+/// Upgrades every directive in scene, returning the new scene and the warnings for it.
+pub fn upgrade_scene(scene: &Scene) -> (Scene, Vec<Warning>) {
+    let mut upgraded = Scene::default();
+    let mut warnings = Vec::new();
+    for (d, span) in scene.directives.iter().zip(&scene.spans) {
+        upgraded.push(upgrade(d.clone(), span, &mut warnings), span.clone());
+    }
+    (upgraded, warnings)
+}
+
+/// Returns the value of v if it's a single float, or an rgb with equal components.
+fn constant(v: &Value) -> Option<Float> {
+    match v {
+        Value::Float(ParamList(vs)) if vs.len() == 1 => Some(vs[0]),
+        Value::RGB(ParamList(vs)) if vs.len() == 3 && vs[0] == vs[1] && vs[1] == vs[2] => {
+            Some(vs[0])
+        }
+        _ => None,
+    }
+}
+
+/// Removes parameter name if it's set to value, which makes it redundant in pbrt-v4.  Returns
+/// whether it was removed.
+fn remove_if_constant(ps: &mut ParamSet, name: &str, value: Float) -> bool {
+    if ps.get(name).and_then(constant) == Some(value) {
+        ps.remove(name);
+        return true;
+    }
+    false
+}
+
+/// Removes parameter name, warning that it has no pbrt-v4 equivalent, if it's set.
+fn drop_param(ps: &mut ParamSet, name: &str, what: &str, w: &mut Warnings) {
+    if ps.remove(name).is_some() {
+        w.warn(format!(
+            "parameter \"{}\" of {} has no pbrt-v4 equivalent, dropping it",
+            name, what
+        ));
+    }
+}
+
+fn upgrade_camera(name: String, ps: &mut ParamSet) -> String {
+    match name.as_str() {
+        "environment" => {
+            ps.add(
+                "mapping",
+                Value::String(vec!["equirectangular".to_owned()].into()),
+            );
+            "spherical".to_owned()
+        }
+        _ => name,
+    }
+}
+
+fn upgrade_sampler(name: String) -> String {
+    match name.as_str() {
+        "lowdiscrepancy" | "02sequence" => "paddedsobol".to_owned(),
+        "maxmindist" => "pmj02bn".to_owned(),
+        _ => name,
+    }
+}
+
+fn upgrade_integrator(name: String, ps: &mut ParamSet, w: &mut Warnings) -> String {
+    match name.as_str() {
+        "directlighting" => {
+            w.warn(
+                "integrator \"directlighting\" was removed in pbrt-v4, approximating it with \
+                 \"path\" and \"maxdepth\" 1"
+                    .to_owned(),
+            );
+            ps.remove("strategy");
+            ps.add("maxdepth", Value::Int(vec![1].into()));
+            "path".to_owned()
+        }
+        "whitted" => {
+            w.warn(
+                "integrator \"whitted\" was removed in pbrt-v4, using \"path\" instead".to_owned(),
+            );
+            "path".to_owned()
+        }
+        _ => name,
+    }
+}
+
+fn upgrade_film(name: String, ps: &mut ParamSet, w: &mut Warnings) -> String {
+    ps.rename("maxsampleluminance", "maxcomponentvalue");
+    if !remove_if_constant(ps, "scale", 1.) {
+        drop_param(ps, "scale", "Film", w);
+    }
+    match name.as_str() {
+        "image" => "rgb".to_owned(),
+        _ => name,
+    }
+}
+
+/// Upgrades the parameters of a LightSource or AreaLightSource.  pbrt-v4 only accepts a float
+/// "scale", and blackbody emission takes just a temperature.  v3 lights are only ever scaled, so
+/// a "power", which v3 ignored but v4 would rescale the light to, is dropped.
+fn upgrade_light(ps: &mut ParamSet, w: &mut Warnings) {
+    ps.rename("mapname", "filename");
+    if ps.remove("power").is_some() {
+        w.warn(
+            "light \"power\" is ignored by pbrt-v3 but rescales the light in pbrt-v4, dropping it"
+                .to_owned(),
+        );
+    }
+    let mut scale = 1.;
+    if let Some(v) = ps.remove("scale") {
+        match constant(&v) {
+            Some(s) => scale = s,
+            None => {
+                // A colored scale can be folded into rgb emission.
+                let mut folded = false;
+                if let Value::RGB(ParamList(ref s)) = v {
+                    for emission in &["L", "I"] {
+                        if let Some(Value::RGB(ParamList(e))) = ps.get(emission) {
+                            if s.len() == 3 && e.len() == 3 {
+                                let rgb = vec![e[0] * s[0], e[1] * s[1], e[2] * s[2]];
+                                ps.add(emission, Value::RGB(rgb.into()));
+                                folded = true;
+                            }
+                        }
+                    }
+                }
+                if !folded {
+                    w.warn(
+                        "light \"scale\" isn't a single value, and pbrt-v4 only supports float \
+                         scales, dropping it"
+                            .to_owned(),
+                    );
+                }
+            }
+        }
+    }
+    for emission in &["L", "I"] {
+        if let Some(Value::Blackbody(ParamList(bb))) = ps.get(emission).cloned() {
+            if bb.len() == 2 {
+                scale *= bb[1];
+                ps.add(emission, Value::Blackbody(vec![bb[0]].into()));
+            }
+            w.warn(format!(
+                "pbrt-v4 normalizes blackbody \"{}\" to unit luminance, so the light's \
+                 brightness will differ",
+                emission
+            ));
+        }
+    }
+    if scale != 1. {
+        ps.add("scale", Value::Float(vec![scale].into()));
+    }
+}
+
+/// Returns the pbrt-v4 name of material typ, upgrading its parameters in ps.
+fn upgrade_material(typ: String, ps: &mut ParamSet, w: &mut Warnings) -> String {
+    ps.rename("bumpmap", "displacement");
+    if typ == "glass" || typ == "uber" {
+        ps.rename("index", "eta");
+    }
+    let what = format!("material \"{}\"", typ);
+    let typ = match typ.as_str() {
+        "matte" => {
+            ps.rename("Kd", "reflectance");
+            if !remove_if_constant(ps, "sigma", 0.) {
+                drop_param(ps, "sigma", &what, w);
+            }
+            "diffuse"
+        }
+        "plastic" | "uber" => {
+            let specular = !remove_if_constant(ps, "Ks", 0.);
+            if typ == "uber" {
+                for name in &["Kr", "Kt"] {
+                    if !remove_if_constant(ps, name, 0.) {
+                        drop_param(ps, name, &what, w);
+                    }
+                }
+                if !remove_if_constant(ps, "opacity", 1.) {
+                    drop_param(ps, "opacity", &what, w);
+                }
+            }
+            ps.rename("Kd", "reflectance");
+            if specular {
+                drop_param(ps, "Ks", &what, w);
+                "coateddiffuse"
+            } else {
+                for name in &["roughness", "uroughness", "vroughness", "remaproughness", "eta"] {
+                    ps.remove(name);
+                }
+                "diffuse"
+            }
+        }
+        "substrate" => {
+            if !remove_if_constant(ps, "Ks", 0.5) {
+                drop_param(ps, "Ks", &what, w);
+            }
+            ps.rename("Kd", "reflectance");
+            "coateddiffuse"
+        }
+        "glass" => {
+            for name in &["Kr", "Kt"] {
+                if !remove_if_constant(ps, name, 1.) {
+                    drop_param(ps, name, &what, w);
+                }
+            }
+            "dielectric"
+        }
+        "metal" => "conductor",
+        "mirror" => {
+            if remove_if_constant(ps, "Kr", 1.) || ps.get("Kr").is_none() {
+                let silver = |name: &str| Value::SpectrumFile(vec![name.to_owned()].into());
+                ps.add("eta", silver("metal-Ag-eta"));
+                ps.add("k", silver("metal-Ag-k"));
+            } else {
+                ps.rename("Kr", "reflectance");
+            }
+            ps.add("roughness", Value::Float(vec![0.].into()));
+            "conductor"
+        }
+        "translucent" => {
+            let kd = ps
+                .remove("Kd")
+                .unwrap_or_else(|| Value::RGB(vec![0.25, 0.25, 0.25].into()));
+            ps.add("reflectance", kd.clone());
+            ps.add("transmittance", kd);
+            let reflect = ps.remove("reflect").map_or(Some(0.5), |v| constant(&v));
+            let transmit = ps.remove("transmit").map_or(Some(0.5), |v| constant(&v));
+            match (reflect, transmit) {
+                (Some(r), Some(t)) if r == t => ps.add("scale", Value::Float(vec![r].into())),
+                _ => w.warn(format!(
+                    "{} with different or textured \"reflect\" and \"transmit\" has no \
+                     pbrt-v4 equivalent, dropping them",
+                    what
+                )),
+            }
+            for name in &["Ks", "roughness", "remaproughness"] {
+                drop_param(ps, name, &what, w);
+            }
+            "diffusetransmission"
+        }
+        "kdsubsurface" => {
+            ps.rename("Kd", "reflectance");
+            "subsurface"
+        }
+        "mix" => {
+            let mut materials = Vec::new();
+            for name in &["namedmaterial1", "namedmaterial2"] {
+                match ps.remove(name) {
+                    Some(Value::String(ParamList(mut m))) if m.len() == 1 => {
+                        materials.push(m.remove(0))
+                    }
+                    _ => w.warn(format!("{} has no \"string {}\"", what, name)),
+                }
+            }
+            if materials.len() == 2 {
+                ps.add("materials", Value::String(materials.into()));
+            }
+            "mix"
+        }
+        "disney" | "fourier" => {
+            w.warn(format!("{} has no pbrt-v4 equivalent", what));
+            return typ;
+        }
+        _ => return typ,
+    };
+    typ.to_owned()
+}
+
+fn upgrade_texture(class: &str, ps: &mut ParamSet) {
+    match class {
+        "imagemap" => {
+            if let Some(Value::Bool(ParamList(gamma))) = ps.remove("gamma") {
+                let encoding = if gamma.first() == Some(&true) {
+                    "sRGB"
+                } else {
+                    "linear"
+                };
+                ps.add(
+                    "encoding",
+                    Value::String(vec![encoding.to_owned()].into()),
+                );
+            }
+        }
+        "scale" => {
+            ps.rename("tex1", "tex");
+            ps.rename("tex2", "scale");
+        }
+        _ => (),
+    }
+}
+
+fn upgrade_shape(name: &str, ps: &mut ParamSet, w: &mut Warnings) {
+    let what = format!("shape \"{}\"", name);
+    drop_param(ps, "shadowalpha", &what, w);
+    match name {
+        "trianglemesh" => {
+            for uv in &["uv", "st"] {
+                if let Some(Value::Float(ParamList(vs))) = ps.get(uv).cloned() {
+                    ps.remove(uv);
+                    if vs.len() % 2 != 0 {
+                        w.warn(format!("odd number of values in \"float {}\", dropping it", uv));
+                        continue;
+                    }
+                    let uvs: Vec<_> = vs
+                        .chunks_exact(2)
+                        .map(|c| Point2f { x: c[0], y: c[1] })
+                        .collect();
+                    ps.add("uv", Value::Point2f(uvs.into()));
+                }
+            }
+        }
+        "loopsubdiv" => ps.rename("nlevels", "levels"),
+        "cone" | "paraboloid" | "hyperboloid" | "heightfield" | "nurbs" => {
+            w.warn(format!("{} has no pbrt-v4 equivalent", what))
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::paramset::ParamSetItem;
+    use core::parser::parse_scene;
+
+    fn upgraded(input: &str) -> (Vec<Directive>, Vec<String>) {
+        let (scene, warnings) = upgrade_scene(&parse_scene(input.as_bytes()).unwrap());
+        (
+            scene.directives,
+            warnings.into_iter().map(|w| w.message).collect(),
+        )
+    }
+
+    fn ps(items: Vec<(&str, Value)>) -> ParamSet {
+        items
+            .into_iter()
+            .map(|(name, v)| ParamSetItem::new(name, &v))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(vec![s.to_owned()].into())
+    }
+
+    #[test]
+    fn test_renames() {
+        let (ds, warnings) = upgraded(
+            r#"Sampler "lowdiscrepancy" "integer pixelsamples" 16
+Film "image" "float maxsampleluminance" 10
+Camera "environment"
+TransformBegin
+TransformEnd"#,
+        );
+        assert_eq!(
+            ds,
+            vec![
+                Directive::Sampler(
+                    "paddedsobol".into(),
+                    ps(vec![("pixelsamples", Value::Int(vec![16].into()))])
+                ),
+                Directive::Film(
+                    "rgb".into(),
+                    ps(vec![("maxcomponentvalue", Value::Float(vec![10.].into()))])
+                ),
+                Directive::Camera(
+                    "spherical".into(),
+                    ps(vec![("mapping", string("equirectangular"))])
+                ),
+                Directive::AttributeBegin,
+                Directive::AttributeEnd,
+            ]
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_materials() {
+        let (ds, warnings) = upgraded(
+            r#"Material "matte" "rgb Kd" [.5 .5 .5] "texture bumpmap" "bumps"
+Material "plastic" "rgb Kd" [.5 .5 .5] "rgb Ks" [0 0 0] "float roughness" .1
+Material "glass" "float index" 1.33 "rgb Kr" [1 1 1]
+Material "uber" "rgb Kr" [.1 .2 .3]
+MakeNamedMaterial "silver" "string type" "mirror"
+MakeNamedMaterial "both" "string type" "mix" "string namedmaterial1" "a"
+    "string namedmaterial2" "b" "float amount" .25"#,
+        );
+        assert_eq!(
+            ds,
+            vec![
+                Directive::Material(
+                    "diffuse".into(),
+                    ps(vec![
+                        ("reflectance", Value::RGB(vec![0.5, 0.5, 0.5].into())),
+                        ("displacement", Value::Texture(vec!["bumps".into()].into())),
+                    ])
+                ),
+                Directive::Material(
+                    "diffuse".into(),
+                    ps(vec![("reflectance", Value::RGB(vec![0.5, 0.5, 0.5].into()))])
+                ),
+                Directive::Material(
+                    "dielectric".into(),
+                    ps(vec![("eta", Value::Float(vec![1.33].into()))])
+                ),
+                Directive::Material("coateddiffuse".into(), ParamSet::default()),
+                Directive::MakeNamedMaterial(
+                    "silver".into(),
+                    ps(vec![
+                        ("type", string("conductor")),
+                        (
+                            "eta",
+                            Value::SpectrumFile(vec!["metal-Ag-eta".into()].into())
+                        ),
+                        ("k", Value::SpectrumFile(vec!["metal-Ag-k".into()].into())),
+                        ("roughness", Value::Float(vec![0.].into())),
+                    ])
+                ),
+                Directive::MakeNamedMaterial(
+                    "both".into(),
+                    ps(vec![
+                        ("type", string("mix")),
+                        (
+                            "materials",
+                            Value::String(vec!["a".to_owned(), "b".to_owned()].into())
+                        ),
+                        ("amount", Value::Float(vec![0.25].into())),
+                    ])
+                ),
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "parameter \"Kr\" of material \"uber\" has no pbrt-v4 equivalent, dropping it"
+                    .to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lights() {
+        let (ds, warnings) = upgraded(
+            r#"LightSource "infinite" "string mapname" "sky.exr" "rgb scale" [2 2 2]
+LightSource "point" "rgb I" [1 1 1] "rgb scale" [1 2 3]
+AreaLightSource "diffuse" "blackbody L" [6500 2] "float scale" 3
+LightSource "spot" "spectrum I" [300 1 800 1] "rgb scale" [1 2 3]
+LightSource "distant" "rgb L" [1 1 1] "float scale" 2 "float power" 10"#,
+        );
+        assert_eq!(
+            ds,
+            vec![
+                Directive::LightSource(
+                    "infinite".into(),
+                    ps(vec![
+                        ("filename", string("sky.exr")),
+                        ("scale", Value::Float(vec![2.].into())),
+                    ])
+                ),
+                Directive::LightSource(
+                    "point".into(),
+                    ps(vec![("I", Value::RGB(vec![1., 2., 3.].into()))])
+                ),
+                Directive::AreaLightSource(
+                    "diffuse".into(),
+                    ps(vec![
+                        ("L", Value::Blackbody(vec![6500.].into())),
+                        ("scale", Value::Float(vec![6.].into())),
+                    ])
+                ),
+                Directive::LightSource(
+                    "spot".into(),
+                    ps(vec![(
+                        "I",
+                        Value::SampledSpectrum(vec![300., 1., 800., 1.].into())
+                    )])
+                ),
+                Directive::LightSource(
+                    "distant".into(),
+                    ps(vec![
+                        ("L", Value::RGB(vec![1., 1., 1.].into())),
+                        ("scale", Value::Float(vec![2.].into())),
+                    ])
+                ),
+            ]
+        );
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("pbrt-v4 normalizes blackbody \"L\""));
+        assert!(warnings[1].starts_with("light \"scale\" isn't a single value"));
+        assert!(warnings[2].starts_with("light \"power\" is ignored by pbrt-v3"));
+    }
+
+    #[test]
+    fn test_textures_and_shapes() {
+        let (ds, warnings) = upgraded(
+            r#"Texture "t" "color" "imagemap" "string filename" "a.png" "bool gamma" true
+Texture "s" "float" "scale" "texture tex1" "t" "float tex2" 2
+Shape "trianglemesh" "float st" [0 0 1 0 1 1]
+Shape "cone""#,
+        );
+        assert_eq!(
+            ds,
+            vec![
+                Directive::Texture(
+                    "t".into(),
+                    "spectrum".into(),
+                    "imagemap".into(),
+                    ps(vec![("filename", string("a.png")), ("encoding", string("sRGB"))])
+                ),
+                Directive::Texture(
+                    "s".into(),
+                    "float".into(),
+                    "scale".into(),
+                    ps(vec![
+                        ("tex", Value::Texture(vec!["t".into()].into())),
+                        ("scale", Value::Float(vec![2.].into())),
+                    ])
+                ),
+                Directive::Shape(
+                    "trianglemesh".into(),
+                    ps(vec![(
+                        "uv",
+                        Value::Point2f(
+                            vec![
+                                Point2f { x: 0., y: 0. },
+                                Point2f { x: 1., y: 0. },
+                                Point2f { x: 1., y: 1. },
+                            ].into()
+                        )
+                    )])
+                ),
+                Directive::Shape("cone".into(), ParamSet::default()),
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec!["shape \"cone\" has no pbrt-v4 equivalent".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_warning_location() {
+        let (_, warnings) = upgrade_scene(
+            &parse_scene(&b"WorldBegin\n  Integrator \"whitted\"\n"[..]).unwrap(),
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().starts_with("2:3: integrator \"whitted\""));
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Writes directives as canonical pbrt-v3 text.  Parsing the output gives back equal directives.
+//!
+//! The canonical form has one directive per line, with each parameter on its own line below its
+//! directive.  Parameters are sorted by name, values are always bracketed, and attribute and
+//! transform blocks are indented.  Parameters that have no text form that parses back to them,
+//! Spectrum values and empty lists, are skipped with a warning.
+use std::fmt;
+use std::io;
+
+use core::paramset::{ParamSet, ParamSetItem, Value};
+use core::parser::{ActiveTransform, Directive, Scene};
+use core::pbrt::Float;
+
+const INDENT: &str = "    ";
+
+/// Writer writes directives to an io::Write, tracking the indentation of nested blocks.
+pub struct Writer<W> {
+    w: W,
+    depth: usize,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn new(w: W) -> Writer<W> {
+        Writer { w, depth: 0 }
+    }
+
+    /// Returns the underlying io::Write.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// Writes d on its own line, followed by its parameters.
+    pub fn write(&mut self, d: &Directive) -> io::Result<()> {
+        if let Directive::AttributeEnd | Directive::TransformEnd = d {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        let indent = INDENT.repeat(self.depth);
+        write!(self.w, "{}", indent)?;
+        match d {
+            Directive::LookAt(ex, ey, ez, lx, ly, lz, ux, uy, uz) => writeln!(
+                self.w,
+                "LookAt {} {} {}  {} {} {}  {} {} {}",
+                ex, ey, ez, lx, ly, lz, ux, uy, uz
+            )?,
+            Directive::Camera(name, ps) => self.named("Camera", &[name], ps, &indent)?,
+            Directive::Sampler(name, ps) => self.named("Sampler", &[name], ps, &indent)?,
+            Directive::Integrator(name, ps) => self.named("Integrator", &[name], ps, &indent)?,
+            Directive::Film(name, ps) => self.named("Film", &[name], ps, &indent)?,
+            Directive::WorldBegin => writeln!(self.w, "WorldBegin")?,
+            Directive::WorldEnd => writeln!(self.w, "WorldEnd")?,
+            Directive::AttributeBegin => writeln!(self.w, "AttributeBegin")?,
+            Directive::AttributeEnd => writeln!(self.w, "AttributeEnd")?,
+            Directive::LightSource(name, ps) => {
+                self.named("LightSource", &[name], ps, &indent)?
+            }
+            Directive::AreaLightSource(name, ps) => {
+                self.named("AreaLightSource", &[name], ps, &indent)?
+            }
+            Directive::Material(name, ps) => self.named("Material", &[name], ps, &indent)?,
+            Directive::MakeNamedMaterial(name, ps) => {
+                self.named("MakeNamedMaterial", &[name], ps, &indent)?
+            }
+            Directive::NamedMaterial(name) => writeln!(self.w, "NamedMaterial {}", Quoted(name))?,
+            Directive::MakeNamedMedium(name, ps) => {
+                self.named("MakeNamedMedium", &[name], ps, &indent)?
+            }
+            Directive::MediumInterface(inside, outside) => writeln!(
+                self.w,
+                "MediumInterface {} {}",
+                Quoted(inside),
+                Quoted(outside)
+            )?,
+            Directive::Shape(name, ps) => self.named("Shape", &[name], ps, &indent)?,
+            Directive::Translate(x, y, z) => writeln!(self.w, "Translate {} {} {}", x, y, z)?,
+            Directive::Scale(x, y, z) => writeln!(self.w, "Scale {} {} {}", x, y, z)?,
+            Directive::Rotate(angle, x, y, z) => {
+                writeln!(self.w, "Rotate {} {} {} {}", angle, x, y, z)?
+            }
+            Directive::Texture(name, typ, class, ps) => {
+                self.named("Texture", &[name, typ, class], ps, &indent)?
+            }
+            Directive::Identity => writeln!(self.w, "Identity")?,
+            Directive::Transform(m) => writeln!(self.w, "Transform {}", Matrix(m))?,
+            Directive::ConcatTransform(m) => writeln!(self.w, "ConcatTransform {}", Matrix(m))?,
+            Directive::CoordinateSystem(name) => {
+                writeln!(self.w, "CoordinateSystem {}", Quoted(name))?
+            }
+            Directive::CoordSysTransform(name) => {
+                writeln!(self.w, "CoordSysTransform {}", Quoted(name))?
+            }
+            Directive::TransformBegin => writeln!(self.w, "TransformBegin")?,
+            Directive::TransformEnd => writeln!(self.w, "TransformEnd")?,
+            Directive::ActiveTransform(which) => {
+                let which = match which {
+                    ActiveTransform::All => "All",
+                    ActiveTransform::StartTime => "StartTime",
+                    ActiveTransform::EndTime => "EndTime",
+                };
+                writeln!(self.w, "ActiveTransform {}", which)?
+            }
+            Directive::TransformTimes(start, end) => {
+                writeln!(self.w, "TransformTimes {} {}", start, end)?
+            }
+            Directive::Include(path) => writeln!(self.w, "Include {}", Quoted(path))?,
+            Directive::Import(path) => writeln!(self.w, "Import {}", Quoted(path))?,
+        }
+        if let Directive::AttributeBegin | Directive::TransformBegin = d {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes a directive taking quoted arguments and a parameter list.
+    fn named(&mut self, kw: &str, args: &[&String], ps: &ParamSet, indent: &str) -> io::Result<()> {
+        write!(self.w, "{}", kw)?;
+        for a in args {
+            write!(self.w, " {}", Quoted(a))?;
+        }
+        writeln!(self.w)?;
+        for item in ps.items() {
+            if let Some(why) = unwritable(&item.values) {
+                warn!("skipping {} parameter \"{}\": {}", kw, item.name, why);
+                continue;
+            }
+            writeln!(self.w, "{}{}{}", indent, INDENT, Param(item))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns why values can't be written as text that parses back to them, if they can't.
+fn unwritable(values: &Value) -> Option<&'static str> {
+    match values {
+        Value::Spectrum(_) => Some("Spectrum values have no text form"),
+        v if v.is_empty() => Some("it has no values"),
+        _ => None,
+    }
+}
+
+/// Writes a string in quotes, escaping the characters the lexer unescapes.
+struct Quoted<'a>(&'a str);
+
+impl<'a> fmt::Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                '\x08' => write!(f, "\\b")?,
+                '\x0c' => write!(f, "\\f")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+struct Matrix<'a>(&'a [Float; 16]);
+
+impl<'a> fmt::Display for Matrix<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, v) in self.0.iter().enumerate() {
+            // Separate the rows to make the matrix easier to read.
+            let sep = if i > 0 && i % 4 == 0 { "  " } else { " " };
+            write!(f, "{}{}", sep, v)?;
+        }
+        write!(f, " ]")
+    }
+}
+
+/// Writes a parameter as its "type name" declaration followed by its bracketed values.  Its
+/// values mustn't be unwritable.
+struct Param<'a>(&'a ParamSetItem);
+
+impl<'a> fmt::Display for Param<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values = &self.0.values;
+        write!(f, "\"{} {}\" [", values.type_name(), self.0.name)?;
+        fn list<T, F>(f: &mut fmt::Formatter, vs: &[T], mut g: F) -> fmt::Result
+        where
+            F: FnMut(&mut fmt::Formatter, &T) -> fmt::Result,
+        {
+            for v in vs {
+                write!(f, " ")?;
+                g(f, v)?;
+            }
+            Ok(())
+        }
+        match values {
+            Value::Bool(pl) => list(f, &pl.0, |f, v| write!(f, "\"{}\"", v))?,
+            Value::Float(pl)
+            | Value::RGB(pl)
+            | Value::XYZ(pl)
+            | Value::Blackbody(pl)
+            | Value::SampledSpectrum(pl) => list(f, &pl.0, |f, v| write!(f, "{}", v))?,
+            Value::Int(pl) => list(f, &pl.0, |f, v| write!(f, "{}", v))?,
+            Value::Point2f(pl) => list(f, &pl.0, |f, v| write!(f, "{} {}", v.x, v.y))?,
+            Value::Vector2f(pl) => list(f, &pl.0, |f, v| write!(f, "{} {}", v.x, v.y))?,
+            Value::Point3f(pl) => list(f, &pl.0, |f, v| write!(f, "{} {} {}", v.x, v.y, v.z))?,
+            Value::Vector3f(pl) => list(f, &pl.0, |f, v| write!(f, "{} {} {}", v.x, v.y, v.z))?,
+            Value::Normal3f(pl) => list(f, &pl.0, |f, v| write!(f, "{} {} {}", v.x, v.y, v.z))?,
+            Value::Spectrum(_) => unreachable!("Spectrum values aren't written"),
+            Value::String(pl) | Value::Texture(pl) | Value::SpectrumFile(pl) => {
+                list(f, &pl.0, |f, v| write!(f, "{}", Quoted(v)))?
+            }
+        }
+        write!(f, " ]")
+    }
+}
+
+/// Writes the scene as canonical pbrt text.
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut w = Writer::new(Vec::new());
+        for d in &self.directives {
+            w.write(d).map_err(|_| fmt::Error)?;
+        }
+        write!(f, "{}", String::from_utf8_lossy(&w.into_inner()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::geometry::{Normal3f, Point2f, Point3f, Vector2f, Vector3f};
+    use core::paramset::ParamList;
+    use core::parser::parse_scene;
+    use core::spectrum::Spectrum;
+
+    /// A scene with every kind of directive and parameter value.
+    fn every_directive() -> Scene {
+        let ps = |items: Vec<ParamSetItem>| -> ParamSet { items.into() };
+        vec![
+            Directive::LookAt(3., 4., 1.5, 0.5, 0.5, 0., 0., 0., 1.),
+            Directive::Camera(
+                "perspective".into(),
+                ps(vec![ParamSetItem::new("fov", &Value::Float(vec![45.].into()))]),
+            ),
+            Directive::Sampler(
+                "halton".into(),
+                ps(vec![ParamSetItem::new(
+                    "pixelsamples",
+                    &Value::Int(vec![128].into()),
+                )]),
+            ),
+            Directive::Integrator("path".into(), ParamSet::default()),
+            Directive::Film(
+                "image".into(),
+                ps(vec![
+                    ParamSetItem::new(
+                        "filename",
+                        &Value::String(vec!["a \"quoted\"\\name.png".to_owned()].into()),
+                    ),
+                    ParamSetItem::new("xresolution", &Value::Int(vec![400].into())),
+                ]),
+            ),
+            Directive::TransformTimes(0., 1.5),
+            Directive::ActiveTransform(ActiveTransform::StartTime),
+            Directive::ActiveTransform(ActiveTransform::EndTime),
+            Directive::ActiveTransform(ActiveTransform::All),
+            Directive::WorldBegin,
+            Directive::Include("geometry/#1.pbrt".into()),
+            Directive::Import("materials.pbrt".into()),
+            Directive::AttributeBegin,
+            Directive::LightSource(
+                "distant".into(),
+                ps(vec![
+                    ParamSetItem::new(
+                        "from",
+                        &Value::Point3f(
+                            vec![Point3f {
+                                x: -30.,
+                                y: 40.,
+                                z: 100.,
+                            }].into(),
+                        ),
+                    ),
+                    ParamSetItem::new("L", &Value::Blackbody(vec![3000., 1.5].into())),
+                    ParamSetItem::new("scale", &Value::RGB(vec![0.1, 0.2, 0.3].into())),
+                ]),
+            ),
+            Directive::TransformBegin,
+            Directive::Identity,
+            Directive::Translate(0., 0., -1.),
+            Directive::Scale(2., 2., 2.),
+            Directive::Rotate(30., 0., 0., 1.),
+            Directive::Transform([
+                1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 2., 3., 4., 1.,
+            ]),
+            Directive::ConcatTransform([
+                2., 0., 0., 0., 0., 2., 0., 0., 0., 0., 2., 0., 0., 0., 0., 1.,
+            ]),
+            Directive::CoordinateSystem("mycs".into()),
+            Directive::CoordSysTransform("mycs".into()),
+            Directive::TransformEnd,
+            Directive::Texture(
+                "checks".into(),
+                "spectrum".into(),
+                "checkerboard".into(),
+                ps(vec![
+                    ParamSetItem::new("uscale", &Value::Float(vec![8.].into())),
+                    ParamSetItem::new("tex1", &Value::XYZ(vec![0.1, 0.1, 0.1].into())),
+                    ParamSetItem::new(
+                        "tex2",
+                        &Value::SampledSpectrum(vec![300., 0.3, 400., 0.6].into()),
+                    ),
+                ]),
+            ),
+            Directive::Material(
+                "metal".into(),
+                ps(vec![
+                    ParamSetItem::new("Kd", &Value::Texture(vec!["checks".into()].into())),
+                    ParamSetItem::new(
+                        "eta",
+                        &Value::SpectrumFile(vec!["spds/Au.eta.spd".into()].into()),
+                    ),
+                    ParamSetItem::new("remaproughness", &Value::Bool(vec![false].into())),
+                ]),
+            ),
+            Directive::MakeNamedMaterial(
+                "gold".into(),
+                ps(vec![ParamSetItem::new(
+                    "type",
+                    &Value::String(vec!["metal".to_owned()].into()),
+                )]),
+            ),
+            Directive::NamedMaterial("gold".into()),
+            Directive::MakeNamedMedium(
+                "fog".into(),
+                ps(vec![
+                    ParamSetItem::new("type", &Value::String(vec!["homogeneous".into()].into())),
+                    ParamSetItem::new("g", &Value::Float(vec![0.5].into())),
+                ]),
+            ),
+            Directive::MediumInterface("fog".into(), "".into()),
+            Directive::AreaLightSource(
+                "diffuse".into(),
+                ps(vec![ParamSetItem::new("L", &Value::RGB(vec![1., 1., 1.].into()))]),
+            ),
+            Directive::Shape(
+                "trianglemesh".into(),
+                ps(vec![
+                    ParamSetItem::new("indices", &Value::Int(vec![0, 1, 2].into())),
+                    ParamSetItem::new(
+                        "P",
+                        &Value::Point3f(
+                            vec![
+                                Point3f {
+                                    x: 0.,
+                                    y: 0.,
+                                    z: 0.,
+                                },
+                                Point3f {
+                                    x: 1.,
+                                    y: 0.,
+                                    z: 0.,
+                                },
+                                Point3f {
+                                    x: 0.,
+                                    y: 1.,
+                                    z: 0.,
+                                },
+                            ].into(),
+                        ),
+                    ),
+                    ParamSetItem::new(
+                        "N",
+                        &Value::Normal3f(
+                            vec![
+                                Normal3f {
+                                    x: 0.,
+                                    y: 0.,
+                                    z: 1.,
+                                };
+                                3
+                            ].into(),
+                        ),
+                    ),
+                    ParamSetItem::new(
+                        "S",
+                        &Value::Vector3f(vec![Vector3f::new(1., 0., 0.); 3].into()),
+                    ),
+                    ParamSetItem::new(
+                        "uv",
+                        &Value::Point2f(
+                            vec![
+                                Point2f { x: 0., y: 0. },
+                                Point2f { x: 1., y: 0. },
+                                Point2f { x: 0., y: 1. },
+                            ].into(),
+                        ),
+                    ),
+                    ParamSetItem::new(
+                        "dir",
+                        &Value::Vector2f(ParamList(vec![Vector2f { x: 0.25, y: -1e-7 }])),
+                    ),
+                ]),
+            ),
+            Directive::AttributeEnd,
+            Directive::WorldEnd,
+        ].into()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let scene = every_directive();
+        let text = scene.to_string();
+        assert_eq!(parse_scene(text.as_bytes()).unwrap(), scene);
+        // Writing is idempotent.
+        assert_eq!(parse_scene(text.as_bytes()).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn test_round_trip_testdata() {
+        let scene = parse_scene(&include_bytes!("testdata/scene1.pbrt")[..]).unwrap();
+        assert_eq!(parse_scene(scene.to_string().as_bytes()).unwrap(), scene);
+    }
+
+    #[test]
+    fn test_unwritable() {
+        // Spectrum values and empty lists would parse back as xyz values and syntax errors, so
+        // they're skipped.
+        let radius = ParamSetItem::new("radius", &Value::Float(vec![1.].into()));
+        let scene: Scene = vec![Directive::Shape(
+            "sphere".into(),
+            vec![
+                radius.clone(),
+                ParamSetItem::new(
+                    "L",
+                    &Value::Spectrum(vec![Spectrum::from_xyz([1., 1., 1.])].into()),
+                ),
+                ParamSetItem::new("indices", &Value::Int(vec![].into())),
+                ParamSetItem::new("Kd", &Value::Texture(vec![].into())),
+            ]
+            .into(),
+        )]
+        .into();
+        let text = scene.to_string();
+        assert_eq!(text, "Shape \"sphere\"\n    \"float radius\" [ 1 ]\n");
+        assert_eq!(
+            parse_scene(text.as_bytes()).unwrap(),
+            vec![Directive::Shape("sphere".into(), vec![radius].into())].into()
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let scene = parse_scene(
+            &br#"WorldBegin AttributeBegin
+Material "matte" "texture Kd" "checks"   "rgb Ks" [ .5 .5 .5 ]
+  Shape "sphere" "float radius" 1 AttributeEnd WorldEnd"#[..],
+        ).unwrap();
+        assert_eq!(
+            scene.to_string(),
+            r#"WorldBegin
+AttributeBegin
+    Material "matte"
+        "texture Kd" [ "checks" ]
+        "rgb Ks" [ 0.5 0.5 0.5 ]
+    Shape "sphere"
+        "float radius" [ 1 ]
+AttributeEnd
+WorldEnd
+"#
+        );
+    }
+}
@@ -15,10 +15,19 @@
 extern crate log;
 #[macro_use]
 extern crate nom;
+#[cfg(feature = "mitsuba")]
+extern crate roxmltree;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
 #[cfg(test)]
 #[macro_use]
 extern crate pretty_assertions;
+#[cfg(test)]
+extern crate proptest;
+#[cfg(any(feature = "gltf", all(test, feature = "serde")))]
+extern crate serde_json;
 
 pub mod core;
 pub mod textures;
@@ -0,0 +1,71 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use pbrt::core::api::Pbrt;
+use pbrt::core::builder::SceneBuilder;
+use pbrt::core::parser::parse_scene;
+use pbrt::core::pbrt::Options;
+
+#[test]
+fn test_execute_built_scene() {
+    let scene = SceneBuilder::new()
+        .look_at([3., 4., 1.5], [0.5, 0.5, 0.], [0., 0., 1.])
+        .camera("perspective", params! {"float fov" => 45.})
+        .sampler("halton", params! {"integer pixelsamples" => 128})
+        .film("image", params! {"string filename" => "spheres.png"})
+        .world(|w| {
+            let mut w = w.light_source("infinite", params! {"rgb L" => [0.4, 0.45, 0.5]});
+            for i in 0..3 {
+                w = w.attribute_block(|w| {
+                    w.translate(i as f32, 0., 0.)
+                        .material("plastic", params! {"rgb Kd" => [0.1, 0.1, 0.8]})
+                        .shape("sphere", params! {"float radius" => 0.5})
+                });
+            }
+            w
+        });
+    let want = parse_scene(
+        &br#"LookAt 3 4 1.5  0.5 0.5 0  0 0 1
+Camera "perspective" "float fov" [45]
+Sampler "halton" "integer pixelsamples" [128]
+Film "image" "string filename" ["spheres.png"]
+WorldBegin
+LightSource "infinite" "rgb L" [0.4 0.45 0.5]
+AttributeBegin
+    Translate 0 0 0
+    Material "plastic" "rgb Kd" [0.1 0.1 0.8]
+    Shape "sphere" "float radius" [0.5]
+AttributeEnd
+AttributeBegin
+    Translate 1 0 0
+    Material "plastic" "rgb Kd" [0.1 0.1 0.8]
+    Shape "sphere" "float radius" [0.5]
+AttributeEnd
+AttributeBegin
+    Translate 2 0 0
+    Material "plastic" "rgb Kd" [0.1 0.1 0.8]
+    Shape "sphere" "float radius" [0.5]
+AttributeEnd
+WorldEnd
+"#[..],
+    )
+    .unwrap();
+    // The builder doesn't know where its directives would be in a file.
+    assert_eq!(scene.directives, want.directives);
+
+    let opts = Options::default();
+    let mut pbrt = Pbrt::new(&opts);
+    pbrt.init();
+    pbrt.execute_scene(scene);
+    pbrt.cleaup();
+}